@@ -0,0 +1,191 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use crate::input::parse_hex;
+use anyhow::{bail, Error};
+use arngll::{Fcs, FrameInfo, FrameType, NetworkId};
+use clap::Parser;
+use hamaddr::HamAddr;
+
+fn parse_frame_type(s: &str) -> Result<FrameType, Error> {
+    Ok(match s.to_ascii_lowercase().as_str() {
+        "beacon" => FrameType::Beacon,
+        "data" => FrameType::Data,
+        "ack" => FrameType::Ack,
+        "mac" | "mac-command" => FrameType::MacCommand,
+        _ => bail!("Expected `beacon`, `data`, `ack` or `mac-command`"),
+    })
+}
+
+fn parse_u16_hex(s: &str) -> Result<u16, Error> {
+    let s = s.trim_start_matches("0x");
+    Ok(u16::from_str_radix(s, 16)?)
+}
+
+/// Builds an ARNGLL frame and prints it in hex.
+#[derive(Parser, Debug)]
+pub struct EncodeOpt {
+    /// Frame type: `beacon`, `data`, `ack` or `mac-command`
+    #[clap(long = "type", default_value = "data", parse(try_from_str = parse_frame_type))]
+    frame_type: FrameType,
+
+    #[clap(long)]
+    src: HamAddr,
+
+    /// Destination address. Defaults to broadcast.
+    #[clap(long)]
+    dst: Option<HamAddr>,
+
+    /// Relay address
+    #[clap(long)]
+    rly: Option<HamAddr>,
+
+    /// Mark the frame as being sent by the relay
+    #[clap(long)]
+    from_relay: bool,
+
+    #[clap(long)]
+    ack_requested: bool,
+
+    /// Network ID, in hex
+    #[clap(long, parse(try_from_str = parse_u16_hex))]
+    netid: Option<u16>,
+
+    /// CRC of the acknowledged frame, in hex. Only for ack frames.
+    #[clap(long, parse(try_from_str = parse_u16_hex))]
+    ack_crc: Option<u16>,
+
+    /// Payload as text
+    #[clap(long, conflicts_with = "payload-hex")]
+    payload: Option<String>,
+
+    /// Payload in hex
+    #[clap(long)]
+    payload_hex: Option<String>,
+
+    /// Don't append the FCS
+    #[clap(long)]
+    no_fcs: bool,
+
+    /// FCS to append: `x25` or `crc32`
+    #[clap(long, default_value = "x25")]
+    crc: Fcs,
+}
+
+impl EncodeOpt {
+    /// Returns the encoded frame.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let is_ack = self.frame_type == FrameType::Ack;
+        let payload = match (&self.payload, &self.payload_hex) {
+            (Some(text), _) => text.as_bytes().to_vec(),
+            (None, Some(hex)) => parse_hex(hex)?,
+            (None, None) => vec![],
+        };
+
+        if is_ack {
+            if self.ack_crc.is_none() {
+                bail!("Ack frames need --ack-crc");
+            }
+            if self.dst.is_some() || self.rly.is_some() || self.netid.is_some() {
+                bail!("Ack frames have no destination, relay or network ID");
+            }
+            if !payload.is_empty() {
+                bail!("Ack frames have no payload");
+            }
+        } else if self.ack_crc.is_some() {
+            bail!("--ack-crc is only for ack frames");
+        }
+
+        if self.from_relay && self.rly.is_none() {
+            bail!("--from-relay needs --rly");
+        }
+
+        let frame_info = FrameInfo {
+            frame_type: self.frame_type,
+            ack_requested: self.ack_requested,
+            is_from_relay: self.from_relay,
+            network_id: self.netid.map(NetworkId),
+            dst_addr: if is_ack {
+                HamAddr::EMPTY
+            } else {
+                self.dst.unwrap_or(HamAddr::BROADCAST)
+            },
+            src_addr: self.src,
+            rly_addr: self.rly,
+            sec_info: None,
+            ack_crc: self.ack_crc.unwrap_or(0),
+        };
+
+        let frame = frame_info.to_vec(&payload);
+
+        Ok(if self.no_fcs {
+            frame
+        } else {
+            self.crc.append(frame)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(args: &[&str]) -> Result<Vec<u8>, Error> {
+        EncodeOpt::try_parse_from(std::iter::once("encode").chain(args.iter().copied()))?.encode()
+    }
+
+    #[test]
+    fn encode_test_vec_1() {
+        let frame = encode(&[
+            "--type=beacon",
+            "--src=N6NFI",
+            "--dst=N6DRC",
+            "--netid=1337",
+            "--payload-hex=062839414D2D54414B002918FA9C",
+            "--no-fcs",
+        ])
+        .unwrap();
+        assert_eq!(
+            hex::encode_upper(frame),
+            "054013375CAC70F85CB626E8062839414D2D54414B002918FA9C"
+        );
+    }
+
+    #[test]
+    fn encode_fcs_and_ack() {
+        let frame = encode(&["--src=N6NFI", "--payload=hi"]).unwrap();
+        let (frame_info, payload) =
+            FrameInfo::try_from_bytes(Fcs::X25.verify(&frame).unwrap()).unwrap();
+        assert_eq!(frame_info.dst_addr, HamAddr::BROADCAST);
+        assert_eq!(payload, b"hi");
+
+        let frame = encode(&["--src=N6NFI", "--crc=crc32"]).unwrap();
+        assert!(Fcs::Crc32.verify(&frame).is_ok());
+
+        let frame = encode(&["--type=ack", "--src=N6NFI", "--ack-crc=beef", "--no-fcs"]).unwrap();
+        let (frame_info, _) = FrameInfo::try_from_bytes(&frame).unwrap();
+        assert_eq!(frame_info.ack_crc, 0xbeef);
+
+        assert!(encode(&["--type=ack", "--src=N6NFI"]).is_err());
+        assert!(encode(&["--src=N6NFI", "--from-relay"]).is_err());
+        assert!(encode(&["--src=N6NFI", "--payload=a", "--payload-hex=00"]).is_err());
+    }
+}
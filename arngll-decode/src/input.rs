@@ -0,0 +1,207 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use anyhow::{bail, format_err, Error};
+use arngll::Fcs;
+use quick_dsp::bell202::{bell_202_decoder, BELL202_OPTIMAL_SAMPLE_RATE};
+use quick_dsp::filter::{Downsampler, Filter};
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+/// Whether input frames end with an FCS.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FcsMode {
+    /// Assume an FCS is present if the last two bytes are a valid one.
+    Auto,
+    Present,
+    Absent,
+}
+
+impl std::str::FromStr for FcsMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(FcsMode::Auto),
+            "yes" => Ok(FcsMode::Present),
+            "no" => Ok(FcsMode::Absent),
+            _ => bail!("Expected `auto`, `yes` or `no`"),
+        }
+    }
+}
+
+/// A frame read from the command line, a file or a recording.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InputFrame {
+    /// Where the frame came from, for display.
+    pub source: String,
+    pub bytes: Vec<u8>,
+    pub fcs: FcsMode,
+    pub crc: Fcs,
+}
+
+/// Parses hex, ignoring whitespace, `:` and `-` separators
+/// and an optional `0x` prefix.
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, Error> {
+    let s = s.trim();
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    let digits = s
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != '-')
+        .collect::<String>();
+    hex::decode(digits).map_err(|err| format_err!("Bad hex: {}", err))
+}
+
+/// Reads the frames from a single command-line argument, which is either
+/// a path to a WAV recording or binary frame, or a frame in hex.
+pub fn read_input(arg: &str, fcs: FcsMode, crc: Fcs) -> Result<Vec<InputFrame>, Error> {
+    let path = Path::new(arg);
+
+    if !path.is_file() {
+        return Ok(vec![InputFrame {
+            source: "hex".to_string(),
+            bytes: parse_hex(arg)?,
+            fcs,
+            crc,
+        }]);
+    }
+
+    let is_wav = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+
+    if is_wav {
+        let frames = decode_wav(&mut File::open(path)?)?;
+        Ok(frames
+            .into_iter()
+            .enumerate()
+            .map(|(i, bytes)| InputFrame {
+                source: format!("{} #{}", arg, i + 1),
+                bytes,
+                fcs: FcsMode::Present,
+                crc: Fcs::X25,
+            })
+            .collect())
+    } else {
+        let mut bytes = vec![];
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Ok(vec![InputFrame {
+            source: arg.to_string(),
+            bytes,
+            fcs,
+            crc,
+        }])
+    }
+}
+
+/// Demodulates the Bell 202 frames in a WAV recording, returning
+/// those with a valid FCS. Only the first channel is used.
+pub fn decode_wav<R: Read + Seek>(reader: &mut R) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = hound::WavReader::new(reader)?;
+    let spec = reader.spec();
+    let channels = spec.channels.max(1) as usize;
+    let sample_rate = spec.sample_rate;
+
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|x| x.map(|x| x as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    // The decoder is happy with anything up to 14kHz, so
+    // only downsample recordings above that.
+    let mut decode: Box<dyn FnMut(f32) -> Option<Vec<u8>>> = if sample_rate > 14000 {
+        let mut decoder = bell_202_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+        let mut downsampler = Downsampler::<f32>::new(sample_rate, BELL202_OPTIMAL_SAMPLE_RATE);
+        Box::new(move |x| downsampler.filter(x).and_then(|x| decoder.filter(x)))
+    } else {
+        let mut decoder = bell_202_decoder(sample_rate);
+        Box::new(move |x| decoder.filter(x))
+    };
+
+    Ok(samples
+        .into_iter()
+        .step_by(channels)
+        .chain(std::iter::repeat_n(0.0, sample_rate as usize / 10))
+        .filter_map(&mut decode)
+        .filter(|frame| Fcs::X25.verify(frame).is_ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_dsp::bell202::bell_202_encode;
+    use std::io::Cursor;
+
+    #[test]
+    fn parse_hex_separators() {
+        assert_eq!(
+            parse_hex("0x0540 1337").unwrap(),
+            vec![0x05, 0x40, 0x13, 0x37]
+        );
+        assert_eq!(parse_hex("05:40-ab").unwrap(), vec![0x05, 0x40, 0xAB]);
+        assert!(parse_hex("054").is_err());
+        assert!(parse_hex("hello").is_err());
+    }
+
+    #[test]
+    fn decode_wav_round_trip() {
+        let frame = hex::decode("054013375CAC70F85CB626E8062839414D2D54414B002918FA9C").unwrap();
+        let with_fcs = Fcs::X25.append(frame);
+
+        for sample_rate in [8000, 44100] {
+            let samples = std::iter::repeat_n(0.0, 1000)
+                .chain(bell_202_encode::<f32, _>(
+                    with_fcs.clone().into_iter(),
+                    sample_rate,
+                    0.5,
+                ))
+                .map(|x| (x * i16::MAX as f32) as i16)
+                .collect::<Vec<_>>();
+
+            let mut wav_file = Cursor::new(vec![]);
+            let spec = hound::WavSpec {
+                channels: 1,
+                sample_rate,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            let mut writer = hound::WavWriter::new(&mut wav_file, spec).unwrap();
+            for sample in samples {
+                writer.write_sample(sample).unwrap();
+            }
+            writer.finalize().unwrap();
+            wav_file.set_position(0);
+
+            assert_eq!(decode_wav(&mut wav_file).unwrap(), vec![with_fcs.clone()]);
+        }
+    }
+}
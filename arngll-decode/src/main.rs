@@ -0,0 +1,324 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Decodes ARNGLL and AX.25 frames given in hex, as binary
+//! files, or as Bell 202 WAV recordings.
+
+mod encode;
+mod input;
+
+use crate::encode::EncodeOpt;
+use crate::input::*;
+use arngll::{dissect, json_string, Dissection, Fcs, FcsError, FrameInfo};
+use ax25::aprs::AprsPacket;
+use ax25::Ax25Frame;
+use clap::{Parser, Subcommand};
+use std::fmt::Write as _;
+use std::io::BufRead;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Opt {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Frames in hex, or paths to binary frames or WAV recordings.
+    /// Hex frames are read from stdin, one per line, if none are given.
+    inputs: Vec<String>,
+
+    /// Whether frames end with an FCS: `auto`, `yes` or `no`.
+    /// Frames from WAV recordings always do.
+    #[clap(long, default_value = "auto")]
+    fcs: FcsMode,
+
+    /// FCS algorithm: `x25` or `crc32`
+    #[clap(long, default_value = "x25")]
+    crc: Fcs,
+
+    /// Print each frame as a JSON object on its own line
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Encode(EncodeOpt),
+}
+
+/// The result of checking the FCS of an input frame.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum FcsStatus {
+    Ok(u32),
+    Bad { received: u32, calculated: u32 },
+    Absent,
+}
+
+/// Everything we could work out about a single frame.
+#[derive(Debug)]
+enum Decoded {
+    Ax25(Ax25Frame),
+    Arngll(FrameInfo, usize, Dissection),
+
+    /// Not a valid frame, but the dissection shows how far we got.
+    Unknown(Dissection),
+}
+
+fn check_fcs(bytes: &[u8], mode: FcsMode, crc: Fcs) -> (&[u8], FcsStatus) {
+    if mode == FcsMode::Absent {
+        return (bytes, FcsStatus::Absent);
+    }
+
+    match crc.verify(bytes) {
+        Ok(frame) => (frame, FcsStatus::Ok(crc.calculate(frame))),
+        Err(FcsError::Mismatch {
+            received,
+            calculated,
+        }) if mode == FcsMode::Present => (
+            &bytes[..bytes.len() - crc.size()],
+            FcsStatus::Bad {
+                received,
+                calculated,
+            },
+        ),
+        Err(_) => (bytes, FcsStatus::Absent),
+    }
+}
+
+fn decode(frame: &[u8]) -> Decoded {
+    if let Ok(ax25) = Ax25Frame::try_from_bytes(frame) {
+        return Decoded::Ax25(ax25);
+    }
+
+    // The dissector copes with truncated frames, so only
+    // hand frames it is happy with to `try_from_bytes`.
+    let dissection = dissect(frame);
+    if dissection.error.is_none() {
+        if let Ok((frame_info, payload)) = FrameInfo::try_from_bytes(frame) {
+            return Decoded::Arngll(frame_info, payload.len(), dissection);
+        }
+    }
+    Decoded::Unknown(dissection)
+}
+
+fn format_text(input: &InputFrame) -> String {
+    let (frame, fcs) = check_fcs(&input.bytes, input.fcs, input.crc);
+    let mut out = format!("== {} ({} bytes)\n", input.source, input.bytes.len());
+    let width = input.crc.size() * 2;
+
+    let _ = match fcs {
+        FcsStatus::Ok(fcs) => writeln!(out, "FCS: OK (0x{:0width$X})", fcs),
+        FcsStatus::Bad {
+            received,
+            calculated,
+        } => writeln!(
+            out,
+            "FCS: BAD (received 0x{:0width$X}, calculated 0x{:0width$X})",
+            received, calculated
+        ),
+        FcsStatus::Absent => writeln!(out, "FCS: None"),
+    };
+
+    match decode(frame) {
+        Decoded::Ax25(ax25) => {
+            let _ = writeln!(out, "AX.25: {}", ax25);
+            if let Ok(aprs) = AprsPacket::try_from_frame(&ax25) {
+                let _ = writeln!(out, "APRS: {:?}", aprs);
+            }
+        }
+        Decoded::Arngll(frame_info, payload_len, dissection) => {
+            let _ = writeln!(
+                out,
+                "ARNGLL: {:?} Payload: {} bytes",
+                frame_info, payload_len
+            );
+            out.push_str(&dissection.to_string());
+        }
+        Decoded::Unknown(dissection) => {
+            out.push_str("Unable to decode\n");
+            out.push_str(&dissection.to_string());
+        }
+    }
+    out
+}
+
+fn format_json(input: &InputFrame) -> String {
+    let (frame, fcs) = check_fcs(&input.bytes, input.fcs, input.crc);
+    let mut out = format!(
+        "{{\"source\":{},\"bytes\":\"{}\",\"fcs\":",
+        json_string(&input.source),
+        hex::encode_upper(&input.bytes)
+    );
+
+    let _ = match fcs {
+        FcsStatus::Ok(fcs) => write!(out, "{{\"status\":\"ok\",\"value\":{}}}", fcs),
+        FcsStatus::Bad {
+            received,
+            calculated,
+        } => write!(
+            out,
+            "{{\"status\":\"bad\",\"value\":{},\"calculated\":{}}}",
+            received, calculated
+        ),
+        FcsStatus::Absent => write!(out, "{{\"status\":\"absent\"}}"),
+    };
+
+    let _ = match decode(frame) {
+        Decoded::Ax25(ax25) => write!(
+            out,
+            ",\"protocol\":\"ax25\",\"summary\":{}",
+            json_string(&ax25.to_string())
+        ),
+        Decoded::Arngll(frame_info, _, dissection) => write!(
+            out,
+            ",\"protocol\":\"arngll\",\"summary\":{},\"dissection\":{}",
+            json_string(&format!("{:?}", frame_info)),
+            dissection.to_json()
+        ),
+        Decoded::Unknown(dissection) => write!(
+            out,
+            ",\"protocol\":null,\"dissection\":{}",
+            dissection.to_json()
+        ),
+    };
+    out.push('}');
+    out
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let opt = Opt::parse();
+
+    if let Some(Command::Encode(encode)) = opt.command {
+        println!("{}", hex::encode_upper(encode.encode()?));
+        return Ok(());
+    }
+
+    let mut frames = vec![];
+    if opt.inputs.is_empty() {
+        for line in std::io::stdin().lock().lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                frames.extend(read_input(&line, opt.fcs, opt.crc)?);
+            }
+        }
+    } else {
+        for input in opt.inputs.iter() {
+            let input_frames = read_input(input, opt.fcs, opt.crc)?;
+            if input_frames.is_empty() {
+                eprintln!("{}: No frames found", input);
+            }
+            frames.extend(input_frames);
+        }
+    }
+
+    for frame in frames.iter() {
+        if opt.json {
+            println!("{}", format_json(frame));
+        } else {
+            println!("{}", format_text(frame));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_VEC_1: &str = "054013375CAC70F85CB626E8062839414D2D54414B002918FA9C";
+
+    fn input(bytes: Vec<u8>, fcs: FcsMode) -> InputFrame {
+        InputFrame {
+            source: "hex".to_string(),
+            bytes,
+            fcs,
+            crc: Fcs::X25,
+        }
+    }
+
+    #[test]
+    fn check_fcs_modes() {
+        let frame = hex::decode(TEST_VEC_1).unwrap();
+        let with_fcs = Fcs::X25.append(frame.clone());
+        let fcs = Fcs::X25.calculate(&frame);
+
+        assert_eq!(
+            check_fcs(&with_fcs, FcsMode::Auto, Fcs::X25),
+            (&frame[..], FcsStatus::Ok(fcs))
+        );
+        assert_eq!(
+            check_fcs(&frame, FcsMode::Auto, Fcs::X25),
+            (&frame[..], FcsStatus::Absent)
+        );
+        assert_eq!(
+            check_fcs(&with_fcs, FcsMode::Absent, Fcs::X25),
+            (&with_fcs[..], FcsStatus::Absent)
+        );
+
+        let (_, status) = check_fcs(&frame, FcsMode::Present, Fcs::X25);
+        assert!(matches!(status, FcsStatus::Bad { .. }));
+    }
+
+    #[test]
+    fn format_arngll() {
+        let frame = hex::decode(TEST_VEC_1).unwrap();
+        let text = format_text(&input(frame.clone(), FcsMode::Auto));
+        assert!(text.starts_with(
+            "== hex (26 bytes)\nFCS: None\nARNGLL: {Beacon NetId=[1337] Dst=N6DRC Src=N6NFI} Payload: 14 bytes\nARNGLL frame (26 bytes)\n"
+        ));
+
+        let json = format_json(&input(frame, FcsMode::Auto));
+        assert!(json.contains("\"fcs\":{\"status\":\"absent\"},\"protocol\":\"arngll\""));
+        assert!(json.contains(",\"dissection\":{\"len\":26,"));
+
+        // Truncated frames still show the fields before the truncation.
+        let text = format_text(&input(hex::decode("05401337").unwrap(), FcsMode::Absent));
+        assert!(text.contains("Unable to decode\n"));
+        assert!(text.contains("[2..4] NETID: 0x1337"));
+        assert!(text.contains("Error: Frame truncated in DST"));
+    }
+
+    #[test]
+    fn format_ax25() {
+        let frame = hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap();
+        let text = format_text(&input(frame, FcsMode::Auto));
+        assert!(text.contains("FCS: OK (0x82F7)\n"));
+        assert!(
+            text.contains("AX.25: WA8LMF>APU25N,WIDE1-1 <UI C pid=F0 len=26>: >202337zhttp"),
+            "{}",
+            text
+        );
+        assert!(text.contains("APRS: "));
+    }
+
+    #[test]
+    fn format_crc32() {
+        let frame = Fcs::Crc32.append(vec![0x00, 0xAB]);
+        let mut input = input(frame, FcsMode::Present);
+        input.crc = Fcs::Crc32;
+        let text = format_text(&input);
+        assert!(text.contains("FCS: OK (0x00DD689F)\n"), "{}", text);
+
+        input.bytes[1] ^= 1;
+        let text = format_text(&input);
+        assert!(text.contains("FCS: BAD (received 0x00DD689F, calculated 0x"));
+    }
+}
@@ -0,0 +1,617 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Field-by-field dissection of encoded frames.
+//!
+//! Unlike [`FrameInfo::try_from_bytes`], the dissector reports where
+//! every field is and what it means, and on a malformed frame it keeps
+//! the fields it was able to decode along with the error.
+
+use super::*;
+use std::fmt::{Display, Formatter, Write as _};
+
+/// The raw value of a field.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FieldValue {
+    /// A bit field or integer.
+    Int(u64),
+
+    /// A field that isn't meaningful as an integer, like an address.
+    Bytes(Vec<u8>),
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Int(x) => write!(f, "{}", x),
+            FieldValue::Bytes(x) => write!(f, "{}", hex::encode_upper(x)),
+        }
+    }
+}
+
+/// A single field of a dissected frame.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+
+    /// Offset of the first byte of the field in the frame.
+    pub offset: usize,
+
+    /// Length of the field in bytes.
+    pub len: usize,
+
+    /// For bit fields, the most and least significant bits
+    /// in the byte at `offset`, with bit 7 being the MSB.
+    pub bits: Option<(u8, u8)>,
+    pub value: FieldValue,
+    pub meaning: String,
+    pub children: Vec<Field>,
+}
+
+impl Field {
+    fn bytes(name: &'static str, offset: usize, bytes: &[u8], meaning: String) -> Field {
+        Field {
+            name,
+            offset,
+            len: bytes.len(),
+            bits: None,
+            value: FieldValue::Bytes(bytes.to_vec()),
+            meaning,
+            children: vec![],
+        }
+    }
+
+    fn int(name: &'static str, offset: usize, bytes: &[u8], meaning: String) -> Field {
+        Field {
+            value: FieldValue::Int(bytes.iter().fold(0, |acc, &x| (acc << 8) | x as u64)),
+            ..Field::bytes(name, offset, bytes, meaning)
+        }
+    }
+
+    fn bit_field(
+        name: &'static str,
+        offset: usize,
+        byte: u8,
+        bits: (u8, u8),
+        meaning: String,
+    ) -> Field {
+        let (msb, lsb) = bits;
+        let mask = (1u16 << (msb - lsb + 1)) - 1;
+        Field {
+            name,
+            offset,
+            len: 1,
+            bits: Some(bits),
+            value: FieldValue::Int(((byte >> lsb) as u16 & mask) as u64),
+            meaning,
+            children: vec![],
+        }
+    }
+
+    fn bit_value(&self) -> u8 {
+        match self.value {
+            FieldValue::Int(x) => x as u8,
+            FieldValue::Bytes(_) => 0,
+        }
+    }
+
+    fn write_tree(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        write!(f, "{:indent$}", "", indent = depth * 2)?;
+        match self.bits {
+            Some((msb, lsb)) => {
+                // Render like `..10....`
+                let pattern = (0..8)
+                    .rev()
+                    .map(|bit| {
+                        if bit > msb || bit < lsb {
+                            '.'
+                        } else if (self.bit_value() >> (bit - lsb)) & 1 != 0 {
+                            '1'
+                        } else {
+                            '0'
+                        }
+                    })
+                    .collect::<String>();
+                write!(
+                    f,
+                    "[{}] {} {}: {}",
+                    self.offset, pattern, self.name, self.value
+                )?;
+            }
+            None => {
+                write!(
+                    f,
+                    "[{}..{}] {}: ",
+                    self.offset,
+                    self.offset + self.len,
+                    self.name
+                )?;
+                match &self.value {
+                    FieldValue::Int(x) => write!(f, "0x{:0width$X}", x, width = self.len * 2)?,
+                    FieldValue::Bytes(x) if x.len() > 16 => write!(f, "({} bytes)", x.len())?,
+                    value => write!(f, "{}", value)?,
+                }
+            }
+        }
+        if !self.meaning.is_empty() {
+            write!(f, " ({})", self.meaning)?;
+        }
+        writeln!(f)?;
+
+        for child in self.children.iter() {
+            child.write_tree(f, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "{{\"name\":{},\"offset\":{},\"len\":{}",
+            json_string(self.name),
+            self.offset,
+            self.len
+        );
+        if let Some((msb, lsb)) = self.bits {
+            let _ = write!(out, ",\"bits\":[{},{}]", msb, lsb);
+        }
+        match &self.value {
+            FieldValue::Int(x) => {
+                let _ = write!(out, ",\"value\":{}", x);
+            }
+            FieldValue::Bytes(x) => {
+                let _ = write!(out, ",\"value\":\"{}\"", hex::encode_upper(x));
+            }
+        }
+        let _ = write!(out, ",\"meaning\":{}", json_string(&self.meaning));
+        if !self.children.is_empty() {
+            out.push_str(",\"children\":[");
+            for (i, child) in self.children.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                child.write_json(out);
+            }
+            out.push(']');
+        }
+        out.push('}');
+    }
+}
+
+/// Quotes and escapes a string for use in JSON.
+pub fn json_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(ret, "\\u{:04x}", c as u32);
+            }
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// The fields of a frame, in the order they appear.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Dissection {
+    pub len: usize,
+    pub fields: Vec<Field>,
+
+    /// Why dissection stopped early, if it did.
+    pub error: Option<String>,
+}
+
+impl Dissection {
+    /// Renders the dissection as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut out = format!("{{\"len\":{},\"fields\":[", self.len);
+        for (i, field) in self.fields.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+            field.write_json(&mut out);
+        }
+        out.push(']');
+        if let Some(error) = self.error.as_ref() {
+            let _ = write!(out, ",\"error\":{}", json_string(error));
+        }
+        out.push('}');
+        out
+    }
+}
+
+/// Renders the dissection as an indented tree.
+impl Display for Dissection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "ARNGLL frame ({} bytes)", self.len)?;
+        for field in self.fields.iter() {
+            field.write_tree(f, 1)?;
+        }
+        if let Some(error) = self.error.as_ref() {
+            writeln!(f, "  Error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+struct Cursor<'a> {
+    frame: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize, what: &str) -> Result<(usize, &'a [u8]), Error> {
+        let offset = self.offset;
+        if self.frame.len() - offset < len {
+            bail!("Frame truncated in {}", what);
+        }
+        self.offset += len;
+        Ok((offset, &self.frame[offset..offset + len]))
+    }
+}
+
+fn flag(x: u8, set: &str, clear: &str) -> String {
+    if x != 0 { set } else { clear }.to_string()
+}
+
+fn address_meaning(bytes: &[u8]) -> String {
+    match HamAddr::try_from_slice(bytes) {
+        Ok(addr) if addr.len() != bytes.len() => format!("{}, not trimmed", addr),
+        Ok(addr) => format!("{} {:?}", addr, addr.get_type()),
+        Err(err) => err.to_string(),
+    }
+}
+
+/// Dissects an encoded frame, without FCS.
+pub fn dissect(frame: &[u8]) -> Dissection {
+    let mut ret = Dissection {
+        len: frame.len(),
+        fields: vec![],
+        error: None,
+    };
+    if let Err(err) = dissect_fields(frame, &mut ret.fields) {
+        ret.error = Some(err.to_string());
+    }
+    ret
+}
+
+fn dissect_fields(frame: &[u8], fields: &mut Vec<Field>) -> Result<(), Error> {
+    let mut cursor = Cursor { frame, offset: 0 };
+
+    let (_, msb) = cursor.take(1, "FCF")?;
+    let msb = msb[0];
+    let ver = msb >> 6;
+    let frame_type = FrameType::try_from_u8((msb >> 4) & 0b11).unwrap();
+    let dst_len = (((msb >> 2) & 0b11) as usize + 1) * 2;
+    let src_len = ((msb & 0b11) as usize + 1) * 2;
+    let is_ack = frame_type == FrameType::Ack;
+
+    let mut fcf = Field::int("FCF", 0, &[msb], String::new());
+    fcf.children.extend([
+        Field::bit_field(
+            "Version",
+            0,
+            msb,
+            (7, 6),
+            match ver {
+                VERSION_EXPERIMENTAL => "Experimental".to_string(),
+                VERSION_1 => "Version 1".to_string(),
+                _ => "Unknown".to_string(),
+            },
+        ),
+        Field::bit_field("Frame type", 0, msb, (5, 4), format!("{:?}", frame_type)),
+        Field::bit_field(
+            "DST length",
+            0,
+            msb,
+            (3, 2),
+            if is_ack {
+                "No DST in ack frames".to_string()
+            } else {
+                format!("{} bytes", dst_len)
+            },
+        ),
+        Field::bit_field("SRC length", 0, msb, (1, 0), format!("{} bytes", src_len)),
+    ]);
+
+    let mut lsb = 0;
+    if !is_ack {
+        let result = cursor.take(1, "FCF");
+        if let Ok((_, x)) = result {
+            lsb = x[0];
+            fcf = Field::int("FCF", 0, &[msb, lsb], String::new()).with_children(fcf.children);
+            fcf.children.extend([
+                Field::bit_field(
+                    "SECINFO present",
+                    1,
+                    lsb,
+                    (7, 7),
+                    flag(lsb & 0x80, "Yes", "No"),
+                ),
+                Field::bit_field(
+                    "NETID present",
+                    1,
+                    lsb,
+                    (6, 6),
+                    flag(lsb & 0x40, "Yes", "No"),
+                ),
+                Field::bit_field(
+                    "Ack requested",
+                    1,
+                    lsb,
+                    (5, 5),
+                    flag(lsb & 0x20, "Yes", "No"),
+                ),
+                Field::bit_field("RLY present", 1, lsb, (4, 4), flag(lsb & 0x10, "Yes", "No")),
+                Field::bit_field("From relay", 1, lsb, (3, 3), flag(lsb & 0x08, "Yes", "No")),
+                Field::bit_field("Reserved", 1, lsb, (2, 2), String::new()),
+                Field::bit_field(
+                    "RLY length",
+                    1,
+                    lsb,
+                    (1, 0),
+                    format!("{} bytes", ((lsb & 0b11) as usize + 1) * 2),
+                ),
+            ]);
+        }
+        fcf.meaning = format!("{:?}", frame_type);
+        fields.push(fcf);
+        result?;
+    } else {
+        fcf.meaning = format!("{:?}", frame_type);
+        fields.push(fcf);
+    }
+
+    if lsb & 0x40 != 0 {
+        let (offset, bytes) = cursor.take(2, "NETID")?;
+        let netid = NetworkId(u16::from_be_bytes([bytes[0], bytes[1]]));
+        fields.push(Field::int("NETID", offset, bytes, format!("{:?}", netid)));
+    }
+
+    if !is_ack {
+        let (offset, bytes) = cursor.take(dst_len, "DST")?;
+        fields.push(Field::bytes("DST", offset, bytes, address_meaning(bytes)));
+    }
+
+    let (offset, bytes) = cursor.take(src_len, "SRC")?;
+    fields.push(Field::bytes("SRC", offset, bytes, address_meaning(bytes)));
+
+    if lsb & 0x10 != 0 {
+        let rly_len = ((lsb & 0b11) as usize + 1) * 2;
+        let (offset, bytes) = cursor.take(rly_len, "RLY")?;
+        fields.push(Field::bytes("RLY", offset, bytes, address_meaning(bytes)));
+    }
+
+    if is_ack {
+        let (offset, bytes) = cursor.take(2, "ack CRC")?;
+        fields.push(Field::int("Ack CRC", offset, bytes, String::new()));
+    }
+
+    let mut mic_len = 0;
+    if lsb & 0x80 != 0 {
+        let (offset, bytes) = cursor.take(1, "SECINFO")?;
+        let scf = bytes[0];
+        let kim = KeyIdentMode::try_from_u8((scf >> 3) & 0b11).unwrap();
+        let mic = MicLen::try_from_u8((scf >> 5) & 0b11).unwrap();
+        mic_len = mic.len();
+
+        let mut secinfo = Field::int("SECINFO", offset, bytes, String::new());
+        let mut scf_field = Field::int("SCF", offset, bytes, String::new());
+        scf_field.children.extend([
+            Field::bit_field(
+                "Encrypted",
+                offset,
+                scf,
+                (7, 7),
+                flag(scf & 0x80, "Yes", "No"),
+            ),
+            Field::bit_field(
+                "MIC length",
+                offset,
+                scf,
+                (6, 5),
+                format!("{} bytes", mic_len),
+            ),
+            Field::bit_field("Key ident mode", offset, scf, (4, 3), format!("{:?}", kim)),
+            Field::bit_field("Reserved", offset, scf, (2, 0), String::new()),
+        ]);
+        secinfo.children.push(scf_field);
+
+        let result = cursor.take(4, "FCNTR").map(|(offset, bytes)| {
+            let fcntr = u32::from_be_bytes(bytes.try_into().unwrap());
+            secinfo
+                .children
+                .push(Field::int("FCNTR", offset, bytes, fcntr.to_string()));
+        });
+        let result = result.and_then(|_| {
+            if kim == KeyIdentMode::KeyIndex {
+                let (offset, bytes) = cursor.take(1, "KID")?;
+                secinfo
+                    .children
+                    .push(Field::int("KID", offset, bytes, String::new()));
+            }
+            Ok(())
+        });
+
+        secinfo.len = cursor.offset - secinfo.offset;
+        secinfo.value = FieldValue::Bytes(frame[secinfo.offset..cursor.offset].to_vec());
+        secinfo.meaning = if scf & 0x80 != 0 {
+            "Encrypted"
+        } else {
+            "Authenticated"
+        }
+        .to_string();
+        fields.push(secinfo);
+        result?;
+    }
+
+    let rest = frame.len() - cursor.offset;
+    if rest < mic_len {
+        bail!("Frame truncated in MIC");
+    }
+
+    let (offset, payload) = cursor.take(rest - mic_len, "payload")?;
+    if is_ack && !payload.is_empty() {
+        bail!("Ack frame has {} extra bytes", payload.len());
+    }
+    if !is_ack {
+        let meaning = format!("{} bytes", payload.len());
+        fields.push(Field::bytes("Payload", offset, payload, meaning));
+    }
+
+    if mic_len > 0 {
+        let (offset, mic) = cursor.take(mic_len, "MIC")?;
+        fields.push(Field::bytes("MIC", offset, mic, String::new()));
+    }
+
+    Ok(())
+}
+
+impl Field {
+    fn with_children(mut self, children: Vec<Field>) -> Field {
+        self.children = children;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dissect_test_vec_1() {
+        let bytes = hex::decode("054013375CAC70F85CB626E8062839414D2D54414B002918FA9C").unwrap();
+        let dissection = dissect(&bytes);
+
+        assert_eq!(dissection.error, None);
+        assert_eq!(
+            dissection
+                .fields
+                .iter()
+                .map(|x| (x.name, x.offset, x.len))
+                .collect::<Vec<_>>(),
+            vec![
+                ("FCF", 0, 2),
+                ("NETID", 2, 2),
+                ("DST", 4, 4),
+                ("SRC", 8, 4),
+                ("Payload", 12, 14)
+            ]
+        );
+
+        let tree = dissection.to_string();
+        assert!(tree.starts_with("ARNGLL frame (26 bytes)\n  [0..2] FCF: 0x0540 (Beacon)\n"));
+        assert!(tree.contains("\n    [0] ..00.... Frame type: 0 (Beacon)\n"));
+        assert!(tree.contains("\n    [1] .1...... NETID present: 1 (Yes)\n"));
+        assert!(tree.contains("\n  [2..4] NETID: 0x1337 ([1337])\n"));
+        assert!(tree.contains("\n  [4..8] DST: 5CAC70F8 (N6DRC Callsign)\n"));
+
+        let json = dissection.to_json();
+        assert!(json.starts_with("{\"len\":26,\"fields\":[{\"name\":\"FCF\",\"offset\":0,\"len\":2,\"value\":1344,\"meaning\":\"Beacon\",\"children\":[{\"name\":\"Version\",\"offset\":0,\"len\":1,\"bits\":[7,6],\"value\":0,\"meaning\":\"Experimental\"}"));
+        assert!(json.contains("{\"name\":\"SRC\",\"offset\":8,\"len\":4,\"value\":\"5CB626E8\",\"meaning\":\"N6NFI Callsign\"}"));
+        assert!(json.ends_with("]}"));
+    }
+
+    #[test]
+    fn dissect_secured() {
+        let frame = FrameInfo {
+            frame_type: FrameType::Data,
+            ack_requested: true,
+            is_from_relay: true,
+            dst_addr: "X1X".parse().unwrap(),
+            src_addr: "HUXLEY".parse().unwrap(),
+            rly_addr: Some("RAD-RELAY".parse().unwrap()),
+            sec_info: Some(SecInfo {
+                enc: true,
+                kim: KeyIdentMode::KeyIndex,
+                fcntr: 0x31337,
+                kid: Some(6),
+                mic: Mic::try_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap(),
+            }),
+            ..FrameInfo::EMPTY
+        };
+        let bytes = frame.to_vec(b"hello");
+        let dissection = dissect(&bytes);
+        assert_eq!(dissection.error, None);
+
+        let names = dissection.fields.iter().map(|x| x.name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["FCF", "DST", "SRC", "RLY", "SECINFO", "Payload", "MIC"]
+        );
+
+        let secinfo = &dissection.fields[4];
+        assert_eq!(secinfo.len, 6);
+        assert_eq!(
+            secinfo.children.iter().map(|x| x.name).collect::<Vec<_>>(),
+            ["SCF", "FCNTR", "KID"]
+        );
+        assert_eq!(secinfo.children[1].value, FieldValue::Int(0x31337));
+        assert_eq!(
+            dissection.fields[5].value,
+            FieldValue::Bytes(b"hello".to_vec())
+        );
+        assert_eq!(
+            dissection.fields[6].value,
+            FieldValue::Bytes(vec![1, 2, 3, 4, 5, 6, 7, 8])
+        );
+    }
+
+    #[test]
+    fn dissect_ack_and_truncated() {
+        let frame = FrameInfo {
+            frame_type: FrameType::Ack,
+            src_addr: "HUXLEY".parse().unwrap(),
+            ack_crc: 0xbeef,
+            ..FrameInfo::EMPTY
+        };
+        let bytes = frame.to_vec(&[]);
+        let dissection = dissect(&bytes);
+        assert_eq!(dissection.error, None);
+        assert_eq!(
+            dissection.fields.iter().map(|x| x.name).collect::<Vec<_>>(),
+            ["FCF", "SRC", "Ack CRC"]
+        );
+        assert_eq!(dissection.fields[2].value, FieldValue::Int(0xbeef));
+
+        // Fields before the truncation are still reported.
+        let dissection = dissect(&bytes[..5]);
+        assert_eq!(dissection.fields.len(), 2);
+        assert_eq!(
+            dissection.error.as_deref(),
+            Some("Frame truncated in ack CRC")
+        );
+        assert!(dissection
+            .to_json()
+            .ends_with(",\"error\":\"Frame truncated in ack CRC\"}"));
+
+        assert_eq!(
+            dissect(&[]).error.as_deref(),
+            Some("Frame truncated in FCF")
+        );
+    }
+}
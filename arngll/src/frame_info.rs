@@ -363,15 +363,8 @@ impl FrameInfo {
             } else {
                 self.dst_addr
             };
-            Some((self
-                    .bytes_with_payload(payload)
-                    .fold(X25.digest(), |mut digest, x| {
-                        digest.update(&[x]);
-                        digest
-                    })
-                    .finalize(),
-                  ack_sender,
-            ))
+            let bytes = self.bytes_with_payload(payload).collect::<Vec<_>>();
+            Some((Fcs::X25.calculate(&bytes) as u16, ack_sender))
         } else {
             None
         }
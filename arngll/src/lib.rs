@@ -21,6 +21,9 @@
 
 mod security;
 mod frame_info;
+mod dissect;
+pub mod pcapng;
+pub mod replay;
 
 use hamaddr::HamAddr;
 use std::iter::once;
@@ -28,9 +31,9 @@ use anyhow::{bail, Error, format_err};
 
 pub use security::*;
 pub use frame_info::*;
+pub use dissect::*;
+pub use quick_dsp::fcs::*;
 
 pub const VERSION_EXPERIMENTAL: u8 = 0;
 pub const VERSION_1: u8 = 1;
 
-pub const X25: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_SDLC);
-
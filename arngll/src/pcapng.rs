@@ -0,0 +1,273 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Writer for [pcapng] capture files, for analyzing traffic with Wireshark.
+//!
+//! [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-00.html
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Link-layer type for AX.25 frames, without FCS.
+pub const LINKTYPE_AX25: u16 = 3;
+
+/// Link-layer type for ARNGLL frames, without FCS. ARNGLL has no
+/// assigned type, so this is `LINKTYPE_USER0`.
+pub const LINKTYPE_ARNGLL: u16 = 147;
+
+const BLOCK_SHB: u32 = 0x0A0D_0D0A;
+const BLOCK_IDB: u32 = 0x0000_0001;
+const BLOCK_EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+/// Direction of a captured packet, relative to us.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    fn epb_flags(&self) -> u32 {
+        match self {
+            Direction::Inbound => 0b01,
+            Direction::Outbound => 0b10,
+        }
+    }
+}
+
+/// A packet to write to a capture file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CapturedPacket<'a> {
+    /// Interface returned by [`PcapngWriter::add_interface`].
+    pub interface: u32,
+    pub timestamp: SystemTime,
+    pub direction: Option<Direction>,
+    pub data: &'a [u8],
+    pub comment: Option<&'a str>,
+}
+
+/// Appends a pcapng option, padded to a multiple of four bytes.
+fn push_option(block: &mut Vec<u8>, code: u16, value: &[u8]) {
+    block.extend(code.to_le_bytes());
+    block.extend((value.len() as u16).to_le_bytes());
+    block.extend_from_slice(value);
+    pad(block);
+}
+
+fn pad(block: &mut Vec<u8>) {
+    block.resize(block.len().next_multiple_of(4), 0);
+}
+
+/// Writes pcapng capture files.
+///
+/// Timestamps have microsecond resolution. The file is
+/// only flushed when [`PcapngWriter::flush`] is called.
+#[derive(Debug)]
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+    interfaces: u32,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and returns a writer with no interfaces.
+    pub fn new(writer: W) -> io::Result<PcapngWriter<W>> {
+        let mut ret = PcapngWriter {
+            writer,
+            interfaces: 0,
+        };
+
+        let mut body = Vec::new();
+        body.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend(1u16.to_le_bytes());
+        body.extend(0u16.to_le_bytes());
+
+        // Section length is not specified.
+        body.extend((-1i64).to_le_bytes());
+
+        let appl = concat!("arngll-rust ", env!("CARGO_PKG_VERSION"));
+        push_option(&mut body, SHB_USERAPPL, appl.as_bytes());
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+
+        ret.write_block(BLOCK_SHB, &body)?;
+        Ok(ret)
+    }
+
+    /// Adds an interface with the given link-layer type, like
+    /// [`LINKTYPE_ARNGLL`], returning its index for [`CapturedPacket`].
+    pub fn add_interface(&mut self, link_type: u16, name: &str) -> io::Result<u32> {
+        let mut body = Vec::new();
+        body.extend(link_type.to_le_bytes());
+        body.extend(0u16.to_le_bytes());
+
+        // No snap length limit.
+        body.extend(0u32.to_le_bytes());
+
+        push_option(&mut body, IF_NAME, name.as_bytes());
+
+        // Microseconds.
+        push_option(&mut body, IF_TSRESOL, &[6]);
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+
+        self.write_block(BLOCK_IDB, &body)?;
+        self.interfaces += 1;
+        Ok(self.interfaces - 1)
+    }
+
+    /// Writes a packet.
+    pub fn write_packet(&mut self, packet: &CapturedPacket<'_>) -> io::Result<()> {
+        if packet.interface >= self.interfaces {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No interface {}", packet.interface),
+            ));
+        }
+
+        let micros = packet
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+            .as_micros() as u64;
+
+        let mut body = Vec::with_capacity(packet.data.len() + 64);
+        body.extend(packet.interface.to_le_bytes());
+        body.extend(((micros >> 32) as u32).to_le_bytes());
+        body.extend((micros as u32).to_le_bytes());
+        body.extend((packet.data.len() as u32).to_le_bytes());
+        body.extend((packet.data.len() as u32).to_le_bytes());
+        body.extend_from_slice(packet.data);
+        pad(&mut body);
+
+        if let Some(direction) = packet.direction {
+            push_option(&mut body, EPB_FLAGS, &direction.epb_flags().to_le_bytes());
+        }
+
+        // Options are limited to 64KiB.
+        if let Some(comment) = packet.comment {
+            let mut len = comment.len().min(u16::MAX as usize);
+            while !comment.is_char_boundary(len) {
+                len -= 1;
+            }
+            push_option(&mut body, OPT_COMMENT, &comment.as_bytes()[..len]);
+        }
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+
+        self.write_block(BLOCK_EPB, &body)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let len = (body.len() + 12) as u32;
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&len.to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Splits a capture into (type, body) pairs, checking block lengths.
+    fn blocks(mut bytes: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut ret = vec![];
+        while !bytes.is_empty() {
+            let len = u32_at(bytes, 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(bytes, len - 4) as usize, len);
+            ret.push((u32_at(bytes, 0), bytes[8..len - 4].to_vec()));
+            bytes = &bytes[len..];
+        }
+        ret
+    }
+
+    #[test]
+    fn pcapng_writer() {
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        assert_eq!(writer.add_interface(LINKTYPE_ARNGLL, "arngll").unwrap(), 0);
+        assert_eq!(writer.add_interface(LINKTYPE_AX25, "ax25").unwrap(), 1);
+
+        let timestamp = UNIX_EPOCH + Duration::from_micros(0x1_2345_6789);
+        writer
+            .write_packet(&CapturedPacket {
+                interface: 1,
+                timestamp,
+                direction: Some(Direction::Outbound),
+                data: b"abcde",
+                comment: Some("hi"),
+            })
+            .unwrap();
+
+        assert!(writer
+            .write_packet(&CapturedPacket {
+                interface: 2,
+                timestamp,
+                direction: None,
+                data: &[],
+                comment: None,
+            })
+            .is_err());
+
+        let blocks = blocks(&writer.into_inner());
+        assert_eq!(
+            blocks.iter().map(|x| x.0).collect::<Vec<_>>(),
+            vec![BLOCK_SHB, BLOCK_IDB, BLOCK_IDB, BLOCK_EPB]
+        );
+
+        let shb = &blocks[0].1;
+        assert_eq!(u32_at(shb, 0), BYTE_ORDER_MAGIC);
+
+        let idb = &blocks[2].1;
+        assert_eq!(&idb[..2], &LINKTYPE_AX25.to_le_bytes());
+        assert_eq!(&idb[8..16], &[2, 0, 4, 0, b'a', b'x', b'2', b'5']);
+
+        let epb = &blocks[3].1;
+        assert_eq!(u32_at(epb, 0), 1);
+        assert_eq!(u32_at(epb, 4), 1);
+        assert_eq!(u32_at(epb, 8), 0x2345_6789);
+        assert_eq!(u32_at(epb, 12), 5);
+        assert_eq!(u32_at(epb, 16), 5);
+        assert_eq!(&epb[20..28], b"abcde\0\0\0");
+        assert_eq!(&epb[28..36], &[2, 0, 4, 0, 2, 0, 0, 0]);
+        assert_eq!(&epb[36..44], &[1, 0, 2, 0, b'h', b'i', 0, 0]);
+        assert_eq!(&epb[44..], &[0, 0, 0, 0]);
+    }
+}
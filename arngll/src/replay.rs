@@ -0,0 +1,586 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Replay of pcap and pcapng capture files as a PHY source.
+
+use crate::pcapng::Direction;
+use crate::Fcs;
+use anyhow::{bail, format_err};
+use futures::prelude::*;
+use futures_timer::Delay;
+use log::warn;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_SPB: u32 = 0x0000_0003;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+
+/// Largest block or record we will read.
+const MAX_RECORD_LEN: usize = 1 << 20;
+
+/// A packet read from a capture file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PcapRecord {
+    pub link_type: u16,
+
+    /// Time since the Unix epoch.
+    pub timestamp: Duration,
+    pub direction: Option<Direction>,
+    pub data: Vec<u8>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Interface {
+    link_type: u16,
+
+    /// Timestamp units per second.
+    units_per_sec: u64,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap { link_type: u16, nanos: bool },
+    Pcapng { interfaces: Vec<Interface> },
+}
+
+/// Reads packets from classic pcap and pcapng files, in either byte order.
+#[derive(Debug)]
+pub struct PcapReader<R: Read> {
+    reader: R,
+    format: Format,
+    big_endian: bool,
+
+    /// Timestamp of the last packet, for pcapng simple packets.
+    last_timestamp: Duration,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        PcapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the file header, detecting the format.
+    pub fn new(mut reader: R) -> anyhow::Result<PcapReader<R>> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        let mut ret = PcapReader {
+            reader,
+            format: Format::Pcapng { interfaces: vec![] },
+            big_endian: false,
+            last_timestamp: Duration::ZERO,
+        };
+
+        match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAPNG_SHB, _) => ret.read_section_header()?,
+            (x, _) if x == PCAP_MAGIC_MICROS || x == PCAP_MAGIC_NANOS => {
+                ret.read_pcap_header(x == PCAP_MAGIC_NANOS)?
+            }
+            (_, x) if x == PCAP_MAGIC_MICROS || x == PCAP_MAGIC_NANOS => {
+                ret.big_endian = true;
+                ret.read_pcap_header(x == PCAP_MAGIC_NANOS)?
+            }
+            _ => bail!("Not a pcap or pcapng file"),
+        }
+
+        Ok(ret)
+    }
+
+    fn u16_at(&self, bytes: &[u8], offset: usize) -> u16 {
+        let x = [bytes[offset], bytes[offset + 1]];
+        if self.big_endian {
+            u16::from_be_bytes(x)
+        } else {
+            u16::from_le_bytes(x)
+        }
+    }
+
+    fn u32_at(&self, bytes: &[u8], offset: usize) -> u32 {
+        let x = bytes[offset..offset + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(x)
+        } else {
+            u32::from_le_bytes(x)
+        }
+    }
+
+    fn read_vec(&mut self, len: usize) -> anyhow::Result<Vec<u8>> {
+        if len > MAX_RECORD_LEN {
+            bail!("Record length {} too large", len);
+        }
+        let mut ret = vec![0u8; len];
+        self.reader.read_exact(&mut ret)?;
+        Ok(ret)
+    }
+
+    /// Reads the rest of a classic pcap file header.
+    fn read_pcap_header(&mut self, nanos: bool) -> anyhow::Result<()> {
+        let header = self.read_vec(20)?;
+        self.format = Format::Pcap {
+            link_type: self.u32_at(&header, 16) as u16,
+            nanos,
+        };
+        Ok(())
+    }
+
+    /// Reads the rest of a pcapng section header block, after its type.
+    fn read_section_header(&mut self) -> anyhow::Result<()> {
+        let mut header = [0u8; 8];
+        self.reader.read_exact(&mut header)?;
+
+        self.big_endian = match u32::from_le_bytes(header[4..].try_into().unwrap()) {
+            PCAPNG_BYTE_ORDER_MAGIC => false,
+            x if x.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+            _ => bail!("Bad pcapng byte order magic"),
+        };
+
+        let len = self.u32_at(&header, 0) as usize;
+        if len < 28 || !len.is_multiple_of(4) {
+            bail!("Bad section header length {}", len);
+        }
+        self.read_vec(len - 12)?;
+        self.format = Format::Pcapng { interfaces: vec![] };
+        Ok(())
+    }
+
+    /// Returns the (code, value) pairs of pcapng options.
+    fn options<'a>(&self, mut bytes: &'a [u8]) -> Vec<(u16, &'a [u8])> {
+        let mut ret = vec![];
+        while bytes.len() >= 4 {
+            let code = self.u16_at(bytes, 0);
+            let len = self.u16_at(bytes, 2) as usize;
+            if code == 0 || bytes.len() < 4 + len {
+                break;
+            }
+            ret.push((code, &bytes[4..4 + len]));
+            bytes = bytes.get(((4 + len + 3) & !3)..).unwrap_or(&[]);
+        }
+        ret
+    }
+
+    /// Reads the next packet, returning `Ok(None)` at the end of the file.
+    pub fn read_packet(&mut self) -> anyhow::Result<Option<PcapRecord>> {
+        match self.format {
+            Format::Pcap { link_type, nanos } => self.read_pcap_packet(link_type, nanos),
+            Format::Pcapng { .. } => self.read_pcapng_packet(),
+        }
+    }
+
+    fn read_pcap_packet(
+        &mut self,
+        link_type: u16,
+        nanos: bool,
+    ) -> anyhow::Result<Option<PcapRecord>> {
+        let mut header = [0u8; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        let secs = self.u32_at(&header, 0) as u64;
+        let frac = self.u32_at(&header, 4);
+        let len = self.u32_at(&header, 8) as usize;
+
+        let timestamp = if nanos {
+            Duration::new(secs, frac)
+        } else {
+            Duration::new(secs, 0) + Duration::from_micros(frac as u64)
+        };
+
+        Ok(Some(PcapRecord {
+            link_type,
+            timestamp,
+            direction: None,
+            data: self.read_vec(len)?,
+            comment: None,
+        }))
+    }
+
+    fn interface(&self, index: usize) -> anyhow::Result<Interface> {
+        match &self.format {
+            Format::Pcapng { interfaces } => interfaces.get(index).copied(),
+            Format::Pcap { .. } => None,
+        }
+        .ok_or_else(|| format_err!("Packet for unknown interface {}", index))
+    }
+
+    fn read_pcapng_packet(&mut self) -> anyhow::Result<Option<PcapRecord>> {
+        loop {
+            let mut block_type = [0u8; 4];
+            match self.reader.read_exact(&mut block_type) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err.into()),
+            }
+
+            // A new section, possibly with a different byte order.
+            if u32::from_le_bytes(block_type) == PCAPNG_SHB {
+                self.read_section_header()?;
+                continue;
+            }
+
+            let block_type = self.u32_at(&block_type, 0);
+            let mut len = [0u8; 4];
+            self.reader.read_exact(&mut len)?;
+            let len = self.u32_at(&len, 0) as usize;
+            if len < 12 || !len.is_multiple_of(4) {
+                bail!("Bad block length {}", len);
+            }
+            let body = self.read_vec(len - 8)?;
+            let body = &body[..body.len() - 4];
+
+            match block_type {
+                PCAPNG_IDB if body.len() >= 8 => {
+                    let link_type = self.u16_at(body, 0);
+                    let mut units_per_sec = 1_000_000;
+                    for (code, value) in self.options(&body[8..]) {
+                        // if_tsresol
+                        if code == 9 && value.len() == 1 {
+                            let exp = (value[0] & 0x7F) as u32;
+                            units_per_sec = if value[0] & 0x80 == 0 {
+                                10u64.checked_pow(exp)
+                            } else {
+                                2u64.checked_pow(exp)
+                            }
+                            .ok_or_else(|| format_err!("Bad timestamp resolution"))?;
+                        }
+                    }
+                    if let Format::Pcapng { interfaces } = &mut self.format {
+                        interfaces.push(Interface {
+                            link_type,
+                            units_per_sec,
+                        });
+                    }
+                }
+                PCAPNG_EPB if body.len() >= 20 => {
+                    let interface = self.interface(self.u32_at(body, 0) as usize)?;
+                    let ts = (self.u32_at(body, 4) as u64) << 32 | self.u32_at(body, 8) as u64;
+                    let caplen = self.u32_at(body, 12) as usize;
+                    let padded = (caplen + 3) & !3;
+                    if body.len() < 20 + padded {
+                        bail!("Truncated packet block");
+                    }
+
+                    let mut direction = None;
+                    let mut comment = None;
+                    for (code, value) in self.options(&body[20 + padded..]) {
+                        match code {
+                            1 => comment = Some(String::from_utf8_lossy(value).into_owned()),
+                            2 if value.len() == 4 => {
+                                direction = match self.u32_at(value, 0) & 0b11 {
+                                    0b01 => Some(Direction::Inbound),
+                                    0b10 => Some(Direction::Outbound),
+                                    _ => None,
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    let units = interface.units_per_sec;
+                    let timestamp = Duration::from_secs(ts / units)
+                        + Duration::from_nanos(
+                            ((ts % units) as u128 * 1_000_000_000 / units as u128) as u64,
+                        );
+                    self.last_timestamp = timestamp;
+
+                    return Ok(Some(PcapRecord {
+                        link_type: interface.link_type,
+                        timestamp,
+                        direction,
+                        data: body[20..20 + caplen].to_vec(),
+                        comment,
+                    }));
+                }
+                PCAPNG_SPB if body.len() >= 4 => {
+                    let interface = self.interface(0)?;
+                    let len = (self.u32_at(body, 0) as usize).min(body.len() - 4);
+
+                    return Ok(Some(PcapRecord {
+                        link_type: interface.link_type,
+                        timestamp: self.last_timestamp,
+                        direction: None,
+                        data: body[4..4 + len].to_vec(),
+                        comment: None,
+                    }));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = anyhow::Result<PcapRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_packet().transpose()
+    }
+}
+
+/// How quickly [`PcapReplay`] yields frames.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReplaySpeed {
+    /// With the time between frames that they were captured with.
+    Original,
+
+    /// With the time between frames divided by the given factor.
+    Speedup(f64),
+
+    /// Without any delay between frames.
+    AsFastAsPossible,
+}
+
+/// A PHY source that replays the frames in a capture file.
+///
+/// Like `Bell202Receiver`, frames are yielded with an FCS
+/// appended. Transmitted frames are skipped by default.
+pub struct PcapReplay<R: Read> {
+    reader: PcapReader<R>,
+    speed: ReplaySpeed,
+    link_types: Option<Vec<u16>>,
+    include_outbound: bool,
+
+    /// When we started, and the timestamp of the first frame.
+    start: Option<(Instant, Duration)>,
+    pending: Option<(Delay, Vec<u8>)>,
+}
+
+impl PcapReplay<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P, speed: ReplaySpeed) -> anyhow::Result<Self> {
+        Ok(PcapReplay::new(PcapReader::open(path)?, speed))
+    }
+}
+
+impl<R: Read> PcapReplay<R> {
+    pub fn new(reader: PcapReader<R>, speed: ReplaySpeed) -> PcapReplay<R> {
+        PcapReplay {
+            reader,
+            speed,
+            link_types: None,
+            include_outbound: false,
+            start: None,
+            pending: None,
+        }
+    }
+
+    /// Only replays frames with the given link-layer types,
+    /// like [`crate::pcapng::LINKTYPE_ARNGLL`].
+    pub fn set_link_types(&mut self, link_types: &[u16]) {
+        self.link_types = Some(link_types.to_vec());
+    }
+
+    /// Also replays frames that were transmitted.
+    pub fn set_include_outbound(&mut self, include_outbound: bool) {
+        self.include_outbound = include_outbound;
+    }
+
+    /// Returns when the given frame should be yielded, if it should wait.
+    fn due(&mut self, timestamp: Duration) -> Option<Instant> {
+        let factor = match self.speed {
+            ReplaySpeed::Original => 1.0,
+            ReplaySpeed::Speedup(x) if x > 0.0 => x,
+            _ => return None,
+        };
+        let (start, first) = *self.start.get_or_insert((Instant::now(), timestamp));
+        Some(start + timestamp.saturating_sub(first).div_f64(factor))
+    }
+}
+
+impl<R: Read + Unpin> Stream for PcapReplay<R> {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some((delay, _)) = self.pending.as_mut() {
+                futures::ready!(delay.poll_unpin(cx));
+                return Poll::Ready(self.pending.take().map(|(_, frame)| frame));
+            }
+
+            let record = match self.reader.read_packet() {
+                Ok(Some(record)) => record,
+                Ok(None) => return Poll::Ready(None),
+                Err(err) => {
+                    warn!("Replay: {:?}", err);
+                    return Poll::Ready(None);
+                }
+            };
+
+            if record.direction == Some(Direction::Outbound) && !self.include_outbound {
+                continue;
+            }
+            if let Some(link_types) = self.link_types.as_ref() {
+                if !link_types.contains(&record.link_type) {
+                    continue;
+                }
+            }
+
+            let frame = Fcs::X25.append(record.data);
+
+            match self.due(record.timestamp) {
+                Some(due) if due > Instant::now() => {
+                    let delay = Delay::new(due.saturating_duration_since(Instant::now()));
+                    self.pending = Some((delay, frame));
+                }
+                _ => return Poll::Ready(Some(frame)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcapng::*;
+    use futures::executor::block_on;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn capture(packets: &[(u64, Direction, &[u8])]) -> Vec<u8> {
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        writer.add_interface(LINKTYPE_ARNGLL, "arngll").unwrap();
+        writer.add_interface(LINKTYPE_AX25, "ax25").unwrap();
+        for (i, &(millis, direction, data)) in packets.iter().enumerate() {
+            writer
+                .write_packet(&CapturedPacket {
+                    interface: (i % 2) as u32,
+                    timestamp: UNIX_EPOCH + Duration::from_millis(millis),
+                    direction: Some(direction),
+                    data,
+                    comment: Some("comment"),
+                })
+                .unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn pcap_reader_pcapng() {
+        let bytes = capture(&[
+            (1000, Direction::Inbound, b"one"),
+            (2500, Direction::Outbound, b"two"),
+        ]);
+        let records = PcapReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(
+            records,
+            vec![
+                PcapRecord {
+                    link_type: LINKTYPE_ARNGLL,
+                    timestamp: Duration::from_millis(1000),
+                    direction: Some(Direction::Inbound),
+                    data: b"one".to_vec(),
+                    comment: Some("comment".to_string()),
+                },
+                PcapRecord {
+                    link_type: LINKTYPE_AX25,
+                    timestamp: Duration::from_millis(2500),
+                    direction: Some(Direction::Outbound),
+                    data: b"two".to_vec(),
+                    comment: Some("comment".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn pcap_reader_pcap() {
+        // Big-endian, nanosecond resolution.
+        let mut bytes = vec![];
+        bytes.extend(PCAP_MAGIC_NANOS.to_be_bytes());
+        bytes.extend([0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF]);
+        bytes.extend((LINKTYPE_ARNGLL as u32).to_be_bytes());
+        bytes.extend(5u32.to_be_bytes());
+        bytes.extend(7u32.to_be_bytes());
+        bytes.extend(2u32.to_be_bytes());
+        bytes.extend(2u32.to_be_bytes());
+        bytes.extend(b"hi");
+
+        let mut reader = PcapReader::new(bytes.as_slice()).unwrap();
+        let record = reader.read_packet().unwrap().unwrap();
+        assert_eq!(record.link_type, LINKTYPE_ARNGLL);
+        assert_eq!(record.timestamp, Duration::new(5, 7));
+        assert_eq!(record.data, b"hi");
+        assert!(reader.read_packet().unwrap().is_none());
+
+        assert!(PcapReader::new(&b"nope"[..]).is_err());
+    }
+
+    #[test]
+    fn pcap_reader_unpadded_option() {
+        let bytes = capture(&[]);
+        let reader = PcapReader::new(bytes.as_slice()).unwrap();
+        let mut option = vec![];
+        option.extend(1u16.to_ne_bytes());
+        option.extend(3u16.to_ne_bytes());
+        option.extend(b"abc");
+        assert_eq!(reader.options(&option), vec![(1, &b"abc"[..])]);
+    }
+
+    #[test]
+    fn pcap_replay() {
+        let bytes = capture(&[
+            (1000, Direction::Inbound, b"one"),
+            (2000, Direction::Outbound, b"two"),
+            (3000, Direction::Inbound, b"three"),
+        ]);
+
+        let replay = PcapReplay::new(
+            PcapReader::new(bytes.as_slice()).unwrap(),
+            ReplaySpeed::AsFastAsPossible,
+        );
+        let frames = block_on(replay.collect::<Vec<_>>());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(&frames[1][..5], b"three");
+        assert!(frames.iter().all(|x| Fcs::X25.verify(x).is_ok()));
+
+        let mut replay = PcapReplay::new(
+            PcapReader::new(bytes.as_slice()).unwrap(),
+            ReplaySpeed::Speedup(20.0),
+        );
+        replay.set_include_outbound(true);
+        replay.set_link_types(&[LINKTYPE_ARNGLL]);
+        let start = SystemTime::now();
+        let frames = block_on(replay.collect::<Vec<_>>());
+        let elapsed = start.elapsed().unwrap();
+
+        // The first and third frames, two seconds apart, sped up 20 times.
+        assert_eq!(frames.len(), 2);
+        assert_eq!(&frames[0][..3], b"one");
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    }
+}
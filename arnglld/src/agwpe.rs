@@ -0,0 +1,148 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::io::{self, Read};
+
+pub const AGWPE_HEADER_LEN: usize = 36;
+pub const AGWPE_CALL_LEN: usize = 10;
+
+/// Largest data field we will accept from a client.
+pub const AGWPE_MAX_DATA_LEN: usize = 65536;
+
+/// A single AGWPE API frame: a fixed 36-byte header followed by data.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct AgwpeFrame {
+    pub port: u8,
+    pub kind: u8,
+    pub pid: u8,
+    pub call_from: String,
+    pub call_to: String,
+    pub data: Vec<u8>,
+}
+
+fn decode_call(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&x| x != 0)
+        .map(|&x| x as char)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+fn encode_call(call: &str) -> [u8; AGWPE_CALL_LEN] {
+    let mut ret = [0u8; AGWPE_CALL_LEN];
+    let len = call.len().min(AGWPE_CALL_LEN - 1);
+    ret[..len].copy_from_slice(&call.as_bytes()[..len]);
+    ret
+}
+
+impl AgwpeFrame {
+    /// Creates an empty frame of the given kind.
+    pub fn new(kind: u8) -> AgwpeFrame {
+        AgwpeFrame {
+            kind,
+            ..Default::default()
+        }
+    }
+
+    /// Reads a single frame. Returns `Ok(None)` on a clean EOF.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<AgwpeFrame>> {
+        let mut header = [0u8; AGWPE_HEADER_LEN];
+
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        let data_len =
+            u32::from_le_bytes([header[28], header[29], header[30], header[31]]) as usize;
+        if data_len > AGWPE_MAX_DATA_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("AGWPE data length {} too large", data_len),
+            ));
+        }
+
+        let mut data = vec![0u8; data_len];
+        reader.read_exact(&mut data)?;
+
+        Ok(Some(AgwpeFrame {
+            port: header[0],
+            kind: header[4],
+            pid: header[6],
+            call_from: decode_call(&header[8..18]),
+            call_to: decode_call(&header[18..28]),
+            data,
+        }))
+    }
+
+    /// Returns the encoded header and data.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut ret = vec![0u8; AGWPE_HEADER_LEN];
+        ret[0] = self.port;
+        ret[4] = self.kind;
+        ret[6] = self.pid;
+        ret[8..18].copy_from_slice(&encode_call(&self.call_from));
+        ret[18..28].copy_from_slice(&encode_call(&self.call_to));
+        ret[28..32].copy_from_slice(&(self.data.len() as u32).to_le_bytes());
+        ret.extend_from_slice(&self.data);
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agwpe_frame_round_trip() {
+        let frame = AgwpeFrame {
+            port: 0,
+            kind: b'M',
+            pid: 0xF0,
+            call_from: "KZ2X-1".to_string(),
+            call_to: "APRS".to_string(),
+            data: b"Hello".to_vec(),
+        };
+
+        let bytes = frame.encode();
+        assert_eq!(bytes.len(), AGWPE_HEADER_LEN + 5);
+        assert_eq!(&bytes[8..15], b"KZ2X-1\0");
+        assert_eq!(&bytes[28..32], &[5, 0, 0, 0]);
+
+        let decoded = AgwpeFrame::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(decoded, Some(frame));
+    }
+
+    #[test]
+    fn agwpe_frame_eof() {
+        assert_eq!(
+            AgwpeFrame::read_from(&mut [0u8; 0].as_slice()).unwrap(),
+            None
+        );
+
+        let mut bytes = AgwpeFrame::new(b'R').encode();
+        bytes[28] = 10;
+        assert!(AgwpeFrame::read_from(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,542 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use crate::agwpe::*;
+use ax25::{Ax25Address, Ax25Frame, Control, UnnumberedKind};
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::SinkExt;
+use log::{debug, info, warn};
+use quick_dsp::modem::ChannelAccessHandle;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const AGWPE_VERSION_MAJOR: u32 = 2005;
+const AGWPE_VERSION_MINOR: u32 = 127;
+
+/// Number of outstanding frames reported to clients, which
+/// don't get connected mode.
+const AGWPE_MAXFRAME: u8 = 7;
+
+/// The radio port, as described to clients.
+#[derive(Clone, Debug)]
+pub struct AgwpePort {
+    /// Shown in the port list, e.g. `Bell 202 1200 bit/s`.
+    pub description: String,
+
+    /// On-air bit rate.
+    pub bit_rate: u32,
+
+    /// The sender's channel access parameters.
+    pub channel_access: ChannelAccessHandle,
+}
+
+impl AgwpePort {
+    /// The reply to a port information ('G') request.
+    fn info(&self) -> Vec<u8> {
+        format!("1;Port1 ARNGLL {};\0", self.description).into_bytes()
+    }
+
+    /// The reply to a port capabilities ('g') request: baud rate code,
+    /// traffic level, TXDELAY, TXTAIL, persist, slottime, maxframe,
+    /// active connections, then bytes received in the last two minutes.
+    fn capabilities(&self) -> Vec<u8> {
+        let baud = match self.bit_rate {
+            0..=2399 => 0,
+            2400..=4799 => 1,
+            4800..=9599 => 2,
+            _ => 3,
+        };
+        let access = self.channel_access.get();
+        let tens_of_ms = |x: Duration| (x.as_millis() / 10).min(255) as u8;
+
+        let mut ret = vec![
+            baud,
+            0xFF,
+            tens_of_ms(access.tx_delay),
+            tens_of_ms(access.tx_tail),
+            access.persistence,
+            tens_of_ms(access.slot_time),
+            AGWPE_MAXFRAME,
+            0,
+        ];
+        ret.extend(0u32.to_le_bytes());
+        ret
+    }
+}
+
+/// Builds an AX.25 UI frame (without FCS) from an unproto request.
+fn ui_frame(frame: &AgwpeFrame, via: &[String], info: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let via = via
+        .iter()
+        .map(|x| x.parse())
+        .collect::<anyhow::Result<Vec<Ax25Address>>>()?;
+
+    Ax25Frame::ui(
+        frame.call_to.parse()?,
+        frame.call_from.parse()?,
+        &via,
+        frame.pid,
+        info,
+    )
+    .to_bytes()
+}
+
+/// Renders a received AX.25 frame as an AGWPE monitor frame.
+fn monitor_frame(frame: &[u8]) -> Option<AgwpeFrame> {
+    let frame = Ax25Frame::try_from_bytes(frame).ok()?;
+
+    let kind = match frame.control {
+        Control::I { .. } => b'I',
+        Control::U {
+            kind: UnnumberedKind::Ui,
+            ..
+        } => b'U',
+        _ => b'S',
+    };
+
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs() % 86400)
+        .unwrap_or(0);
+
+    let mut text = format!(" 1:Fm {} To {}", frame.src, frame.dst);
+    if !frame.digipeaters.is_empty() {
+        let via = frame
+            .digipeaters
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        text += &format!(" Via {}", via.join(","));
+    }
+    text += &format!(" <{}", frame.control);
+    if let Some(pid) = frame.pid {
+        text += &format!(" pid={:02X}", pid);
+    }
+    text += &format!(
+        " Len={} >[{:02}:{:02}:{:02}]\r",
+        frame.info.len(),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+
+    let mut data = text.into_bytes();
+    if !frame.info.is_empty() {
+        data.extend_from_slice(&frame.info);
+        data.push(b'\r');
+    }
+
+    Some(AgwpeFrame {
+        kind,
+        pid: frame.pid.unwrap_or(0),
+        call_from: frame.src.to_string(),
+        call_to: frame.dst.to_string(),
+        data,
+        ..Default::default()
+    })
+}
+
+struct AgwpeClient {
+    id: usize,
+    writer: Box<dyn Write + Send>,
+    monitor: bool,
+    raw: bool,
+    callsigns: Vec<String>,
+}
+
+impl AgwpeClient {
+    fn send(&mut self, frame: &AgwpeFrame) -> io::Result<()> {
+        self.writer.write_all(&frame.encode())?;
+        self.writer.flush()
+    }
+}
+
+/// Serves AGWPE clients, forwarding their frames to the transmit
+/// channel and sending received frames to monitoring clients.
+#[derive(Clone)]
+pub struct AgwpeServer {
+    clients: Arc<Mutex<Vec<AgwpeClient>>>,
+    next_id: Arc<AtomicUsize>,
+    transmit: mpsc::Sender<Vec<u8>>,
+    port: Arc<AgwpePort>,
+}
+
+impl AgwpeServer {
+    /// Creates a new server for `port`. Frames (without FCS) from
+    /// clients are sent to `transmit`.
+    pub fn new(transmit: mpsc::Sender<Vec<u8>>, port: AgwpePort) -> AgwpeServer {
+        AgwpeServer {
+            clients: Default::default(),
+            next_id: Default::default(),
+            transmit,
+            port: Arc::new(port),
+        }
+    }
+
+    /// Registers a client writer, returning the client id to pass to [`AgwpeServer::serve`].
+    pub fn add_client<W: Write + Send + 'static>(&self, writer: W) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.clients.lock().unwrap().push(AgwpeClient {
+            id,
+            writer: Box::new(writer),
+            monitor: false,
+            raw: false,
+            callsigns: vec![],
+        });
+        id
+    }
+
+    fn remove_client(&self, id: usize) {
+        self.clients.lock().unwrap().retain(|x| x.id != id);
+    }
+
+    fn with_client<F: FnOnce(&mut AgwpeClient)>(&self, id: usize, f: F) {
+        if let Some(client) = self.clients.lock().unwrap().iter_mut().find(|x| x.id == id) {
+            f(client)
+        }
+    }
+
+    fn reply(&self, id: usize, frame: AgwpeFrame) {
+        self.with_client(id, |client| {
+            if let Err(err) = client.send(&frame) {
+                debug!("AGWPE: {:?}", err);
+            }
+        });
+    }
+
+    /// Sends a received frame (without FCS) to every client that has
+    /// enabled raw or monitor mode, dropping clients that have gone away.
+    pub fn broadcast(&self, frame: &[u8]) {
+        let mut raw_data = vec![0x00];
+        raw_data.extend_from_slice(frame);
+        let raw = AgwpeFrame {
+            data: raw_data,
+            ..AgwpeFrame::new(b'K')
+        };
+        let monitor = monitor_frame(frame);
+
+        self.clients.lock().unwrap().retain_mut(|client| {
+            let mut ret = Ok(());
+            if client.raw {
+                ret = ret.and_then(|_| client.send(&raw));
+            }
+            if let (true, Some(monitor)) = (client.monitor, monitor.as_ref()) {
+                ret = ret.and_then(|_| client.send(monitor));
+            }
+            ret.is_ok()
+        });
+    }
+
+    /// Reads AGWPE frames from `reader` until EOF, handling each one.
+    pub fn serve<R: Read>(&self, id: usize, mut reader: R) -> io::Result<()> {
+        let mut transmit = self.transmit.clone();
+
+        let ret = loop {
+            match AgwpeFrame::read_from(&mut reader) {
+                Ok(Some(frame)) => {
+                    if let Some(bytes) = self.handle(id, frame) {
+                        if block_on(transmit.send(bytes)).is_err() {
+                            break Ok(());
+                        }
+                    }
+                }
+                Ok(None) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+
+        self.remove_client(id);
+        ret
+    }
+
+    /// Handles a frame from a client, returning a frame to transmit, if any.
+    fn handle(&self, id: usize, frame: AgwpeFrame) -> Option<Vec<u8>> {
+        match frame.kind {
+            b'R' => {
+                let mut data = AGWPE_VERSION_MAJOR.to_le_bytes().to_vec();
+                data.extend(AGWPE_VERSION_MINOR.to_le_bytes());
+                self.reply(
+                    id,
+                    AgwpeFrame {
+                        data,
+                        ..AgwpeFrame::new(b'R')
+                    },
+                );
+            }
+            b'G' => {
+                let data = self.port.info();
+                self.reply(
+                    id,
+                    AgwpeFrame {
+                        data,
+                        ..AgwpeFrame::new(b'G')
+                    },
+                );
+            }
+            b'g' => {
+                let data = self.port.capabilities();
+                let port = frame.port;
+                self.reply(
+                    id,
+                    AgwpeFrame {
+                        port,
+                        data,
+                        ..AgwpeFrame::new(b'g')
+                    },
+                );
+            }
+            b'X' => {
+                let call_from = frame.call_from.clone();
+                self.with_client(id, |client| client.callsigns.push(frame.call_from));
+                self.reply(
+                    id,
+                    AgwpeFrame {
+                        call_from,
+                        data: vec![1],
+                        ..AgwpeFrame::new(b'X')
+                    },
+                );
+            }
+            b'x' => {
+                self.with_client(id, |client| {
+                    client.callsigns.retain(|x| x != &frame.call_from)
+                });
+            }
+            b'm' => self.with_client(id, |client| client.monitor = !client.monitor),
+            b'k' => self.with_client(id, |client| client.raw = !client.raw),
+            b'y' | b'Y' => {
+                self.reply(
+                    id,
+                    AgwpeFrame {
+                        port: frame.port,
+                        call_from: frame.call_from,
+                        call_to: frame.call_to,
+                        data: 0u32.to_le_bytes().to_vec(),
+                        ..AgwpeFrame::new(frame.kind)
+                    },
+                );
+            }
+            b'K' if frame.data.len() > 1 => return Some(frame.data[1..].to_vec()),
+            b'M' => {
+                return ui_frame(&frame, &[], &frame.data)
+                    .map_err(|err| debug!("AGWPE: Bad unproto frame {:?}: {:?}", frame, err))
+                    .ok();
+            }
+            b'V' => {
+                let count = *frame.data.first()? as usize;
+                let info_start = 1 + count * AGWPE_CALL_LEN;
+                if frame.data.len() < info_start {
+                    debug!("AGWPE: Truncated via path in {:?}", frame);
+                    return None;
+                }
+                let via = frame.data[1..info_start]
+                    .chunks(AGWPE_CALL_LEN)
+                    .map(|x| {
+                        let len = x.iter().position(|&c| c == 0).unwrap_or(x.len());
+                        String::from_utf8_lossy(&x[..len]).trim().to_string()
+                    })
+                    .collect::<Vec<_>>();
+                return ui_frame(&frame, &via, &frame.data[info_start..])
+                    .map_err(|err| debug!("AGWPE: Bad unproto frame {:?}: {:?}", frame, err))
+                    .ok();
+            }
+            kind => debug!("AGWPE: Ignoring frame kind {:?}", kind as char),
+        }
+        None
+    }
+
+    /// Starts accepting AGWPE connections on a background thread.
+    pub fn listen_tcp<A: ToSocketAddrs>(&self, addr: A) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let server = self.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("AGWPE: accept failed: {:?}", err);
+                        continue;
+                    }
+                };
+                let writer = match stream.try_clone() {
+                    Ok(writer) => writer,
+                    Err(err) => {
+                        warn!("AGWPE: {:?}", err);
+                        continue;
+                    }
+                };
+
+                info!("AGWPE: client connected from {:?}", stream.peer_addr());
+                let id = server.add_client(writer);
+
+                let server = server.clone();
+                std::thread::spawn(move || {
+                    let peer = stream.peer_addr();
+                    if let Err(err) = server.serve(id, stream) {
+                        debug!("AGWPE: {:?}", err);
+                    }
+                    info!("AGWPE: client {:?} disconnected", peer);
+                });
+            }
+        });
+
+        Ok(local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use quick_dsp::modem::ChannelAccess;
+    use std::net::TcpStream;
+
+    const APRS_FRAME: &str = "82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0d";
+
+    fn start_server() -> (AgwpeServer, mpsc::Receiver<Vec<u8>>, TcpStream) {
+        let (sender, receiver) = mpsc::channel(10);
+        let port = AgwpePort {
+            description: "G3RUH 9600 bit/s".to_string(),
+            bit_rate: 9600,
+            channel_access: ChannelAccessHandle::default(),
+        };
+        port.channel_access.set(ChannelAccess {
+            tx_delay: Duration::from_millis(300),
+            tx_tail: Duration::from_millis(20),
+            persistence: 63,
+            slot_time: Duration::from_millis(100),
+            full_duplex: false,
+        });
+        let server = AgwpeServer::new(sender, port);
+        let addr = server.listen_tcp("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(addr).unwrap();
+        (server, receiver, client)
+    }
+
+    fn request(client: &mut TcpStream, frame: AgwpeFrame) -> AgwpeFrame {
+        client.write_all(&frame.encode()).unwrap();
+        AgwpeFrame::read_from(client).unwrap().unwrap()
+    }
+
+    #[test]
+    fn agwpe_server_version_and_ports() {
+        let (_server, _receiver, mut client) = start_server();
+
+        let reply = request(&mut client, AgwpeFrame::new(b'R'));
+        assert_eq!(reply.kind, b'R');
+        assert_eq!(&reply.data[..4], &AGWPE_VERSION_MAJOR.to_le_bytes());
+
+        let reply = request(&mut client, AgwpeFrame::new(b'G'));
+        assert_eq!(reply.kind, b'G');
+        assert_eq!(reply.data, b"1;Port1 ARNGLL G3RUH 9600 bit/s;\0");
+
+        let reply = request(&mut client, AgwpeFrame::new(b'g'));
+        assert_eq!(reply.kind, b'g');
+        assert_eq!(reply.data[..8], [3, 0xFF, 30, 2, 63, 10, 7, 0]);
+
+        let reply = request(
+            &mut client,
+            AgwpeFrame {
+                call_from: "KZ2X-1".to_string(),
+                ..AgwpeFrame::new(b'X')
+            },
+        );
+        assert_eq!(reply.kind, b'X');
+        assert_eq!(reply.call_from, "KZ2X-1");
+        assert_eq!(reply.data, vec![1]);
+    }
+
+    #[test]
+    fn agwpe_server_unproto() {
+        let (_server, mut receiver, mut client) = start_server();
+
+        let mut data = vec![1u8];
+        data.extend(b"WIDE1-1\0\0\0");
+        data.extend(b">202337zhttp://wa8lmf.com\r");
+        let frame = AgwpeFrame {
+            pid: 0xF0,
+            call_from: "WA8LMF".to_string(),
+            call_to: "APU25N".to_string(),
+            data,
+            ..AgwpeFrame::new(b'V')
+        };
+        client.write_all(&frame.encode()).unwrap();
+
+        assert_eq!(hex::encode(block_on(receiver.next()).unwrap()), APRS_FRAME);
+
+        let frame = AgwpeFrame {
+            pid: 0xF0,
+            call_from: "WA8LMF".to_string(),
+            call_to: "APU25N".to_string(),
+            data: b"x".to_vec(),
+            ..AgwpeFrame::new(b'M')
+        };
+        client.write_all(&frame.encode()).unwrap();
+        let sent = block_on(receiver.next()).unwrap();
+        assert_eq!(sent.len(), 14 + 2 + 1);
+        assert_eq!(sent[13] & 1, 1);
+    }
+
+    #[test]
+    fn agwpe_server_raw_and_monitor() {
+        let (server, mut receiver, mut client) = start_server();
+        let frame = hex::decode(APRS_FRAME).unwrap();
+
+        let mut data = vec![0u8];
+        data.extend(&frame);
+        client
+            .write_all(
+                &AgwpeFrame {
+                    data,
+                    ..AgwpeFrame::new(b'K')
+                }
+                .encode(),
+            )
+            .unwrap();
+        assert_eq!(block_on(receiver.next()).unwrap(), frame);
+
+        client.write_all(&AgwpeFrame::new(b'k').encode()).unwrap();
+        client.write_all(&AgwpeFrame::new(b'm').encode()).unwrap();
+
+        // Make sure both toggles have been processed.
+        request(&mut client, AgwpeFrame::new(b'R'));
+
+        server.broadcast(&frame);
+
+        let raw = AgwpeFrame::read_from(&mut client).unwrap().unwrap();
+        assert_eq!(raw.kind, b'K');
+        assert_eq!(&raw.data[1..], frame.as_slice());
+
+        let monitor = AgwpeFrame::read_from(&mut client).unwrap().unwrap();
+        assert_eq!(monitor.kind, b'U');
+        assert_eq!(monitor.call_from, "WA8LMF");
+        assert_eq!(monitor.call_to, "APU25N");
+        let text = String::from_utf8(monitor.data).unwrap();
+        assert!(text.starts_with(" 1:Fm WA8LMF To APU25N Via WIDE1-1 <UI pid=F0 Len=26 >"));
+        assert!(text.ends_with("\r>202337zhttp://wa8lmf.com\r\r"));
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use anyhow::{bail, format_err};
+use arngll::{FrameInfo, FrameType};
+use ax25::{Ax25Address, Ax25Frame, Control};
+use hamaddr::HamAddr;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How ARNGLL data frames are carried in AX.25 UI frames.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Encapsulation {
+    /// Only the payload, with the given PID. Secured frames and frames with
+    /// fields that have no AX.25 equivalent are rejected.
+    Payload { pid: u8 },
+
+    /// The whole ARNGLL frame (without FCS), with the given PID,
+    /// so that every field survives the trip.
+    Frame { pid: u8 },
+}
+
+impl Encapsulation {
+    pub fn pid(&self) -> u8 {
+        match *self {
+            Encapsulation::Payload { pid } | Encapsulation::Frame { pid } => pid,
+        }
+    }
+}
+
+/// Parses `payload` or `frame`, followed by `:` and a hex PID.
+///
+/// There is no default PID: with the usual no-layer-3 PID, every
+/// APRS frame heard would be gatewayed back onto the channel.
+impl FromStr for Encapsulation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, pid) = s
+            .split_once(':')
+            .ok_or_else(|| format_err!("Missing PID in {:?}", s))?;
+        let pid = u8::from_str_radix(pid.trim_start_matches("0x"), 16)
+            .map_err(|_| format_err!("Bad PID {:?}", pid))?;
+
+        match kind {
+            "payload" => Ok(Encapsulation::Payload { pid }),
+            "frame" => Ok(Encapsulation::Frame { pid }),
+            _ => bail!("Unknown encapsulation {:?}", kind),
+        }
+    }
+}
+
+/// Translates between ARNGLL data frames and AX.25 UI frames,
+/// so that stations using either can reach each other.
+pub struct Ax25Gateway {
+    encapsulation: Encapsulation,
+
+    /// AX.25 destination used for the ARNGLL broadcast address.
+    broadcast: Ax25Address,
+    digipeaters: Vec<Ax25Address>,
+
+    /// Frames we translated or produced recently, so we don't translate
+    /// them again. Keeping both stops two gateways on one channel
+    /// bouncing a frame between them.
+    recent: VecDeque<(Instant, Vec<u8>)>,
+}
+
+const GATEWAY_RECENT_WINDOW: Duration = Duration::from_secs(30);
+
+impl Ax25Gateway {
+    pub fn new(encapsulation: Encapsulation) -> Ax25Gateway {
+        Ax25Gateway {
+            encapsulation,
+            broadcast: "QST".parse().unwrap(),
+            digipeaters: vec![],
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Sets the via path of the AX.25 frames we send.
+    pub fn set_digipeaters(&mut self, digipeaters: Vec<Ax25Address>) {
+        self.digipeaters = digipeaters;
+    }
+
+    fn to_ax25_dst(&self, addr: &HamAddr) -> anyhow::Result<Ax25Address> {
+        if addr.is_broadcast() {
+            Ok(self.broadcast)
+        } else {
+            Ax25Address::try_from(*addr)
+        }
+    }
+
+    fn to_ham_dst(&self, addr: &Ax25Address) -> anyhow::Result<HamAddr> {
+        if *addr == self.broadcast {
+            Ok(HamAddr::BROADCAST)
+        } else {
+            HamAddr::try_from(*addr)
+        }
+    }
+
+    /// Translates an ARNGLL data frame to an AX.25 UI frame.
+    pub fn arngll_to_ax25(&self, frame: &FrameInfo, payload: &[u8]) -> anyhow::Result<Ax25Frame> {
+        if frame.frame_type != FrameType::Data {
+            bail!(
+                "Only data frames can be gatewayed, not {:?}",
+                frame.frame_type
+            );
+        }
+
+        let dst = self.to_ax25_dst(&frame.dst_addr)?;
+        let src = Ax25Address::try_from(frame.src_addr)?;
+
+        let info = match self.encapsulation {
+            Encapsulation::Payload { .. } => {
+                if frame.sec_info.is_some() {
+                    bail!("Secured frames require frame encapsulation");
+                }
+                if frame.network_id.is_some() || frame.rly_addr.is_some() || frame.ack_requested {
+                    bail!("{:?} requires frame encapsulation", frame);
+                }
+                payload.to_vec()
+            }
+            Encapsulation::Frame { .. } => frame.to_vec(payload),
+        };
+
+        Ok(Ax25Frame::ui(
+            dst,
+            src,
+            &self.digipeaters,
+            self.encapsulation.pid(),
+            &info,
+        ))
+    }
+
+    /// Translates an AX.25 UI frame to an ARNGLL data frame and payload.
+    pub fn ax25_to_arngll(&self, frame: &Ax25Frame) -> anyhow::Result<(FrameInfo, Vec<u8>)> {
+        if !matches!(
+            frame.control,
+            Control::U {
+                kind: ax25::UnnumberedKind::Ui,
+                ..
+            }
+        ) {
+            bail!("Only UI frames can be gatewayed, not {}", frame.control);
+        }
+        if frame.pid != Some(self.encapsulation.pid()) {
+            bail!("Unexpected PID {:02X?}", frame.pid);
+        }
+
+        let dst_addr = self.to_ham_dst(&frame.dst)?;
+        let src_addr = HamAddr::try_from(frame.src)?;
+
+        match self.encapsulation {
+            Encapsulation::Payload { .. } => Ok((
+                FrameInfo {
+                    frame_type: FrameType::Data,
+                    dst_addr,
+                    src_addr,
+                    ..FrameInfo::EMPTY
+                },
+                frame.info.clone(),
+            )),
+            Encapsulation::Frame { .. } => {
+                let (info, payload) = FrameInfo::try_from_bytes(&frame.info)?;
+                if info.frame_type != FrameType::Data {
+                    bail!("Encapsulated frame is not a data frame");
+                }
+                if info.dst_addr != dst_addr || info.src_addr != src_addr {
+                    bail!("Encapsulated {:?} does not match {}", info, frame);
+                }
+                Ok((info, payload.to_vec()))
+            }
+        }
+    }
+
+    /// Translates a frame (without FCS) received from the PHY in either
+    /// format, returning the translated frame (without FCS).
+    ///
+    /// Returns `Ok(None)` for frames that this gateway produced.
+    pub fn translate(&mut self, frame: &[u8], now: Instant) -> anyhow::Result<Option<Vec<u8>>> {
+        while let Some((time, _)) = self.recent.front() {
+            if now.saturating_duration_since(*time) < GATEWAY_RECENT_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+
+        if self.recent.iter().any(|(_, x)| x == frame) {
+            return Ok(None);
+        }
+
+        let ax25 = Ax25Frame::try_from_bytes(frame).ok().filter(|x| {
+            matches!(
+                x.control,
+                Control::U {
+                    kind: ax25::UnnumberedKind::Ui,
+                    ..
+                }
+            )
+        });
+
+        let ret = match ax25 {
+            Some(ax25) => {
+                let (info, payload) = self.ax25_to_arngll(&ax25)?;
+                info.to_vec(&payload)
+            }
+            None => {
+                let (info, payload) = FrameInfo::try_from_bytes(frame)?;
+                self.arngll_to_ax25(&info, payload)?.to_bytes()?
+            }
+        };
+
+        self.recent.push_back((now, frame.to_vec()));
+        self.recent.push_back((now, ret.clone()));
+        Ok(Some(ret))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_frame(dst: &str, src: &str) -> FrameInfo {
+        FrameInfo {
+            frame_type: FrameType::Data,
+            dst_addr: dst.parse().unwrap(),
+            src_addr: src.parse().unwrap(),
+            ..FrameInfo::EMPTY
+        }
+    }
+
+    #[test]
+    fn ax25_gateway_payload() {
+        let mut gateway = Ax25Gateway::new(Encapsulation::Payload { pid: 0xF0 });
+        gateway.set_digipeaters(vec!["WIDE1-1".parse().unwrap()]);
+        let now = Instant::now();
+
+        let frame = data_frame("N6DRC-2", "KZ2X-1");
+        let ax25 = gateway.arngll_to_ax25(&frame, b"hello").unwrap();
+        assert_eq!(
+            ax25.to_string(),
+            "KZ2X-1>N6DRC-2,WIDE1-1 <UI C pid=F0 len=5>: hello"
+        );
+        assert_eq!(
+            gateway.ax25_to_arngll(&ax25).unwrap(),
+            (frame.clone(), b"hello".to_vec())
+        );
+
+        // Broadcasts map onto QST.
+        let broadcast = FrameInfo {
+            dst_addr: HamAddr::BROADCAST,
+            ..frame.clone()
+        };
+        let ax25 = gateway.arngll_to_ax25(&broadcast, b"hi").unwrap();
+        assert_eq!(ax25.dst, "QST".parse().unwrap());
+        assert_eq!(gateway.ax25_to_arngll(&ax25).unwrap().0, broadcast);
+
+        // Fields without an AX.25 equivalent are rejected.
+        let acked = FrameInfo {
+            ack_requested: true,
+            ..frame.clone()
+        };
+        assert!(gateway.arngll_to_ax25(&acked, b"hello").is_err());
+        let ack = acked.generate_ack_frame(b"hello").unwrap();
+        assert!(gateway.arngll_to_ax25(&ack, &[]).is_err());
+
+        // Translated frames aren't translated back.
+        let bytes = frame.to_vec(b"hello");
+        let translated = gateway.translate(&bytes, now).unwrap().unwrap();
+        assert_eq!(gateway.translate(&translated, now).unwrap(), None);
+    }
+
+    #[test]
+    fn ax25_gateway_frame() {
+        let gateway = Ax25Gateway::new("frame:f0".parse().unwrap());
+
+        let frame = FrameInfo {
+            ack_requested: true,
+            rly_addr: Some("N6DRC-1".parse().unwrap()),
+            ..data_frame("N6DRC-2", "KZ2X-1")
+        };
+        let ax25 = gateway.arngll_to_ax25(&frame, b"hello").unwrap();
+        assert_eq!(ax25.info, frame.to_vec(b"hello"));
+        assert_eq!(
+            gateway.ax25_to_arngll(&ax25).unwrap(),
+            (frame, b"hello".to_vec())
+        );
+
+        // The AX.25 header must agree with the encapsulated frame.
+        let mut ax25 = ax25;
+        ax25.src = "KZ2X-2".parse().unwrap();
+        assert!(gateway.ax25_to_arngll(&ax25).is_err());
+
+        assert!("frame:zz".parse::<Encapsulation>().is_err());
+        assert!("frame".parse::<Encapsulation>().is_err());
+        assert!("bogus".parse::<Encapsulation>().is_err());
+    }
+
+    #[test]
+    fn ax25_gateway_two_gateways() {
+        let mut gateways = [
+            Ax25Gateway::new("frame:f0".parse().unwrap()),
+            Ax25Gateway::new("frame:f0".parse().unwrap()),
+        ];
+        let now = Instant::now();
+
+        // Only the first gateway hears the original, but both hear
+        // everything the gateways send.
+        let original = data_frame("N6DRC-2", "KZ2X-1").to_vec(b"hello");
+        let mut channel = vec![(original, 1)];
+        let mut sent = 0;
+        while let Some((frame, heard_by)) = channel.pop() {
+            for gateway in gateways[..heard_by].iter_mut() {
+                if let Some(translated) = gateway.translate(&frame, now).unwrap() {
+                    channel.push((translated, 2));
+                    sent += 1;
+                }
+            }
+            assert!(sent < 10, "Frames bounced between gateways");
+        }
+
+        // The first translates the original, the second translates
+        // that back, and neither translates the other's output again.
+        assert_eq!(sent, 2);
+    }
+}
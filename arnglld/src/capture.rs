@@ -0,0 +1,140 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use arngll::pcapng::*;
+use arngll::{Fcs, FrameInfo};
+use ax25::aprs::AprsPacket;
+use ax25::Ax25Frame;
+use log::warn;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Records frames to a pcapng file, with AX.25 and ARNGLL frames on
+/// separate interfaces so Wireshark can dissect the AX.25 ones.
+pub struct Capture<W: Write> {
+    writer: PcapngWriter<W>,
+    arngll: u32,
+    ax25: u32,
+}
+
+impl Capture<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Capture::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> Capture<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        let mut writer = PcapngWriter::new(writer)?;
+        let arngll = writer.add_interface(LINKTYPE_ARNGLL, "arngll")?;
+        let ax25 = writer.add_interface(LINKTYPE_AX25, "ax25")?;
+        Ok(Capture {
+            writer,
+            arngll,
+            ax25,
+        })
+    }
+
+    /// Records a frame (including FCS), with what we could
+    /// decode from it in the packet comment.
+    pub fn record(&mut self, frame: &[u8], direction: Direction) {
+        let frame = &frame[..frame.len().saturating_sub(Fcs::X25.size())];
+
+        let (interface, comment) = if let Ok(ax25) = Ax25Frame::try_from_bytes(frame) {
+            let comment = match AprsPacket::try_from_frame(&ax25) {
+                Ok(aprs) => format!("{}\n{:?}", ax25, aprs),
+                Err(_) => ax25.to_string(),
+            };
+            (self.ax25, comment)
+        } else if let Ok((frame_info, payload)) = FrameInfo::try_from_bytes(frame) {
+            let comment = format!("{:?} Payload: {} bytes", frame_info, payload.len());
+            (self.arngll, comment)
+        } else {
+            (self.arngll, "Unable to decode".to_string())
+        };
+
+        let packet = CapturedPacket {
+            interface,
+            timestamp: SystemTime::now(),
+            direction: Some(direction),
+            data: frame,
+            comment: Some(&comment),
+        };
+
+        // Flush each packet so nothing is lost if we don't exit cleanly.
+        if let Err(err) = self
+            .writer
+            .write_packet(&packet)
+            .and_then(|_| self.writer.flush())
+        {
+            warn!("Capture: {:?}", err);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arngll::FrameType;
+
+    #[test]
+    fn capture_record() {
+        let mut capture = Capture::new(Vec::new()).unwrap();
+
+        let ax25 = Ax25Frame::ui(
+            "APZARN".parse().unwrap(),
+            "KZ2X-1".parse().unwrap(),
+            &[],
+            ax25::PID_NO_L3,
+            b">Testing",
+        );
+        let ax25 = Fcs::X25.append(ax25.to_bytes().unwrap());
+        capture.record(&ax25, Direction::Inbound);
+
+        let frame = FrameInfo {
+            frame_type: FrameType::Data,
+            dst_addr: "N6DRC".parse().unwrap(),
+            src_addr: "KZ2X-1".parse().unwrap(),
+            ..FrameInfo::EMPTY
+        };
+        let arngll = Fcs::X25.append(frame.to_vec(b"hello"));
+        capture.record(&arngll, Direction::Outbound);
+
+        let bytes = capture.into_inner();
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|x| x == needle);
+
+        // Frames are written without FCS.
+        assert!(contains(&ax25[..ax25.len() - 2]));
+        assert!(!contains(&ax25));
+        assert!(contains(&arngll[..arngll.len() - 2]));
+        assert!(contains(
+            b"KZ2X-1>APZARN <UI C pid=F0 len=8>: >Testing\nStatus"
+        ));
+        assert!(contains(b"Payload: 5 bytes"));
+    }
+}
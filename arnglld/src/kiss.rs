@@ -0,0 +1,330 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use anyhow::{bail, format_err};
+use log::trace;
+use quick_dsp::filter::{Filter, Reset};
+use quick_dsp::modem::ChannelAccess;
+use std::iter::once;
+use std::time::Duration;
+
+pub const FEND: u8 = 0xC0;
+pub const FESC: u8 = 0xDB;
+pub const TFEND: u8 = 0xDC;
+pub const TFESC: u8 = 0xDD;
+
+/// Largest unescaped KISS frame we are willing to buffer.
+pub const KISS_MAX_FRAME_LEN: usize = 2048;
+
+/// A KISS command, as carried in the low nibble of the type byte.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum KissCommand {
+    /// A frame to send or that was received, without FCS.
+    Data(Vec<u8>),
+
+    /// Keyup delay, in 10ms units.
+    TxDelay(u8),
+
+    /// Persistence parameter `p`, scaled as `(p * 256) - 1`.
+    Persistence(u8),
+
+    /// Slot interval, in 10ms units.
+    SlotTime(u8),
+
+    /// Time to hold the transmitter after the frame, in 10ms units.
+    TxTail(u8),
+
+    /// Full-duplex mode.
+    FullDuplex(bool),
+
+    /// Hardware specific command.
+    SetHardware(Vec<u8>),
+
+    /// Exit KISS mode.
+    Return,
+}
+
+impl KissCommand {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Data(_) => 0x00,
+            Self::TxDelay(_) => 0x01,
+            Self::Persistence(_) => 0x02,
+            Self::SlotTime(_) => 0x03,
+            Self::TxTail(_) => 0x04,
+            Self::FullDuplex(_) => 0x05,
+            Self::SetHardware(_) => 0x06,
+            Self::Return => 0x0F,
+        }
+    }
+
+    fn param_bytes(&self) -> &[u8] {
+        match self {
+            Self::Data(x) | Self::SetHardware(x) => x.as_slice(),
+            Self::TxDelay(x) | Self::Persistence(x) | Self::SlotTime(x) | Self::TxTail(x) => {
+                std::slice::from_ref(x)
+            }
+            Self::FullDuplex(true) => &[1],
+            Self::FullDuplex(false) => &[0],
+            Self::Return => &[],
+        }
+    }
+
+    /// Applies a parameter command to `access`. Returns `true`
+    /// if the command was a parameter command.
+    pub fn apply(&self, access: &mut ChannelAccess) -> bool {
+        let tens_of_ms = |x: u8| Duration::from_millis(10 * x as u64);
+        match *self {
+            Self::TxDelay(x) => access.tx_delay = tens_of_ms(x),
+            Self::Persistence(x) => access.persistence = x,
+            Self::SlotTime(x) => access.slot_time = tens_of_ms(x),
+            Self::TxTail(x) => access.tx_tail = tens_of_ms(x),
+            Self::FullDuplex(x) => access.full_duplex = x,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A single KISS frame: a port number and a command.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KissFrame {
+    /// Port number, from 0 to 15.
+    pub port: u8,
+    pub command: KissCommand,
+}
+
+impl KissFrame {
+    /// Creates a data frame for the given port.
+    pub fn data(port: u8, frame: Vec<u8>) -> KissFrame {
+        KissFrame {
+            port,
+            command: KissCommand::Data(frame),
+        }
+    }
+
+    /// Parses an unescaped frame, excluding the `FEND` delimiters.
+    pub fn try_from_bytes(bytes: &[u8]) -> anyhow::Result<KissFrame> {
+        let (&type_byte, params) = bytes
+            .split_first()
+            .ok_or_else(|| format_err!("Empty KISS frame"))?;
+
+        if type_byte == 0xFF {
+            return Ok(KissFrame {
+                port: 0,
+                command: KissCommand::Return,
+            });
+        }
+
+        let port = type_byte >> 4;
+        let param = || {
+            params
+                .first()
+                .copied()
+                .ok_or_else(|| format_err!("Missing KISS parameter"))
+        };
+
+        let command = match type_byte & 0x0F {
+            0x00 => KissCommand::Data(params.to_vec()),
+            0x01 => KissCommand::TxDelay(param()?),
+            0x02 => KissCommand::Persistence(param()?),
+            0x03 => KissCommand::SlotTime(param()?),
+            0x04 => KissCommand::TxTail(param()?),
+            0x05 => KissCommand::FullDuplex(param()? != 0),
+            0x06 => KissCommand::SetHardware(params.to_vec()),
+            x => bail!("Unknown KISS command 0x{:X}", x),
+        };
+
+        Ok(KissFrame { port, command })
+    }
+
+    /// Returns the escaped frame, including the `FEND` delimiters.
+    ///
+    /// Panics if the port doesn't fit in the type byte's high nibble.
+    pub fn encode(&self) -> Vec<u8> {
+        assert!(self.port <= 0x0F, "bad KISS port: {}", self.port);
+        let type_byte = match self.command {
+            KissCommand::Return => 0xFF,
+            _ => (self.port << 4) | self.command.to_u8(),
+        };
+
+        let mut ret = vec![FEND];
+        for byte in once(type_byte).chain(self.command.param_bytes().iter().copied()) {
+            match byte {
+                FEND => ret.extend([FESC, TFEND]),
+                FESC => ret.extend([FESC, TFESC]),
+                x => ret.push(x),
+            }
+        }
+        ret.push(FEND);
+        ret
+    }
+}
+
+/// KISS byte stream decoder.
+///
+/// Un-escapes bytes and separates frames.
+/// Output is `Option<KissFrame>`
+#[derive(Clone, Default, Debug)]
+pub struct KissDecode {
+    frame: Vec<u8>,
+    escaped: bool,
+    overflow: bool,
+}
+
+impl Reset for KissDecode {
+    fn reset(&mut self) {
+        self.frame.clear();
+        self.escaped = false;
+        self.overflow = false;
+    }
+}
+
+impl Filter<u8> for KissDecode {
+    type Output = Option<KissFrame>;
+
+    fn filter(&mut self, byte: u8) -> Self::Output {
+        let byte = match (self.escaped, byte) {
+            (_, FEND) => {
+                let ret = if self.frame.is_empty() || self.overflow {
+                    None
+                } else {
+                    KissFrame::try_from_bytes(&self.frame)
+                        .map_err(|err| trace!("Bad KISS frame: {:?}", err))
+                        .ok()
+                };
+                self.reset();
+                return ret;
+            }
+            (false, FESC) => {
+                self.escaped = true;
+                return None;
+            }
+            (true, TFEND) => FEND,
+            (true, TFESC) => FESC,
+            (_, x) => x,
+        };
+
+        self.escaped = false;
+
+        if self.frame.len() < KISS_MAX_FRAME_LEN {
+            self.frame.push(byte);
+        } else {
+            self.overflow = true;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(bytes: &[u8]) -> Vec<KissFrame> {
+        let mut decode = KissDecode::default();
+        bytes.iter().filter_map(|&x| decode.filter(x)).collect()
+    }
+
+    #[test]
+    fn kiss_encode_escapes() {
+        let frame = KissFrame::data(0, vec![0x01, FEND, 0x02, FESC, 0x03]);
+        assert_eq!(
+            frame.encode(),
+            vec![FEND, 0x00, 0x01, FESC, TFEND, 0x02, FESC, TFESC, 0x03, FEND]
+        );
+
+        let frame = KissFrame::data(3, vec![0xAA]);
+        assert_eq!(frame.encode(), vec![FEND, 0x30, 0xAA, FEND]);
+    }
+
+    #[test]
+    fn kiss_decode_round_trip() {
+        let frames = vec![
+            KissFrame::data(0, vec![FEND, FESC, TFEND, TFESC, 0x00, 0xFF]),
+            KissFrame::data(15, b"Hello".to_vec()),
+            KissFrame {
+                port: 1,
+                command: KissCommand::TxDelay(30),
+            },
+            KissFrame {
+                port: 0,
+                command: KissCommand::FullDuplex(true),
+            },
+            KissFrame {
+                port: 0,
+                command: KissCommand::Return,
+            },
+        ];
+
+        let bytes = frames
+            .iter()
+            .flat_map(KissFrame::encode)
+            .collect::<Vec<_>>();
+
+        assert_eq!(decode_all(&bytes), frames);
+    }
+
+    #[test]
+    fn kiss_decode_ignores_garbage() {
+        // Back-to-back FENDs, an unknown command, and a
+        // parameter command with no parameter.
+        let bytes = [
+            FEND, FEND, FEND, 0x0A, 0x01, FEND, 0x01, FEND, 0x00, 0x42, FEND,
+        ];
+        assert_eq!(decode_all(&bytes), vec![KissFrame::data(0, vec![0x42])]);
+    }
+
+    #[test]
+    fn kiss_decode_overflow() {
+        let mut bytes = vec![FEND, 0x00];
+        bytes.extend(vec![0x55; KISS_MAX_FRAME_LEN + 10]);
+        bytes.extend([FEND, 0x00, 0x42, FEND]);
+        assert_eq!(decode_all(&bytes), vec![KissFrame::data(0, vec![0x42])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "bad KISS port: 16")]
+    fn kiss_encode_bad_port() {
+        KissFrame::data(16, vec![0xAA]).encode();
+    }
+
+    #[test]
+    fn kiss_command_apply() {
+        let mut access = ChannelAccess::default();
+        assert!(KissCommand::TxDelay(30).apply(&mut access));
+        assert!(KissCommand::Persistence(63).apply(&mut access));
+        assert!(KissCommand::SlotTime(5).apply(&mut access));
+        assert!(KissCommand::TxTail(2).apply(&mut access));
+        assert!(KissCommand::FullDuplex(true).apply(&mut access));
+        assert!(!KissCommand::Data(vec![]).apply(&mut access));
+        assert_eq!(
+            access,
+            ChannelAccess {
+                tx_delay: Duration::from_millis(300),
+                tx_tail: Duration::from_millis(20),
+                persistence: 63,
+                slot_time: Duration::from_millis(50),
+                full_duplex: true,
+            }
+        );
+    }
+}
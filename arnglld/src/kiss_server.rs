@@ -0,0 +1,330 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use crate::kiss::*;
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::SinkExt;
+use log::{debug, info, warn};
+use quick_dsp::filter::Filter;
+use quick_dsp::modem::ChannelAccessHandle;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+/// Serves KISS clients, forwarding their data frames to the
+/// transmit channel and broadcasting received frames to them.
+#[derive(Clone)]
+pub struct KissServer {
+    clients: Arc<Mutex<Vec<Box<dyn Write + Send>>>>,
+    channel_access: ChannelAccessHandle,
+    transmit: mpsc::Sender<Vec<u8>>,
+}
+
+impl KissServer {
+    /// Creates a new server. Data frames (without FCS) from
+    /// clients are sent to `transmit`, and parameter commands
+    /// change `channel_access`.
+    pub fn new(transmit: mpsc::Sender<Vec<u8>>, channel_access: ChannelAccessHandle) -> KissServer {
+        KissServer {
+            clients: Default::default(),
+            channel_access,
+            transmit,
+        }
+    }
+
+    /// Registers a writer that will receive all broadcast frames.
+    pub fn add_client<W: Write + Send + 'static>(&self, writer: W) {
+        self.clients.lock().unwrap().push(Box::new(writer));
+    }
+
+    /// Sends a received frame (without FCS) to every client,
+    /// dropping any clients that can no longer be written to.
+    pub fn broadcast(&self, frame: &[u8]) {
+        let bytes = KissFrame::data(0, frame.to_vec()).encode();
+        self.clients.lock().unwrap().retain_mut(|client| {
+            client
+                .write_all(&bytes)
+                .and_then(|_| client.flush())
+                .is_ok()
+        });
+    }
+
+    /// Reads KISS frames from `reader` until EOF, handling each one.
+    pub fn serve<R: Read>(&self, mut reader: R) -> io::Result<()> {
+        let mut decode = KissDecode::default();
+        let mut transmit = self.transmit.clone();
+        let mut buffer = [0u8; 1024];
+
+        loop {
+            let len = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            for frame in buffer[..len].iter().filter_map(|&x| decode.filter(x)) {
+                match frame.command {
+                    KissCommand::Data(data) if frame.port == 0 => {
+                        if block_on(transmit.send(data)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    KissCommand::Data(_) => {
+                        debug!("KISS: Ignoring frame for port {}", frame.port);
+                    }
+                    command => {
+                        if self.channel_access.update(|access| command.apply(access)) {
+                            debug!("KISS: {:?}", self.channel_access.get());
+                        } else {
+                            debug!("KISS: Ignoring {:?}", command);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Starts accepting KISS-over-TCP connections on a background thread.
+    pub fn listen_tcp<A: ToSocketAddrs>(&self, addr: A) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let server = self.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("KISS: accept failed: {:?}", err);
+                        continue;
+                    }
+                };
+                let writer = match stream.try_clone() {
+                    Ok(writer) => writer,
+                    Err(err) => {
+                        warn!("KISS: {:?}", err);
+                        continue;
+                    }
+                };
+
+                info!("KISS: client connected from {:?}", stream.peer_addr());
+                server.add_client(writer);
+
+                let server = server.clone();
+                std::thread::spawn(move || {
+                    let peer = stream.peer_addr();
+                    if let Err(err) = server.serve(stream) {
+                        debug!("KISS: {:?}", err);
+                    }
+                    info!("KISS: client {:?} disconnected", peer);
+                });
+            }
+        });
+
+        Ok(local_addr)
+    }
+
+    /// Opens a pseudo-terminal for KISS clients like `kissattach`,
+    /// returning the path of the client side.
+    #[cfg(target_os = "linux")]
+    pub fn open_pty(&self) -> io::Result<std::path::PathBuf> {
+        let (master, slave, path) = pty::open()?;
+
+        self.add_client(master.try_clone()?);
+
+        let server = self.clone();
+        std::thread::spawn(move || {
+            // Holding the client side open keeps reads from failing
+            // with `EIO` while no client is attached.
+            let _slave = slave;
+            if let Err(err) = server.serve(master) {
+                warn!("KISS: pty closed: {:?}", err);
+            }
+        });
+
+        Ok(path)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod pty {
+    use std::ffi::CStr;
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::path::PathBuf;
+
+    fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    /// Opens a raw-mode pseudo-terminal, returning the
+    /// master side, the slave side, and the slave path.
+    pub fn open() -> io::Result<(File, File, PathBuf)> {
+        // SAFETY: Plain libc calls on a file descriptor we own.
+        let master = unsafe {
+            let fd = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?;
+            File::from_raw_fd(fd)
+        };
+
+        let mut name = [0 as libc::c_char; 128];
+
+        // SAFETY: `name` is valid for writes of `name.len()` bytes and
+        // is NUL-terminated by `ptsname_r` on success.
+        let path = unsafe {
+            check(libc::grantpt(master.as_raw_fd()))?;
+            check(libc::unlockpt(master.as_raw_fd()))?;
+            let ret = libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len());
+            if ret != 0 {
+                return Err(io::Error::from_raw_os_error(ret));
+            }
+            PathBuf::from(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned())
+        };
+
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+
+        // SAFETY: `termios` is fully initialized by `tcgetattr` before use.
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            check(libc::tcgetattr(slave.as_raw_fd(), &mut termios))?;
+            libc::cfmakeraw(&mut termios);
+            check(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios))?;
+        }
+
+        Ok((master, slave, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn kiss_server_serve() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let channel_access = ChannelAccessHandle::default();
+        let server = KissServer::new(sender, channel_access.clone());
+
+        let mut input = KissFrame::data(0, b"frame one".to_vec()).encode();
+        input.extend(
+            KissFrame {
+                port: 0,
+                command: KissCommand::TxDelay(25),
+            }
+            .encode(),
+        );
+        input.extend(KissFrame::data(1, b"other port".to_vec()).encode());
+        input.extend(KissFrame::data(0, b"frame two".to_vec()).encode());
+
+        server.serve(input.as_slice()).unwrap();
+
+        assert_eq!(block_on(receiver.next()).unwrap(), b"frame one".to_vec());
+        assert_eq!(block_on(receiver.next()).unwrap(), b"frame two".to_vec());
+        assert_eq!(channel_access.get().tx_delay, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn kiss_server_broadcast() {
+        let (sender, _receiver) = mpsc::channel(10);
+        let server = KissServer::new(sender, Default::default());
+        let buffer = SharedBuffer::default();
+
+        server.add_client(buffer.clone());
+        server.broadcast(&[0x01, FEND]);
+
+        assert_eq!(
+            *buffer.0.lock().unwrap(),
+            vec![FEND, 0x00, 0x01, FESC, TFEND, FEND]
+        );
+    }
+
+    #[test]
+    fn kiss_server_tcp() {
+        use std::net::TcpStream;
+
+        let (sender, mut receiver) = mpsc::channel(10);
+        let server = KissServer::new(sender, Default::default());
+        let addr = server.listen_tcp("127.0.0.1:0").unwrap();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(&KissFrame::data(0, b"via tcp".to_vec()).encode())
+            .unwrap();
+
+        assert_eq!(block_on(receiver.next()).unwrap(), b"via tcp".to_vec());
+
+        server.broadcast(b"hello");
+        let expected = KissFrame::data(0, b"hello".to_vec()).encode();
+        let mut received = vec![0u8; expected.len()];
+        client.read_exact(&mut received).unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn kiss_server_pty() {
+        use std::fs::OpenOptions;
+
+        let (sender, mut receiver) = mpsc::channel(10);
+        let server = KissServer::new(sender, Default::default());
+        let path = server.open_pty().unwrap();
+
+        let mut client = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        client
+            .write_all(&KissFrame::data(0, vec![0x00, 0x0A, 0x0D, 0xFF]).encode())
+            .unwrap();
+
+        assert_eq!(
+            block_on(receiver.next()).unwrap(),
+            vec![0x00, 0x0A, 0x0D, 0xFF]
+        );
+    }
+}
@@ -19,17 +19,45 @@
 // TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use anyhow::format_err;
+mod agwpe;
+mod agwpe_server;
+mod ax25_gateway;
+mod capture;
+mod kiss;
+mod kiss_server;
+mod tun;
+mod tun_bridge;
+
+use anyhow::{bail, format_err, Context as _};
 //use arngll::{FrameData, NetworkId};
+use crate::agwpe_server::{AgwpePort, AgwpeServer};
+use crate::ax25_gateway::{Ax25Gateway, Encapsulation};
+use crate::capture::Capture;
+use crate::kiss_server::KissServer;
+use crate::tun::TunInterface;
+use crate::tun_bridge::TunBridge;
+use arngll::pcapng::{Direction, LINKTYPE_ARNGLL, LINKTYPE_AX25};
+use arngll::replay::{PcapReplay, ReplaySpeed};
+use arngll::{Fcs, FrameInfo, FrameType};
+use ax25::aprs::{AprsPacket, AprsStatus};
+use ax25::{AliasConfig, Ax25Address, Ax25Frame, Digipeater, DigipeaterConfig};
 use clap::Parser;
 use cpal::traits::{DeviceTrait, HostTrait};
+use futures::channel::mpsc;
 use futures::executor::{block_on, block_on_stream};
 use futures::prelude::*;
+use futures::stream::LocalBoxStream;
 use hamaddr::HamAddr;
-use log::info;
-use arngll::{FrameInfo, FrameType};
-use quick_dsp::bell202::{Ax25Debug, Bell202Receiver, Bell202Sender};
-use quick_dsp::filter::IteratorExt as _;
+use log::{debug, info, warn};
+use quick_dsp::bell202::{AfskProfile, Decoding, DemodBranch, Framing};
+use quick_dsp::filter::FcsRepair;
+use quick_dsp::g3ruh::G3ruh;
+use quick_dsp::modem::{ChannelAccessHandle, Modem, ModemReceiver, ModemSender};
+use quick_dsp::psk::PskProfile;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -54,6 +82,92 @@ struct Opt {
 
     #[clap(long)]
     output_audio_device: Option<String>,
+
+    /// Bridge IPv6 packets from the named TUN interface (Linux only)
+    #[clap(long)]
+    tun: Option<String>,
+
+    /// Serve KISS over TCP on the given address (e.g. `127.0.0.1:8001`)
+    #[clap(long)]
+    kiss_tcp: Option<String>,
+
+    /// Serve KISS over a pseudo-terminal (Linux only)
+    #[clap(long)]
+    kiss_pty: bool,
+
+    /// Serve the AGWPE API over TCP on the given address (e.g. `127.0.0.1:8000`)
+    #[clap(long)]
+    agwpe_tcp: Option<String>,
+
+    /// Send an APRS status report with the given text on startup
+    #[clap(long)]
+    aprs_status: Option<String>,
+
+    /// Digipeat AX.25 frames via the given alias, which may be given more than once.
+    /// For example `WIDE1-1` or `WIDEn-N,max=2`. Options are `max=<n>`, `nosub`,
+    /// `notrace` and `viscous=<seconds>`. Frames via our callsign are also digipeated.
+    #[clap(long)]
+    digipeat: Vec<AliasConfig>,
+
+    /// Seconds during which a digipeated frame is not digipeated again
+    #[clap(long, default_value = "30")]
+    digipeat_dedupe: u64,
+
+    /// Gateway between ARNGLL data frames and AX.25 UI frames, carrying either the
+    /// `payload` or the whole `frame`, followed by `:` and a hex PID
+    #[clap(long)]
+    ax25_gateway: Option<Encapsulation>,
+
+    /// Digipeater to send gatewayed AX.25 frames via, which may be given more than once
+    #[clap(long)]
+    ax25_gateway_via: Vec<Ax25Address>,
+
+    /// Record received and transmitted frames to the given pcapng file
+    #[clap(long)]
+    capture: Option<String>,
+
+    /// Replay received frames from the given pcap or pcapng file instead of the input audio device
+    #[clap(long)]
+    replay: Option<String>,
+
+    /// Speed up replay by the given factor, or replay as fast as possible if zero
+    #[clap(long, default_value = "1")]
+    replay_speed: f64,
+
+    /// Repair received frames that fail the FCS check by flipping bits,
+    /// checking at most the given number of candidates per frame
+    #[clap(long)]
+    fcs_repair: Option<usize>,
+
+    /// Use soft decisions to choose which bits to flip when repairing frames
+    #[clap(long, requires = "fcs-repair")]
+    soft_decision: bool,
+
+    /// Transmit frames with FX.25 forward error correction, using the given
+    /// number of Reed-Solomon check bytes. FX.25 frames are always received.
+    #[clap(long, possible_values = &["16", "32", "64"])]
+    fx25: Option<usize>,
+
+    /// Use IL2P framing instead of HDLC, for both transmitting and receiving
+    #[clap(long, conflicts_with_all = &["fx25", "fcs-repair"])]
+    il2p: bool,
+
+    /// Decode with a bank of demodulators tuned for twist and off-nominal tones,
+    /// at the cost of more CPU time
+    #[clap(long, conflicts_with_all = &["il2p", "fcs-repair"])]
+    decoder_bank: bool,
+
+    /// Modem to use: Bell 202 for VHF, Bell 103 for HF, V.23, 2400 baud AFSK,
+    /// G3RUH 9600 baud for UHF, which needs a flat-audio radio port, or PSK
+    #[clap(long, default_value = "bell202", possible_values = &[
+        "bell202", "bell103", "v23", "afsk2400", "g3ruh", "bpsk300", "bpsk1200", "qpsk2400", "qpsk4800",
+    ])]
+    modem: String,
+
+    /// Recover the bit clock of AFSK modems by restarting it on every
+    /// transition, rather than with a PLL
+    #[clap(long)]
+    bit_sampler: bool,
 }
 
 fn find_device<I: IntoIterator<Item = cpal::Device>>(
@@ -106,6 +220,21 @@ fn find_device<I: IntoIterator<Item = cpal::Device>>(
 }
 
 impl Opt {
+    /// Checks combinations of options that clap can't: the decoder bank
+    /// and soft decisions only work with the AFSK modems.
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        let afsk = self.modem != "g3ruh" && self.psk_profile().is_none();
+        for (flag, set) in [
+            ("--decoder-bank", self.decoder_bank),
+            ("--soft-decision", self.soft_decision),
+        ] {
+            if set && !afsk {
+                bail!("{} isn't supported by --modem {}", flag, self.modem);
+            }
+        }
+        Ok(())
+    }
+
     fn get_output_device(&self) -> Result<cpal::Device, anyhow::Error> {
         let host = cpal::default_host();
 
@@ -130,25 +259,269 @@ impl Opt {
             .ok_or_else(|| format_err!("no default input device"))
     }
 
-    fn get_packet_stream(&self) -> Result<Bell202Receiver, anyhow::Error> {
+    fn get_packet_stream(&self) -> Result<LocalBoxStream<'static, Vec<u8>>, anyhow::Error> {
+        if let Some(path) = self.replay.as_ref() {
+            let speed = if self.replay_speed > 0.0 {
+                ReplaySpeed::Speedup(self.replay_speed)
+            } else {
+                ReplaySpeed::AsFastAsPossible
+            };
+            info!("Replaying {:?} at {:?}", path, speed);
+            let mut replay = PcapReplay::open(path, speed)?;
+            replay.set_link_types(&[LINKTYPE_ARNGLL, LINKTYPE_AX25]);
+            return Ok(replay.boxed_local());
+        }
+
         let device = self.get_input_device()?;
         info!("Using input device {:?}", device.name());
-        let receiver = Bell202Receiver::new(&device)?;
-
-        Ok(receiver)
+        let decoding = match self.fcs_repair {
+            Some(max_attempts) => Decoding::Repair {
+                repair: FcsRepair::new(max_attempts),
+                soft: self.soft_decision,
+            },
+            None if self.decoder_bank => Decoding::Bank(DemodBranch::default_bank()),
+            None => Decoding::Framed(self.framing()),
+        };
+        if self.modem == "g3ruh" {
+            Ok(ModemReceiver::open(&device, G3ruh::default(), decoding, Fcs::X25)?.boxed_local())
+        } else if let Some(profile) = self.psk_profile() {
+            Ok(ModemReceiver::open(&device, profile, decoding, Fcs::X25)?.boxed_local())
+        } else {
+            Ok(ModemReceiver::open(&device, self.profile(), decoding, Fcs::X25)?.boxed_local())
+        }
     }
 
-    fn get_packet_sink(&self) -> Result<Bell202Sender, anyhow::Error> {
+    /// Opens the output device, returning the sink along with
+    /// a handle for changing how it shares the channel.
+    fn get_packet_sink(&self) -> Result<(PacketSink, ChannelAccessHandle), anyhow::Error> {
         let device = self.get_output_device()?;
         info!("Using output device {:?}", device.name());
-        let sender = Bell202Sender::new(&device)?;
+        if self.modem == "g3ruh" {
+            self.open_sender(&device, G3ruh::default())
+        } else if let Some(profile) = self.psk_profile() {
+            self.open_sender(&device, profile)
+        } else {
+            self.open_sender(&device, self.profile())
+        }
+    }
+
+    fn open_sender<M: Modem + Unpin>(
+        &self,
+        device: &cpal::Device,
+        modem: M,
+    ) -> Result<(PacketSink, ChannelAccessHandle), anyhow::Error> {
+        let sender = ModemSender::open(device, modem)?;
+        sender.set_framing(self.framing());
+        sender.set_fcs(Fcs::X25);
+        let channel_access = sender.channel_access();
+
+        Ok((Box::new(sender), channel_access))
+    }
+
+    /// On-air bit rate of `--modem`.
+    fn bit_rate(&self) -> u32 {
+        if self.modem == "g3ruh" {
+            G3ruh::default().bit_rate()
+        } else if let Some(profile) = self.psk_profile() {
+            profile.bit_rate()
+        } else {
+            self.profile().bit_rate()
+        }
+    }
+
+    /// Describes the modem and framing, like `Bell 202 1200 bit/s IL2P`.
+    fn port_description(&self) -> String {
+        let name = match self.modem.as_str() {
+            "bell202" => "Bell 202",
+            "bell103" => "Bell 103",
+            "v23" => "V.23",
+            "afsk2400" => "AFSK",
+            "g3ruh" => "G3RUH",
+            x if x.starts_with("bpsk") => "BPSK",
+            _ => "QPSK",
+        };
+        let framing = match self.framing() {
+            Framing::Hdlc => String::new(),
+            Framing::Fx25(check_bytes) => format!(" FX.25/{}", check_bytes),
+            Framing::Il2p => " IL2P".to_string(),
+        };
+        format!("{} {} bit/s{}", name, self.bit_rate(), framing)
+    }
 
-        Ok(sender)
+    /// Returns the PSK profile for `--modem`, if it is PSK.
+    fn psk_profile(&self) -> Option<PskProfile> {
+        match self.modem.as_str() {
+            "bpsk300" => Some(PskProfile::BPSK_300),
+            "bpsk1200" => Some(PskProfile::BPSK_1200),
+            "qpsk2400" => Some(PskProfile::QPSK_2400),
+            "qpsk4800" => Some(PskProfile::QPSK_4800),
+            _ => None,
+        }
+    }
+
+    /// Returns the AFSK profile for `--modem`. Not used for G3RUH or PSK.
+    fn profile(&self) -> AfskProfile {
+        let profile = match self.modem.as_str() {
+            "bell103" => AfskProfile::BELL_103,
+            "v23" => AfskProfile::V23,
+            "afsk2400" => AfskProfile::AFSK_2400,
+            _ => AfskProfile::BELL_202,
+        };
+        profile.with_pll(!self.bit_sampler)
+    }
+
+    fn framing(&self) -> Framing {
+        if self.il2p {
+            Framing::Il2p
+        } else if let Some(check_bytes) = self.fx25 {
+            Framing::Fx25(check_bytes)
+        } else {
+            Framing::Hdlc
+        }
     }
 }
 
-fn main() {
+/// Where frames to transmit go, whatever the modem.
+type PacketSink = Box<dyn Sink<Vec<u8>, Error = anyhow::Error> + Unpin>;
+
+/// Opens the named TUN interface.
+#[cfg(target_os = "linux")]
+fn open_tun(name: &str) -> Result<Arc<dyn TunInterface>, anyhow::Error> {
+    Ok(Arc::new(tun::LinuxTun::open(name)?))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_tun(_name: &str) -> Result<Arc<dyn TunInterface>, anyhow::Error> {
+    anyhow::bail!("TUN interfaces are only supported on Linux")
+}
+
+/// Starts the KISS server on the interfaces given in `opt`.
+fn start_kiss_server(
+    opt: &Opt,
+    transmit: mpsc::Sender<Vec<u8>>,
+    channel_access: ChannelAccessHandle,
+) -> Result<KissServer, anyhow::Error> {
+    let server = KissServer::new(transmit, channel_access);
+
+    if let Some(addr) = opt.kiss_tcp.as_ref() {
+        info!("KISS: listening on {}", server.listen_tcp(addr.as_str())?);
+    }
+
+    if opt.kiss_pty {
+        #[cfg(target_os = "linux")]
+        info!("KISS: pseudo-terminal at {:?}", server.open_pty()?);
+
+        #[cfg(not(target_os = "linux"))]
+        anyhow::bail!("KISS pseudo-terminals are only supported on Linux");
+    }
+
+    Ok(server)
+}
+
+/// Sends a hard-coded test frame and its ack.
+fn send_test_frames(
+    packet_sink: &mut PacketSink,
+    capture: &mut Option<Capture<BufWriter<File>>>,
+    callsign: HamAddr,
+) {
+    let frame = FrameInfo {
+        frame_type: FrameType::Data,
+        ack_requested: true,
+        dst_addr: "QX3NAN".parse().unwrap(),
+        src_addr: callsign,
+        .. FrameInfo::EMPTY
+    };
+    let payload = b"Payload! TEST: This is a test frame of ASCII text.";
+
+    println!("Sending test frame: {:?}", frame);
+
+    // Calc bytes for test frame.
+    let frame_bytes = Fcs::X25.append(frame.to_vec(payload));
+
+    // Play the test packet.
+    transmit(packet_sink, capture, frame_bytes);
+
+    let frame = frame
+        .generate_ack_frame(payload).unwrap();
+
+    println!("Sending test ack frame: {:?}", frame);
+
+    // Calc bytes for test ack frame.
+    let frame_bytes = Fcs::X25.append(frame.to_vec(&[]));
+
+    // Play the test ack.
+    transmit(packet_sink, capture, frame_bytes);
+}
+
+/// Builds an APRS status report frame (without FCS).
+fn aprs_status_frame(callsign: HamAddr, text: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let packet = AprsPacket::Status(AprsStatus {
+        timestamp: None,
+        text: text.to_string(),
+    });
+
+    packet
+        .to_frame(Ax25Address::try_from(callsign)?, &["WIDE2-1".parse()?])?
+        .to_bytes()
+}
+
+/// Sends a frame (including FCS) to the PHY, recording it if capturing.
+fn transmit(
+    packet_sink: &mut PacketSink,
+    capture: &mut Option<Capture<BufWriter<File>>>,
+    frame: Vec<u8>,
+) {
+    if let Some(capture) = capture.as_mut() {
+        capture.record(&frame, Direction::Outbound);
+    }
+    if let Err(err) = block_on(packet_sink.send(frame)) {
+        warn!("Unable to send frame: {:?}", err);
+    }
+}
+
+/// Sends an AX.25 frame, appending the FCS.
+fn send_ax25(
+    packet_sink: &mut PacketSink,
+    capture: &mut Option<Capture<BufWriter<File>>>,
+    frame: &Ax25Frame,
+) {
+    match frame.to_bytes() {
+        Ok(bytes) => transmit(packet_sink, capture, Fcs::X25.append(bytes)),
+        Err(err) => warn!("Unable to send frame: {:?}", err),
+    }
+}
+
+/// Logs a frame (without FCS) received from the PHY.
+fn log_frame(frame: &[u8]) {
+    if let Ok(ax25) = Ax25Frame::try_from_bytes(frame) {
+        info!("Received AX25: {}", ax25);
+        if let Ok(aprs) = AprsPacket::try_from_frame(&ax25) {
+            info!("Received APRS: {:?}", aprs);
+        }
+    } else if let Ok((frame_info, payload)) = FrameInfo::try_from_bytes(frame) {
+        info!("Received ARNGLL: {:?} Payload: {:?}", frame_info, hex::encode(payload));
+    } else {
+        info!("Received: {:?}", hex::encode(frame));
+    }
+}
+
+enum Event {
+    /// Frame (including FCS) received from the PHY.
+    Received(Vec<u8>),
+
+    /// Packet read from the TUN interface.
+    TunPacket(Vec<u8>),
+
+    /// Frame (without FCS) from a local client, to be transmitted.
+    Transmit(Vec<u8>),
+
+    /// Time to check for delayed digipeater frames.
+    Tick,
+}
+
+fn main() -> Result<(), anyhow::Error> {
     let opt = Opt::parse();
+    opt.validate()?;
 
     {
         // Work around for weird cpal issues.
@@ -183,7 +556,7 @@ fn main() {
             .collect::<Vec<_>>();
         println!("Input Devices: {:#?}", input_device_names);
         println!("Output Devices: {:#?}", output_device_names);
-        return;
+        return Ok(());
     }
 
     stderrlog::new()
@@ -192,59 +565,209 @@ fn main() {
         .init()
         .unwrap();
 
-    println!("Callsign: {}", opt.callsign.expect("Missing callsign"));
+    let callsign = opt.callsign.expect("Missing callsign");
+    println!("Callsign: {}", callsign);
     println!("opt = {:?}", opt);
 
-    const X25: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_SDLC);
-
-    let frame = FrameInfo {
-        frame_type: FrameType::Data,
-        ack_requested: true,
-        dst_addr: "QX3NAN".parse().unwrap(),
-        src_addr: opt.callsign.unwrap(),
-        .. FrameInfo::EMPTY
+    let (mut packet_sink, channel_access) = opt.get_packet_sink()?;
+
+    let mut capture = opt.capture.as_ref().map(|path| {
+        info!("Capturing to {:?}", path);
+        Capture::create(path).unwrap()
+    });
+
+    let (tun_sender, tun_receiver) = mpsc::channel::<Vec<u8>>(10);
+    let (transmit_sender, transmit_receiver) = mpsc::channel::<Vec<u8>>(10);
+
+    let mut tun_bridge = opt.tun.as_ref().map(|name| {
+        let tun = open_tun(name).unwrap();
+        info!("Opened TUN interface {:?}", tun.name());
+        info!(
+            "Link-local address for {}: {}",
+            callsign,
+            callsign.to_ipv6_link_local().unwrap()
+        );
+        tun::spawn_reader(tun.clone(), tun_sender.clone());
+        TunBridge::new(tun, callsign)
+    });
+
+    let kiss_server = if opt.kiss_tcp.is_some() || opt.kiss_pty {
+        Some(start_kiss_server(&opt, transmit_sender.clone(), channel_access.clone()).unwrap())
+    } else {
+        None
     };
-    let payload = b"Payload! TEST: This is a test frame of ASCII text.";
 
-    let mut packet_sink = opt.get_packet_sink().unwrap();
-
-    println!("Sending test frame: {:?}", frame);
-
-    // Calc bytes for test frame.
-    let frame_bytes = frame
-        .bytes_with_payload(payload)
-        .append_crc(&X25)
-        .collect::<Vec<_>>();
+    let agwpe_server = opt.agwpe_tcp.as_ref().map(|addr| {
+        let port = AgwpePort {
+            description: opt.port_description(),
+            bit_rate: opt.bit_rate(),
+            channel_access: channel_access.clone(),
+        };
+        let server = AgwpeServer::new(transmit_sender.clone(), port);
+        info!(
+            "AGWPE: listening on {}",
+            server.listen_tcp(addr.as_str()).unwrap()
+        );
+        server
+    });
+
+    if tun_bridge.is_none() && kiss_server.is_none() && agwpe_server.is_none() {
+        send_test_frames(&mut packet_sink, &mut capture, callsign);
+    }
 
-    // Play the test packet.
-    block_on(packet_sink.send(frame_bytes.clone())).unwrap();
+    let mut digipeater = if opt.digipeat.is_empty() {
+        None
+    } else {
+        let mut config = DigipeaterConfig::new(
+            Ax25Address::try_from(callsign).context("Can't digipeat as callsign")?,
+            opt.digipeat.clone(),
+        );
+        config.dedupe_window = Duration::from_secs(opt.digipeat_dedupe);
+        info!(
+            "Digipeating as {} via {:?}",
+            config.callsign, config.aliases
+        );
+        Some(Digipeater::new(config))
+    };
 
-    let frame = frame
-        .generate_ack_frame(payload).unwrap();
+    let mut gateway = opt.ax25_gateway.map(|encapsulation| {
+        let mut gateway = Ax25Gateway::new(encapsulation);
+        gateway.set_digipeaters(opt.ax25_gateway_via.clone());
+        gateway
+    });
+
+    if let Some(text) = opt.aprs_status.as_ref() {
+        let frame = aprs_status_frame(callsign, text).unwrap();
+        info!(
+            "Sending APRS status: {}",
+            Ax25Frame::try_from_bytes(&frame).unwrap()
+        );
+        if transmit_sender.clone().try_send(frame).is_err() {
+            warn!("Unable to queue APRS status");
+        }
+    }
 
-    println!("Sending test ack frame: {:?}", frame);
+    println!("Listening for packets...");
 
-    // Calc bytes for test ack frame.
-    let frame_bytes = frame
-        .bytes_with_payload(&[])
-        .append_crc(&X25)
-        .collect::<Vec<_>>();
+    let packet_stream = opt.get_packet_stream()?;
 
-    // Play the test ack.
-    block_on(packet_sink.send(frame_bytes.clone())).unwrap();
+    let ticks = Box::pin(stream::unfold(digipeater.is_some(), |enabled| async move {
+        if enabled {
+            futures_timer::Delay::new(Duration::from_millis(100)).await;
+            Some((Event::Tick, enabled))
+        } else {
+            None
+        }
+    }));
+
+    let events = stream::select(
+        stream::select(packet_stream.map(Event::Received), ticks),
+        stream::select(
+            tun_receiver.map(Event::TunPacket),
+            transmit_receiver.map(Event::Transmit),
+        ),
+    );
 
-    println!("Listening for packets...");
+    for event in block_on_stream(events) {
+        match event {
+            Event::Received(frame) => {
+                let body = match Fcs::X25.verify(&frame) {
+                    Ok(body) => body,
+                    Err(err) => {
+                        debug!("Dropping frame: {}", err);
+                        continue;
+                    }
+                };
+
+                log_frame(body);
+
+                if let Some(capture) = capture.as_mut() {
+                    capture.record(&frame, Direction::Inbound);
+                }
+
+                if let Some(digipeater) = digipeater.as_mut() {
+                    let frame = Ax25Frame::try_from_bytes(body);
+                    if let Some(frame) = frame
+                        .ok()
+                        .and_then(|x| digipeater.handle_frame(&x, Instant::now()))
+                    {
+                        info!("Digipeating: {}", frame);
+                        send_ax25(&mut packet_sink, &mut capture, &frame);
+                    }
+                }
+
+                if let Some(bridge) = tun_bridge.as_mut() {
+                    if let Err(err) = bridge.handle_frame(&frame) {
+                        debug!("TUN: Ignoring frame: {:?}", err);
+                    }
+                }
+
+                if let Some(gateway) = gateway.as_mut() {
+                    match gateway.translate(body, Instant::now()) {
+                        Ok(Some(translated)) => {
+                            let translated = Fcs::X25.append(translated);
+                            transmit(&mut packet_sink, &mut capture, translated);
+                        }
+                        Ok(None) => {}
+                        Err(err) => debug!("Gateway: Ignoring frame: {:?}", err),
+                    }
+                }
+
+                if let Some(server) = kiss_server.as_ref() {
+                    server.broadcast(body);
+                }
+                if let Some(server) = agwpe_server.as_ref() {
+                    server.broadcast(body);
+                }
+            }
+            Event::TunPacket(packet) => {
+                match tun_bridge.as_ref().map(|x| x.packet_to_frame(&packet)) {
+                    Some(Ok(frame)) => transmit(&mut packet_sink, &mut capture, frame),
+                    Some(Err(err)) => debug!("TUN: Dropping packet: {:?}", err),
+                    None => {}
+                }
+            }
+            Event::Transmit(frame) => {
+                transmit(&mut packet_sink, &mut capture, Fcs::X25.append(frame));
+            }
+            Event::Tick => {
+                while let Some(frame) = digipeater
+                    .as_mut()
+                    .and_then(|x| x.poll_delayed(Instant::now()))
+                {
+                    info!("Digipeating: {}", frame);
+                    send_ax25(&mut packet_sink, &mut capture, &frame);
+                }
+            }
+        }
+    }
 
-    let packet_stream = opt.get_packet_stream().unwrap();
+    Ok(())
+}
 
-    for frame in block_on_stream(packet_stream) {
-        let debug = Ax25Debug(&frame);
-        if debug.is_ax25() {
-            info!("Received AX25: {:?}", debug);
-        } else if let Ok((frame_info, payload)) = FrameInfo::try_from_bytes(&frame) {
-            info!("Received ARNGLL: {:?} Payload: {:?}", frame_info, hex::encode(payload));
-        } else {
-            info!("Received: {:?}", hex::encode(frame));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opt_validate() {
+        let parse = |args: &str| {
+            let args = format!("arnglld --callsign KZ2X {}", args);
+            Opt::try_parse_from(args.split_whitespace()).unwrap()
+        };
+
+        assert!(parse("--decoder-bank").validate().is_ok());
+        assert!(parse("--modem v23 --fcs-repair 100 --soft-decision")
+            .validate()
+            .is_ok());
+        assert!(parse("--modem g3ruh").validate().is_ok());
+
+        for args in [
+            "--modem g3ruh --decoder-bank",
+            "--modem bpsk1200 --decoder-bank",
+            "--modem qpsk2400 --fcs-repair 100 --soft-decision",
+        ] {
+            assert!(parse(args).validate().is_err(), "{}", args);
         }
     }
 }
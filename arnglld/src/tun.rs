@@ -0,0 +1,182 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use futures::channel::mpsc;
+use log::{error, trace};
+use std::io;
+use std::sync::Arc;
+
+/// A layer-3 network interface that exchanges raw IP packets.
+pub trait TunInterface: Send + Sync {
+    /// Returns the name of the interface.
+    fn name(&self) -> &str;
+
+    /// Blocks until a packet is available, writing it into `buf`.
+    /// Returns the length of the packet.
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes a single packet to the interface.
+    fn send(&self, packet: &[u8]) -> io::Result<()>;
+}
+
+/// Spawns a thread that reads packets from `tun` and forwards them to `sender`.
+pub fn spawn_reader<T: TunInterface + ?Sized + 'static>(
+    tun: Arc<T>,
+    mut sender: mpsc::Sender<Vec<u8>>,
+) {
+    std::thread::spawn(move || {
+        let mut buffer = vec![0u8; 2048];
+        loop {
+            match tun.recv(&mut buffer) {
+                Ok(len) => {
+                    if sender.try_send(buffer[..len].to_vec()).is_err() {
+                        trace!("Dropped outbound packet");
+                    }
+                }
+                Err(err) => {
+                    error!("TUN read failed: {:?}", err);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::LinuxTun;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use std::fs::{File, OpenOptions};
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+
+    const TUNSETIFF: libc::c_ulong = 0x4004_54ca;
+    const IFF_TUN: libc::c_short = 0x0001;
+    const IFF_NO_PI: libc::c_short = 0x1000;
+
+    #[repr(C)]
+    struct IfReq {
+        ifr_name: [u8; libc::IFNAMSIZ],
+        ifr_flags: libc::c_short,
+        _pad: [u8; 22],
+    }
+
+    /// A Linux TUN device opened via `/dev/net/tun`, without packet info headers.
+    pub struct LinuxTun {
+        file: File,
+        name: String,
+    }
+
+    impl LinuxTun {
+        /// Opens (or creates) the TUN interface with the given name.
+        /// Requires `CAP_NET_ADMIN`.
+        pub fn open(name: &str) -> io::Result<LinuxTun> {
+            if name.len() >= libc::IFNAMSIZ {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Interface name too long",
+                ));
+            }
+
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/net/tun")?;
+
+            let mut req = IfReq {
+                ifr_name: [0; libc::IFNAMSIZ],
+                ifr_flags: IFF_TUN | IFF_NO_PI,
+                _pad: [0; 22],
+            };
+            req.ifr_name[..name.len()].copy_from_slice(name.as_bytes());
+
+            // SAFETY: `req` is a properly sized `struct ifreq` that outlives the call.
+            if unsafe { libc::ioctl(file.as_raw_fd(), TUNSETIFF as _, &mut req) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let len = req.ifr_name.iter().position(|&x| x == 0).unwrap_or(0);
+            let name = String::from_utf8_lossy(&req.ifr_name[..len]).into_owned();
+
+            Ok(LinuxTun { file, name })
+        }
+    }
+
+    impl TunInterface for LinuxTun {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            (&self.file).read(buf)
+        }
+
+        fn send(&self, packet: &[u8]) -> io::Result<()> {
+            (&self.file).write_all(packet)
+        }
+    }
+}
+
+/// In-memory stand-in for a TUN interface, for testing.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryTun {
+    inbound: std::sync::Mutex<std::collections::VecDeque<Vec<u8>>>,
+    outbound: std::sync::Mutex<Vec<Vec<u8>>>,
+}
+
+#[cfg(test)]
+impl MemoryTun {
+    /// Queues a packet to be returned by [`TunInterface::recv`].
+    pub fn push_inbound(&self, packet: Vec<u8>) {
+        self.inbound.lock().unwrap().push_back(packet);
+    }
+
+    /// Removes and returns all packets written with [`TunInterface::send`].
+    pub fn take_outbound(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut *self.outbound.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+impl TunInterface for MemoryTun {
+    fn name(&self) -> &str {
+        "mem0"
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let packet = self
+            .inbound
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))?;
+        buf[..packet.len()].copy_from_slice(&packet);
+        Ok(packet.len())
+    }
+
+    fn send(&self, packet: &[u8]) -> io::Result<()> {
+        self.outbound.lock().unwrap().push(packet.to_vec());
+        Ok(())
+    }
+}
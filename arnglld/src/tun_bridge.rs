@@ -0,0 +1,224 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use crate::tun::TunInterface;
+use anyhow::format_err;
+use arngll::{Fcs, FrameInfo, FrameType};
+use hamaddr::{Eui48, HamAddr};
+use log::debug;
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+use std::sync::Arc;
+
+const IPV6_HEADER_LEN: usize = 40;
+
+/// Returns the (source, destination) addresses of an IPv6 packet.
+fn ipv6_addrs(packet: &[u8]) -> Option<(Ipv6Addr, Ipv6Addr)> {
+    if packet.len() < IPV6_HEADER_LEN || packet[0] >> 4 != 6 {
+        return None;
+    }
+    let mut src = [0u8; 16];
+    let mut dst = [0u8; 16];
+    src.copy_from_slice(&packet[8..24]);
+    dst.copy_from_slice(&packet[24..40]);
+    Some((src.into(), dst.into()))
+}
+
+/// Bridges IPv6 packets between a [`TunInterface`] and ARNGLL data frames.
+pub struct TunBridge<T: ?Sized> {
+    tun: Arc<T>,
+    local_addr: HamAddr,
+    neighbors: HashMap<Ipv6Addr, HamAddr>,
+}
+
+impl<T: TunInterface + ?Sized> TunBridge<T> {
+    pub fn new(tun: Arc<T>, local_addr: HamAddr) -> TunBridge<T> {
+        TunBridge {
+            tun,
+            local_addr,
+            neighbors: HashMap::new(),
+        }
+    }
+
+    /// Resolves an IPv6 address to the `HamAddr` of the station using it.
+    ///
+    /// Multicast addresses map onto ARNCE IPv6 multicast addresses, addresses
+    /// with callsign-derived interface identifiers map onto that callsign, and
+    /// anything else is looked up in the table of neighbors we have heard from.
+    pub fn resolve(&self, addr: &Ipv6Addr) -> Option<HamAddr> {
+        if addr.is_multicast() {
+            let o = addr.octets();
+            return HamAddr::try_from(Eui48::new([0xcc, 0xcc, o[12], o[13], o[14], o[15]])).ok();
+        }
+
+        HamAddr::try_from_ipv6(addr)
+            .ok()
+            .or_else(|| self.neighbors.get(addr).copied())
+    }
+
+    /// Encodes an outbound IP packet from the TUN interface into
+    /// a frame (including FCS) ready to be handed to the PHY.
+    pub fn packet_to_frame(&self, packet: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let (_, dst) = ipv6_addrs(packet).ok_or_else(|| format_err!("Not an IPv6 packet"))?;
+        let dst_addr = self
+            .resolve(&dst)
+            .ok_or_else(|| format_err!("Unable to resolve {}", dst))?;
+
+        let frame = FrameInfo {
+            frame_type: FrameType::Data,
+            dst_addr,
+            src_addr: self.local_addr,
+            ..FrameInfo::EMPTY
+        };
+
+        debug!("TUN -> PHY: {:?} ({} bytes)", frame, packet.len());
+
+        Ok(Fcs::X25.append(frame.to_vec(packet)))
+    }
+
+    /// Handles a frame (including FCS) received from the PHY, writing
+    /// the contained packet to the TUN interface if it is addressed to us.
+    ///
+    /// Returns `Ok(true)` if a packet was written.
+    pub fn handle_frame(&mut self, frame: &[u8]) -> anyhow::Result<bool> {
+        let (frame_info, payload) = FrameInfo::try_from_bytes(Fcs::X25.verify(frame)?)?;
+
+        if frame_info.frame_type != FrameType::Data
+            || !(frame_info.dst_addr == self.local_addr
+                || frame_info.dst_addr.is_multicast_or_broadcast())
+        {
+            return Ok(false);
+        }
+
+        let (src, _) = ipv6_addrs(payload).ok_or_else(|| format_err!("Not an IPv6 packet"))?;
+
+        if frame_info.src_addr.is_unicast() && !src.is_unspecified() {
+            self.neighbors.insert(src, frame_info.src_addr);
+        }
+
+        debug!("PHY -> TUN: {:?} ({} bytes)", frame_info, payload.len());
+
+        self.tun.send(payload)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tun::MemoryTun;
+
+    fn ipv6_packet(src: Ipv6Addr, dst: Ipv6Addr, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend((payload.len() as u16).to_be_bytes());
+        packet.extend([17, 64]);
+        packet.extend(src.octets());
+        packet.extend(dst.octets());
+        packet.extend(payload);
+        packet
+    }
+
+    #[test]
+    fn tun_bridge_resolve() {
+        let bridge = TunBridge::new(Arc::new(MemoryTun::default()), "N6DRC".parse().unwrap());
+        let kz2x: HamAddr = "KZ2X-1".parse().unwrap();
+
+        let ip = kz2x.to_ipv6_link_local().unwrap();
+        assert_eq!(bridge.resolve(&ip), Some(kz2x));
+
+        let ip = "ff02::1".parse().unwrap();
+        let addr = bridge.resolve(&ip).unwrap();
+        assert!(addr.is_multicast());
+        assert_eq!(addr.len(), 2);
+
+        let ip = "2001:db8::1".parse().unwrap();
+        assert_eq!(bridge.resolve(&ip), None);
+    }
+
+    #[test]
+    fn tun_bridge_round_trip() {
+        let kz2x: HamAddr = "KZ2X-1".parse().unwrap();
+        let n6drc: HamAddr = "N6DRC".parse().unwrap();
+
+        let tun_a = Arc::new(MemoryTun::default());
+        let tun_b = Arc::new(MemoryTun::default());
+        let bridge_a = TunBridge::new(tun_a.clone(), kz2x);
+        let mut bridge_b = TunBridge::new(tun_b.clone(), n6drc);
+
+        let packet = ipv6_packet(
+            kz2x.to_ipv6_link_local().unwrap(),
+            n6drc.to_ipv6_link_local().unwrap(),
+            b"hello",
+        );
+
+        tun_a.push_inbound(packet.clone());
+        let mut buffer = [0u8; 2048];
+        let len = tun_a.recv(&mut buffer).unwrap();
+
+        let frame = bridge_a.packet_to_frame(&buffer[..len]).unwrap();
+        assert!(Fcs::X25.verify(&frame).is_ok());
+
+        assert!(bridge_b.handle_frame(&frame).unwrap());
+        assert_eq!(tun_b.take_outbound(), vec![packet]);
+    }
+
+    #[test]
+    fn tun_bridge_learns_neighbors() {
+        let n6drc: HamAddr = "N6DRC".parse().unwrap();
+        let remote: HamAddr = "KZ2X-1".parse().unwrap();
+        let remote_ip: Ipv6Addr = "2001:db8::1234".parse().unwrap();
+
+        let tun = Arc::new(MemoryTun::default());
+        let mut bridge = TunBridge::new(tun.clone(), n6drc);
+
+        let frame = FrameInfo {
+            frame_type: FrameType::Data,
+            dst_addr: n6drc,
+            src_addr: remote,
+            ..FrameInfo::EMPTY
+        };
+        let packet = ipv6_packet(remote_ip, "2001:db8::1".parse().unwrap(), b"x");
+        let bytes = Fcs::X25.append(frame.to_vec(&packet));
+
+        assert_eq!(bridge.resolve(&remote_ip), None);
+        assert!(bridge.handle_frame(&bytes).unwrap());
+        assert_eq!(bridge.resolve(&remote_ip), Some(remote));
+    }
+
+    #[test]
+    fn tun_bridge_ignores_other_stations() {
+        let tun = Arc::new(MemoryTun::default());
+        let mut bridge = TunBridge::new(tun.clone(), "N6DRC".parse().unwrap());
+
+        let frame = FrameInfo {
+            frame_type: FrameType::Data,
+            dst_addr: "AC2OI".parse().unwrap(),
+            src_addr: "KZ2X-1".parse().unwrap(),
+            ..FrameInfo::EMPTY
+        };
+        let packet = ipv6_packet(Ipv6Addr::LOCALHOST, Ipv6Addr::LOCALHOST, b"x");
+        let bytes = Fcs::X25.append(frame.to_vec(&packet));
+
+        assert!(!bridge.handle_frame(&bytes).unwrap());
+        assert!(tun.take_outbound().is_empty());
+    }
+}
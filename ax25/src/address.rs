@@ -0,0 +1,305 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use anyhow::{bail, format_err, Error};
+use hamaddr::HamAddr;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+
+/// Length of an encoded AX.25 address.
+pub const AX25_ADDR_LEN: usize = 7;
+
+/// Maximum number of characters in an AX.25 callsign.
+pub const AX25_MAX_CALL_LEN: usize = 6;
+
+/// Maximum AX.25 SSID.
+pub const AX25_MAX_SSID: u8 = 15;
+
+/// An AX.25 address: a callsign of up to six uppercase
+/// letters or digits, and an SSID from 0 to 15.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Ax25Address {
+    call: [u8; AX25_MAX_CALL_LEN],
+    ssid: u8,
+}
+
+impl Ax25Address {
+    /// Creates a new address. Lowercase letters in `call` are converted to uppercase.
+    pub fn new(call: &str, ssid: u8) -> Result<Ax25Address, Error> {
+        if call.is_empty() {
+            bail!("Empty callsign");
+        }
+        if call.len() > AX25_MAX_CALL_LEN {
+            bail!(
+                "Callsign {:?} is longer than {} characters",
+                call,
+                AX25_MAX_CALL_LEN
+            );
+        }
+        if !call.bytes().all(|x| x.is_ascii_alphanumeric()) {
+            bail!("Callsign {:?} contains invalid characters", call);
+        }
+        if ssid > AX25_MAX_SSID {
+            bail!("SSID {} is out of range", ssid);
+        }
+
+        let mut ret = Ax25Address {
+            call: [b' '; AX25_MAX_CALL_LEN],
+            ssid,
+        };
+        for (dst, src) in ret.call.iter_mut().zip(call.bytes()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        Ok(ret)
+    }
+
+    /// Returns the callsign, without the SSID.
+    pub fn callsign(&self) -> &str {
+        let len = self
+            .call
+            .iter()
+            .position(|&x| x == b' ')
+            .unwrap_or(self.call.len());
+        std::str::from_utf8(&self.call[..len]).unwrap()
+    }
+
+    pub fn ssid(&self) -> u8 {
+        self.ssid
+    }
+
+    /// Returns a copy of this address with a different SSID.
+    pub fn with_ssid(&self, ssid: u8) -> Result<Ax25Address, Error> {
+        Ax25Address::new(self.callsign(), ssid)
+    }
+
+    /// Encodes this address. `flag` is the C-bit for the destination and
+    /// source addresses and the H-bit for digipeater addresses. `last`
+    /// sets the extension bit that marks the end of the address field.
+    pub fn encode(&self, flag: bool, last: bool) -> [u8; AX25_ADDR_LEN] {
+        let mut ret = [0u8; AX25_ADDR_LEN];
+        for (dst, src) in ret.iter_mut().zip(self.call) {
+            *dst = src << 1;
+        }
+        ret[6] = (u8::from(flag) << 7) | 0x60 | (self.ssid << 1) | u8::from(last);
+        ret
+    }
+
+    /// Decodes an encoded address, returning the address,
+    /// the C/H-bit, and the extension bit.
+    pub fn decode(bytes: &[u8]) -> Result<(Ax25Address, bool, bool), Error> {
+        if bytes.len() < AX25_ADDR_LEN {
+            bail!("Address too short");
+        }
+
+        let mut call = [b' '; AX25_MAX_CALL_LEN];
+        let mut len = 0;
+        for (i, &byte) in bytes[..6].iter().enumerate() {
+            if byte & 1 != 0 {
+                bail!("Unexpected end of address field");
+            }
+            let c = byte >> 1;
+            match c {
+                b' ' => {}
+                b'A'..=b'Z' | b'0'..=b'9' if len == i => {
+                    call[i] = c;
+                    len += 1;
+                }
+                _ => bail!("Invalid callsign character 0x{:02X}", c),
+            }
+        }
+        if len == 0 {
+            bail!("Empty callsign");
+        }
+
+        let ssid_byte = bytes[6];
+        let addr = Ax25Address {
+            call,
+            ssid: (ssid_byte >> 1) & 0x0F,
+        };
+
+        Ok((addr, ssid_byte & 0x80 != 0, ssid_byte & 1 != 0))
+    }
+}
+
+impl FromStr for Ax25Address {
+    type Err = Error;
+
+    /// Parses an address in the form `CALL` or `CALL-SSID`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((call, ssid)) => {
+                let ssid = ssid
+                    .parse::<u8>()
+                    .map_err(|_| format_err!("Invalid SSID {:?}", ssid))?;
+                Ax25Address::new(call, ssid)
+            }
+            None => Ax25Address::new(s, 0),
+        }
+    }
+}
+
+impl Display for Ax25Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.ssid == 0 {
+            write!(f, "{}", self.callsign())
+        } else {
+            write!(f, "{}-{}", self.callsign(), self.ssid)
+        }
+    }
+}
+
+impl Debug for Ax25Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Converts a callsign `HamAddr` like `KZ2X-1` to an AX.25 address.
+///
+/// Fails for addresses that aren't callsigns, callsigns longer than six
+/// characters, and suffixes that aren't an SSID from 0 to 15.
+impl TryFrom<HamAddr> for Ax25Address {
+    type Error = Error;
+
+    fn try_from(value: HamAddr) -> Result<Self, Self::Error> {
+        if !value.is_callsign() {
+            bail!("{} is not a callsign", value);
+        }
+
+        let string = value.to_string();
+        match string.split_once('-') {
+            Some((call, ssid)) => {
+                if ssid.is_empty() || !ssid.bytes().all(|x| x.is_ascii_digit()) {
+                    bail!("{} does not have a numeric SSID", value);
+                }
+                let ssid = ssid
+                    .parse::<u8>()
+                    .map_err(|_| format_err!("SSID of {} is out of range", value))?;
+                Ax25Address::new(call, ssid)
+            }
+            None => Ax25Address::new(&string, 0),
+        }
+    }
+}
+
+/// Converts an AX.25 address to a callsign `HamAddr`, leaving off SSID 0.
+impl TryFrom<Ax25Address> for HamAddr {
+    type Error = Error;
+
+    fn try_from(value: Ax25Address) -> Result<Self, Self::Error> {
+        HamAddr::try_from_callsign(&value.to_string())
+    }
+}
+
+/// A digipeater address and its has-been-repeated (H) bit.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Ax25Digipeater {
+    pub addr: Ax25Address,
+    pub repeated: bool,
+}
+
+impl Ax25Digipeater {
+    /// Creates a digipeater entry that has not yet been repeated.
+    pub fn new(addr: Ax25Address) -> Ax25Digipeater {
+        Ax25Digipeater {
+            addr,
+            repeated: false,
+        }
+    }
+}
+
+impl Display for Ax25Digipeater {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.addr)?;
+        if self.repeated {
+            write!(f, "*")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ax25_address_parse() {
+        let addr: Ax25Address = "kz2x-15".parse().unwrap();
+        assert_eq!(addr.callsign(), "KZ2X");
+        assert_eq!(addr.ssid(), 15);
+        assert_eq!(addr.to_string(), "KZ2X-15");
+
+        let addr: Ax25Address = "APRS".parse().unwrap();
+        assert_eq!(addr.ssid(), 0);
+        assert_eq!(addr.to_string(), "APRS");
+
+        assert!("KZ2X-16".parse::<Ax25Address>().is_err());
+        assert!("KZ2X-A".parse::<Ax25Address>().is_err());
+        assert!("VI2BMARC".parse::<Ax25Address>().is_err());
+        assert!("KJ6QOH/P".parse::<Ax25Address>().is_err());
+        assert!("".parse::<Ax25Address>().is_err());
+    }
+
+    #[test]
+    fn ax25_address_encode_decode() {
+        let addr: Ax25Address = "WA8LMF".parse().unwrap();
+        assert_eq!(
+            addr.encode(false, false),
+            [0xae, 0x82, 0x70, 0x98, 0x9a, 0x8c, 0x60]
+        );
+
+        let addr: Ax25Address = "WIDE1-1".parse().unwrap();
+        let bytes = addr.encode(true, true);
+        assert_eq!(bytes, [0xae, 0x92, 0x88, 0x8a, 0x62, 0x40, 0xe3]);
+        assert_eq!(Ax25Address::decode(&bytes).unwrap(), (addr, true, true));
+
+        // Embedded space
+        assert!(Ax25Address::decode(&[0x82, 0x40, 0x82, 0x40, 0x40, 0x40, 0x60]).is_err());
+
+        // All spaces
+        assert!(Ax25Address::decode(&[0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x60]).is_err());
+    }
+
+    #[test]
+    fn ax25_address_ham_addr() {
+        for call in ["KZ2X-1", "N6DRC", "KJ6QOH-15"] {
+            let ham: HamAddr = call.parse().unwrap();
+            let ax25 = Ax25Address::try_from(ham).unwrap();
+            assert_eq!(ax25, call.parse().unwrap());
+            assert_eq!(HamAddr::try_from(ax25).unwrap(), ham);
+        }
+
+        let ham: HamAddr = "KZ2X-1".parse().unwrap();
+        assert_eq!(
+            Ax25Address::try_from(ham).unwrap().encode(false, false),
+            [0x96, 0xb4, 0x64, 0xb0, 0x40, 0x40, 0x62]
+        );
+
+        for bad in [
+            "KZ2X-16", "KZ2X-A", "KZ2X-", "KZ2X/P", "KJ6QOHX", "KZ2X-1-1", "~FFFF",
+        ] {
+            let ham: HamAddr = bad.parse().unwrap();
+            assert!(Ax25Address::try_from(ham).is_err(), "{}", bad);
+        }
+        assert!(Ax25Address::try_from(HamAddr::BROADCAST).is_err());
+        assert!(Ax25Address::try_from(HamAddr::EMPTY).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+/// Maximum length of the addressee of a message.
+pub const APRS_MAX_ADDRESSEE_LEN: usize = 9;
+
+/// Maximum length of the text of a message.
+pub const APRS_MAX_MESSAGE_LEN: usize = 67;
+
+/// Maximum length of a message identifier.
+pub const APRS_MAX_MESSAGE_ID_LEN: usize = 5;
+
+/// The body of an APRS message.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum AprsMessageKind {
+    /// A message, with an identifier if the sender wants an ack.
+    Message { text: String, id: Option<String> },
+
+    /// Acknowledges the message with the given identifier.
+    Ack(String),
+
+    /// Rejects the message with the given identifier.
+    Rej(String),
+}
+
+/// An APRS message (`:`), including acks and rejects.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AprsMessage {
+    pub addressee: String,
+    pub kind: AprsMessageKind,
+}
+
+impl AprsMessage {
+    pub fn new(addressee: &str, text: &str, id: Option<&str>) -> Self {
+        AprsMessage {
+            addressee: addressee.to_string(),
+            kind: AprsMessageKind::Message {
+                text: text.to_string(),
+                id: id.map(str::to_string),
+            },
+        }
+    }
+
+    /// Returns the ack to send to `sender` for this message,
+    /// if it asked for one.
+    pub fn ack(&self, sender: &str) -> Option<AprsMessage> {
+        match &self.kind {
+            AprsMessageKind::Message { id: Some(id), .. } => Some(AprsMessage {
+                addressee: sender.to_string(),
+                kind: AprsMessageKind::Ack(id.clone()),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the reject to send to `sender` for this message,
+    /// if it has an identifier.
+    pub fn rej(&self, sender: &str) -> Option<AprsMessage> {
+        match &self.kind {
+            AprsMessageKind::Message { id: Some(id), .. } => Some(AprsMessage {
+                addressee: sender.to_string(),
+                kind: AprsMessageKind::Rej(id.clone()),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn decode(info: &[u8]) -> Result<Self, Error> {
+        if info.len() < 11 || info[0] != b':' || info[10] != b':' {
+            bail!("Bad message header");
+        }
+
+        let addressee = text(&info[1..10]).trim_end().to_string();
+        let body = text(&info[11..]);
+        let body = body.trim_end_matches(['\r', '\n']);
+
+        let is_id = |x: &str| {
+            !x.is_empty()
+                && x.len() <= APRS_MAX_MESSAGE_ID_LEN
+                && x.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'}')
+        };
+
+        let kind = match (body.get(..3), body.get(3..)) {
+            (Some("ack"), Some(id)) if is_id(id) => AprsMessageKind::Ack(id.to_string()),
+            (Some("rej"), Some(id)) if is_id(id) => AprsMessageKind::Rej(id.to_string()),
+            _ => match body.rsplit_once('{') {
+                Some((text, id)) if is_id(id) => AprsMessageKind::Message {
+                    text: text.to_string(),
+                    id: Some(id.to_string()),
+                },
+                _ => AprsMessageKind::Message {
+                    text: body.to_string(),
+                    id: None,
+                },
+            },
+        };
+
+        Ok(AprsMessage { addressee, kind })
+    }
+
+    /// Returns the encoded info field.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        if self.addressee.is_empty() || self.addressee.len() > APRS_MAX_ADDRESSEE_LEN {
+            bail!("Bad addressee {:?}", self.addressee);
+        }
+
+        let body = match &self.kind {
+            AprsMessageKind::Message { text, id } => {
+                if text.len() > APRS_MAX_MESSAGE_LEN {
+                    bail!("Message longer than {} characters", APRS_MAX_MESSAGE_LEN);
+                }
+                if text.contains(['{', '|', '~']) {
+                    bail!("Message contains a reserved character");
+                }
+                match id {
+                    Some(id) => format!("{}{{{}", text, check_id(id)?),
+                    None => text.clone(),
+                }
+            }
+            AprsMessageKind::Ack(id) => format!("ack{}", check_id(id)?),
+            AprsMessageKind::Rej(id) => format!("rej{}", check_id(id)?),
+        };
+
+        Ok(format!(":{:<9}:{}", self.addressee, body).into_bytes())
+    }
+}
+
+fn check_id(id: &str) -> Result<&str, Error> {
+    if id.is_empty() || id.len() > APRS_MAX_MESSAGE_ID_LEN {
+        bail!("Bad message identifier {:?}", id);
+    }
+    Ok(id)
+}
+
+/// A status report (`>`).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AprsStatus {
+    /// Only [`AprsTimestamp::DhmZulu`] timestamps are allowed.
+    pub timestamp: Option<AprsTimestamp>,
+    pub text: String,
+}
+
+impl AprsStatus {
+    pub fn decode(info: &[u8]) -> Result<Self, Error> {
+        let data = info
+            .strip_prefix(b">")
+            .ok_or_else(|| format_err!("Not a status report"))?;
+
+        let (timestamp, data) = match AprsTimestamp::decode(data) {
+            Ok((ts @ AprsTimestamp::DhmZulu { .. }, rest)) => (Some(ts), rest),
+            _ => (None, data),
+        };
+
+        Ok(AprsStatus {
+            timestamp,
+            text: text(data).trim_end_matches(['\r', '\n']).to_string(),
+        })
+    }
+
+    /// Returns the encoded info field.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut ret = vec![b'>'];
+        if let Some(ts) = self.timestamp {
+            ret.extend(ts.encode().bytes());
+        }
+        ret.extend(self.text.bytes());
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aprs_message() {
+        let message = AprsMessage::decode(b":WU2Z     :Testing{003").unwrap();
+        assert_eq!(message, AprsMessage::new("WU2Z", "Testing", Some("003")));
+        assert_eq!(message.encode().unwrap(), b":WU2Z     :Testing{003");
+
+        let ack = message.ack("KZ2X-1").unwrap();
+        assert_eq!(ack.encode().unwrap(), b":KZ2X-1   :ack003");
+        assert_eq!(AprsMessage::decode(b":KZ2X-1   :ack003").unwrap(), ack);
+
+        let rej = message.rej("KZ2X-1").unwrap();
+        assert_eq!(rej.encode().unwrap(), b":KZ2X-1   :rej003");
+        assert_eq!(AprsMessage::decode(b":KZ2X-1   :rej003\r").unwrap(), rej);
+
+        let message = AprsMessage::decode(b":BLN1     :Snow expected").unwrap();
+        assert_eq!(message, AprsMessage::new("BLN1", "Snow expected", None));
+        assert_eq!(message.ack("KZ2X"), None);
+    }
+
+    #[test]
+    fn aprs_message_errors() {
+        assert!(AprsMessage::decode(b":WU2Z:Testing").is_err());
+        assert!(AprsMessage::new("TOOLONGCALL", "Hi", None)
+            .encode()
+            .is_err());
+        assert!(AprsMessage::new("WU2Z", "{", None).encode().is_err());
+        assert!(AprsMessage::new("WU2Z", "Hi", Some("123456"))
+            .encode()
+            .is_err());
+        assert!(AprsMessage::new("WU2Z", &"x".repeat(68), None)
+            .encode()
+            .is_err());
+    }
+
+    #[test]
+    fn aprs_status() {
+        let status = AprsStatus::decode(b">Net Control Center").unwrap();
+        assert_eq!(status.timestamp, None);
+        assert_eq!(status.encode(), b">Net Control Center");
+
+        let status = AprsStatus::decode(b">092345zNet Control Center").unwrap();
+        assert!(status.timestamp.is_some());
+        assert_eq!(status.text, "Net Control Center");
+        assert_eq!(status.encode(), b">092345zNet Control Center");
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+/// Mic-E message codes, carried in the first three
+/// characters of the destination address.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum MicEStatus {
+    OffDuty,
+    EnRoute,
+    InService,
+    Returning,
+    Committed,
+    Special,
+    Priority,
+
+    /// Custom message 0 to 6.
+    Custom(u8),
+    Emergency,
+}
+
+impl MicEStatus {
+    const STANDARD: [MicEStatus; 7] = [
+        Self::OffDuty,
+        Self::EnRoute,
+        Self::InService,
+        Self::Returning,
+        Self::Committed,
+        Self::Special,
+        Self::Priority,
+    ];
+
+    /// Returns the three message bits and whether they are custom.
+    fn bits(&self) -> (u8, bool) {
+        match *self {
+            Self::Emergency => (0, false),
+            Self::Custom(x) => (7 - x.min(6), true),
+            x => (
+                7 - Self::STANDARD.iter().position(|&y| y == x).unwrap() as u8,
+                false,
+            ),
+        }
+    }
+
+    fn from_bits(bits: u8, custom: bool) -> MicEStatus {
+        match (bits, custom) {
+            (0, _) => Self::Emergency,
+            (x, true) => Self::Custom(7 - x),
+            (x, false) => Self::STANDARD[7 - x as usize],
+        }
+    }
+}
+
+/// Mic-E altitudes are in metres; reports use feet.
+const FEET_PER_METRE: f64 = 3.28084;
+
+/// Decodes a Mic-E position report.
+pub(crate) fn decode(dst: &Ax25Address, info: &[u8]) -> Result<AprsPositionReport, Error> {
+    let dst = dst.callsign().as_bytes();
+    if dst.len() != 6 {
+        bail!("Mic-E destination must be six characters");
+    }
+    if info.len() < 9 {
+        bail!("Truncated Mic-E packet");
+    }
+
+    // Each destination character is a latitude digit (or a space
+    // for ambiguity), a message bit, and a flag.
+    let mut digits = [0u8; 6];
+    let mut ambiguity = 0;
+    let mut message_bits = 0;
+    let mut custom = false;
+    let mut flags = [false; 6];
+
+    for (i, &c) in dst.iter().enumerate() {
+        let (digit, bit, flag) = match c {
+            b'0'..=b'9' => (Some(c - b'0'), false, false),
+            b'A'..=b'J' if i < 3 => {
+                custom = true;
+                (Some(c - b'A'), true, false)
+            }
+            b'K' if i < 3 => {
+                custom = true;
+                (None, true, false)
+            }
+            b'L' => (None, false, false),
+            b'P'..=b'Y' => (Some(c - b'P'), true, true),
+            b'Z' => (None, true, true),
+            _ => bail!("Bad Mic-E destination character {:?}", c as char),
+        };
+
+        match digit {
+            Some(x) if ambiguity == 0 => digits[i] = x,
+            None if i >= 2 => ambiguity += 1,
+            _ => bail!("Bad Mic-E latitude"),
+        }
+        if i < 3 {
+            message_bits = (message_bits << 1) | u8::from(bit);
+        }
+        flags[i] = flag;
+    }
+
+    let mut latitude = (digits[0] * 10 + digits[1]) as f64
+        + ((digits[2] * 10 + digits[3]) as f64 + (digits[4] * 10 + digits[5]) as f64 / 100.0)
+            / 60.0;
+    if !flags[3] {
+        latitude = -latitude;
+    }
+
+    let b = |i: usize| info[i].wrapping_sub(28) as u32;
+
+    let mut lon_deg = b(1);
+    if flags[4] {
+        lon_deg += 100;
+    }
+    if (180..=189).contains(&lon_deg) {
+        lon_deg -= 80;
+    } else if (190..=199).contains(&lon_deg) {
+        lon_deg -= 190;
+    }
+    let mut lon_min = b(2);
+    if lon_min >= 60 {
+        lon_min -= 60;
+    }
+    let mut longitude = lon_deg as f64 + (lon_min as f64 + b(3) as f64 / 100.0) / 60.0;
+    if flags[5] {
+        longitude = -longitude;
+    }
+
+    let mut speed = b(4) * 10 + b(5) / 10;
+    if speed >= 800 {
+        speed -= 800;
+    }
+    let mut course = (b(5) % 10) * 100 + b(6);
+    if course >= 400 {
+        course -= 400;
+    }
+
+    let position = AprsPosition {
+        latitude,
+        longitude,
+        symbol_table: info[8] as char,
+        symbol_code: info[7] as char,
+        ambiguity,
+    };
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        bail!("Bad Mic-E position");
+    }
+
+    let mut ret = AprsPositionReport::new(position);
+    ret.encoding = PositionEncoding::MicE;
+    ret.mic_e_status = Some(MicEStatus::from_bits(message_bits, custom));
+    ret.speed = Some(speed as f64);
+    ret.course = Some(course as u16);
+
+    // Altitude may follow a one character type indicator.
+    let mut rest = &info[9..];
+    for start in 0..=1 {
+        if rest.get(start + 3) == Some(&b'}') {
+            if let Some(x) = base91_decode(&rest[start..start + 3]) {
+                let metres = x as f64 - 10000.0;
+                ret.altitude = Some((metres * FEET_PER_METRE).round() as i32);
+                rest = &rest[start + 4..];
+                break;
+            }
+        }
+    }
+
+    ret.comment = text(rest);
+    Ok(ret)
+}
+
+/// Returns the destination address for a Mic-E report.
+pub(crate) fn encode_destination(report: &AprsPositionReport) -> Result<Ax25Address, Error> {
+    let position = &report.position;
+    if !(-90.0..=90.0).contains(&position.latitude) {
+        bail!("Latitude {} out of range", position.latitude);
+    }
+    if !(-180.0..=180.0).contains(&position.longitude) {
+        bail!("Longitude {} out of range", position.longitude);
+    }
+
+    let total = (position.latitude.abs() * 6000.0).round() as u32;
+    let digits = [
+        total / 6000 / 10,
+        total / 6000 % 10,
+        total / 100 % 60 / 10,
+        total / 100 % 60 % 10,
+        total % 100 / 10,
+        total % 100 % 10,
+    ]
+    .map(|x| x as u8);
+
+    let (bits, custom) = report.mic_e_status.unwrap_or(MicEStatus::OffDuty).bits();
+    let lon_deg = position.longitude.abs().floor() as u32;
+
+    let mut ret = String::new();
+    for (i, &digit) in digits.iter().enumerate() {
+        let set = match i {
+            0..=2 => bits & (4 >> i) != 0,
+            3 => position.latitude >= 0.0,
+            4 => !(10..=99).contains(&lon_deg),
+            _ => position.longitude < 0.0,
+        };
+        ret.push(match (set, custom && i < 3) {
+            (false, _) => (b'0' + digit) as char,
+            (true, true) => (b'A' + digit) as char,
+            (true, false) => (b'P' + digit) as char,
+        });
+    }
+
+    Ax25Address::new(&ret, 0)
+}
+
+/// Returns the info field for a Mic-E report.
+pub(crate) fn encode(report: &AprsPositionReport) -> Result<Vec<u8>, Error> {
+    let position = &report.position;
+    let total = (position.longitude.abs() * 6000.0).round() as u32;
+    let (deg, min, hundredths) = (total / 6000, total / 100 % 60, total % 100);
+
+    let d = match deg {
+        0..=9 => deg + 90,
+        10..=99 => deg,
+        100..=109 => deg - 20,
+        _ => deg - 100,
+    };
+    let m = if min < 10 { min + 60 } else { min };
+
+    let speed = report.speed.unwrap_or(0.0).round() as u32 % 800;
+    let course = report.course.unwrap_or(0) as u32 % 360;
+
+    let mut ret = vec![
+        b'`',
+        (d + 28) as u8,
+        (m + 28) as u8,
+        (hundredths + 28) as u8,
+        (speed / 10 + 28) as u8,
+        ((speed % 10) * 10 + course / 100 + 28) as u8,
+        (course % 100 + 28) as u8,
+        position.symbol_code as u8,
+        position.symbol_table as u8,
+    ];
+
+    if let Some(altitude) = report.altitude {
+        let metres = (altitude as f64 / FEET_PER_METRE).round() as i32 + 10000;
+        if !(0..91 * 91 * 91).contains(&metres) {
+            bail!("Altitude {} out of range", altitude);
+        }
+        ret.extend(base91_encode(metres as u32, 3));
+        ret.push(b'}');
+    }
+
+    ret.extend(report.comment.bytes());
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_packet(dst: &str, info: &[u8]) -> AprsPositionReport {
+        match AprsPacket::decode(&dst.parse().unwrap(), info).unwrap() {
+            AprsPacket::Position(x) => x,
+            x => panic!("Unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn aprs_mic_e_decode() {
+        let report = decode_packet("S32U6T", b"`(_fn\"Oj/");
+        assert_eq!(report.encoding, PositionEncoding::MicE);
+        assert!((report.position.latitude - (33.0 + 25.64 / 60.0)).abs() < 1e-6);
+        assert!((report.position.longitude + (12.0 + 7.74 / 60.0)).abs() < 1e-6);
+        assert_eq!(report.speed, Some(20.0));
+        assert_eq!(report.course, Some(251));
+        assert_eq!(report.position.symbol_table, '/');
+        assert_eq!(report.position.symbol_code, 'j');
+        assert_eq!(report.mic_e_status, Some(MicEStatus::Returning));
+    }
+
+    #[test]
+    fn aprs_mic_e_round_trip() {
+        let cases = [
+            (33.42733, -112.129, MicEStatus::EnRoute, Some(1000)),
+            (-5.01, 8.5, MicEStatus::Custom(3), None),
+            (51.5, 105.75, MicEStatus::Emergency, Some(-100)),
+            (0.0, -179.99, MicEStatus::OffDuty, Some(0)),
+        ];
+
+        for (latitude, longitude, status, altitude) in cases {
+            let mut report =
+                AprsPositionReport::new(AprsPosition::new(latitude, longitude, '/', '>'));
+            report.encoding = PositionEncoding::MicE;
+            report.mic_e_status = Some(status);
+            report.speed = Some(123.0);
+            report.course = Some(359);
+            report.altitude = altitude;
+            report.comment = "Hello".to_string();
+
+            let packet = AprsPacket::Position(report);
+            let frame = packet.to_frame("KZ2X".parse().unwrap(), &[]).unwrap();
+            let decoded = decode_packet(&frame.dst.to_string(), &frame.info);
+
+            assert!(
+                (decoded.position.latitude - latitude).abs() < 1e-3,
+                "{:?}",
+                decoded
+            );
+            assert!(
+                (decoded.position.longitude - longitude).abs() < 1e-3,
+                "{:?}",
+                decoded
+            );
+            assert_eq!(decoded.mic_e_status, Some(status));
+            assert_eq!(decoded.speed, Some(123.0));
+            assert_eq!(decoded.course, Some(359));
+            match (decoded.altitude, altitude) {
+                (Some(a), Some(b)) => assert!((a - b).abs() <= 2),
+                (a, b) => assert_eq!(a, b),
+            }
+            assert_eq!(decoded.comment, "Hello");
+        }
+    }
+
+    #[test]
+    fn aprs_mic_e_errors() {
+        let dst = "S32U6T".parse().unwrap();
+        assert!(AprsPacket::decode(&dst, b"`(_fn\"O").is_err());
+        assert!(AprsPacket::decode(&"S32U6".parse().unwrap(), b"`(_fn\"Oj/").is_err());
+        assert!(AprsPacket::decode(&"S3LU6T".parse().unwrap(), b"`(_fn\"Oj/").is_err());
+    }
+}
@@ -0,0 +1,341 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! APRS packet decoding and encoding.
+
+mod message;
+mod mic_e;
+mod object;
+mod position;
+mod telemetry;
+mod weather;
+
+pub use message::*;
+pub use mic_e::*;
+pub use object::*;
+pub use position::*;
+pub use telemetry::*;
+pub use weather::*;
+
+use crate::*;
+use anyhow::{bail, format_err, Error};
+
+/// Destination used for APRS frames that do not encode data in the
+/// destination address. `APZ` is the experimental software range.
+pub const APRS_DEFAULT_DEST: &str = "APZARN";
+
+/// A decoded APRS packet.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AprsPacket {
+    Position(AprsPositionReport),
+    Status(AprsStatus),
+    Message(AprsMessage),
+    Object(AprsObject),
+    Item(AprsItem),
+    Weather(AprsWeatherReport),
+    Telemetry(AprsTelemetry),
+}
+
+impl AprsPacket {
+    /// Decodes the info field of an APRS frame. The destination
+    /// address is needed to decode Mic-E positions.
+    pub fn decode(dst: &Ax25Address, info: &[u8]) -> Result<AprsPacket, Error> {
+        let dti = *info
+            .first()
+            .ok_or_else(|| format_err!("Empty APRS packet"))?;
+
+        Ok(match dti {
+            b'!' | b'=' | b'/' | b'@' => AprsPacket::Position(AprsPositionReport::decode(info)?),
+            b'`' | b'\'' | 0x1C | 0x1D => AprsPacket::Position(mic_e::decode(dst, info)?),
+            b'>' => AprsPacket::Status(AprsStatus::decode(info)?),
+            b':' => AprsPacket::Message(AprsMessage::decode(info)?),
+            b';' => AprsPacket::Object(AprsObject::decode(info)?),
+            b')' => AprsPacket::Item(AprsItem::decode(info)?),
+            b'_' => AprsPacket::Weather(AprsWeatherReport::decode(info)?),
+            b'T' => AprsPacket::Telemetry(AprsTelemetry::decode(info)?),
+            x => bail!("Unsupported APRS data type {:?}", x as char),
+        })
+    }
+
+    /// Decodes an APRS packet from a UI frame.
+    pub fn try_from_frame(frame: &Ax25Frame) -> Result<AprsPacket, Error> {
+        if frame.control != Control::UI || frame.pid != Some(PID_NO_L3) {
+            bail!("Not an APRS frame");
+        }
+        Self::decode(&frame.dst, &frame.info)
+    }
+
+    /// Returns the encoded info field.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            AprsPacket::Position(x) if x.encoding == PositionEncoding::MicE => mic_e::encode(x),
+            AprsPacket::Position(x) => x.encode(),
+            AprsPacket::Status(x) => Ok(x.encode()),
+            AprsPacket::Message(x) => x.encode(),
+            AprsPacket::Object(x) => x.encode(),
+            AprsPacket::Item(x) => x.encode(),
+            AprsPacket::Weather(x) => x.encode(),
+            AprsPacket::Telemetry(x) => Ok(x.encode()),
+        }
+    }
+
+    /// Returns the destination address to send this packet to. This is
+    /// [`APRS_DEFAULT_DEST`] except for Mic-E, which encodes the latitude
+    /// in the destination.
+    pub fn destination(&self) -> Result<Ax25Address, Error> {
+        match self {
+            AprsPacket::Position(x) if x.encoding == PositionEncoding::MicE => {
+                mic_e::encode_destination(x)
+            }
+            _ => APRS_DEFAULT_DEST.parse(),
+        }
+    }
+
+    /// Builds a UI frame carrying this packet.
+    pub fn to_frame(
+        &self,
+        src: Ax25Address,
+        digipeaters: &[Ax25Address],
+    ) -> Result<Ax25Frame, Error> {
+        Ok(Ax25Frame::ui(
+            self.destination()?,
+            src,
+            digipeaters,
+            PID_NO_L3,
+            &self.encode()?,
+        ))
+    }
+}
+
+/// An APRS timestamp.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AprsTimestamp {
+    /// Day of month, hour and minute in UTC (`DDHHMMz`).
+    DhmZulu { day: u8, hour: u8, minute: u8 },
+
+    /// Day of month, hour and minute in local time (`DDHHMM/`).
+    DhmLocal { day: u8, hour: u8, minute: u8 },
+
+    /// Hour, minute and second in UTC (`HHMMSSh`).
+    Hms { hour: u8, minute: u8, second: u8 },
+
+    /// Month, day, hour and minute in UTC (`MMDDHHMM`). Only
+    /// used by positionless weather reports.
+    Mdhm {
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+    },
+}
+
+impl AprsTimestamp {
+    /// Decodes a seven character timestamp from the start
+    /// of `bytes`, returning the timestamp and the rest.
+    pub fn decode(bytes: &[u8]) -> Result<(AprsTimestamp, &[u8]), Error> {
+        if bytes.len() < 7 {
+            bail!("Truncated timestamp");
+        }
+        let a = parse_num(&bytes[0..2]).ok_or_else(|| format_err!("Bad timestamp"))? as u8;
+        let b = parse_num(&bytes[2..4]).ok_or_else(|| format_err!("Bad timestamp"))? as u8;
+        let c = parse_num(&bytes[4..6]).ok_or_else(|| format_err!("Bad timestamp"))? as u8;
+
+        let ret = match bytes[6] {
+            b'z' => AprsTimestamp::DhmZulu {
+                day: a,
+                hour: b,
+                minute: c,
+            },
+            b'/' => AprsTimestamp::DhmLocal {
+                day: a,
+                hour: b,
+                minute: c,
+            },
+            b'h' => AprsTimestamp::Hms {
+                hour: a,
+                minute: b,
+                second: c,
+            },
+            x => bail!("Unknown timestamp format {:?}", x as char),
+        };
+
+        Ok((ret, &bytes[7..]))
+    }
+
+    /// Decodes an eight digit `MMDDHHMM` timestamp from
+    /// the start of `bytes`, returning the timestamp and the rest.
+    pub fn decode_mdhm(bytes: &[u8]) -> Result<(AprsTimestamp, &[u8]), Error> {
+        if bytes.len() < 8 {
+            bail!("Truncated timestamp");
+        }
+        let mut fields = bytes[..8].chunks(2).map(parse_num);
+        let mut next = || {
+            fields
+                .next()
+                .flatten()
+                .map(|x| x as u8)
+                .ok_or_else(|| format_err!("Bad timestamp"))
+        };
+
+        let ret = AprsTimestamp::Mdhm {
+            month: next()?,
+            day: next()?,
+            hour: next()?,
+            minute: next()?,
+        };
+
+        Ok((ret, &bytes[8..]))
+    }
+
+    pub fn encode(&self) -> String {
+        match *self {
+            AprsTimestamp::DhmZulu { day, hour, minute } => {
+                format!("{:02}{:02}{:02}z", day, hour, minute)
+            }
+            AprsTimestamp::DhmLocal { day, hour, minute } => {
+                format!("{:02}{:02}{:02}/", day, hour, minute)
+            }
+            AprsTimestamp::Hms {
+                hour,
+                minute,
+                second,
+            } => format!("{:02}{:02}{:02}h", hour, minute, second),
+            AprsTimestamp::Mdhm {
+                month,
+                day,
+                hour,
+                minute,
+            } => format!("{:02}{:02}{:02}{:02}", month, day, hour, minute),
+        }
+    }
+}
+
+/// Parses a string of ASCII digits.
+fn parse_num(bytes: &[u8]) -> Option<u32> {
+    if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+fn base91_decode(bytes: &[u8]) -> Option<u32> {
+    bytes.iter().try_fold(0u32, |acc, &x| match x {
+        33..=123 => acc.checked_mul(91)?.checked_add((x - 33) as u32),
+        _ => None,
+    })
+}
+
+fn base91_encode(mut value: u32, len: usize) -> Vec<u8> {
+    let mut ret = vec![b'!'; len];
+    for x in ret.iter_mut().rev() {
+        *x = (value % 91) as u8 + 33;
+        value /= 91;
+    }
+    ret
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APRS_FRAME: &str = "82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0d";
+
+    #[test]
+    fn aprs_decode_frame() {
+        let frame = Ax25Frame::try_from_bytes(&hex::decode(APRS_FRAME).unwrap()).unwrap();
+        let packet = AprsPacket::try_from_frame(&frame).unwrap();
+
+        assert_eq!(
+            packet,
+            AprsPacket::Status(AprsStatus {
+                timestamp: Some(AprsTimestamp::DhmZulu {
+                    day: 20,
+                    hour: 23,
+                    minute: 37
+                }),
+                text: "http://wa8lmf.com".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn aprs_to_frame() {
+        let packet = AprsPacket::Status(AprsStatus {
+            timestamp: None,
+            text: "Testing".to_string(),
+        });
+        let frame = packet
+            .to_frame("KZ2X-1".parse().unwrap(), &["WIDE2-1".parse().unwrap()])
+            .unwrap();
+
+        assert_eq!(frame.dst.to_string(), APRS_DEFAULT_DEST);
+        assert_eq!(frame.info, b">Testing");
+        assert_eq!(AprsPacket::try_from_frame(&frame).unwrap(), packet);
+    }
+
+    #[test]
+    fn aprs_timestamp() {
+        for s in ["092345z", "092345/", "234517h"] {
+            let (ts, rest) = AprsTimestamp::decode(s.as_bytes()).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(ts.encode(), s);
+        }
+
+        let (ts, rest) = AprsTimestamp::decode_mdhm(b"10090556c220").unwrap();
+        assert_eq!(
+            ts,
+            AprsTimestamp::Mdhm {
+                month: 10,
+                day: 9,
+                hour: 5,
+                minute: 56
+            }
+        );
+        assert_eq!(rest, b"c220");
+
+        assert!(AprsTimestamp::decode(b"0923x5z").is_err());
+        assert!(AprsTimestamp::decode(b"092345x").is_err());
+    }
+
+    #[test]
+    fn aprs_base91() {
+        assert_eq!(
+            base91_decode(b"5L!!"),
+            Some(20 * 91 * 91 * 91 + 43 * 91 * 91)
+        );
+        assert_eq!(base91_encode(base91_decode(b"<*e7").unwrap(), 4), b"<*e7");
+        assert_eq!(base91_decode(b" "), None);
+
+        // Too long to fit.
+        assert_eq!(base91_decode(b"{{{{{"), None);
+    }
+
+    #[test]
+    fn aprs_unsupported() {
+        let dst = APRS_DEFAULT_DEST.parse().unwrap();
+        assert!(AprsPacket::decode(&dst, b"").is_err());
+        assert!(AprsPacket::decode(&dst, b"{{unknown").is_err());
+    }
+}
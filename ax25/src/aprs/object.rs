@@ -0,0 +1,164 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+/// Maximum length of an object or item name.
+pub const APRS_MAX_OBJECT_NAME_LEN: usize = 9;
+
+/// An object report (`;`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AprsObject {
+    pub name: String,
+
+    /// `false` if the object has been killed.
+    pub live: bool,
+
+    /// The object's position. Objects always have a timestamp.
+    pub report: AprsPositionReport,
+}
+
+impl AprsObject {
+    pub fn decode(info: &[u8]) -> Result<Self, Error> {
+        if info.len() < 18 || info[0] != b';' {
+            bail!("Bad object header");
+        }
+
+        let name = text(&info[1..10]).trim_end().to_string();
+        let live = match info[10] {
+            b'*' => true,
+            b'_' => false,
+            x => bail!("Bad object state {:?}", x as char),
+        };
+        let (timestamp, data) = AprsTimestamp::decode(&info[11..])?;
+
+        let mut report = AprsPositionReport::decode_body(data)?;
+        report.timestamp = Some(timestamp);
+
+        Ok(AprsObject { name, live, report })
+    }
+
+    /// Returns the encoded info field.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        if self.name.is_empty() || self.name.len() > APRS_MAX_OBJECT_NAME_LEN {
+            bail!("Bad object name {:?}", self.name);
+        }
+        let timestamp = self
+            .report
+            .timestamp
+            .ok_or_else(|| format_err!("Objects require a timestamp"))?;
+
+        let mut ret = format!(
+            ";{:<9}{}{}",
+            self.name,
+            if self.live { '*' } else { '_' },
+            timestamp.encode()
+        )
+        .into_bytes();
+        self.report.encode_body(&mut ret)?;
+        Ok(ret)
+    }
+}
+
+/// An item report (`)`): like an object, but without a timestamp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AprsItem {
+    pub name: String,
+
+    /// `false` if the item has been killed.
+    pub live: bool,
+    pub report: AprsPositionReport,
+}
+
+impl AprsItem {
+    pub fn decode(info: &[u8]) -> Result<Self, Error> {
+        let data = info
+            .strip_prefix(b")")
+            .ok_or_else(|| format_err!("Not an item"))?;
+
+        let len = data
+            .iter()
+            .take(APRS_MAX_OBJECT_NAME_LEN + 1)
+            .skip(3)
+            .position(|&x| x == b'!' || x == b'_')
+            .ok_or_else(|| format_err!("Bad item name"))?
+            + 3;
+
+        Ok(AprsItem {
+            name: text(&data[..len]),
+            live: data[len] == b'!',
+            report: AprsPositionReport::decode_body(&data[len + 1..])?,
+        })
+    }
+
+    /// Returns the encoded info field.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        if !(3..=APRS_MAX_OBJECT_NAME_LEN).contains(&self.name.len())
+            || self.name.contains(['!', '_'])
+        {
+            bail!("Bad item name {:?}", self.name);
+        }
+
+        let mut ret = format!("){}{}", self.name, if self.live { '!' } else { '_' }).into_bytes();
+        self.report.encode_body(&mut ret)?;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aprs_object() {
+        let info = b";LEADER   *092345z4903.50N/07201.75W>088/036";
+        let object = AprsObject::decode(info).unwrap();
+        assert_eq!(object.name, "LEADER");
+        assert!(object.live);
+        assert_eq!(object.report.course, Some(88));
+        assert_eq!(object.encode().unwrap(), info);
+
+        let info = b";LEADER   _092345z/5L!!<*e7>7P[";
+        let object = AprsObject::decode(info).unwrap();
+        assert!(!object.live);
+        assert_eq!(object.report.encoding, PositionEncoding::Compressed);
+
+        let mut object = object;
+        object.report.timestamp = None;
+        assert!(object.encode().is_err());
+    }
+
+    #[test]
+    fn aprs_item() {
+        let info = b")AID #2!4903.50N/07201.75WA";
+        let item = AprsItem::decode(info).unwrap();
+        assert_eq!(item.name, "AID #2");
+        assert!(item.live);
+        assert_eq!(item.report.position.symbol_code, 'A');
+        assert_eq!(item.encode().unwrap(), info);
+
+        let item = AprsItem::decode(b")G/WB4APR_4903.50N/07201.75WA").unwrap();
+        assert_eq!(item.name, "G/WB4APR");
+        assert!(!item.live);
+
+        assert!(AprsItem::decode(b")AB!4903.50N/07201.75WA").is_err());
+    }
+}
@@ -0,0 +1,592 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+use std::fmt::Write;
+
+/// A position and map symbol.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AprsPosition {
+    /// Degrees north.
+    pub latitude: f64,
+
+    /// Degrees east.
+    pub longitude: f64,
+
+    /// Symbol table identifier: `/`, `\` or an overlay character.
+    pub symbol_table: char,
+    pub symbol_code: char,
+
+    /// Number of trailing digits of the minutes that are
+    /// blanked out for privacy (0 to 4).
+    pub ambiguity: u8,
+}
+
+impl AprsPosition {
+    pub fn new(latitude: f64, longitude: f64, symbol_table: char, symbol_code: char) -> Self {
+        AprsPosition {
+            latitude,
+            longitude,
+            symbol_table,
+            symbol_code,
+            ambiguity: 0,
+        }
+    }
+}
+
+/// How a position is encoded on the air.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum PositionEncoding {
+    /// Human-readable `DDMM.hhN/DDDMM.hhW`.
+    #[default]
+    Uncompressed,
+
+    /// Base-91 compressed.
+    Compressed,
+
+    /// Mic-E, with the latitude carried in the destination address.
+    MicE,
+}
+
+/// A position report, also used for the body of objects and items.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AprsPositionReport {
+    pub timestamp: Option<AprsTimestamp>,
+
+    /// The station can receive APRS messages.
+    pub messaging: bool,
+    pub position: AprsPosition,
+    pub encoding: PositionEncoding,
+
+    /// Course in degrees.
+    pub course: Option<u16>,
+
+    /// Speed in knots.
+    pub speed: Option<f64>,
+
+    /// Altitude in feet.
+    pub altitude: Option<i32>,
+
+    /// Weather data, for reports with the weather station symbol.
+    pub weather: Option<AprsWeather>,
+
+    /// Mic-E message code.
+    pub mic_e_status: Option<MicEStatus>,
+    pub comment: String,
+}
+
+impl AprsPositionReport {
+    /// Creates an uncompressed report with no extensions.
+    pub fn new(position: AprsPosition) -> Self {
+        AprsPositionReport {
+            timestamp: None,
+            messaging: false,
+            position,
+            encoding: PositionEncoding::Uncompressed,
+            course: None,
+            speed: None,
+            altitude: None,
+            weather: None,
+            mic_e_status: None,
+            comment: String::new(),
+        }
+    }
+
+    /// Decodes a `!`, `=`, `/` or `@` position report.
+    pub fn decode(info: &[u8]) -> Result<Self, Error> {
+        let (&dti, mut data) = info
+            .split_first()
+            .ok_or_else(|| format_err!("Empty position report"))?;
+
+        let timestamp = match dti {
+            b'!' | b'=' => None,
+            b'/' | b'@' => {
+                let (ts, rest) = AprsTimestamp::decode(data)?;
+                data = rest;
+                Some(ts)
+            }
+            x => bail!("{:?} is not a position report", x as char),
+        };
+
+        let mut ret = Self::decode_body(data)?;
+        ret.timestamp = timestamp;
+        ret.messaging = matches!(dti, b'=' | b'@');
+        Ok(ret)
+    }
+
+    /// Decodes a position, its data extension, and the comment.
+    pub(crate) fn decode_body(data: &[u8]) -> Result<Self, Error> {
+        let first = *data
+            .first()
+            .ok_or_else(|| format_err!("Missing position"))?;
+
+        let (mut ret, mut rest) = if first.is_ascii_digit() || first == b' ' {
+            decode_uncompressed(data)?
+        } else {
+            decode_compressed(data)?
+        };
+
+        let is_weather = ret.position.symbol_code == '_';
+
+        if ret.encoding == PositionEncoding::Uncompressed {
+            if let Some(ext) = rest.get(..7).filter(|x| is_course_speed(x)) {
+                let course = parse_num(&ext[0..3]).map(|x| x as u16);
+                let speed = parse_num(&ext[4..7]).map(|x| x as u16);
+                if is_weather {
+                    ret.weather = Some(AprsWeather {
+                        wind_direction: course,
+                        wind_speed: speed,
+                        ..Default::default()
+                    });
+                } else {
+                    ret.course = course;
+                    ret.speed = speed.map(f64::from);
+                }
+                rest = &rest[7..];
+            }
+        } else if is_weather {
+            ret.weather = Some(AprsWeather {
+                wind_direction: ret.course.take(),
+                wind_speed: ret.speed.take().map(|x| x.round() as u16),
+                ..Default::default()
+            });
+        }
+
+        if is_weather {
+            let mut weather = ret.weather.take().unwrap_or_default();
+            rest = weather.decode_fields(rest);
+            ret.weather = Some(weather);
+        }
+
+        let (altitude, comment) = extract_altitude(&text(rest));
+        ret.altitude = ret.altitude.or(altitude);
+        ret.comment = comment;
+
+        Ok(ret)
+    }
+
+    /// Returns the encoded info field.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut ret = Vec::new();
+        match (self.timestamp, self.messaging) {
+            (None, false) => ret.push(b'!'),
+            (None, true) => ret.push(b'='),
+            (Some(ts), messaging) => {
+                ret.push(if messaging { b'@' } else { b'/' });
+                ret.extend(ts.encode().bytes());
+            }
+        }
+        self.encode_body(&mut ret)?;
+        Ok(ret)
+    }
+
+    /// Encodes the position, its data extension, and the comment.
+    pub(crate) fn encode_body(&self, out: &mut Vec<u8>) -> Result<(), Error> {
+        let mut altitude = self.altitude;
+        let mut ext = String::new();
+
+        match self.encoding {
+            PositionEncoding::Uncompressed => {
+                out.extend(encode_uncompressed(&self.position)?);
+
+                if let Some(weather) = self.weather.as_ref() {
+                    write_opt(&mut ext, weather.wind_direction, 3);
+                    ext.push('/');
+                    write_opt(&mut ext, weather.wind_speed, 3);
+                } else if let (Some(course), Some(speed)) = (self.course, self.speed) {
+                    write!(ext, "{:03}/{:03}", course % 360, speed.round() as u16).unwrap();
+                }
+            }
+            PositionEncoding::Compressed => {
+                let (bytes, altitude_in_cs) = encode_compressed(self)?;
+                out.extend(bytes);
+                if altitude_in_cs {
+                    altitude = None;
+                }
+            }
+            PositionEncoding::MicE => bail!("Mic-E positions cannot be embedded"),
+        }
+
+        if let Some(weather) = self.weather.as_ref() {
+            weather.encode_fields(&mut ext, false);
+        }
+
+        if let Some(altitude) = altitude {
+            write!(ext, "/A={:06}", altitude).unwrap();
+        }
+
+        out.extend(ext.bytes());
+        out.extend(self.comment.bytes());
+        Ok(())
+    }
+}
+
+/// Writes a zero-padded number, or dots if it is unknown.
+pub(crate) fn write_opt<T: Into<i32>>(out: &mut String, value: Option<T>, width: usize) {
+    match value {
+        Some(x) => write!(out, "{:0width$}", x.into(), width = width).unwrap(),
+        None => out.push_str(&".".repeat(width)),
+    }
+}
+
+/// Returns true if `ext` is a `CSE/SPD` data extension.
+fn is_course_speed(ext: &[u8]) -> bool {
+    ext[3] == b'/'
+        && ext
+            .iter()
+            .enumerate()
+            .all(|(i, &x)| i == 3 || x.is_ascii_digit() || x == b'.' || x == b' ')
+}
+
+/// Removes an `/A=nnnnnn` altitude from a comment.
+fn extract_altitude(comment: &str) -> (Option<i32>, String) {
+    if let Some(i) = comment.find("/A=") {
+        if let Some(altitude) = comment.get(i + 3..i + 9).and_then(|x| x.parse().ok()) {
+            return (
+                Some(altitude),
+                format!("{}{}", &comment[..i], &comment[i + 9..]),
+            );
+        }
+    }
+    (None, comment.to_string())
+}
+
+/// Positions of the digits blanked by position ambiguity, last first.
+const LAT_AMBIGUOUS_DIGITS: [usize; 4] = [6, 5, 3, 2];
+const LON_AMBIGUOUS_DIGITS: [usize; 4] = [7, 6, 4, 3];
+
+/// Decodes `DDMM.hhN` or `DDDMM.hhW`, returning the
+/// signed value in degrees and the ambiguity.
+fn decode_degrees(bytes: &[u8], deg_len: usize) -> Result<(f64, u8), Error> {
+    let ambiguous = if deg_len == 2 {
+        LAT_AMBIGUOUS_DIGITS
+    } else {
+        LON_AMBIGUOUS_DIGITS
+    };
+
+    if bytes.len() != deg_len + 6 || bytes[deg_len + 2] != b'.' {
+        bail!("Bad position {:?}", text(bytes));
+    }
+
+    let mut bytes = bytes.to_vec();
+    let mut ambiguity = 0;
+    for &i in ambiguous.iter() {
+        if bytes[i] != b' ' {
+            break;
+        }
+        bytes[i] = b'0';
+        ambiguity += 1;
+    }
+
+    let bad = || format_err!("Bad position {:?}", text(&bytes));
+    let deg = parse_num(&bytes[..deg_len]).ok_or_else(bad)? as f64;
+    let min = parse_num(&bytes[deg_len..deg_len + 2]).ok_or_else(bad)? as f64;
+    let hundredths = parse_num(&bytes[deg_len + 3..deg_len + 5]).ok_or_else(bad)? as f64;
+
+    let value = deg + (min + hundredths / 100.0) / 60.0;
+    let sign = match (deg_len, bytes[deg_len + 5]) {
+        (2, b'N') | (3, b'E') => 1.0,
+        (2, b'S') | (3, b'W') => -1.0,
+        _ => return Err(bad()),
+    };
+
+    Ok((value * sign, ambiguity))
+}
+
+/// Encodes degrees as `DDMM.hh` or `DDDMM.hh` followed by the hemisphere.
+fn encode_degrees(value: f64, deg_len: usize, ambiguity: u8) -> String {
+    let total = (value.abs() * 6000.0).round() as u32;
+    let hemisphere = match (deg_len, value < 0.0) {
+        (2, false) => 'N',
+        (2, true) => 'S',
+        (_, false) => 'E',
+        (_, true) => 'W',
+    };
+
+    let mut ret = format!(
+        "{:0deg_len$}{:02}.{:02}{}",
+        total / 6000,
+        total / 100 % 60,
+        total % 100,
+        hemisphere,
+        deg_len = deg_len
+    )
+    .into_bytes();
+
+    let ambiguous = if deg_len == 2 {
+        LAT_AMBIGUOUS_DIGITS
+    } else {
+        LON_AMBIGUOUS_DIGITS
+    };
+    for &i in ambiguous.iter().take(ambiguity as usize) {
+        ret[i] = b' ';
+    }
+
+    String::from_utf8(ret).unwrap()
+}
+
+fn check_range(position: &AprsPosition) -> Result<(), Error> {
+    if !(-90.0..=90.0).contains(&position.latitude) {
+        bail!("Latitude {} out of range", position.latitude);
+    }
+    if !(-180.0..=180.0).contains(&position.longitude) {
+        bail!("Longitude {} out of range", position.longitude);
+    }
+    Ok(())
+}
+
+fn decode_uncompressed(data: &[u8]) -> Result<(AprsPositionReport, &[u8]), Error> {
+    if data.len() < 19 {
+        bail!("Truncated position");
+    }
+
+    let (latitude, ambiguity) = decode_degrees(&data[0..8], 2)?;
+    let (longitude, _) = decode_degrees(&data[9..18], 3)?;
+
+    let position = AprsPosition {
+        latitude,
+        longitude,
+        symbol_table: data[8] as char,
+        symbol_code: data[18] as char,
+        ambiguity,
+    };
+    check_range(&position)?;
+
+    Ok((AprsPositionReport::new(position), &data[19..]))
+}
+
+fn encode_uncompressed(position: &AprsPosition) -> Result<Vec<u8>, Error> {
+    check_range(position)?;
+    if position.ambiguity > 4 {
+        bail!("Position ambiguity {} out of range", position.ambiguity);
+    }
+
+    let mut ret = encode_degrees(position.latitude, 2, position.ambiguity);
+    ret.push(position.symbol_table);
+    ret += &encode_degrees(position.longitude, 3, position.ambiguity);
+    ret.push(position.symbol_code);
+    Ok(ret.into_bytes())
+}
+
+/// Compression type byte bits for a current fix from software.
+const COMPRESSION_TYPE: u8 = 0b0010_0010;
+
+/// NMEA source bits in the compression type byte that
+/// indicate the `cs` bytes carry altitude.
+const COMPRESSION_TYPE_GGA: u8 = 0b0001_0000;
+
+fn decode_compressed(data: &[u8]) -> Result<(AprsPositionReport, &[u8]), Error> {
+    if data.len() < 13 {
+        bail!("Truncated compressed position");
+    }
+
+    let symbol_table = match data[0] {
+        x @ b'a'..=b'j' => (x - b'a' + b'0') as char,
+        x @ (b'/' | b'\\' | b'A'..=b'Z') => x as char,
+        x => bail!("Bad symbol table {:?}", x as char),
+    };
+
+    let bad = || format_err!("Bad compressed position {:?}", text(&data[..13]));
+    let y = base91_decode(&data[1..5]).ok_or_else(bad)?;
+    let x = base91_decode(&data[5..9]).ok_or_else(bad)?;
+
+    let mut ret = AprsPositionReport::new(AprsPosition::new(
+        90.0 - y as f64 / 380926.0,
+        -180.0 + x as f64 / 190463.0,
+        symbol_table,
+        data[9] as char,
+    ));
+    ret.encoding = PositionEncoding::Compressed;
+
+    let (c, s, t) = (data[10], data[11], data[12]);
+    if c != b' ' {
+        if t >= 33 && (t - 33) & 0b0001_1000 == COMPRESSION_TYPE_GGA {
+            let cs = base91_decode(&[c, s]).ok_or_else(bad)?;
+            ret.altitude = Some(1.002f64.powi(cs as i32).round() as i32);
+        } else if (b'!'..=b'z').contains(&c) && s >= 33 {
+            ret.course = Some((c - 33) as u16 * 4);
+            ret.speed = Some(1.08f64.powi((s - 33) as i32) - 1.0);
+        }
+    }
+
+    Ok((ret, &data[13..]))
+}
+
+/// Returns the compressed position, and whether the
+/// altitude was encoded in the `cs` bytes.
+fn encode_compressed(report: &AprsPositionReport) -> Result<(Vec<u8>, bool), Error> {
+    let position = &report.position;
+    check_range(position)?;
+
+    let symbol_table = match position.symbol_table {
+        x @ '0'..='9' => x as u8 - b'0' + b'a',
+        x @ ('/' | '\\' | 'A'..='Z') => x as u8,
+        x => bail!("Bad symbol table {:?}", x),
+    };
+
+    let mut ret = vec![symbol_table];
+    ret.extend(base91_encode(
+        (380926.0 * (90.0 - position.latitude)).round() as u32,
+        4,
+    ));
+    ret.extend(base91_encode(
+        (190463.0 * (180.0 + position.longitude)).round() as u32,
+        4,
+    ));
+    ret.push(position.symbol_code as u8);
+
+    let course_speed = match report.weather.as_ref() {
+        Some(weather) => weather
+            .wind_direction
+            .zip(weather.wind_speed.map(f64::from)),
+        None => report.course.zip(report.speed),
+    };
+
+    if let Some((course, speed)) = course_speed {
+        let s = ((speed.max(0.0) + 1.0).ln() / 1.08f64.ln())
+            .round()
+            .min(89.0) as u8;
+        ret.extend([(course % 360 / 4) as u8 + 33, s + 33, COMPRESSION_TYPE + 33]);
+        Ok((ret, false))
+    } else if let Some(altitude) = report.altitude.filter(|&x| x >= 1) {
+        let cs = ((altitude as f64).ln() / 1.002f64.ln()).round().min(8280.0) as u32;
+        ret.extend(base91_encode(cs, 2));
+        ret.push(COMPRESSION_TYPE + COMPRESSION_TYPE_GGA + 33);
+        Ok((ret, true))
+    } else {
+        ret.extend(b" sT");
+        Ok((ret, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(info: &str) -> AprsPositionReport {
+        AprsPositionReport::decode(info.as_bytes()).unwrap()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn aprs_position_uncompressed() {
+        let report = decode("!4903.50N/07201.75W-Test 001234");
+        assert_close(report.position.latitude, 49.0 + 3.5 / 60.0);
+        assert_close(report.position.longitude, -(72.0 + 1.75 / 60.0));
+        assert_eq!(report.position.symbol_table, '/');
+        assert_eq!(report.position.symbol_code, '-');
+        assert_eq!(report.encoding, PositionEncoding::Uncompressed);
+        assert!(!report.messaging);
+        assert_eq!(report.comment, "Test 001234");
+        assert_eq!(report.encode().unwrap(), b"!4903.50N/07201.75W-Test 001234");
+
+        let report = decode("@092345z4903.50S\\07201.75E>088/036/A=001234 Hi");
+        assert!(report.messaging);
+        assert_eq!(
+            report.timestamp,
+            Some(AprsTimestamp::DhmZulu {
+                day: 9,
+                hour: 23,
+                minute: 45
+            })
+        );
+        assert!(report.position.latitude < 0.0);
+        assert!(report.position.longitude > 0.0);
+        assert_eq!(report.course, Some(88));
+        assert_eq!(report.speed, Some(36.0));
+        assert_eq!(report.altitude, Some(1234));
+        assert_eq!(report.comment, " Hi");
+        assert_eq!(
+            report.encode().unwrap(),
+            b"@092345z4903.50S\\07201.75E>088/036/A=001234 Hi"
+        );
+    }
+
+    #[test]
+    fn aprs_position_ambiguity() {
+        let report = decode("=49  .  N/072  .  W-");
+        assert_eq!(report.position.ambiguity, 4);
+        assert_close(report.position.latitude, 49.0);
+        assert_close(report.position.longitude, -72.0);
+        assert_eq!(report.encode().unwrap(), b"=49  .  N/072  .  W-");
+
+        let report = decode("!4903.5 N/07201.7 W-");
+        assert_eq!(report.position.ambiguity, 1);
+    }
+
+    #[test]
+    fn aprs_position_compressed() {
+        let report = decode("=/5L!!<*e7>7P[");
+        assert_eq!(report.encoding, PositionEncoding::Compressed);
+        assert_close(report.position.latitude, 49.5);
+        assert_close(report.position.longitude, -72.75);
+        assert_eq!(report.position.symbol_code, '>');
+        assert_eq!(report.course, Some(88));
+        assert!((report.speed.unwrap() - 36.2).abs() < 0.1);
+
+        let encoded = report.encode().unwrap();
+        assert_eq!(&encoded[..13], b"=/5L!!<*e7>7P");
+        assert_eq!(decode(std::str::from_utf8(&encoded).unwrap()), report);
+
+        let mut report = AprsPositionReport::new(AprsPosition::new(-33.9, 151.2, '5', '#'));
+        report.encoding = PositionEncoding::Compressed;
+        report.altitude = Some(10004);
+        let encoded = report.encode().unwrap();
+        assert_eq!(encoded[1], b'f');
+
+        let decoded = decode(std::str::from_utf8(&encoded).unwrap());
+        assert_eq!(decoded.position.symbol_table, '5');
+        assert_close(decoded.position.latitude, -33.9);
+        assert_close(decoded.position.longitude, 151.2);
+        assert!((decoded.altitude.unwrap() - 10004).abs() < 20);
+        assert_eq!(decoded.course, None);
+    }
+
+    #[test]
+    fn aprs_position_weather() {
+        let info = "!4903.50N/07201.75W_220/004g005t-07r000p000P000h50b09900wRSW";
+        let report = decode(info);
+        let weather = report.weather.as_ref().unwrap();
+        assert_eq!(weather.wind_direction, Some(220));
+        assert_eq!(weather.wind_speed, Some(4));
+        assert_eq!(weather.wind_gust, Some(5));
+        assert_eq!(weather.temperature, Some(-7));
+        assert_eq!(weather.humidity, Some(50));
+        assert_eq!(weather.pressure, Some(9900));
+        assert_eq!(report.course, None);
+        assert_eq!(report.comment, "wRSW");
+        assert_eq!(report.encode().unwrap(), info.as_bytes());
+    }
+
+    #[test]
+    fn aprs_position_errors() {
+        assert!(AprsPositionReport::decode(b"!4903.50N/07201.75").is_err());
+        assert!(AprsPositionReport::decode(b"!9103.50N/07201.75W-").is_err());
+        assert!(AprsPositionReport::decode(b"!4903.50X/07201.75W-").is_err());
+        assert!(AprsPositionReport::decode(b"!/5L!!<*e7>7P").is_err());
+
+        let report = AprsPositionReport::new(AprsPosition::new(91.0, 0.0, '/', '-'));
+        assert!(report.encode().is_err());
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+/// A telemetry report (`T#`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AprsTelemetry {
+    /// Sequence number, or `None` for `MIC`.
+    pub sequence: Option<u16>,
+
+    /// Up to five analog values.
+    pub analog: Vec<f64>,
+
+    /// Eight digital values, in transmission order.
+    pub digital: Option<[bool; 8]>,
+    pub comment: String,
+}
+
+impl AprsTelemetry {
+    pub fn decode(info: &[u8]) -> Result<Self, Error> {
+        let data = info
+            .strip_prefix(b"T#")
+            .ok_or_else(|| format_err!("Not a telemetry report"))?;
+
+        let mut fields = data.splitn(7, |&x| x == b',');
+        let sequence = match fields.next().unwrap() {
+            b"MIC" => None,
+            x => Some(parse_num(x).ok_or_else(|| format_err!("Bad telemetry sequence"))? as u16),
+        };
+
+        let mut analog = Vec::new();
+        for field in fields.by_ref().take(5) {
+            analog.push(
+                text(field)
+                    .trim()
+                    .parse()
+                    .map_err(|_| format_err!("Bad telemetry value {:?}", text(field)))?,
+            );
+        }
+
+        let (digital, comment) = match fields.next() {
+            Some(field) if field.len() >= 8 => {
+                let mut digital = [false; 8];
+                for (bit, &x) in digital.iter_mut().zip(field.iter()) {
+                    *bit = match x {
+                        b'0' => false,
+                        b'1' => true,
+                        _ => bail!("Bad telemetry bits"),
+                    };
+                }
+                (Some(digital), text(&field[8..]))
+            }
+            Some(_) => bail!("Bad telemetry bits"),
+            None => (None, String::new()),
+        };
+
+        Ok(AprsTelemetry {
+            sequence,
+            analog,
+            digital,
+            comment,
+        })
+    }
+
+    /// Returns the encoded info field.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut ret = match self.sequence {
+            Some(x) => format!("T#{:03}", x),
+            None => "T#MIC".to_string(),
+        };
+
+        for &value in self.analog.iter().take(5) {
+            if value.fract() == 0.0 && (0.0..1000.0).contains(&value) {
+                ret += &format!(",{:03}", value as u16);
+            } else {
+                ret += &format!(",{}", value);
+            }
+        }
+
+        if let Some(digital) = self.digital {
+            ret.push(',');
+            ret.extend(digital.iter().map(|&x| if x { '1' } else { '0' }));
+            ret += &self.comment;
+        }
+
+        ret.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aprs_telemetry() {
+        let info = b"T#005,199,000,255,073,123,01101001Solar";
+        let telemetry = AprsTelemetry::decode(info).unwrap();
+        assert_eq!(telemetry.sequence, Some(5));
+        assert_eq!(telemetry.analog, vec![199.0, 0.0, 255.0, 73.0, 123.0]);
+        assert_eq!(
+            telemetry.digital,
+            Some([false, true, true, false, true, false, false, true])
+        );
+        assert_eq!(telemetry.comment, "Solar");
+        assert_eq!(telemetry.encode(), info);
+
+        let telemetry = AprsTelemetry::decode(b"T#MIC,12.5,-3").unwrap();
+        assert_eq!(telemetry.sequence, None);
+        assert_eq!(telemetry.analog, vec![12.5, -3.0]);
+        assert_eq!(telemetry.digital, None);
+        assert_eq!(telemetry.encode(), b"T#MIC,12.5,-3");
+
+        assert!(AprsTelemetry::decode(b"T#005,abc").is_err());
+        assert!(AprsTelemetry::decode(b"T#005,1,2,3,4,5,0110").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+/// Weather station readings. Unknown values are `None`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct AprsWeather {
+    /// Degrees.
+    pub wind_direction: Option<u16>,
+
+    /// Sustained one-minute wind speed, in mph.
+    pub wind_speed: Option<u16>,
+
+    /// Peak wind speed in the last five minutes, in mph.
+    pub wind_gust: Option<u16>,
+
+    /// Degrees Fahrenheit.
+    pub temperature: Option<i16>,
+
+    /// Rainfall in the last hour, in hundredths of an inch.
+    pub rain_last_hour: Option<u16>,
+
+    /// Rainfall in the last 24 hours, in hundredths of an inch.
+    pub rain_last_24h: Option<u16>,
+
+    /// Rainfall since midnight, in hundredths of an inch.
+    pub rain_since_midnight: Option<u16>,
+
+    /// Relative humidity, in percent.
+    pub humidity: Option<u8>,
+
+    /// Barometric pressure, in tenths of a millibar.
+    pub pressure: Option<u32>,
+
+    /// Watts per square metre.
+    pub luminosity: Option<u16>,
+}
+
+impl AprsWeather {
+    /// Decodes weather fields like `g005t077`, stopping at the
+    /// first one it does not recognize. Returns the rest.
+    pub(crate) fn decode_fields<'a>(&mut self, mut bytes: &'a [u8]) -> &'a [u8] {
+        while let Some((&key, rest)) = bytes.split_first() {
+            let len = match key {
+                b'h' => 2,
+                b'b' => 5,
+                b'c' | b's' | b'g' | b't' | b'r' | b'p' | b'P' | b'L' | b'l' => 3,
+                _ => break,
+            };
+            let field = match rest.get(..len) {
+                Some(x) => x,
+                None => break,
+            };
+
+            let value = if field.iter().all(|&x| x == b'.' || x == b' ') {
+                None
+            } else {
+                match std::str::from_utf8(field)
+                    .ok()
+                    .and_then(|x| x.parse::<i32>().ok())
+                {
+                    Some(x) => Some(x),
+                    None => break,
+                }
+            };
+
+            match key {
+                b'c' => self.wind_direction = value.map(|x| x as u16),
+                b's' => self.wind_speed = value.map(|x| x as u16),
+                b'g' => self.wind_gust = value.map(|x| x as u16),
+                b't' => self.temperature = value.map(|x| x as i16),
+                b'r' => self.rain_last_hour = value.map(|x| x as u16),
+                b'p' => self.rain_last_24h = value.map(|x| x as u16),
+                b'P' => self.rain_since_midnight = value.map(|x| x as u16),
+                b'h' => self.humidity = value.map(|x| if x == 0 { 100 } else { x as u8 }),
+                b'b' => self.pressure = value.map(|x| x as u32),
+                b'L' => self.luminosity = value.map(|x| x as u16),
+                b'l' => self.luminosity = value.map(|x| x as u16 + 1000),
+                _ => unreachable!(),
+            }
+
+            bytes = &rest[len..];
+        }
+        bytes
+    }
+
+    /// Encodes the weather fields. Wind direction and speed are only
+    /// included for positionless reports; position reports carry them
+    /// in the course/speed extension.
+    pub(crate) fn encode_fields(&self, out: &mut String, positionless: bool) {
+        if positionless {
+            out.push('c');
+            write_opt(out, self.wind_direction, 3);
+            out.push('s');
+            write_opt(out, self.wind_speed, 3);
+        }
+
+        out.push('g');
+        write_opt(out, self.wind_gust, 3);
+        out.push('t');
+        write_opt(out, self.temperature, 3);
+
+        let optional = [
+            ('r', self.rain_last_hour.map(i32::from), 3),
+            ('p', self.rain_last_24h.map(i32::from), 3),
+            ('P', self.rain_since_midnight.map(i32::from), 3),
+            ('h', self.humidity.map(|x| i32::from(x) % 100), 2),
+            ('b', self.pressure.map(|x| x as i32), 5),
+        ];
+        for (key, value, width) in optional {
+            if value.is_some() {
+                out.push(key);
+                write_opt(out, value, width);
+            }
+        }
+
+        match self.luminosity {
+            Some(x) if x >= 1000 => {
+                out.push('l');
+                write_opt(out, Some(x - 1000), 3);
+            }
+            Some(x) => {
+                out.push('L');
+                write_opt(out, Some(x), 3);
+            }
+            None => {}
+        }
+    }
+}
+
+/// A positionless weather report (`_`).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AprsWeatherReport {
+    /// Always an [`AprsTimestamp::Mdhm`] timestamp.
+    pub timestamp: AprsTimestamp,
+    pub weather: AprsWeather,
+
+    /// Software and station type, and any other trailing text.
+    pub comment: String,
+}
+
+impl AprsWeatherReport {
+    pub fn decode(info: &[u8]) -> Result<Self, Error> {
+        let data = info
+            .strip_prefix(b"_")
+            .ok_or_else(|| format_err!("Not a weather report"))?;
+        let (timestamp, data) = AprsTimestamp::decode_mdhm(data)?;
+
+        let mut weather = AprsWeather::default();
+        let rest = weather.decode_fields(data);
+
+        Ok(AprsWeatherReport {
+            timestamp,
+            weather,
+            comment: text(rest),
+        })
+    }
+
+    /// Returns the encoded info field.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        if !matches!(self.timestamp, AprsTimestamp::Mdhm { .. }) {
+            bail!("Weather reports require a MDHM timestamp");
+        }
+
+        let mut ret = format!("_{}", self.timestamp.encode());
+        self.weather.encode_fields(&mut ret, true);
+        ret += &self.comment;
+        Ok(ret.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aprs_weather_positionless() {
+        let info = "_10090556c220s004g005t077r001p002P003h00b09900l010wRSW";
+        let report = AprsWeatherReport::decode(info.as_bytes()).unwrap();
+        assert_eq!(
+            report.weather,
+            AprsWeather {
+                wind_direction: Some(220),
+                wind_speed: Some(4),
+                wind_gust: Some(5),
+                temperature: Some(77),
+                rain_last_hour: Some(1),
+                rain_last_24h: Some(2),
+                rain_since_midnight: Some(3),
+                humidity: Some(100),
+                pressure: Some(9900),
+                luminosity: Some(1010),
+            }
+        );
+        assert_eq!(report.comment, "wRSW");
+        assert_eq!(report.encode().unwrap(), info.as_bytes());
+    }
+
+    #[test]
+    fn aprs_weather_unknown_values() {
+        let report = AprsWeatherReport::decode(b"_10090556c...s   g...t050").unwrap();
+        assert_eq!(report.weather.wind_direction, None);
+        assert_eq!(report.weather.wind_speed, None);
+        assert_eq!(report.weather.temperature, Some(50));
+        assert_eq!(report.encode().unwrap(), b"_10090556c...s...g...t050");
+
+        let report = AprsWeatherReport {
+            timestamp: AprsTimestamp::Hms {
+                hour: 1,
+                minute: 2,
+                second: 3,
+            },
+            weather: Default::default(),
+            comment: String::new(),
+        };
+        assert!(report.encode().is_err());
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use anyhow::{bail, Error};
+use std::fmt::{Display, Formatter};
+
+/// Sequence number modulus used by a connection.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum Modulo {
+    /// Three-bit sequence numbers and single-byte I and S control fields.
+    #[default]
+    Mod8,
+
+    /// Seven-bit sequence numbers and two-byte I and S control fields.
+    Mod128,
+}
+
+impl Modulo {
+    pub fn modulus(&self) -> u8 {
+        match self {
+            Self::Mod8 => 8,
+            Self::Mod128 => 128,
+        }
+    }
+
+    /// Length of the control field of I and S frames.
+    pub fn control_len(&self) -> usize {
+        match self {
+            Self::Mod8 => 1,
+            Self::Mod128 => 2,
+        }
+    }
+}
+
+/// Supervisory frame types.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SupervisoryKind {
+    /// Receive Ready
+    Rr,
+
+    /// Receive Not Ready
+    Rnr,
+
+    /// Reject
+    Rej,
+
+    /// Selective Reject
+    Srej,
+}
+
+impl SupervisoryKind {
+    pub fn try_from_u8(x: u8) -> Option<SupervisoryKind> {
+        match x {
+            0 => Some(Self::Rr),
+            1 => Some(Self::Rnr),
+            2 => Some(Self::Rej),
+            3 => Some(Self::Srej),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Rr => 0,
+            Self::Rnr => 1,
+            Self::Rej => 2,
+            Self::Srej => 3,
+        }
+    }
+}
+
+/// Unnumbered frame types.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum UnnumberedKind {
+    /// Set Asynchronous Balanced Mode Extended (modulo 128)
+    Sabme,
+
+    /// Set Asynchronous Balanced Mode (modulo 8)
+    Sabm,
+
+    /// Disconnect
+    Disc,
+
+    /// Disconnected Mode
+    Dm,
+
+    /// Unnumbered Acknowledge
+    Ua,
+
+    /// Frame Reject
+    Frmr,
+
+    /// Unnumbered Information
+    Ui,
+
+    /// Exchange Identification
+    Xid,
+
+    /// Test
+    Test,
+}
+
+impl UnnumberedKind {
+    /// Decodes the control byte with the P/F bit masked off.
+    pub fn try_from_u8(x: u8) -> Option<UnnumberedKind> {
+        match x {
+            0x6F => Some(Self::Sabme),
+            0x2F => Some(Self::Sabm),
+            0x43 => Some(Self::Disc),
+            0x0F => Some(Self::Dm),
+            0x63 => Some(Self::Ua),
+            0x87 => Some(Self::Frmr),
+            0x03 => Some(Self::Ui),
+            0xAF => Some(Self::Xid),
+            0xE3 => Some(Self::Test),
+            _ => None,
+        }
+    }
+
+    /// Returns the control byte with the P/F bit clear.
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Sabme => 0x6F,
+            Self::Sabm => 0x2F,
+            Self::Disc => 0x43,
+            Self::Dm => 0x0F,
+            Self::Ua => 0x63,
+            Self::Frmr => 0x87,
+            Self::Ui => 0x03,
+            Self::Xid => 0xAF,
+            Self::Test => 0xE3,
+        }
+    }
+}
+
+const PF_MOD8: u8 = 0x10;
+
+/// An AX.25 control field.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Control {
+    /// Information frame.
+    I { ns: u8, nr: u8, poll: bool },
+
+    /// Supervisory frame.
+    S {
+        kind: SupervisoryKind,
+        nr: u8,
+        poll_final: bool,
+    },
+
+    /// Unnumbered frame.
+    U {
+        kind: UnnumberedKind,
+        poll_final: bool,
+    },
+}
+
+impl Control {
+    /// Control field of a UI frame.
+    pub const UI: Control = Control::U {
+        kind: UnnumberedKind::Ui,
+        poll_final: false,
+    };
+
+    /// Decodes a control field from the start of `bytes`,
+    /// returning the control field and its length.
+    pub fn decode(bytes: &[u8], modulo: Modulo) -> Result<(Control, usize), Error> {
+        let first = match bytes.first() {
+            Some(&x) => x,
+            None => bail!("Missing control field"),
+        };
+
+        if first & 3 == 3 {
+            let kind = match UnnumberedKind::try_from_u8(first & !PF_MOD8) {
+                Some(kind) => kind,
+                None => bail!("Unknown U frame control byte 0x{:02X}", first),
+            };
+            let poll_final = first & PF_MOD8 != 0;
+            return Ok((Control::U { kind, poll_final }, 1));
+        }
+
+        let (nr, pf, len) = match modulo {
+            Modulo::Mod8 => (first >> 5, first & PF_MOD8 != 0, 1),
+            Modulo::Mod128 => match bytes.get(1) {
+                Some(&second) => (second >> 1, second & 1 != 0, 2),
+                None => bail!("Truncated control field"),
+            },
+        };
+
+        let ret = if first & 1 == 0 {
+            let ns = match modulo {
+                Modulo::Mod8 => (first >> 1) & 0x07,
+                Modulo::Mod128 => first >> 1,
+            };
+            Control::I { ns, nr, poll: pf }
+        } else {
+            if modulo == Modulo::Mod128 && first & 0xF0 != 0 {
+                bail!("Invalid S frame control byte 0x{:02X}", first);
+            }
+            Control::S {
+                kind: SupervisoryKind::try_from_u8((first >> 2) & 3).unwrap(),
+                nr,
+                poll_final: pf,
+            }
+        };
+
+        Ok((ret, len))
+    }
+
+    /// Returns the encoded control field.
+    pub fn encode(&self, modulo: Modulo) -> Vec<u8> {
+        let mask = modulo.modulus() - 1;
+        match (*self, modulo) {
+            (Control::I { ns, nr, poll }, Modulo::Mod8) => {
+                vec![((nr & mask) << 5) | (u8::from(poll) << 4) | ((ns & mask) << 1)]
+            }
+            (Control::I { ns, nr, poll }, Modulo::Mod128) => {
+                vec![(ns & mask) << 1, ((nr & mask) << 1) | u8::from(poll)]
+            }
+            (
+                Control::S {
+                    kind,
+                    nr,
+                    poll_final,
+                },
+                Modulo::Mod8,
+            ) => {
+                vec![((nr & mask) << 5) | (u8::from(poll_final) << 4) | (kind.to_u8() << 2) | 1]
+            }
+            (
+                Control::S {
+                    kind,
+                    nr,
+                    poll_final,
+                },
+                Modulo::Mod128,
+            ) => {
+                vec![
+                    (kind.to_u8() << 2) | 1,
+                    ((nr & mask) << 1) | u8::from(poll_final),
+                ]
+            }
+            (Control::U { kind, poll_final }, _) => {
+                vec![kind.to_u8() | (u8::from(poll_final) << 4)]
+            }
+        }
+    }
+
+    /// Returns true if frames with this control field carry a PID.
+    pub fn has_pid(&self) -> bool {
+        matches!(
+            self,
+            Control::I { .. }
+                | Control::U {
+                    kind: UnnumberedKind::Ui,
+                    ..
+                }
+        )
+    }
+
+    /// Returns the receive sequence number, if any.
+    pub fn nr(&self) -> Option<u8> {
+        match *self {
+            Control::I { nr, .. } | Control::S { nr, .. } => Some(nr),
+            Control::U { .. } => None,
+        }
+    }
+
+    /// Returns the state of the P/F bit.
+    pub fn poll_final(&self) -> bool {
+        match *self {
+            Control::I { poll, .. } => poll,
+            Control::S { poll_final, .. } | Control::U { poll_final, .. } => poll_final,
+        }
+    }
+}
+
+impl Display for Control {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Control::I { ns, nr, .. } => write!(f, "I R{} S{}", nr, ns)?,
+            Control::S { kind, nr, .. } => {
+                write!(f, "{} R{}", format!("{:?}", kind).to_uppercase(), nr)?
+            }
+            Control::U { kind, .. } => write!(f, "{}", format!("{:?}", kind).to_uppercase())?,
+        }
+        if self.poll_final() {
+            write!(f, " P/F")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ax25_control_mod8() {
+        let cases = [
+            (
+                Control::I {
+                    ns: 3,
+                    nr: 5,
+                    poll: true,
+                },
+                0xB6,
+            ),
+            (
+                Control::S {
+                    kind: SupervisoryKind::Rej,
+                    nr: 7,
+                    poll_final: false,
+                },
+                0xE9,
+            ),
+            (
+                Control::S {
+                    kind: SupervisoryKind::Rr,
+                    nr: 0,
+                    poll_final: true,
+                },
+                0x11,
+            ),
+            (
+                Control::U {
+                    kind: UnnumberedKind::Sabm,
+                    poll_final: true,
+                },
+                0x3F,
+            ),
+            (
+                Control::U {
+                    kind: UnnumberedKind::Ua,
+                    poll_final: false,
+                },
+                0x63,
+            ),
+            (Control::UI, 0x03),
+        ];
+
+        for (control, byte) in cases {
+            assert_eq!(control.encode(Modulo::Mod8), vec![byte], "{}", control);
+            assert_eq!(
+                Control::decode(&[byte], Modulo::Mod8).unwrap(),
+                (control, 1)
+            );
+        }
+    }
+
+    #[test]
+    fn ax25_control_mod128() {
+        let cases = [
+            (
+                Control::I {
+                    ns: 100,
+                    nr: 127,
+                    poll: false,
+                },
+                vec![0xC8, 0xFE],
+            ),
+            (
+                Control::S {
+                    kind: SupervisoryKind::Srej,
+                    nr: 64,
+                    poll_final: true,
+                },
+                vec![0x0D, 0x81],
+            ),
+            (
+                Control::U {
+                    kind: UnnumberedKind::Sabme,
+                    poll_final: true,
+                },
+                vec![0x7F],
+            ),
+        ];
+
+        for (control, bytes) in cases {
+            assert_eq!(control.encode(Modulo::Mod128), bytes, "{}", control);
+            assert_eq!(
+                Control::decode(&bytes, Modulo::Mod128).unwrap(),
+                (control, bytes.len())
+            );
+        }
+
+        assert!(Control::decode(&[0x00], Modulo::Mod128).is_err());
+    }
+
+    #[test]
+    fn ax25_control_unknown() {
+        assert!(Control::decode(&[0xFF], Modulo::Mod8).is_err());
+        assert!(Control::decode(&[], Modulo::Mod8).is_err());
+    }
+}
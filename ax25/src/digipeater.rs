@@ -0,0 +1,489 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! AX.25 digipeater.
+//!
+//! [`Digipeater`] decides which received frames to repeat and how to
+//! rewrite their via path. Like [`crate::link::Ax25Link`] it does no I/O:
+//! frames are fed in with the current time, and frames to transmit come out.
+
+use super::*;
+use anyhow::{bail, format_err, Error};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Which via path entries an alias responds to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AliasMatch {
+    /// A single address, like `RELAY` or `WIDE1-1`.
+    Exact(Ax25Address),
+
+    /// A `<prefix>n-N` alias like `WIDE2-1`, where `n` is the number of
+    /// hops requested (at most `max_n`) and the SSID `N` is the number of
+    /// hops remaining.
+    NN { prefix: String, max_n: u8 },
+}
+
+impl AliasMatch {
+    fn matches(&self, addr: &Ax25Address) -> bool {
+        match self {
+            AliasMatch::Exact(alias) => alias == addr,
+            AliasMatch::NN { prefix, max_n } => {
+                let call = addr.callsign();
+                let n = match call.strip_prefix(prefix.as_str()) {
+                    Some(n) if n.len() == 1 => n.as_bytes()[0].wrapping_sub(b'0'),
+                    _ => return false,
+                };
+                (1..=(*max_n).min(7)).contains(&n) && (1..=n).contains(&addr.ssid())
+            }
+        }
+    }
+}
+
+/// How to handle frames addressed to a digipeater alias.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AliasConfig {
+    pub matches: AliasMatch,
+
+    /// Replace the alias with our callsign once it is used up,
+    /// so stations can see which digipeater repeated the frame.
+    pub substitute: bool,
+
+    /// Insert our callsign before `n-N` aliases that still have hops
+    /// remaining. Ignored if the path is already full.
+    pub trace: bool,
+
+    /// Wait this long before repeating, and don't repeat at all if
+    /// another digipeater is heard repeating the frame first.
+    pub viscous_delay: Option<Duration>,
+}
+
+impl AliasConfig {
+    /// Creates an alias that substitutes and traces, without a viscous delay.
+    pub fn new(matches: AliasMatch) -> AliasConfig {
+        AliasConfig {
+            matches,
+            substitute: true,
+            trace: true,
+            viscous_delay: None,
+        }
+    }
+}
+
+/// Parses an alias like `WIDE1-1` or `WIDEn-N`, optionally followed by
+/// comma separated options: `max=<n>`, `nosub`, `notrace` and
+/// `viscous=<seconds>`. For example, `WIDEn-N,max=2,viscous=5`.
+impl FromStr for AliasConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let alias = parts.next().unwrap_or_default();
+
+        let mut ret = AliasConfig::new(match alias.strip_suffix("n-N") {
+            Some(prefix) => AliasMatch::NN {
+                prefix: Ax25Address::new(prefix, 0)?.callsign().to_string(),
+                max_n: 7,
+            },
+            None => AliasMatch::Exact(alias.parse()?),
+        });
+
+        for option in parts {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (option, None),
+            };
+            match (key, value, &mut ret.matches) {
+                ("max", Some(value), AliasMatch::NN { max_n, .. }) => {
+                    *max_n = value
+                        .parse()
+                        .map_err(|_| format_err!("Bad hop limit {:?}", value))?;
+                }
+                ("nosub", None, _) => ret.substitute = false,
+                ("notrace", None, _) => ret.trace = false,
+                ("viscous", Some(value), _) => {
+                    let secs = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|x| x.is_finite() && *x >= 0.0)
+                        .ok_or_else(|| format_err!("Bad viscous delay {:?}", value))?;
+                    ret.viscous_delay = Some(Duration::from_secs_f64(secs));
+                }
+                _ => bail!("Unknown alias option {:?}", option),
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Digipeater parameters.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DigipeaterConfig {
+    /// Our callsign. Frames addressed via it are always repeated.
+    pub callsign: Ax25Address,
+    pub aliases: Vec<AliasConfig>,
+
+    /// Frames repeated within this window are not repeated again.
+    pub dedupe_window: Duration,
+}
+
+impl DigipeaterConfig {
+    pub fn new(callsign: Ax25Address, aliases: Vec<AliasConfig>) -> DigipeaterConfig {
+        DigipeaterConfig {
+            callsign,
+            aliases,
+            dedupe_window: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A frame held back by a viscous delay.
+#[derive(Debug)]
+struct Delayed {
+    due: Instant,
+    key: Vec<u8>,
+    frame: Ax25Frame,
+}
+
+/// Decides which frames to repeat. See the module documentation.
+#[derive(Debug)]
+pub struct Digipeater {
+    config: DigipeaterConfig,
+
+    /// Keys of recently repeated frames and when they were repeated.
+    recent: VecDeque<(Instant, Vec<u8>)>,
+    delayed: Vec<Delayed>,
+}
+
+/// Returns the parts of the frame that don't change as it is repeated.
+fn dedupe_key(frame: &Ax25Frame) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(2 * AX25_ADDR_LEN + 2 + frame.info.len());
+    ret.extend(frame.dst.encode(false, false));
+    ret.extend(frame.src.encode(false, false));
+    ret.extend(frame.control.encode(frame.modulo));
+    ret.extend(frame.pid);
+    ret.extend_from_slice(&frame.info);
+    ret
+}
+
+impl Digipeater {
+    pub fn new(config: DigipeaterConfig) -> Digipeater {
+        Digipeater {
+            config,
+            recent: VecDeque::new(),
+            delayed: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &DigipeaterConfig {
+        &self.config
+    }
+
+    /// Handles a received frame, returning the frame to transmit if it
+    /// should be repeated right away.
+    pub fn handle_frame(&mut self, frame: &Ax25Frame, now: Instant) -> Option<Ax25Frame> {
+        self.expire(now);
+
+        let key = dedupe_key(frame);
+
+        // Another digipeater beat us to a frame we were holding. Count it
+        // as repeated, so we don't hold it again if we hear it once more.
+        if frame.digipeaters.iter().any(|x| x.repeated) {
+            let before = self.delayed.len();
+            self.delayed.retain(|x| x.key != key);
+            if self.delayed.len() != before {
+                self.recent.push_back((now, key));
+                return None;
+            }
+        }
+
+        let index = frame.next_digipeater()?;
+        let callsign = self.config.callsign;
+
+        if frame.src == callsign
+            || frame.digipeaters[..index]
+                .iter()
+                .any(|x| x.addr == callsign)
+            || self.recent.iter().any(|(_, x)| *x == key)
+            || self.delayed.iter().any(|x| x.key == key)
+        {
+            return None;
+        }
+
+        let addr = frame.digipeaters[index].addr;
+        let mut ret = frame.clone();
+
+        let delay = if addr == callsign {
+            ret.digipeaters[index].repeated = true;
+            None
+        } else {
+            let alias = self
+                .config
+                .aliases
+                .iter()
+                .find(|x| x.matches.matches(&addr))?;
+            Self::rewrite(alias, callsign, &mut ret, index);
+            alias.viscous_delay
+        };
+
+        match delay {
+            Some(delay) => {
+                self.delayed.push(Delayed {
+                    due: now + delay,
+                    key,
+                    frame: ret,
+                });
+                None
+            }
+            None => {
+                self.recent.push_back((now, key));
+                Some(ret)
+            }
+        }
+    }
+
+    /// Rewrites the via path of a frame we are repeating for `alias`.
+    fn rewrite(alias: &AliasConfig, callsign: Ax25Address, frame: &mut Ax25Frame, index: usize) {
+        let digi = &mut frame.digipeaters[index];
+        let used_up = match alias.matches {
+            AliasMatch::Exact(_) => true,
+            AliasMatch::NN { .. } => {
+                let ssid = digi.addr.ssid() - 1;
+                digi.addr = digi.addr.with_ssid(ssid).unwrap();
+                ssid == 0
+            }
+        };
+
+        let ours = Ax25Digipeater {
+            addr: callsign,
+            repeated: true,
+        };
+
+        if used_up && alias.substitute {
+            frame.digipeaters[index] = ours;
+        } else {
+            frame.digipeaters[index].repeated = used_up;
+            let traced = matches!(alias.matches, AliasMatch::NN { .. }) && alias.trace;
+            if traced && frame.digipeaters.len() < AX25_MAX_DIGIPEATERS {
+                frame.digipeaters.insert(index, ours);
+            }
+        }
+    }
+
+    /// Returns the next delayed frame that is due for transmission.
+    pub fn poll_delayed(&mut self, now: Instant) -> Option<Ax25Frame> {
+        self.expire(now);
+
+        let index = self.delayed.iter().position(|x| x.due <= now)?;
+        let delayed = self.delayed.remove(index);
+        self.recent.push_back((now, delayed.key));
+        Some(delayed.frame)
+    }
+
+    /// Returns when [`Digipeater::poll_delayed`] should next be called.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.delayed.iter().map(|x| x.due).min()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((time, _)) = self.recent.front() {
+            if now.saturating_duration_since(*time) < self.config.dedupe_window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recorded position report from WA8LMF via WIDE1-1, without FCS.
+    const RECORDED_FRAME: &str = "82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0d";
+
+    fn recorded_frame() -> Ax25Frame {
+        Ax25Frame::try_from_bytes(&hex::decode(RECORDED_FRAME).unwrap()).unwrap()
+    }
+
+    fn frame_via(path: &[&str]) -> Ax25Frame {
+        let mut frame = recorded_frame();
+        frame.digipeaters = path
+            .iter()
+            .map(|x| Ax25Digipeater {
+                addr: x.trim_end_matches('*').parse().unwrap(),
+                repeated: x.ends_with('*'),
+            })
+            .collect();
+        frame
+    }
+
+    fn path(frame: &Ax25Frame) -> String {
+        frame
+            .digipeaters
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn digipeater(aliases: &[&str]) -> Digipeater {
+        Digipeater::new(DigipeaterConfig::new(
+            "KZ2X-3".parse().unwrap(),
+            aliases.iter().map(|x| x.parse().unwrap()).collect(),
+        ))
+    }
+
+    #[test]
+    fn alias_config_from_str() {
+        let alias: AliasConfig = "WIDEn-N,max=2,notrace,viscous=5".parse().unwrap();
+        assert_eq!(
+            alias.matches,
+            AliasMatch::NN {
+                prefix: "WIDE".to_string(),
+                max_n: 2
+            }
+        );
+        assert!(alias.substitute);
+        assert!(!alias.trace);
+        assert_eq!(alias.viscous_delay, Some(Duration::from_secs(5)));
+
+        let alias: AliasConfig = "relay,nosub".parse().unwrap();
+        assert_eq!(alias.matches, AliasMatch::Exact("RELAY".parse().unwrap()));
+        assert!(!alias.substitute);
+
+        assert!("WIDE1-1,max=2".parse::<AliasConfig>().is_err());
+        assert!("WIDEn-N,bogus".parse::<AliasConfig>().is_err());
+        assert!("TOOLONGn-N".parse::<AliasConfig>().is_err());
+    }
+
+    #[test]
+    fn digipeater_recorded_frame() {
+        let mut digi = digipeater(&["WIDE1-1"]);
+        let now = Instant::now();
+
+        let repeated = digi.handle_frame(&recorded_frame(), now).unwrap();
+        assert_eq!(path(&repeated), "KZ2X-3*");
+        assert_eq!(repeated.info, recorded_frame().info);
+        assert_eq!(
+            repeated.to_string(),
+            "WA8LMF>APU25N,KZ2X-3* <UI C pid=F0 len=26>: >202337zhttp://wa8lmf.com."
+        );
+
+        // We hear ourselves, or the original again.
+        assert_eq!(digi.handle_frame(&repeated, now), None);
+        assert_eq!(digi.handle_frame(&recorded_frame(), now), None);
+
+        // Once the window has passed, it is repeated again.
+        let later = now + Duration::from_secs(31);
+        assert!(digi.handle_frame(&recorded_frame(), later).is_some());
+    }
+
+    #[test]
+    fn digipeater_wide_n_n() {
+        let now = Instant::now();
+        let cases = [
+            (&["WIDEn-N"][..], &["WIDE2-2"][..], Some("KZ2X-3*,WIDE2-1")),
+            (
+                &["WIDEn-N"],
+                &["WIDE1-1", "WIDE2-1"],
+                Some("KZ2X-3*,WIDE2-1"),
+            ),
+            (&["WIDEn-N"], &["N6DRC*", "WIDE2-1"], Some("N6DRC*,KZ2X-3*")),
+            (&["WIDEn-N,notrace"], &["WIDE3-3"], Some("WIDE3-2")),
+            (&["WIDEn-N,nosub"], &["WIDE2-1"], Some("KZ2X-3*,WIDE2*")),
+            (&["WIDEn-N,nosub,notrace"], &["WIDE2-1"], Some("WIDE2*")),
+            (&["WIDEn-N,max=2"], &["WIDE3-3"], None),
+            (&["WIDEn-N"], &["WIDE2-3"], None),
+            (&["WIDEn-N"], &["WIDE2*"], None),
+            (&["WIDEn-N"], &["TRACE2-2"], None),
+            (&["WIDEn-N"], &["KZ2X-3"], Some("KZ2X-3*")),
+            (&["WIDEn-N"], &["KZ2X-3*", "WIDE2-1"], None),
+            (&["RELAY"], &["RELAY", "WIDE2-2"], Some("KZ2X-3*,WIDE2-2")),
+            (
+                &["RELAY,nosub"],
+                &["RELAY", "WIDE2-2"],
+                Some("RELAY*,WIDE2-2"),
+            ),
+        ];
+
+        for (aliases, via, expected) in cases {
+            let mut digi = digipeater(aliases);
+            let repeated = digi.handle_frame(&frame_via(via), now);
+            assert_eq!(
+                repeated.as_ref().map(path).as_deref(),
+                expected,
+                "{:?} via {:?}",
+                aliases,
+                via
+            );
+        }
+    }
+
+    #[test]
+    fn digipeater_full_path() {
+        let now = Instant::now();
+        let mut digi = digipeater(&["WIDEn-N"]);
+        let via = ["A*", "B*", "C*", "D*", "E*", "F*", "G*", "WIDE7-2"];
+
+        let repeated = digi.handle_frame(&frame_via(&via), now).unwrap();
+        assert_eq!(path(&repeated), "A*,B*,C*,D*,E*,F*,G*,WIDE7-1");
+    }
+
+    #[test]
+    fn digipeater_viscous() {
+        let now = Instant::now();
+        let delay = Duration::from_secs(5);
+        let mut digi = digipeater(&["WIDE1-1,viscous=5"]);
+
+        // Held back, then sent once the delay passes.
+        assert_eq!(digi.handle_frame(&recorded_frame(), now), None);
+        assert_eq!(digi.next_timeout(), Some(now + delay));
+        assert_eq!(digi.poll_delayed(now), None);
+        let repeated = digi.poll_delayed(now + delay).unwrap();
+        assert_eq!(path(&repeated), "KZ2X-3*");
+        assert_eq!(digi.next_timeout(), None);
+
+        // Sent once even if heard twice while held back.
+        let mut digi = digipeater(&["WIDE1-1,viscous=5"]);
+        assert_eq!(digi.handle_frame(&recorded_frame(), now), None);
+        assert_eq!(digi.handle_frame(&recorded_frame(), now + delay / 2), None);
+        assert!(digi.poll_delayed(now + delay).is_some());
+        assert_eq!(digi.poll_delayed(now + delay * 2), None);
+        assert_eq!(digi.next_timeout(), None);
+
+        // Dropped if another digipeater repeats it first.
+        let mut digi = digipeater(&["WIDE1-1,viscous=5"]);
+        assert_eq!(digi.handle_frame(&recorded_frame(), now), None);
+        assert_eq!(digi.handle_frame(&recorded_frame(), now), None);
+        assert!(digi.next_timeout().is_some());
+        let other = frame_via(&["N6DRC*"]);
+        assert_eq!(digi.handle_frame(&other, now + delay / 2), None);
+        assert_eq!(digi.poll_delayed(now + delay), None);
+
+        // And isn't held again if heard once more afterwards.
+        assert_eq!(digi.handle_frame(&recorded_frame(), now + delay), None);
+        assert_eq!(digi.next_timeout(), None);
+        assert_eq!(digi.poll_delayed(now + delay * 2), None);
+    }
+}
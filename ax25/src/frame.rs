@@ -0,0 +1,362 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+use anyhow::{bail, Error};
+use std::fmt::{Display, Formatter};
+
+/// Maximum number of digipeater addresses in a frame.
+pub const AX25_MAX_DIGIPEATERS: usize = 8;
+
+/// The command/response bits of the destination and source addresses.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum CommandResponse {
+    Command,
+    Response,
+
+    /// Pre-v2.0 frame, with both bits equal to the given value.
+    Legacy(bool),
+}
+
+impl CommandResponse {
+    /// Returns the C-bits of the destination and source addresses.
+    pub fn bits(&self) -> (bool, bool) {
+        match *self {
+            Self::Command => (true, false),
+            Self::Response => (false, true),
+            Self::Legacy(x) => (x, x),
+        }
+    }
+
+    pub fn from_bits(dst: bool, src: bool) -> CommandResponse {
+        match (dst, src) {
+            (true, false) => Self::Command,
+            (false, true) => Self::Response,
+            (x, _) => Self::Legacy(x),
+        }
+    }
+}
+
+/// An AX.25 frame, without FCS.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Ax25Frame {
+    pub dst: Ax25Address,
+    pub src: Ax25Address,
+    pub digipeaters: Vec<Ax25Digipeater>,
+    pub command: CommandResponse,
+    pub modulo: Modulo,
+    pub control: Control,
+
+    /// Protocol identifier. Only present on I and UI frames.
+    pub pid: Option<u8>,
+    pub info: Vec<u8>,
+}
+
+impl Ax25Frame {
+    /// Creates a UI command frame.
+    pub fn ui(
+        dst: Ax25Address,
+        src: Ax25Address,
+        digipeaters: &[Ax25Address],
+        pid: u8,
+        info: &[u8],
+    ) -> Ax25Frame {
+        Ax25Frame {
+            dst,
+            src,
+            digipeaters: digipeaters
+                .iter()
+                .copied()
+                .map(Ax25Digipeater::new)
+                .collect(),
+            command: CommandResponse::Command,
+            modulo: Modulo::Mod8,
+            control: Control::UI,
+            pid: Some(pid),
+            info: info.to_vec(),
+        }
+    }
+
+    /// Parses a modulo-8 frame, without FCS.
+    pub fn try_from_bytes(frame: &[u8]) -> Result<Ax25Frame, Error> {
+        Self::try_from_bytes_with_modulo(frame, Modulo::Mod8)
+    }
+
+    /// Parses a frame without FCS, using `modulo` to decode I and S
+    /// control fields. The modulo is negotiated when a connection is
+    /// set up and cannot be determined from the frame alone.
+    pub fn try_from_bytes_with_modulo(frame: &[u8], modulo: Modulo) -> Result<Ax25Frame, Error> {
+        let addr_len = match frame.iter().position(|x| x & 1 != 0) {
+            Some(i) => i + 1,
+            None => bail!("Unterminated address field"),
+        };
+
+        if addr_len % AX25_ADDR_LEN != 0 {
+            bail!("Address field length {} is not a multiple of 7", addr_len);
+        }
+
+        let addr_count = addr_len / AX25_ADDR_LEN;
+        if addr_count < 2 {
+            bail!("Missing source address");
+        }
+        if addr_count > 2 + AX25_MAX_DIGIPEATERS {
+            bail!("Too many digipeaters");
+        }
+
+        let mut addrs = frame[..addr_len]
+            .chunks(AX25_ADDR_LEN)
+            .map(Ax25Address::decode);
+        let (dst, dst_c, _) = addrs.next().unwrap()?;
+        let (src, src_c, _) = addrs.next().unwrap()?;
+        let digipeaters = addrs
+            .map(|x| x.map(|(addr, repeated, _)| Ax25Digipeater { addr, repeated }))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (control, control_len) = Control::decode(&frame[addr_len..], modulo)?;
+        let mut rest = &frame[addr_len + control_len..];
+
+        let pid = if control.has_pid() {
+            match rest.split_first() {
+                Some((&pid, info)) => {
+                    rest = info;
+                    Some(pid)
+                }
+                None => bail!("Missing PID"),
+            }
+        } else {
+            None
+        };
+
+        if matches!(control, Control::S { .. }) && !rest.is_empty() {
+            bail!("Unexpected info field in S frame");
+        }
+
+        Ok(Ax25Frame {
+            dst,
+            src,
+            digipeaters,
+            command: CommandResponse::from_bits(dst_c, src_c),
+            modulo,
+            control,
+            pid,
+            info: rest.to_vec(),
+        })
+    }
+
+    /// Returns the encoded frame, without FCS.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        if self.digipeaters.len() > AX25_MAX_DIGIPEATERS {
+            bail!("Too many digipeaters");
+        }
+        if self.control.has_pid() != self.pid.is_some() {
+            bail!("PID must be present on I and UI frames only");
+        }
+
+        let (dst_c, src_c) = self.command.bits();
+        let mut ret =
+            Vec::with_capacity(AX25_ADDR_LEN * (2 + self.digipeaters.len()) + 3 + self.info.len());
+
+        ret.extend(self.dst.encode(dst_c, false));
+        ret.extend(self.src.encode(src_c, self.digipeaters.is_empty()));
+        for (i, digi) in self.digipeaters.iter().enumerate() {
+            ret.extend(
+                digi.addr
+                    .encode(digi.repeated, i + 1 == self.digipeaters.len()),
+            );
+        }
+        ret.extend(self.control.encode(self.modulo));
+        ret.extend(self.pid);
+        ret.extend_from_slice(&self.info);
+
+        Ok(ret)
+    }
+
+    /// Returns the digipeater that should handle this frame next, if any.
+    pub fn next_digipeater(&self) -> Option<usize> {
+        self.digipeaters.iter().position(|x| !x.repeated)
+    }
+}
+
+/// Formats the frame in a style similar to a TNC monitor, with
+/// non-printable characters in the info field replaced by `.`.
+impl Display for Ax25Frame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}>{}", self.src, self.dst)?;
+        for digi in self.digipeaters.iter() {
+            write!(f, ",{}", digi)?;
+        }
+
+        write!(f, " <{}", self.control)?;
+        match self.command {
+            CommandResponse::Command => write!(f, " C")?,
+            CommandResponse::Response => write!(f, " R")?,
+            CommandResponse::Legacy(_) => {}
+        }
+        if let Some(pid) = self.pid {
+            write!(f, " pid={:02X}", pid)?;
+        }
+        write!(f, " len={}>", self.info.len())?;
+
+        if !self.info.is_empty() {
+            let info = self
+                .info
+                .iter()
+                .map(|&x| {
+                    if x.is_ascii_graphic() || x == b' ' {
+                        x as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            write!(f, ": {}", info)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APRS_FRAME: &str = "82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0d";
+
+    #[test]
+    fn ax25_frame_decode_ui() {
+        let bytes = hex::decode(APRS_FRAME).unwrap();
+        let frame = Ax25Frame::try_from_bytes(&bytes).unwrap();
+
+        assert_eq!(frame.dst.to_string(), "APU25N");
+        assert_eq!(frame.src.to_string(), "WA8LMF");
+        assert_eq!(frame.digipeaters.len(), 1);
+        assert_eq!(frame.digipeaters[0].addr.to_string(), "WIDE1-1");
+        assert!(!frame.digipeaters[0].repeated);
+        assert_eq!(frame.command, CommandResponse::Command);
+        assert_eq!(frame.control, Control::UI);
+        assert_eq!(frame.pid, Some(PID_NO_L3));
+        assert_eq!(frame.info, b">202337zhttp://wa8lmf.com\r");
+        assert_eq!(frame.next_digipeater(), Some(0));
+
+        assert_eq!(
+            frame.to_string(),
+            "WA8LMF>APU25N,WIDE1-1 <UI C pid=F0 len=26>: >202337zhttp://wa8lmf.com."
+        );
+
+        assert_eq!(frame.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn ax25_frame_ui_constructor() {
+        let frame = Ax25Frame::ui(
+            "APU25N".parse().unwrap(),
+            "WA8LMF".parse().unwrap(),
+            &["WIDE1-1".parse().unwrap()],
+            PID_NO_L3,
+            b">202337zhttp://wa8lmf.com\r",
+        );
+        assert_eq!(hex::encode(frame.to_bytes().unwrap()), APRS_FRAME);
+    }
+
+    #[test]
+    fn ax25_frame_round_trip() {
+        let digipeaters = (1..=AX25_MAX_DIGIPEATERS)
+            .map(|i| Ax25Digipeater {
+                addr: Ax25Address::new("DIGI", i as u8).unwrap(),
+                repeated: i < 3,
+            })
+            .collect::<Vec<_>>();
+
+        let frames = [
+            Ax25Frame {
+                dst: "KZ2X-1".parse().unwrap(),
+                src: "N6DRC-15".parse().unwrap(),
+                digipeaters: digipeaters.clone(),
+                command: CommandResponse::Command,
+                modulo: Modulo::Mod128,
+                control: Control::I {
+                    ns: 99,
+                    nr: 12,
+                    poll: true,
+                },
+                pid: Some(PID_IP),
+                info: vec![0x45, 0x00, 0xC0, 0xFF],
+            },
+            Ax25Frame {
+                dst: "KZ2X-1".parse().unwrap(),
+                src: "N6DRC-15".parse().unwrap(),
+                digipeaters: vec![],
+                command: CommandResponse::Response,
+                modulo: Modulo::Mod8,
+                control: Control::S {
+                    kind: SupervisoryKind::Rnr,
+                    nr: 6,
+                    poll_final: true,
+                },
+                pid: None,
+                info: vec![],
+            },
+            Ax25Frame {
+                dst: "KZ2X".parse().unwrap(),
+                src: "N6DRC".parse().unwrap(),
+                digipeaters,
+                command: CommandResponse::Legacy(false),
+                modulo: Modulo::Mod8,
+                control: Control::U {
+                    kind: UnnumberedKind::Test,
+                    poll_final: false,
+                },
+                pid: None,
+                info: b"test".to_vec(),
+            },
+        ];
+
+        for frame in frames {
+            let bytes = frame.to_bytes().unwrap();
+            assert_eq!(
+                Ax25Frame::try_from_bytes_with_modulo(&bytes, frame.modulo).unwrap(),
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn ax25_frame_decode_errors() {
+        let bytes = hex::decode(APRS_FRAME).unwrap();
+
+        // Unterminated address field.
+        assert!(Ax25Frame::try_from_bytes(&bytes[..13]).is_err());
+
+        // Address field that is not a multiple of 7 bytes.
+        assert!(Ax25Frame::try_from_bytes(&hex::decode("82a0aa646a9ce0ae82").unwrap()).is_err());
+
+        // Missing control field.
+        assert!(Ax25Frame::try_from_bytes(&bytes[..21]).is_err());
+
+        // Missing PID.
+        assert!(Ax25Frame::try_from_bytes(&bytes[..22]).is_err());
+
+        // Too many digipeaters.
+        let mut frame = Ax25Frame::try_from_bytes(&bytes).unwrap();
+        frame.digipeaters = vec![frame.digipeaters[0]; AX25_MAX_DIGIPEATERS + 1];
+        assert!(frame.to_bytes().is_err());
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! AX.25 v2.2 frame codec.
+
+mod address;
+mod control;
+mod digipeater;
+mod frame;
+
+pub mod aprs;
+pub mod link;
+
+pub use address::*;
+pub use control::*;
+pub use digipeater::*;
+pub use frame::*;
+
+/// PID for frames carrying no layer 3 protocol.
+pub const PID_NO_L3: u8 = 0xF0;
+
+/// PID for frames carrying IP datagrams.
+pub const PID_IP: u8 = 0xCC;
+
+/// PID for frames carrying ARP.
+pub const PID_ARP: u8 = 0xCD;
+
+/// PID for frames carrying NET/ROM.
+pub const PID_NETROM: u8 = 0xCF;
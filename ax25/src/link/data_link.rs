@@ -0,0 +1,1091 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+use crate::*;
+use anyhow::Error;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Instant;
+
+/// The data link state machine for a single connection.
+#[derive(Debug)]
+pub struct Ax25Link {
+    local: Ax25Address,
+    remote: Ax25Address,
+    digipeaters: Vec<Ax25Address>,
+    config: LinkConfig,
+    modulo: Modulo,
+
+    /// Maximum number of outstanding I frames, which is
+    /// less than the modulus.
+    window: u8,
+    state: LinkState,
+
+    /// Send state variable V(S).
+    vs: u8,
+
+    /// Receive state variable V(R).
+    vr: u8,
+
+    /// Acknowledge state variable V(A).
+    va: u8,
+
+    /// Retry count (RC).
+    retries: u32,
+    t1: Option<Instant>,
+    t3: Option<Instant>,
+    peer_busy: bool,
+
+    /// Whether we've told the remote station we're busy.
+    own_busy: bool,
+    reject_exception: bool,
+    ack_pending: bool,
+
+    /// Whether we're connecting again after a protocol error.
+    reestablishing: bool,
+
+    /// N(R) of the outstanding SREJ we sent, if any.
+    srej_requested: Option<u8>,
+
+    /// Out-of-sequence I frames held until the missing
+    /// ones arrive, when using SREJ.
+    held: BTreeMap<u8, Vec<u8>>,
+
+    /// I frame payloads that have not been acknowledged,
+    /// starting with N(S) = V(A).
+    unacked: VecDeque<Vec<u8>>,
+
+    /// I frame payloads waiting for room in the window.
+    send_queue: VecDeque<Vec<u8>>,
+    transmit: VecDeque<Ax25Frame>,
+    events: VecDeque<LinkEvent>,
+}
+
+impl Ax25Link {
+    /// Creates a disconnected link. `digipeaters` is the path to `remote`.
+    /// Fails if `config` isn't valid.
+    pub fn new(
+        local: Ax25Address,
+        remote: Ax25Address,
+        digipeaters: &[Ax25Address],
+        config: LinkConfig,
+    ) -> Result<Ax25Link, Error> {
+        config.validate()?;
+        Ok(Ax25Link {
+            local,
+            remote,
+            digipeaters: digipeaters.to_vec(),
+            config,
+            modulo: config.modulo,
+            window: config.window,
+            state: LinkState::Disconnected,
+            vs: 0,
+            vr: 0,
+            va: 0,
+            retries: 0,
+            t1: None,
+            t3: None,
+            peer_busy: false,
+            own_busy: false,
+            reject_exception: false,
+            ack_pending: false,
+            reestablishing: false,
+            srej_requested: None,
+            held: BTreeMap::new(),
+            unacked: VecDeque::new(),
+            send_queue: VecDeque::new(),
+            transmit: VecDeque::new(),
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn state(&self) -> LinkState {
+        self.state
+    }
+
+    pub fn local(&self) -> Ax25Address {
+        self.local
+    }
+
+    pub fn remote(&self) -> Ax25Address {
+        self.remote
+    }
+
+    pub fn config(&self) -> LinkConfig {
+        self.config
+    }
+
+    /// The modulo in use, which determines how I and S frames are decoded.
+    pub fn modulo(&self) -> Modulo {
+        self.modulo
+    }
+
+    /// The maximum number of outstanding I frames for the modulo in use.
+    pub fn window(&self) -> u8 {
+        self.window
+    }
+
+    /// Returns true if the link is connected.
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, LinkState::Connected | LinkState::TimerRecovery)
+    }
+
+    /// Returns true if all queued data has been acknowledged.
+    pub fn is_flushed(&self) -> bool {
+        self.unacked.is_empty() && self.send_queue.is_empty()
+    }
+
+    /// Number of bytes queued or awaiting acknowledgement.
+    pub fn pending_len(&self) -> usize {
+        self.unacked
+            .iter()
+            .chain(self.send_queue.iter())
+            .map(Vec::len)
+            .sum()
+    }
+
+    /// Starts connecting to the remote station.
+    pub fn connect(&mut self, now: Instant) {
+        if self.state != LinkState::Disconnected {
+            return;
+        }
+        self.set_modulo(self.config.modulo);
+        self.retries = 0;
+        self.send_sabm();
+        self.start_t1(now);
+        self.state = LinkState::AwaitingConnection;
+    }
+
+    /// Starts disconnecting, discarding any data that has not been sent.
+    pub fn disconnect(&mut self, now: Instant) {
+        match self.state {
+            LinkState::Disconnected | LinkState::AwaitingRelease => {}
+            LinkState::AwaitingConnection => self.disconnected(DisconnectReason::Local),
+            LinkState::Connected | LinkState::TimerRecovery => {
+                self.send_queue.clear();
+                self.unacked.clear();
+                self.retries = 0;
+                self.send_u(UnnumberedKind::Disc, true, true);
+                self.t3 = None;
+                self.start_t1(now);
+                self.state = LinkState::AwaitingRelease;
+            }
+        }
+    }
+
+    /// Queues data to send, split into I frames of at most N1 bytes.
+    pub fn send(&mut self, data: &[u8], now: Instant) {
+        self.send_queue.extend(
+            data.chunks(self.config.max_info_len.max(1))
+                .map(<[u8]>::to_vec),
+        );
+        self.pump(now);
+    }
+
+    /// Sets whether we can take more I frames. While busy, the remote
+    /// station is sent RNR instead of RR, and I frames are discarded
+    /// rather than acknowledged. Once not busy, RR is sent so the remote
+    /// station sends them again.
+    pub fn set_busy(&mut self, busy: bool) {
+        if busy == self.own_busy {
+            return;
+        }
+        self.own_busy = busy;
+        if self.is_connected() {
+            self.send_s(SupervisoryKind::Rr, false, false);
+        }
+    }
+
+    /// Returns true if we've told the remote station we're busy.
+    pub fn is_busy(&self) -> bool {
+        self.own_busy
+    }
+
+    /// Returns the next frame to transmit, if any.
+    pub fn poll_transmit(&mut self) -> Option<Ax25Frame> {
+        self.transmit.pop_front()
+    }
+
+    /// Returns the next event, if any.
+    pub fn poll_event(&mut self) -> Option<LinkEvent> {
+        self.events.pop_front()
+    }
+
+    /// Returns when [`Ax25Link::handle_timeout`] should next be called.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        match (self.t1, self.t3) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Handles expired timers.
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if self.t1.is_some_and(|x| now >= x) {
+            self.t1 = None;
+            self.t1_expired(now);
+        }
+        if self.t3.is_some_and(|x| now >= x) {
+            self.t3 = None;
+            if self.state == LinkState::Connected {
+                self.retries = 0;
+                self.transmit_enquiry(now);
+                self.state = LinkState::TimerRecovery;
+            }
+        }
+        self.pump(now);
+    }
+
+    /// Handles a frame from the remote station to us. The frame must have
+    /// been decoded with [`Ax25Link::modulo`].
+    pub fn handle_frame(&mut self, frame: &Ax25Frame, now: Instant) {
+        let command = !matches!(frame.command, CommandResponse::Response);
+        let pf = frame.control.poll_final();
+
+        match frame.control {
+            Control::U { kind, .. } => self.handle_u(kind, pf, command, now),
+            _ if !self.is_connected() => {
+                if self.state == LinkState::Disconnected && command && pf {
+                    self.send_u(UnnumberedKind::Dm, true, false);
+                }
+            }
+            Control::S { kind, nr, .. } => self.handle_s(kind, nr, pf, command, now),
+            Control::I { ns, nr, .. } => self.handle_i(ns, nr, pf, &frame.info, now),
+        }
+
+        self.pump(now);
+    }
+
+    fn handle_u(&mut self, kind: UnnumberedKind, pf: bool, command: bool, now: Instant) {
+        use UnnumberedKind::*;
+
+        match (self.state, kind) {
+            (LinkState::AwaitingRelease, Sabm | Sabme) => self.send_u(Dm, pf, false),
+            (state, Sabm | Sabme) => {
+                self.set_modulo(if kind == Sabme {
+                    Modulo::Mod128
+                } else {
+                    Modulo::Mod8
+                });
+                self.send_u(Ua, pf, false);
+                let reestablishing = self.reset();
+                self.t1 = None;
+                self.start_t3(now);
+                self.state = LinkState::Connected;
+                match state {
+                    LinkState::AwaitingConnection if !reestablishing => {
+                        self.events.push_back(LinkEvent::Connected)
+                    }
+                    LinkState::Disconnected => self.events.push_back(LinkEvent::Connected),
+                    _ => self.events.push_back(LinkEvent::Reset),
+                }
+            }
+            (LinkState::Connected | LinkState::TimerRecovery, Disc) => {
+                self.send_u(Ua, pf, false);
+                self.disconnected(DisconnectReason::Remote);
+            }
+            (LinkState::AwaitingRelease, Disc) => self.send_u(Ua, pf, false),
+            (_, Disc) => self.send_u(Dm, pf, false),
+            (LinkState::AwaitingConnection, Ua) if pf => {
+                let reestablishing = self.reset();
+                self.t1 = None;
+                self.start_t3(now);
+                self.state = LinkState::Connected;
+                self.events.push_back(if reestablishing {
+                    LinkEvent::Reset
+                } else {
+                    LinkEvent::Connected
+                });
+            }
+            (LinkState::AwaitingConnection, Dm) if pf => {
+                self.disconnected(DisconnectReason::Refused);
+            }
+            (LinkState::AwaitingRelease, Ua | Dm) if pf => {
+                self.disconnected(DisconnectReason::Local);
+            }
+            (LinkState::Connected | LinkState::TimerRecovery, Dm) => {
+                self.disconnected(DisconnectReason::Remote);
+            }
+            (LinkState::Connected | LinkState::TimerRecovery, Frmr) => self.reestablish(now),
+            (LinkState::Disconnected, _) if command && pf => self.send_u(Dm, true, false),
+            _ => {}
+        }
+    }
+
+    fn handle_s(&mut self, kind: SupervisoryKind, nr: u8, pf: bool, command: bool, now: Instant) {
+        self.peer_busy = kind == SupervisoryKind::Rnr;
+
+        if command && pf {
+            self.send_s(SupervisoryKind::Rr, true, false);
+        }
+
+        if !self.nr_valid(nr) {
+            self.reestablish(now);
+            return;
+        }
+
+        if self.state == LinkState::TimerRecovery && !command && pf {
+            // Response to our poll: leave timer recovery.
+            self.ack_to(nr);
+            self.t1 = None;
+            self.state = LinkState::Connected;
+            if kind == SupervisoryKind::Srej {
+                self.retransmit_one(nr);
+            } else {
+                self.vs = self.va;
+            }
+            if self.unacked.is_empty() {
+                self.start_t3(now);
+            } else {
+                self.start_t1(now);
+            }
+            return;
+        }
+
+        match kind {
+            SupervisoryKind::Rr | SupervisoryKind::Rnr => {
+                if self.state == LinkState::Connected {
+                    self.check_acked(nr, now);
+                } else {
+                    self.ack_to(nr);
+                }
+            }
+            SupervisoryKind::Rej => {
+                self.ack_to(nr);
+                self.vs = self.va;
+                if self.state == LinkState::Connected {
+                    self.t1 = None;
+                    self.start_t3(now);
+                }
+            }
+            SupervisoryKind::Srej => self.retransmit_one(nr),
+        }
+    }
+
+    fn handle_i(&mut self, ns: u8, nr: u8, pf: bool, info: &[u8], now: Instant) {
+        if !self.nr_valid(nr) {
+            self.reestablish(now);
+            return;
+        }
+
+        if self.state == LinkState::Connected {
+            self.check_acked(nr, now);
+        } else {
+            self.ack_to(nr);
+        }
+
+        if self.own_busy {
+            if pf {
+                self.send_s(SupervisoryKind::Rnr, true, false);
+            }
+        } else if ns == self.vr {
+            self.vr = self.inc(self.vr);
+            self.reject_exception = false;
+            self.srej_requested = None;
+            self.events.push_back(LinkEvent::Data(info.to_vec()));
+
+            while let Some(data) = self.held.remove(&self.vr) {
+                self.vr = self.inc(self.vr);
+                self.events.push_back(LinkEvent::Data(data));
+            }
+
+            if pf {
+                self.send_s(SupervisoryKind::Rr, true, false);
+            } else {
+                self.ack_pending = true;
+            }
+        } else if self.config.srej && self.sub(ns, self.vr) < self.window {
+            self.held.insert(ns, info.to_vec());
+            if self.srej_requested != Some(self.vr) || pf {
+                self.srej_requested = Some(self.vr);
+                self.send_s(SupervisoryKind::Srej, pf, false);
+            }
+        } else if self.reject_exception {
+            if pf {
+                self.send_s(SupervisoryKind::Rr, true, false);
+            }
+        } else {
+            self.reject_exception = true;
+            self.send_s(SupervisoryKind::Rej, pf, false);
+        }
+    }
+
+    fn t1_expired(&mut self, now: Instant) {
+        match self.state {
+            LinkState::Disconnected => {}
+            _ if self.retries >= self.config.max_retries => {
+                if self.is_connected() {
+                    self.send_u(UnnumberedKind::Dm, false, false);
+                }
+                self.disconnected(DisconnectReason::Timeout);
+            }
+            LinkState::AwaitingConnection => {
+                self.retries += 1;
+                self.send_sabm();
+                self.start_t1(now);
+            }
+            LinkState::AwaitingRelease => {
+                self.retries += 1;
+                self.send_u(UnnumberedKind::Disc, true, true);
+                self.start_t1(now);
+            }
+            LinkState::Connected | LinkState::TimerRecovery => {
+                self.retries += 1;
+                self.transmit_enquiry(now);
+                self.state = LinkState::TimerRecovery;
+            }
+        }
+    }
+
+    /// Sends waiting I frames as the window allows, then any pending ack.
+    fn pump(&mut self, now: Instant) {
+        if self.state == LinkState::Connected && !self.peer_busy {
+            loop {
+                let outstanding = self.sub(self.vs, self.va) as usize;
+                if outstanding >= self.window as usize {
+                    break;
+                }
+                if outstanding >= self.unacked.len() {
+                    match self.send_queue.pop_front() {
+                        Some(data) => self.unacked.push_back(data),
+                        None => break,
+                    }
+                }
+
+                let ns = self.vs;
+                self.send_i(ns, outstanding);
+                self.vs = self.inc(self.vs);
+
+                if self.t1.is_none() {
+                    self.t3 = None;
+                    self.start_t1(now);
+                }
+            }
+        }
+
+        if self.ack_pending {
+            self.send_s(SupervisoryKind::Rr, false, false);
+        }
+    }
+
+    /// Re-establishes the link after a protocol error.
+    fn reestablish(&mut self, now: Instant) {
+        self.reestablishing = true;
+        self.retries = 0;
+        self.send_sabm();
+        self.t3 = None;
+        self.start_t1(now);
+        self.state = LinkState::AwaitingConnection;
+    }
+
+    fn disconnected(&mut self, reason: DisconnectReason) {
+        self.state = LinkState::Disconnected;
+        self.reestablishing = false;
+        self.t1 = None;
+        self.t3 = None;
+        self.send_queue.clear();
+        self.unacked.clear();
+        self.held.clear();
+        self.own_busy = false;
+        self.events.push_back(LinkEvent::Disconnected(reason));
+    }
+
+    /// Resets the state variables when the link is (re-)established.
+    /// Unacknowledged I frames are queued to be sent again. Returns true
+    /// if we were re-establishing the link.
+    fn reset(&mut self) -> bool {
+        self.vs = 0;
+        self.vr = 0;
+        self.va = 0;
+        self.retries = 0;
+        self.peer_busy = false;
+        self.reject_exception = false;
+        self.ack_pending = false;
+        self.srej_requested = None;
+        self.held.clear();
+        while let Some(data) = self.unacked.pop_back() {
+            self.send_queue.push_front(data);
+        }
+        std::mem::take(&mut self.reestablishing)
+    }
+
+    /// Sets the modulo, limiting the window to one less than the modulus
+    /// so that N(S) never wraps around to an unacknowledged frame.
+    fn set_modulo(&mut self, modulo: Modulo) {
+        self.modulo = modulo;
+        self.window = self.config.window.min(modulo.modulus() - 1);
+    }
+
+    fn start_t1(&mut self, now: Instant) {
+        self.t1 = Some(now + self.config.t1);
+    }
+
+    fn start_t3(&mut self, now: Instant) {
+        self.t3 = Some(now + self.config.t3);
+    }
+
+    fn inc(&self, x: u8) -> u8 {
+        (x + 1) % self.modulo.modulus()
+    }
+
+    fn sub(&self, a: u8, b: u8) -> u8 {
+        a.wrapping_sub(b) % self.modulo.modulus()
+    }
+
+    /// Returns true if V(A) <= N(R) <= V(S).
+    fn nr_valid(&self, nr: u8) -> bool {
+        self.sub(nr, self.va) <= self.sub(self.vs, self.va)
+    }
+
+    /// Removes acknowledged frames, up to but not including N(R).
+    fn ack_to(&mut self, nr: u8) {
+        let count = self.sub(nr, self.va) as usize;
+        if count > 0 {
+            self.unacked.drain(..count.min(self.unacked.len()));
+            self.va = nr;
+            self.retries = 0;
+        }
+    }
+
+    fn check_acked(&mut self, nr: u8, now: Instant) {
+        if nr == self.vs {
+            self.ack_to(nr);
+            self.t1 = None;
+            self.start_t3(now);
+        } else if nr != self.va {
+            self.ack_to(nr);
+            self.start_t1(now);
+        }
+    }
+
+    fn retransmit_one(&mut self, ns: u8) {
+        let index = self.sub(ns, self.va) as usize;
+        if index < self.unacked.len() {
+            self.send_i(ns, index);
+        }
+    }
+
+    fn transmit_enquiry(&mut self, now: Instant) {
+        self.send_s(SupervisoryKind::Rr, true, true);
+        self.start_t1(now);
+    }
+
+    fn send_sabm(&mut self) {
+        let kind = match self.modulo {
+            Modulo::Mod8 => UnnumberedKind::Sabm,
+            Modulo::Mod128 => UnnumberedKind::Sabme,
+        };
+        self.send_u(kind, true, true);
+    }
+
+    fn send_u(&mut self, kind: UnnumberedKind, poll_final: bool, command: bool) {
+        self.send_frame(Control::U { kind, poll_final }, command, None);
+    }
+
+    /// Sends an S frame. RR is sent as RNR while we're busy.
+    fn send_s(&mut self, kind: SupervisoryKind, poll_final: bool, command: bool) {
+        let kind = match kind {
+            SupervisoryKind::Rr if self.own_busy => SupervisoryKind::Rnr,
+            kind => kind,
+        };
+        let control = Control::S {
+            kind,
+            nr: self.vr,
+            poll_final,
+        };
+        self.ack_pending = false;
+        self.send_frame(control, command, None);
+    }
+
+    fn send_i(&mut self, ns: u8, index: usize) {
+        let control = Control::I {
+            ns,
+            nr: self.vr,
+            poll: false,
+        };
+        let info = self.unacked[index].clone();
+        self.ack_pending = false;
+        self.send_frame(control, true, Some(info));
+    }
+
+    fn send_frame(&mut self, control: Control, command: bool, info: Option<Vec<u8>>) {
+        self.transmit.push_back(Ax25Frame {
+            dst: self.remote,
+            src: self.local,
+            digipeaters: self
+                .digipeaters
+                .iter()
+                .copied()
+                .map(Ax25Digipeater::new)
+                .collect(),
+            command: if command {
+                CommandResponse::Command
+            } else {
+                CommandResponse::Response
+            },
+            modulo: self.modulo,
+            control,
+            pid: info.as_ref().map(|_| PID_NO_L3),
+            info: info.unwrap_or_default(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct Pair {
+        a: Ax25Link,
+        b: Ax25Link,
+        now: Instant,
+    }
+
+    impl Pair {
+        fn new(config: LinkConfig) -> Pair {
+            let a = "KZ2X-1".parse().unwrap();
+            let b = "N6DRC-2".parse().unwrap();
+            Pair {
+                a: Ax25Link::new(a, b, &[], config).unwrap(),
+                b: Ax25Link::new(b, a, &[], config).unwrap(),
+                now: Instant::now(),
+            }
+        }
+
+        /// Delivers frames in both directions until both links are quiet,
+        /// dropping frames for which `drop` returns true.
+        fn run<F: FnMut(&Ax25Frame) -> bool>(&mut self, mut drop: F) -> usize {
+            let mut count = 0;
+            loop {
+                let mut moved = false;
+                while let Some(frame) = self.a.poll_transmit() {
+                    moved = true;
+                    count += 1;
+                    if !drop(&frame) {
+                        self.b.handle_frame(&frame, self.now);
+                    }
+                }
+                while let Some(frame) = self.b.poll_transmit() {
+                    moved = true;
+                    count += 1;
+                    if !drop(&frame) {
+                        self.a.handle_frame(&frame, self.now);
+                    }
+                }
+                if !moved {
+                    return count;
+                }
+            }
+        }
+
+        fn advance(&mut self, duration: Duration) {
+            self.now += duration;
+            self.a.handle_timeout(self.now);
+            self.b.handle_timeout(self.now);
+        }
+
+        fn connect(&mut self) {
+            self.a.connect(self.now);
+            self.run(|_| false);
+            assert_eq!(self.a.poll_event(), Some(LinkEvent::Connected));
+            assert_eq!(self.b.poll_event(), Some(LinkEvent::Connected));
+        }
+    }
+
+    fn received(link: &mut Ax25Link) -> Vec<u8> {
+        let mut ret = vec![];
+        while let Some(event) = link.poll_event() {
+            match event {
+                LinkEvent::Data(data) => ret.extend(data),
+                x => panic!("Unexpected {:?}", x),
+            }
+        }
+        ret
+    }
+
+    fn is_i_frame(frame: &Ax25Frame, n: u8) -> bool {
+        matches!(frame.control, Control::I { ns, .. } if ns == n)
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|x| x as u8).collect()
+    }
+
+    #[test]
+    fn ax25_link_connect_disconnect() {
+        let mut pair = Pair::new(LinkConfig::default());
+
+        pair.a.connect(pair.now);
+        let sabm = pair.a.poll_transmit().unwrap();
+        assert_eq!(
+            sabm.control,
+            Control::U {
+                kind: UnnumberedKind::Sabm,
+                poll_final: true
+            }
+        );
+        assert_eq!(sabm.command, CommandResponse::Command);
+        pair.b.handle_frame(&sabm, pair.now);
+        pair.run(|_| false);
+
+        assert_eq!(pair.a.state(), LinkState::Connected);
+        assert_eq!(pair.b.state(), LinkState::Connected);
+        assert_eq!(pair.a.poll_event(), Some(LinkEvent::Connected));
+        assert_eq!(pair.b.poll_event(), Some(LinkEvent::Connected));
+
+        pair.b.disconnect(pair.now);
+        pair.run(|_| false);
+        assert_eq!(
+            pair.a.poll_event(),
+            Some(LinkEvent::Disconnected(DisconnectReason::Remote))
+        );
+        assert_eq!(
+            pair.b.poll_event(),
+            Some(LinkEvent::Disconnected(DisconnectReason::Local))
+        );
+    }
+
+    #[test]
+    fn ax25_link_refused() {
+        let mut pair = Pair::new(LinkConfig::default());
+        pair.a.connect(pair.now);
+        let sabm = pair.a.poll_transmit().unwrap();
+
+        // A station that is not listening answers with DM.
+        let mut dm = sabm.clone();
+        std::mem::swap(&mut dm.src, &mut dm.dst);
+        dm.command = CommandResponse::Response;
+        dm.control = Control::U {
+            kind: UnnumberedKind::Dm,
+            poll_final: true,
+        };
+        pair.a.handle_frame(&dm, pair.now);
+
+        assert_eq!(
+            pair.a.poll_event(),
+            Some(LinkEvent::Disconnected(DisconnectReason::Refused))
+        );
+    }
+
+    #[test]
+    fn ax25_link_connect_timeout() {
+        let config = LinkConfig::default();
+        let mut pair = Pair::new(config);
+        pair.a.connect(pair.now);
+
+        let mut sabms = 0;
+        for _ in 0..=config.max_retries {
+            sabms += pair.run(|_| true);
+            pair.advance(config.t1);
+        }
+
+        assert_eq!(sabms as u32, config.max_retries + 1);
+        assert_eq!(
+            pair.a.poll_event(),
+            Some(LinkEvent::Disconnected(DisconnectReason::Timeout))
+        );
+        assert_eq!(pair.a.next_timeout(), None);
+    }
+
+    #[test]
+    fn ax25_link_data_transfer() {
+        let config = LinkConfig {
+            max_info_len: 10,
+            ..Default::default()
+        };
+        let mut pair = Pair::new(config);
+        pair.connect();
+
+        let data = payload(1000);
+        pair.a.send(&data, pair.now);
+
+        // The window limits the number of outstanding I frames.
+        let mut outstanding = 0;
+        while let Some(frame) = pair.a.poll_transmit() {
+            assert!(is_i_frame(&frame, outstanding));
+            outstanding += 1;
+            pair.b.handle_frame(&frame, pair.now);
+        }
+        assert_eq!(outstanding, config.window);
+
+        pair.run(|_| false);
+        assert_eq!(received(&mut pair.b), data);
+        assert!(pair.a.is_flushed());
+
+        // Idle: T1 is stopped and T3 is running.
+        assert_eq!(pair.a.next_timeout(), Some(pair.now + config.t3));
+    }
+
+    #[test]
+    fn ax25_link_config_window() {
+        let a = "KZ2X-1".parse().unwrap();
+        let b = "N6DRC-2".parse().unwrap();
+
+        for (modulo, window, valid) in [
+            (Modulo::Mod8, 0, false),
+            (Modulo::Mod8, 7, true),
+            (Modulo::Mod8, 8, false),
+            (Modulo::Mod128, 32, true),
+            (Modulo::Mod128, 127, true),
+            (Modulo::Mod128, 128, false),
+        ] {
+            let config = LinkConfig {
+                modulo,
+                window,
+                ..Default::default()
+            };
+            assert_eq!(config.validate().is_ok(), valid, "{:?}", config);
+            assert_eq!(Ax25Link::new(a, b, &[], config).is_ok(), valid);
+        }
+
+        // A station configured for mod-128 accepting a mod-8 connection
+        // limits its window to 7.
+        let config = LinkConfig {
+            max_info_len: 10,
+            modulo: Modulo::Mod128,
+            window: 32,
+            ..Default::default()
+        };
+        let mut pair = Pair::new(LinkConfig::default());
+        pair.b = Ax25Link::new(b, a, &[], config).unwrap();
+        pair.connect();
+        assert_eq!(pair.b.modulo(), Modulo::Mod8);
+        assert_eq!(pair.b.window(), 7);
+
+        let data = payload(1000);
+        pair.b.send(&data, pair.now);
+        let mut outstanding = 0;
+        while let Some(frame) = pair.b.poll_transmit() {
+            assert!(is_i_frame(&frame, outstanding));
+            outstanding += 1;
+            pair.a.handle_frame(&frame, pair.now);
+        }
+        assert_eq!(outstanding, 7);
+
+        pair.run(|_| false);
+        assert_eq!(received(&mut pair.a), data);
+    }
+
+    #[test]
+    fn ax25_link_reject_recovery() {
+        let config = LinkConfig {
+            max_info_len: 10,
+            ..Default::default()
+        };
+        let mut pair = Pair::new(config);
+        pair.connect();
+
+        let data = payload(200);
+        pair.a.send(&data, pair.now);
+
+        // Lose the second I frame once.
+        let mut dropped = false;
+        pair.run(|frame| {
+            if !dropped && is_i_frame(frame, 1) {
+                dropped = true;
+                return true;
+            }
+            false
+        });
+
+        assert!(dropped);
+        assert_eq!(received(&mut pair.b), data);
+        assert!(pair.a.is_flushed());
+    }
+
+    #[test]
+    fn ax25_link_srej_recovery() {
+        let config = LinkConfig {
+            max_info_len: 10,
+            modulo: Modulo::Mod128,
+            window: 32,
+            srej: true,
+            ..Default::default()
+        };
+        let mut pair = Pair::new(config);
+        pair.connect();
+        assert_eq!(pair.b.modulo(), Modulo::Mod128);
+
+        let data = payload(1000);
+        pair.a.send(&data, pair.now);
+
+        let mut dropped = false;
+        let mut retransmitted = 0;
+        pair.run(|frame| {
+            if is_i_frame(frame, 3) {
+                if dropped {
+                    retransmitted += 1;
+                } else {
+                    dropped = true;
+                    return true;
+                }
+            }
+            false
+        });
+
+        // Only the lost frame is sent again.
+        assert_eq!(retransmitted, 1);
+        assert_eq!(received(&mut pair.b), data);
+        assert!(pair.a.is_flushed());
+    }
+
+    #[test]
+    fn ax25_link_reestablish() {
+        let config = LinkConfig {
+            max_info_len: 10,
+            ..Default::default()
+        };
+        let mut pair = Pair::new(config);
+        pair.connect();
+
+        // Lose the I frames, then receive an RR acknowledging
+        // frames that were never sent.
+        let data = payload(30);
+        pair.a.send(&data, pair.now);
+        assert_eq!(pair.run(|_| true), 3);
+        let rr = Ax25Frame {
+            dst: pair.a.local(),
+            src: pair.b.local(),
+            digipeaters: vec![],
+            command: CommandResponse::Response,
+            modulo: Modulo::Mod8,
+            control: Control::S {
+                kind: SupervisoryKind::Rr,
+                nr: 5,
+                poll_final: false,
+            },
+            pid: None,
+            info: vec![],
+        };
+        pair.a.handle_frame(&rr, pair.now);
+        assert_eq!(pair.a.state(), LinkState::AwaitingConnection);
+
+        // Both ends report the reset, and the data is sent again.
+        pair.run(|_| false);
+        assert_eq!(pair.a.poll_event(), Some(LinkEvent::Reset));
+        assert_eq!(pair.b.poll_event(), Some(LinkEvent::Reset));
+        assert_eq!(received(&mut pair.b), data);
+        assert!(pair.a.is_flushed());
+        assert_eq!(pair.a.poll_event(), None);
+    }
+
+    #[test]
+    fn ax25_link_busy() {
+        let config = LinkConfig {
+            max_info_len: 10,
+            ..Default::default()
+        };
+        let mut pair = Pair::new(config);
+        pair.connect();
+
+        pair.b.set_busy(true);
+        let rnr = pair.b.poll_transmit().unwrap();
+        assert!(matches!(
+            rnr.control,
+            Control::S {
+                kind: SupervisoryKind::Rnr,
+                ..
+            }
+        ));
+
+        // I frames sent before the RNR arrives are discarded.
+        let data = payload(100);
+        pair.a.send(&data, pair.now);
+        let mut sent = 0;
+        while let Some(frame) = pair.a.poll_transmit() {
+            sent += 1;
+            pair.b.handle_frame(&frame, pair.now);
+        }
+        assert_eq!(sent, config.window);
+        assert_eq!(received(&mut pair.b), vec![]);
+
+        // Then nothing more is sent until the RR.
+        pair.a.handle_frame(&rnr, pair.now);
+        assert_eq!(pair.run(|_| false), 0);
+        pair.advance(config.t1);
+        pair.run(|_| false);
+        assert_eq!(received(&mut pair.b), vec![]);
+
+        pair.b.set_busy(false);
+        pair.run(|_| false);
+        pair.advance(config.t1);
+        pair.run(|_| false);
+        assert_eq!(received(&mut pair.b), data);
+        assert!(pair.a.is_flushed());
+    }
+
+    #[test]
+    fn ax25_link_timer_recovery() {
+        let config = LinkConfig::default();
+        let mut pair = Pair::new(config);
+        pair.connect();
+
+        // Lose the I frame and its retransmission after T1.
+        pair.a.send(b"hello", pair.now);
+        pair.run(|_| true);
+        pair.advance(config.t1);
+        assert_eq!(pair.a.state(), LinkState::TimerRecovery);
+
+        let enquiry = pair.a.poll_transmit().unwrap();
+        assert_eq!(
+            enquiry.control,
+            Control::S {
+                kind: SupervisoryKind::Rr,
+                nr: 0,
+                poll_final: true
+            }
+        );
+        pair.b.handle_frame(&enquiry, pair.now);
+        pair.run(|_| false);
+
+        assert_eq!(pair.a.state(), LinkState::Connected);
+        assert_eq!(received(&mut pair.b), b"hello");
+        assert!(pair.a.is_flushed());
+
+        // T3 polls an idle link; the remote station stops responding.
+        for _ in 0..config.max_retries + 2 {
+            pair.advance(config.t3.max(config.t1));
+            pair.run(|_| true);
+        }
+        assert_eq!(
+            pair.a.poll_event(),
+            Some(LinkEvent::Disconnected(DisconnectReason::Timeout))
+        );
+    }
+
+    #[test]
+    fn ax25_link_disconnected_responds_dm() {
+        let mut pair = Pair::new(LinkConfig::default());
+        pair.connect();
+
+        pair.b =
+            Ax25Link::new(pair.b.local(), pair.b.remote(), &[], LinkConfig::default()).unwrap();
+        pair.a.send(b"x", pair.now);
+        pair.advance(LinkConfig::default().t1);
+        pair.run(|_| false);
+
+        assert_eq!(
+            pair.a.poll_event(),
+            Some(LinkEvent::Disconnected(DisconnectReason::Remote))
+        );
+    }
+}
@@ -0,0 +1,776 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+use crate::*;
+use anyhow::Error;
+use futures::future::poll_fn;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::prelude::*;
+use futures::select;
+use futures_timer::Delay;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+const FCS: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_SDLC);
+
+/// Appends the FCS to a frame, least-significant byte first.
+fn append_fcs(mut frame: Vec<u8>) -> Vec<u8> {
+    let fcs = FCS.checksum(&frame);
+    frame.extend(fcs.to_le_bytes());
+    frame
+}
+
+/// Checks the FCS at the end of a frame, returning the frame without it.
+fn verify_fcs(frame: &[u8]) -> Option<&[u8]> {
+    let (frame, fcs) = frame.split_at(frame.len().checked_sub(2)?);
+    (FCS.checksum(frame).to_le_bytes() == fcs).then_some(frame)
+}
+
+/// Drives AX.25 connections for a single local address.
+///
+/// [`Ax25LinkLayer::run`] must be polled for any connection to make
+/// progress. Connections are created with [`Ax25LinkLayer::connect`]
+/// or, after calling [`Ax25LinkLayer::listen`], [`Ax25LinkLayer::accept`].
+#[derive(Clone)]
+pub struct Ax25LinkLayer {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Slot {
+    link: Ax25Link,
+
+    /// Whether the link has connected, even if it's since disconnected.
+    connected: bool,
+    received: VecDeque<u8>,
+    reason: Option<DisconnectReason>,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+    dropped: bool,
+}
+
+impl Slot {
+    /// Returns true if data can be sent: the link is connected, or is
+    /// being re-established (connections are only handed out once
+    /// connected, so that's the only way one can be connecting).
+    fn is_open(&self) -> bool {
+        self.link.is_connected() || self.link.state() == LinkState::AwaitingConnection
+    }
+}
+
+struct Inner {
+    local: Ax25Address,
+    config: LinkConfig,
+    listening: bool,
+    next_id: u64,
+    slots: HashMap<u64, Slot>,
+
+    /// Maps remote addresses to the slot of their connection.
+    active: HashMap<Ax25Address, u64>,
+    incoming: VecDeque<u64>,
+    accept_waker: Option<Waker>,
+
+    /// Frames not belonging to any connection, like DM responses.
+    stray: VecDeque<Ax25Frame>,
+    nudged: bool,
+    driver_waker: Option<Waker>,
+}
+
+impl Inner {
+    fn nudge(&mut self) {
+        self.nudged = true;
+        if let Some(waker) = self.driver_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn add_slot(&mut self, link: Ax25Link) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.active.insert(link.remote(), id);
+        self.slots.insert(
+            id,
+            Slot {
+                link,
+                connected: false,
+                received: VecDeque::new(),
+                reason: None,
+                reader: None,
+                writer: None,
+                dropped: false,
+            },
+        );
+        id
+    }
+
+    /// Handles a received frame, with FCS.
+    fn receive(&mut self, bytes: &[u8], now: Instant) {
+        let bytes = match verify_fcs(bytes) {
+            Some(bytes) if bytes.len() > 2 * AX25_ADDR_LEN => bytes,
+            _ => return,
+        };
+
+        let (dst, src) = match (
+            Ax25Address::decode(&bytes[..AX25_ADDR_LEN]),
+            Ax25Address::decode(&bytes[AX25_ADDR_LEN..]),
+        ) {
+            (Ok((dst, _, _)), Ok((src, _, _))) => (dst, src),
+            _ => return,
+        };
+        if dst != self.local {
+            return;
+        }
+
+        let id = self.active.get(&src).copied();
+        let modulo = id
+            .and_then(|id| self.slots.get(&id))
+            .map_or(Modulo::Mod8, |slot| slot.link.modulo());
+
+        let frame = match Ax25Frame::try_from_bytes_with_modulo(bytes, modulo) {
+            Ok(frame) => frame,
+            Err(_) => return,
+        };
+        if frame.next_digipeater().is_some() {
+            // Still on its way.
+            return;
+        }
+
+        if let Some(id) = id {
+            if let Some(slot) = self.slots.get_mut(&id) {
+                slot.link.handle_frame(&frame, now);
+            }
+            return;
+        }
+
+        // The path back is the reverse of the path the frame took.
+        let digipeaters = frame
+            .digipeaters
+            .iter()
+            .rev()
+            .map(|x| x.addr)
+            .collect::<Vec<_>>();
+
+        let connect = matches!(
+            frame.control,
+            Control::U {
+                kind: UnnumberedKind::Sabm | UnnumberedKind::Sabme,
+                ..
+            }
+        );
+
+        if connect && !self.listening {
+            self.stray.push_back(Ax25Frame {
+                dst: src,
+                src: self.local,
+                digipeaters: digipeaters.into_iter().map(Ax25Digipeater::new).collect(),
+                command: CommandResponse::Response,
+                modulo: Modulo::Mod8,
+                control: Control::U {
+                    kind: UnnumberedKind::Dm,
+                    poll_final: frame.control.poll_final(),
+                },
+                pid: None,
+                info: vec![],
+            });
+            return;
+        }
+
+        let mut link = Ax25Link::new(self.local, src, &digipeaters, self.config)
+            .expect("checked in Ax25LinkLayer::new");
+        link.handle_frame(&frame, now);
+
+        if connect {
+            let id = self.add_slot(link);
+            self.incoming.push_back(id);
+            if let Some(waker) = self.accept_waker.take() {
+                waker.wake();
+            }
+        } else {
+            self.stray
+                .extend(std::iter::from_fn(|| link.poll_transmit()));
+        }
+    }
+
+    /// Handles timeouts and events, returning the frames to
+    /// transmit (with FCS) and when to call again.
+    fn service(&mut self, now: Instant) -> (Vec<Vec<u8>>, Option<Instant>) {
+        let mut frames = self.stray.drain(..).collect::<Vec<_>>();
+        let mut next_timeout: Option<Instant> = None;
+
+        for slot in self.slots.values_mut() {
+            if slot.link.next_timeout().is_some_and(|x| x <= now) {
+                slot.link.handle_timeout(now);
+            }
+
+            while let Some(event) = slot.link.poll_event() {
+                match event {
+                    LinkEvent::Connected => slot.connected = true,
+                    LinkEvent::Reset => {}
+                    LinkEvent::Data(data) => {
+                        slot.received.extend(data);
+                        if slot.received.len() >= self.config.max_received {
+                            slot.link.set_busy(true);
+                        }
+                    }
+                    LinkEvent::Disconnected(reason) => {
+                        slot.reason = Some(reason);
+                        self.active.remove(&slot.link.remote());
+                    }
+                }
+                if let Some(waker) = slot.reader.take() {
+                    waker.wake();
+                }
+            }
+
+            frames.extend(std::iter::from_fn(|| slot.link.poll_transmit()));
+
+            // Acknowledgements may have made room to write.
+            if let Some(waker) = slot.writer.take() {
+                waker.wake();
+            }
+
+            if let Some(timeout) = slot.link.next_timeout() {
+                next_timeout = Some(next_timeout.map_or(timeout, |x| x.min(timeout)));
+            }
+        }
+
+        self.slots
+            .retain(|_, slot| !(slot.dropped && slot.link.state() == LinkState::Disconnected));
+
+        let frames = frames
+            .iter()
+            .filter_map(|frame| frame.to_bytes().ok())
+            .map(append_fcs)
+            .collect();
+
+        (frames, next_timeout)
+    }
+}
+
+impl Ax25LinkLayer {
+    /// Creates a link layer for `local`. Fails if `config` isn't valid.
+    pub fn new(local: Ax25Address, config: LinkConfig) -> Result<Ax25LinkLayer, Error> {
+        config.validate()?;
+        Ok(Ax25LinkLayer {
+            inner: Arc::new(Mutex::new(Inner {
+                local,
+                config,
+                listening: false,
+                next_id: 0,
+                slots: HashMap::new(),
+                active: HashMap::new(),
+                incoming: VecDeque::new(),
+                accept_waker: None,
+                stray: VecDeque::new(),
+                nudged: false,
+                driver_waker: None,
+            })),
+        })
+    }
+
+    pub fn local(&self) -> Ax25Address {
+        self.inner.lock().unwrap().local
+    }
+
+    /// Starts accepting incoming connections. Until this is called,
+    /// connection requests are refused.
+    pub fn listen(&self) {
+        self.inner.lock().unwrap().listening = true;
+    }
+
+    /// Connects to `remote` via `digipeaters`.
+    pub async fn connect(
+        &self,
+        remote: Ax25Address,
+        digipeaters: &[Ax25Address],
+    ) -> io::Result<Ax25Connection> {
+        let connection = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.active.contains_key(&remote) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("Already connected to {}", remote),
+                ));
+            }
+
+            let mut link = Ax25Link::new(inner.local, remote, digipeaters, inner.config)
+                .expect("checked in Ax25LinkLayer::new");
+            link.connect(Instant::now());
+            let id = inner.add_slot(link);
+            inner.nudge();
+
+            Ax25Connection {
+                inner: self.inner.clone(),
+                id,
+            }
+        };
+
+        poll_fn(|cx| connection.poll_connected(cx)).await?;
+        Ok(connection)
+    }
+
+    /// Waits for an incoming connection.
+    pub async fn accept(&self) -> io::Result<Ax25Connection> {
+        poll_fn(|cx| {
+            let mut inner = self.inner.lock().unwrap();
+            match inner.incoming.pop_front() {
+                Some(id) => Poll::Ready(Ok(Ax25Connection {
+                    inner: self.inner.clone(),
+                    id,
+                })),
+                None => {
+                    inner.accept_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Runs the connections over a frame sink and stream, like
+    /// `Bell202Sender` and `Bell202Receiver`. Frames in both
+    /// directions include the FCS. Returns when `stream` ends.
+    pub async fn run<S, R>(&self, mut sink: S, stream: R) -> anyhow::Result<()>
+    where
+        S: Sink<Vec<u8>, Error = anyhow::Error> + Unpin,
+        R: Stream<Item = Vec<u8>> + Unpin,
+    {
+        let mut stream = stream.fuse();
+
+        loop {
+            let (frames, timeout) = self.inner.lock().unwrap().service(Instant::now());
+
+            for frame in frames {
+                sink.send(frame).await?;
+            }
+
+            let delay = timeout.map_or(Duration::from_secs(3600), |x| {
+                x.saturating_duration_since(Instant::now())
+            });
+            let mut delay = Delay::new(delay).fuse();
+            let mut nudged = poll_fn(|cx| {
+                let mut inner = self.inner.lock().unwrap();
+                if std::mem::take(&mut inner.nudged) {
+                    Poll::Ready(())
+                } else {
+                    inner.driver_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+            .fuse();
+
+            select! {
+                frame = stream.next() => match frame {
+                    Some(frame) => self.inner.lock().unwrap().receive(&frame, Instant::now()),
+                    None => return Ok(()),
+                },
+                _ = delay => {},
+                _ = nudged => {},
+            }
+        }
+    }
+}
+
+/// A connection to a remote station, readable and writable as a byte stream.
+///
+/// Closing the connection waits for written data to be acknowledged
+/// and then disconnects. Dropping it disconnects immediately.
+pub struct Ax25Connection {
+    inner: Arc<Mutex<Inner>>,
+    id: u64,
+}
+
+impl Ax25Connection {
+    fn with_slot<T, F: FnOnce(&mut Slot, &mut bool) -> T>(&self, f: F) -> T {
+        let mut inner = self.inner.lock().unwrap();
+        let mut nudge = false;
+        let ret = f(inner.slots.get_mut(&self.id).unwrap(), &mut nudge);
+        if nudge {
+            inner.nudge();
+        }
+        ret
+    }
+
+    pub fn remote(&self) -> Ax25Address {
+        self.with_slot(|slot, _| slot.link.remote())
+    }
+
+    pub fn state(&self) -> LinkState {
+        self.with_slot(|slot, _| slot.link.state())
+    }
+
+    /// Why the connection was disconnected, if it was.
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.with_slot(|slot, _| slot.reason)
+    }
+
+    fn poll_connected(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.with_slot(|slot, _| {
+            // Data may have been received before a quick disconnect.
+            if slot.connected || slot.link.is_connected() {
+                return Poll::Ready(Ok(()));
+            }
+            match slot.reason {
+                Some(DisconnectReason::Refused) => {
+                    Poll::Ready(Err(io::ErrorKind::ConnectionRefused.into()))
+                }
+                Some(DisconnectReason::Timeout) => Poll::Ready(Err(io::ErrorKind::TimedOut.into())),
+                Some(_) => Poll::Ready(Err(io::ErrorKind::ConnectionAborted.into())),
+                None => {
+                    slot.reader = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+fn not_connected() -> io::Error {
+    io::ErrorKind::NotConnected.into()
+}
+
+impl AsyncRead for Ax25Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.with_slot(|slot, nudge| {
+            if !slot.received.is_empty() {
+                let len = buf.len().min(slot.received.len());
+                for (dst, src) in buf.iter_mut().zip(slot.received.drain(..len)) {
+                    *dst = src;
+                }
+                if slot.received.is_empty() {
+                    slot.link.set_busy(false);
+                }
+                *nudge = true;
+                Poll::Ready(Ok(len))
+            } else if slot.link.state() == LinkState::Disconnected {
+                Poll::Ready(Ok(0))
+            } else {
+                slot.reader = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl AsyncWrite for Ax25Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.with_slot(|slot, nudge| {
+            if !slot.is_open() {
+                return Poll::Ready(Err(not_connected()));
+            }
+
+            let limit = slot.link.window() as usize * slot.link.config().max_info_len;
+            let room = limit.saturating_sub(slot.link.pending_len());
+            if room == 0 {
+                slot.writer = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let len = buf.len().min(room);
+            slot.link.send(&buf[..len], Instant::now());
+            *nudge = true;
+            Poll::Ready(Ok(len))
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.with_slot(|slot, _| {
+            if slot.link.is_flushed() {
+                Poll::Ready(Ok(()))
+            } else if !slot.is_open() {
+                Poll::Ready(Err(not_connected()))
+            } else {
+                slot.writer = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(self.as_mut().poll_flush(cx))?;
+
+        self.with_slot(|slot, nudge| match slot.link.state() {
+            LinkState::Disconnected => Poll::Ready(Ok(())),
+            state => {
+                if state != LinkState::AwaitingRelease {
+                    slot.link.disconnect(Instant::now());
+                    *nudge = true;
+                }
+                slot.reader = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl Drop for Ax25Connection {
+    fn drop(&mut self) {
+        self.with_slot(|slot, nudge| {
+            slot.dropped = true;
+            slot.link.disconnect(Instant::now());
+            *nudge = true;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::ThreadPool;
+    use futures::task::SpawnExt;
+
+    fn decode(bytes: &[u8]) -> Ax25Frame {
+        let bytes = verify_fcs(bytes).unwrap();
+        Ax25Frame::try_from_bytes_with_modulo(bytes, Modulo::Mod8).unwrap()
+    }
+
+    fn run_pair(a: &Ax25LinkLayer, b: &Ax25LinkLayer, pool: &ThreadPool) {
+        run_lossy_pair(a, b, pool, |_| false);
+    }
+
+    /// Runs `a` and `b` over a channel that drops the frames from `a` for
+    /// which `drop` returns true. Returns the frames `b` has sent so far.
+    fn run_lossy_pair<F>(
+        a: &Ax25LinkLayer,
+        b: &Ax25LinkLayer,
+        pool: &ThreadPool,
+        mut drop: F,
+    ) -> Arc<Mutex<Vec<Ax25Frame>>>
+    where
+        F: FnMut(&Ax25Frame) -> bool + Send + 'static,
+    {
+        let (a_sender, a_receiver) = mpsc::channel(16);
+        let (b_sender, b_receiver) = mpsc::channel(16);
+        let a_receiver = a_receiver.filter(move |x: &Vec<u8>| future::ready(!drop(&decode(x))));
+        let sent = Arc::new(Mutex::new(vec![]));
+        let log = sent.clone();
+        let b_receiver = b_receiver.inspect(move |x: &Vec<u8>| log.lock().unwrap().push(decode(x)));
+
+        let layer = a.clone();
+        pool.spawn_ok(async move {
+            layer
+                .run(a_sender.sink_map_err(anyhow::Error::from), b_receiver)
+                .await
+                .unwrap();
+        });
+
+        let layer = b.clone();
+        pool.spawn_ok(async move {
+            layer
+                .run(b_sender.sink_map_err(anyhow::Error::from), a_receiver)
+                .await
+                .unwrap();
+        });
+
+        sent
+    }
+
+    fn sent_s(sent: &Mutex<Vec<Ax25Frame>>) -> Vec<SupervisoryKind> {
+        sent.lock()
+            .unwrap()
+            .iter()
+            .filter_map(|frame| match frame.control {
+                Control::S { kind, .. } => Some(kind),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn is_i_frame(frame: &Ax25Frame, n: u8) -> bool {
+        matches!(frame.control, Control::I { ns, .. } if ns == n)
+    }
+
+    #[test]
+    fn ax25_link_layer_connection() {
+        let config = LinkConfig {
+            max_info_len: 64,
+            ..Default::default()
+        };
+        let a = Ax25LinkLayer::new("KZ2X-1".parse().unwrap(), config).unwrap();
+        let b = Ax25LinkLayer::new("N6DRC".parse().unwrap(), config).unwrap();
+        let pool = ThreadPool::new().unwrap();
+        run_pair(&a, &b, &pool);
+
+        // Not listening yet.
+        let err = futures::executor::block_on(a.connect(b.local(), &[])).err();
+        assert_eq!(
+            err.map(|x| x.kind()),
+            Some(io::ErrorKind::ConnectionRefused)
+        );
+
+        b.listen();
+        let data = (0..2000).map(|x| x as u8).collect::<Vec<_>>();
+        let expected = data.clone();
+
+        let server = b.clone();
+        let (done_sender, done_receiver) = futures::channel::oneshot::channel();
+        pool.spawn_ok(async move {
+            let mut connection = server.accept().await.unwrap();
+            let mut received = vec![];
+            connection.read_to_end(&mut received).await.unwrap();
+            done_sender.send((connection.remote(), received)).unwrap();
+        });
+
+        futures::executor::block_on(async {
+            let mut connection = a.connect(b.local(), &[]).await.unwrap();
+            assert_eq!(connection.state(), LinkState::Connected);
+            connection.write_all(&data).await.unwrap();
+            connection.close().await.unwrap();
+            assert_eq!(
+                connection.disconnect_reason(),
+                Some(DisconnectReason::Local)
+            );
+
+            let (remote, received) = done_receiver.await.unwrap();
+            assert_eq!(remote, a.local());
+            assert_eq!(received, expected);
+        });
+    }
+
+    #[test]
+    fn ax25_link_layer_reject() {
+        let config = LinkConfig {
+            max_info_len: 16,
+            ..Default::default()
+        };
+        let a = Ax25LinkLayer::new("KZ2X-1".parse().unwrap(), config).unwrap();
+        let b = Ax25LinkLayer::new("N6DRC".parse().unwrap(), config).unwrap();
+        let pool = ThreadPool::new().unwrap();
+
+        // Lose the second I frame once.
+        let mut dropped = false;
+        let sent = run_lossy_pair(&a, &b, &pool, move |frame| {
+            if !dropped && is_i_frame(frame, 1) {
+                dropped = true;
+                return true;
+            }
+            false
+        });
+        b.listen();
+
+        let data = (0..200).map(|x| x as u8).collect::<Vec<_>>();
+        let expected = data.clone();
+        let server = b.clone();
+        let received = pool.spawn_with_handle(async move {
+            let mut connection = server.accept().await.unwrap();
+            let mut received = vec![];
+            connection.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        futures::executor::block_on(async {
+            let mut connection = a.connect(b.local(), &[]).await.unwrap();
+            connection.write_all(&data).await.unwrap();
+            connection.close().await.unwrap();
+            assert_eq!(received.unwrap().await, expected);
+        });
+        assert!(sent_s(&sent).contains(&SupervisoryKind::Rej));
+    }
+
+    #[test]
+    fn ax25_link_layer_busy() {
+        let config = LinkConfig {
+            max_info_len: 10,
+            max_received: 50,
+            t1: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let a = Ax25LinkLayer::new("KZ2X-1".parse().unwrap(), config).unwrap();
+        let b = Ax25LinkLayer::new("N6DRC".parse().unwrap(), config).unwrap();
+        let pool = ThreadPool::new().unwrap();
+        let sent = run_lossy_pair(&a, &b, &pool, |_| false);
+        b.listen();
+
+        let data = (0..500).map(|x| x as u8).collect::<Vec<_>>();
+        let expected = data.clone();
+        let client = a.clone();
+        let remote = b.local();
+        pool.spawn_ok(async move {
+            let mut connection = client.connect(remote, &[]).await.unwrap();
+            connection.write_all(&data).await.unwrap();
+            connection.close().await.unwrap();
+        });
+
+        futures::executor::block_on(async {
+            let mut connection = b.accept().await.unwrap();
+
+            // Don't read until the buffer has filled up.
+            while !sent_s(&sent).contains(&SupervisoryKind::Rnr) {
+                Delay::new(Duration::from_millis(10)).await;
+            }
+            let mut received = vec![];
+            connection.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, expected);
+        });
+
+        // Reading the buffer sent RR after the RNR.
+        let kinds = sent_s(&sent);
+        let rnr = kinds
+            .iter()
+            .position(|&x| x == SupervisoryKind::Rnr)
+            .unwrap();
+        assert!(kinds[rnr..].contains(&SupervisoryKind::Rr));
+    }
+
+    #[test]
+    fn ax25_link_layer_disconnect() {
+        let a = Ax25LinkLayer::new("KZ2X-1".parse().unwrap(), LinkConfig::default()).unwrap();
+        let b = Ax25LinkLayer::new("N6DRC".parse().unwrap(), LinkConfig::default()).unwrap();
+        let pool = ThreadPool::new().unwrap();
+        run_pair(&a, &b, &pool);
+        b.listen();
+
+        let server = b.clone();
+        pool.spawn_ok(async move {
+            let mut connection = server.accept().await.unwrap();
+            connection.write_all(b"bye").await.unwrap();
+            connection.close().await.unwrap();
+        });
+
+        futures::executor::block_on(async {
+            let mut connection = a.connect(b.local(), &[]).await.unwrap();
+            let mut received = vec![];
+            connection.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, b"bye");
+            assert_eq!(connection.state(), LinkState::Disconnected);
+            assert_eq!(
+                connection.disconnect_reason(),
+                Some(DisconnectReason::Remote)
+            );
+
+            let err = connection.write_all(b"hello").await.err();
+            assert_eq!(err.map(|x| x.kind()), Some(io::ErrorKind::NotConnected));
+        });
+    }
+}
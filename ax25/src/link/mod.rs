@@ -0,0 +1,149 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! AX.25 connected-mode data link.
+//!
+//! [`Ax25Link`] is the state machine for a single connection. It does no
+//! I/O: frames and timeouts are fed in, and frames to transmit and events
+//! are polled out. [`Ax25LinkLayer`] drives a set of links over a
+//! frame sink and stream, such as `Bell202Sender` and `Bell202Receiver`,
+//! and exposes each connection as an async byte stream.
+
+mod data_link;
+mod layer;
+
+pub use data_link::*;
+pub use layer::*;
+
+use crate::Modulo;
+use anyhow::{bail, Error};
+use std::time::Duration;
+
+/// Data link parameters.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LinkConfig {
+    /// Sequence number modulus to request when connecting.
+    pub modulo: Modulo,
+
+    /// Maximum number of outstanding I frames (k). If the remote station
+    /// connects with mod-8 sequence numbers, at most 7 are outstanding.
+    pub window: u8,
+
+    /// Maximum number of bytes in the info field of an I frame (N1).
+    pub max_info_len: usize,
+
+    /// Number of received bytes an [`Ax25Connection`] buffers before
+    /// telling the remote station it's busy with RNR. Up to a window of
+    /// I frames more may arrive before the RNR does.
+    pub max_received: usize,
+
+    /// Maximum number of retries (N2).
+    pub max_retries: u32,
+
+    /// Acknowledgement timer (T1).
+    pub t1: Duration,
+
+    /// Idle link check timer (T3).
+    pub t3: Duration,
+
+    /// Request selective retransmission of lost I frames
+    /// with SREJ rather than REJ.
+    pub srej: bool,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            modulo: Modulo::Mod8,
+            window: 4,
+            max_info_len: 256,
+            max_received: 4096,
+            max_retries: 10,
+            t1: Duration::from_secs(4),
+            t3: Duration::from_secs(180),
+            srej: false,
+        }
+    }
+}
+
+impl LinkConfig {
+    /// Checks that the parameters can be used for a link.
+    pub fn validate(&self) -> Result<(), Error> {
+        let max_window = self.modulo.modulus() - 1;
+        if self.window == 0 || self.window > max_window {
+            bail!(
+                "Window must be between 1 and {} for {:?}, not {}",
+                max_window,
+                self.modulo,
+                self.window
+            );
+        }
+        if self.max_info_len == 0 || self.max_received == 0 {
+            bail!("Maximum info field length and receive buffer must not be zero");
+        }
+        if self.t1.is_zero() || self.t3.is_zero() {
+            bail!("T1 and T3 must not be zero");
+        }
+        Ok(())
+    }
+}
+
+/// Data link states.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum LinkState {
+    Disconnected,
+    AwaitingConnection,
+    AwaitingRelease,
+    Connected,
+
+    /// Connected, and waiting for a response to a poll
+    /// after T1 or T3 expired.
+    TimerRecovery,
+}
+
+/// Why a link was disconnected.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum DisconnectReason {
+    /// We asked to disconnect.
+    Local,
+
+    /// The remote station disconnected.
+    Remote,
+
+    /// The remote station refused our connection request.
+    Refused,
+
+    /// The remote station stopped responding.
+    Timeout,
+}
+
+/// Events reported to the user of a link.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum LinkEvent {
+    Connected,
+    Data(Vec<u8>),
+
+    /// The link was re-established after a protocol error, or reset by
+    /// the remote station. I frames that weren't acknowledged are sent
+    /// again, so the remote station may receive some of them twice.
+    Reset,
+    Disconnected(DisconnectReason),
+}
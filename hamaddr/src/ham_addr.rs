@@ -589,7 +589,9 @@ impl TryFrom<Eui48> for HamAddr {
             octets.rotate_left(1);
             let mut bytes = [0; 8];
             bytes[..6].copy_from_slice(&octets);
-            let ret = HamAddr(bytes).reverse_eui48_hack().unwrap();
+            let ret = HamAddr(bytes)
+                .reverse_eui48_hack()
+                .ok_or(anyhow::Error::msg("EUI48 did not contain a callsign."))?;
             match ret.get_type() {
                 HamAddrType::Callsign => Ok(ret),
                 _ => bail!("EUI48 did not contain a callsign."),
@@ -617,7 +619,9 @@ impl TryFrom<Eui64> for HamAddr {
         if bytes[0] & 0b111 == 0b010 {
             bytes[0] &= 0b1111_1101;
             bytes.rotate_left(1);
-            let ret = HamAddr(bytes).reverse_eui64_hack().unwrap();
+            let ret = HamAddr(bytes)
+                .reverse_eui64_hack()
+                .ok_or(anyhow::Error::msg("EUI64 did not contain a callsign."))?;
             match ret.get_type() {
                 HamAddrType::Callsign => Ok(ret),
                 _ => bail!("EUI64 did not contain a callsign."),
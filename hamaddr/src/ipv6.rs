@@ -0,0 +1,169 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use crate::*;
+use anyhow::bail;
+use std::net::Ipv6Addr;
+
+/// The IPv6 link-local prefix, `fe80::/64`.
+pub const IPV6_LINK_LOCAL_PREFIX: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0);
+
+impl Eui64 {
+    /// Converts this EUI64 into an IPv6 interface identifier by
+    /// inverting the universal/local bit. See RFC 4291, App A.
+    ///
+    /// ```
+    /// # use hamaddr::Eui64;
+    /// let eui64 = Eui64::new([0x02, 0x48, 0xed, 0xff, 0xfe, 0x9c, 0x0c, 0x00]);
+    /// assert_eq!(eui64.to_ipv6_iid(), [0x00, 0x48, 0xed, 0xff, 0xfe, 0x9c, 0x0c, 0x00]);
+    /// ```
+    pub const fn to_ipv6_iid(self) -> [u8; 8] {
+        let mut iid = self.0;
+        iid[0] ^= 0b0010;
+        iid
+    }
+
+    /// Converts an IPv6 interface identifier back into an EUI64 by
+    /// inverting the universal/local bit.
+    ///
+    /// ```
+    /// # use hamaddr::Eui64;
+    /// let eui64 = Eui64::from_ipv6_iid([0x00, 0x48, 0xed, 0xff, 0xfe, 0x9c, 0x0c, 0x00]);
+    /// assert_eq!(eui64.to_string(), "02:48:ed:ff:fe:9c:0c:00");
+    /// ```
+    pub const fn from_ipv6_iid(mut iid: [u8; 8]) -> Eui64 {
+        iid[0] ^= 0b0010;
+        Eui64(iid)
+    }
+}
+
+impl HamAddr {
+    /// Returns the IPv6 interface identifier derived from this callsign.
+    ///
+    /// Only callsigns that can be represented as an [`Eui64`] have an
+    /// interface identifier.
+    pub fn to_ipv6_iid(self) -> Result<[u8; 8]> {
+        if !self.is_callsign() {
+            bail!("Cannot derive an IPv6 IID from {:?}", self.get_type());
+        }
+        Ok(Eui64::try_from(self)?.to_ipv6_iid())
+    }
+
+    /// Returns the IPv6 link-local address (`fe80::/64`) for this callsign.
+    ///
+    /// ```
+    /// # use hamaddr::HamAddr;
+    /// let addr: HamAddr = "KZ2X-1".parse().unwrap();
+    /// assert_eq!(
+    ///     addr.to_ipv6_link_local().unwrap().to_string(),
+    ///     "fe80::48:edff:fe9c:c00"
+    /// );
+    /// ```
+    pub fn to_ipv6_link_local(self) -> Result<Ipv6Addr> {
+        self.to_ipv6_slaac(IPV6_LINK_LOCAL_PREFIX)
+    }
+
+    /// Returns the SLAAC address for this callsign within the given
+    /// prefix. Only the upper 64 bits of `prefix` are used.
+    ///
+    /// ```
+    /// # use hamaddr::HamAddr;
+    /// let addr: HamAddr = "KZ2X-1".parse().unwrap();
+    /// let prefix = "2001:db8:44::".parse().unwrap();
+    /// assert_eq!(
+    ///     addr.to_ipv6_slaac(prefix).unwrap().to_string(),
+    ///     "2001:db8:44:0:48:edff:fe9c:c00"
+    /// );
+    /// ```
+    pub fn to_ipv6_slaac(self, prefix: Ipv6Addr) -> Result<Ipv6Addr> {
+        let mut octets = prefix.octets();
+        octets[8..].copy_from_slice(&self.to_ipv6_iid()?);
+        Ok(Ipv6Addr::from(octets))
+    }
+
+    /// Tries to recover the callsign from an IPv6 interface identifier.
+    pub fn try_from_ipv6_iid(iid: [u8; 8]) -> Result<HamAddr> {
+        let ret = HamAddr::try_from(Eui64::from_ipv6_iid(iid))?;
+        if !ret.is_callsign() {
+            bail!("IPv6 IID did not contain a callsign.");
+        }
+        Ok(ret)
+    }
+
+    /// Tries to recover the callsign from the interface identifier
+    /// (lower 64 bits) of an IPv6 address.
+    ///
+    /// ```
+    /// # use hamaddr::HamAddr;
+    /// let ip = "fe80::48:edff:fe9c:c00".parse().unwrap();
+    /// assert_eq!(HamAddr::try_from_ipv6(&ip).unwrap().to_string(), "KZ2X-1");
+    /// ```
+    pub fn try_from_ipv6(addr: &Ipv6Addr) -> Result<HamAddr> {
+        let mut iid = [0u8; 8];
+        iid.copy_from_slice(&addr.octets()[8..]);
+        HamAddr::try_from_ipv6_iid(iid)
+    }
+}
+
+#[cfg(test)]
+mod ipv6_tests {
+    use super::*;
+
+    #[test]
+    fn test_ham_addr_to_ipv6_link_local() {
+        let addr = "KZ2X-1".parse::<HamAddr>().unwrap();
+        let ip = addr.to_ipv6_link_local().unwrap();
+        assert_eq!(ip.to_string(), "fe80::48:edff:fe9c:c00");
+        assert_eq!(HamAddr::try_from_ipv6(&ip).unwrap(), addr);
+
+        let addr = "VI2BMARC50".parse::<HamAddr>().unwrap();
+        let ip = addr.to_ipv6_link_local().unwrap();
+        assert_eq!(ip.to_string(), "fe80::c08b:50e:8971:18a8");
+        assert_eq!(HamAddr::try_from_ipv6(&ip).unwrap(), addr);
+
+        let addr = "KJ6QOH-23".parse::<HamAddr>().unwrap();
+        let ip = addr.to_ipv6_link_local().unwrap();
+        assert_eq!(HamAddr::try_from_ipv6(&ip).unwrap(), addr);
+    }
+
+    #[test]
+    fn test_ham_addr_to_ipv6_slaac() {
+        let prefix: Ipv6Addr = "2001:db8:1:2:ffff::1".parse().unwrap();
+        let addr = "N6DRC".parse::<HamAddr>().unwrap();
+        let ip = addr.to_ipv6_slaac(prefix).unwrap();
+        assert_eq!(&ip.octets()[..8], &prefix.octets()[..8]);
+        assert_eq!(HamAddr::try_from_ipv6(&ip).unwrap(), addr);
+    }
+
+    #[test]
+    fn test_ham_addr_to_ipv6_non_callsign() {
+        assert!(HamAddr::EMPTY.to_ipv6_link_local().is_err());
+        assert!(HamAddr::BROADCAST.to_ipv6_link_local().is_err());
+        assert!("VI2BMARC50-X"
+            .parse::<HamAddr>()
+            .unwrap()
+            .to_ipv6_link_local()
+            .is_err());
+
+        assert!(HamAddr::try_from_ipv6(&Ipv6Addr::UNSPECIFIED).is_err());
+        assert!(HamAddr::try_from_ipv6(&"fe80::1".parse().unwrap()).is_err());
+    }
+}
@@ -23,11 +23,13 @@ mod error;
 mod eui;
 mod ham_addr;
 mod ham_char;
+mod ipv6;
 
 pub use crate::error::*;
 pub use crate::eui::*;
 pub use crate::ham_addr::*;
 pub use crate::ham_char::*;
+pub use crate::ipv6::*;
 
 #[cfg(test)]
 mod ham_addr_tests {
@@ -0,0 +1,294 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::AfskProfile;
+use crate::fcs::Fcs;
+use crate::filter::*;
+use std::collections::VecDeque;
+
+/// Demodulator variant used by a [`DemodBranch`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum DemodFilter {
+    /// [`Discriminator::digital_default`], as used by `bell_202_decoder`.
+    Digital,
+
+    /// [`Discriminator::analog_default`], with wider filters.
+    Analog,
+}
+
+/// Settings for one branch of a [`Bell202DecoderBank`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DemodBranch {
+    /// Gain applied to the space tone relative to the mark tone, to
+    /// compensate for twist. Values above one boost the space tone.
+    pub space_gain: f32,
+
+    pub filter: DemodFilter,
+
+    /// Slicer threshold, as a fraction of the distance between the tones,
+    /// relative to halfway between them. Positive values move it towards
+    /// the mark tone.
+    pub threshold: f32,
+}
+
+impl Default for DemodBranch {
+    fn default() -> Self {
+        DemodBranch {
+            space_gain: 1.0,
+            filter: DemodFilter::Digital,
+            threshold: 0.0,
+        }
+    }
+}
+
+impl DemodBranch {
+    /// A bank covering a space tone up to about 6dB louder than the
+    /// mark tone, as from pre-emphasized audio that isn't de-emphasized,
+    /// and tones somewhat off their nominal frequencies, starting with the
+    /// default branch. There are no branches boosting the space tone,
+    /// since that boosts the noise along with it, and doesn't decode
+    /// any more frames.
+    pub fn default_bank() -> Vec<DemodBranch> {
+        let mut ret = vec![];
+        for space_gain in [1.0, 0.5] {
+            for threshold in [0.0, 0.1, -0.1] {
+                ret.push(DemodBranch {
+                    space_gain,
+                    threshold,
+                    ..Default::default()
+                });
+            }
+        }
+        ret.push(DemodBranch {
+            filter: DemodFilter::Analog,
+            ..Default::default()
+        });
+        ret
+    }
+
+    fn decoder(
+        &self,
+        profile: AfskProfile,
+        sample_rate: u32,
+    ) -> Box<dyn Filter<f32, Output = Option<Vec<u8>>> + Send> {
+        let (space, mark) = profile.tones(sample_rate);
+
+        let discriminator = match self.filter {
+            DemodFilter::Digital => Discriminator::<_>::digital_default(),
+            DemodFilter::Analog => Discriminator::<_>::analog_default(),
+        };
+
+        Box::new(
+            twist_filter(mark, space, self.space_gain)
+                .chain(discriminator)
+                .chain(FskDemod::with_threshold(space, mark, self.threshold))
+                .chain(profile.bit_sampler(sample_rate))
+                .chain(NrziDecode::new().optional())
+                .chain(HdlcDecode::default())
+                .chain(FrameCollector::default()),
+        )
+    }
+}
+
+/// Returns a three-tap filter with unity gain at `mark` and a gain
+/// of `space_gain` at `space`, with frequencies relative to the
+/// sample rate.
+fn twist_filter(mark: f32, space: f32, space_gain: f32) -> FilterFir<f32> {
+    if space_gain == 1.0 {
+        return FilterFirKernel::new(vec![1.0], 0).into_filter();
+    }
+
+    // The response is b + 2a*cos(w), which is linear in a and b.
+    let cos_mark = (mark * std::f32::consts::TAU).cos();
+    let cos_space = (space * std::f32::consts::TAU).cos();
+    let a = (space_gain - 1.0) / (2.0 * (cos_space - cos_mark));
+    let b = 1.0 - 2.0 * a * cos_mark;
+
+    FilterFirKernel::new(vec![a, b, a], 1).into_filter()
+}
+
+/// A frame decoded by a [`Bell202DecoderBank`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BankFrame {
+    /// The frame, including its (valid) FCS.
+    pub frame: Vec<u8>,
+
+    /// Index of the branch that decoded the frame first.
+    pub branch: usize,
+}
+
+/// Bank of AFSK decoders, for Bell 202 unless given another profile.
+///
+/// Feeds the same samples to a decoder for each [`DemodBranch`], and
+/// outputs the frames with a valid FCS that any of them decode. Frames
+/// decoded by more than one branch are only output once.
+pub struct Bell202DecoderBank {
+    profile: AfskProfile,
+    sample_rate: u32,
+    branches: Vec<DemodBranch>,
+    decoders: Vec<Box<dyn Filter<f32, Output = Option<Vec<u8>>> + Send>>,
+    counts: Vec<u32>,
+    fcs: Fcs,
+
+    /// Frames recently output, with the sample count after
+    /// which duplicates of them are no longer suppressed.
+    recent: VecDeque<(Vec<u8>, u64)>,
+    pending: VecDeque<BankFrame>,
+    samples: u64,
+}
+
+impl Bell202DecoderBank {
+    pub fn new(sample_rate: u32, branches: &[DemodBranch]) -> Bell202DecoderBank {
+        Self::with_profile(AfskProfile::BELL_202, sample_rate, branches)
+    }
+
+    pub fn with_profile(
+        profile: AfskProfile,
+        sample_rate: u32,
+        branches: &[DemodBranch],
+    ) -> Bell202DecoderBank {
+        assert!(!branches.is_empty(), "no branches");
+
+        Bell202DecoderBank {
+            profile,
+            sample_rate,
+            branches: branches.to_vec(),
+            decoders: branches
+                .iter()
+                .map(|x| x.decoder(profile, sample_rate))
+                .collect(),
+            counts: vec![0; branches.len()],
+            fcs: Fcs::default(),
+            recent: VecDeque::new(),
+            pending: VecDeque::new(),
+            samples: 0,
+        }
+    }
+
+    /// Sets the FCS frames are checked with. Defaults to [`Fcs::X25`].
+    pub fn set_fcs(&mut self, fcs: Fcs) {
+        self.fcs = fcs;
+    }
+
+    pub fn branches(&self) -> &[DemodBranch] {
+        &self.branches
+    }
+
+    /// The number of frames output that each branch decoded first.
+    pub fn counts(&self) -> &[u32] {
+        &self.counts
+    }
+
+    fn add_frame(&mut self, frame: Vec<u8>, branch: usize) {
+        if self.fcs.verify(&frame).is_err() {
+            return;
+        }
+
+        let now = self.samples;
+        self.recent.retain(|(_, expires)| *expires > now);
+
+        if self.recent.iter().any(|(x, _)| *x == frame) {
+            return;
+        }
+
+        // Branches are only a few samples apart, but two separate
+        // transmissions of the same frame can't be closer together
+        // than the time it takes to send it.
+        let duration = frame.len() as u64 * 8 * self.sample_rate as u64 / self.profile.baud as u64;
+        self.recent.push_back((frame.clone(), now + duration));
+
+        self.counts[branch] += 1;
+        self.pending.push_back(BankFrame { frame, branch });
+    }
+}
+
+impl Filter<f32> for Bell202DecoderBank {
+    type Output = Option<BankFrame>;
+
+    fn filter(&mut self, sample: f32) -> Self::Output {
+        self.samples += 1;
+
+        for branch in 0..self.decoders.len() {
+            if let Some(frame) = self.decoders[branch].filter(sample) {
+                self.add_frame(frame, branch);
+            }
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+
+    fn test_frame() -> Vec<u8> {
+        hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap()
+    }
+
+    fn gain_at(filter: &FilterFir<f32>, freq: f32) -> f32 {
+        let mut filter = filter.clone();
+        let mut modulator = FmMod::<f32>::new(1.0);
+        (0..200)
+            .map(|_| filter.filter(modulator.filter(freq)))
+            .skip(100)
+            .fold(0.0f32, |a, b| a.max(b.abs()))
+    }
+
+    #[test]
+    fn twist_filter_gains() {
+        let mark = 1200.0 / 8000.0;
+        let space = 2200.0 / 8000.0;
+        for space_gain in [0.5, 1.0, 2.0] {
+            let filter = twist_filter(mark, space, space_gain);
+            assert!((gain_at(&filter, mark) - 1.0).abs() < 0.05);
+            assert!((gain_at(&filter, space) - space_gain).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn decoder_bank_suppresses_duplicates() {
+        let frame = test_frame();
+        let mut samples = vec![];
+        for _ in 0..2 {
+            samples.extend(bell_202_encode::<f32, _>(
+                frame.clone().into_iter(),
+                BELL202_OPTIMAL_SAMPLE_RATE,
+                0.75,
+            ));
+        }
+        samples.extend(std::iter::repeat_n(0.0, 1000));
+
+        let mut bank =
+            Bell202DecoderBank::new(BELL202_OPTIMAL_SAMPLE_RATE, &DemodBranch::default_bank());
+        let frames = samples
+            .iter()
+            .filter_map(|&x| bank.filter(x))
+            .collect::<Vec<_>>();
+
+        // Every branch decodes both frames, but each is only output once.
+        let expected = BankFrame { frame, branch: 0 };
+        assert_eq!(frames, vec![expected.clone(), expected]);
+        assert_eq!(bank.counts()[0], 2);
+        assert_eq!(bank.counts()[1..].iter().sum::<u32>(), 0);
+    }
+}
@@ -19,13 +19,18 @@
 // TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+mod bank;
+mod profile;
 mod receiver;
 mod sender;
 
+use crate::fcs::Fcs;
 use crate::filter::*;
+pub use crate::modem::{Decoding, Framing, Modem};
+pub use bank::*;
+pub use profile::*;
 pub use receiver::*;
 pub use sender::*;
-use std::fmt::{Debug, Formatter};
 
 pub const BELL202_RATE: u32 = 1200;
 pub const BELL202_MARK: u32 = 1200;
@@ -41,29 +46,60 @@ pub const BELL202_OPTIMAL_SAMPLE_RATE: u32 = (BELL202_MARK + BELL202_SPACE) * 2
 /// works fine, too. Maximum usable sample rate is around 10,000Hz. If
 /// your sample rate is too high, you will need to downsample first.
 pub fn bell_202_decoder(sample_rate: u32) -> impl Filter<f32, Output = Option<Vec<u8>>> {
-    #[cfg(not(test))]
-    assert!(
-        sample_rate <= 14000,
-        "max sample rate:14000, given: {}",
-        sample_rate
-    );
-
-    let mark = (BELL202_MARK as f32) / (sample_rate as f32);
-    let space = (BELL202_SPACE as f32) / (sample_rate as f32);
-
-    let chain = Discriminator::<_>::digital_default()
-        .chain(FskDemod::new(space, mark))
-        .chain(BitSampler::new(sample_rate, BELL202_RATE))
-        .chain(NrziDecode::new().optional())
-        .chain(HdlcDecode::default())
-        // .inspect(|x| {
-        //     if let Some(x) = x {
-        //         println!("{:?}", x);
-        //     }
-        // })
-        .chain(FrameCollector::default());
-
-    chain
+    AfskProfile::BELL_202.decoder(sample_rate)
+}
+
+/// Bell 202 decoder that checks the FCS, repairing frames where possible.
+///
+/// See [`Modem::repairing_decoder`].
+pub fn bell_202_repairing_decoder(
+    sample_rate: u32,
+    repair: FcsRepair,
+) -> impl Filter<f32, Output = Option<CheckedFrame>> {
+    AfskProfile::BELL_202.repairing_decoder(sample_rate, repair)
+}
+
+/// Bell 202 decoder with soft decisions.
+///
+/// See [`AfskProfile::soft_decoder`].
+pub fn bell_202_soft_decoder(sample_rate: u32) -> impl Filter<f32, Output = Option<SoftFrame>> {
+    AfskProfile::BELL_202.soft_decoder(sample_rate)
+}
+
+/// Bell 202 decoder that uses soft decisions to guide repairs.
+///
+/// See [`AfskProfile::soft_repairing_decoder`].
+pub fn bell_202_soft_repairing_decoder(
+    sample_rate: u32,
+    repair: FcsRepair,
+) -> impl Filter<f32, Output = Option<CheckedFrame>> {
+    AfskProfile::BELL_202.soft_repairing_decoder(sample_rate, repair)
+}
+
+/// Bell 202 decoder that also decodes FX.25 code blocks, with an
+/// [`Fcs::X25`] FCS.
+///
+/// See [`Modem::fx25_decoder`].
+pub fn bell_202_fx25_decoder(sample_rate: u32) -> impl Filter<f32, Output = Option<Vec<u8>>> {
+    AfskProfile::BELL_202.fx25_decoder(sample_rate, Fcs::X25)
+}
+
+/// Bell 202 IL2P decoder, appending an [`Fcs::X25`] FCS.
+///
+/// See [`Modem::il2p_decoder`].
+pub fn bell_202_il2p_decoder(sample_rate: u32) -> impl Filter<f32, Output = Option<Vec<u8>>> {
+    AfskProfile::BELL_202.il2p_decoder(sample_rate, Fcs::X25)
+}
+
+/// Returns the Bell 202 decoder for the given framing, with an
+/// [`Fcs::X25`] FCS.
+///
+/// See [`Modem::framed_decoder`].
+pub fn bell_202_framed_decoder(
+    sample_rate: u32,
+    framing: Framing,
+) -> Box<dyn Filter<f32, Output = Option<Vec<u8>>> + Send> {
+    AfskProfile::BELL_202.framed_decoder(sample_rate, framing, Fcs::X25)
 }
 
 /// Bell 202 encoder.
@@ -71,7 +107,7 @@ pub fn bell_202_decoder(sample_rate: u32) -> impl Filter<f32, Output = Option<Ve
 /// Encodes a single frame of octets. Does not add CRC.
 /// Input is an iterator of octets. Output is an iterator
 /// samples at the given sample rate, with a preamble.
-pub fn bell_202_encode<'a, Out, InIterator: Iterator<Item = u8> + 'a>(
+pub fn bell_202_encode<'a, Out, InIterator: Iterator<Item = u8> + Send + 'a>(
     iter: InIterator,
     sample_rate: u32,
     amplitude: f32,
@@ -80,75 +116,57 @@ where
     Decimator<f32, Out>: Default + Filter<f32>,
     Out: 'a,
 {
-    let samples_per_bit = (sample_rate as f32) / (BELL202_RATE as f32);
-    let mark_freq = (BELL202_MARK as f32) / (sample_rate as f32);
-    let space_freq = (BELL202_SPACE as f32) / (sample_rate as f32);
-
-    iter.bits_lsb()
-        .hdlc_encode()
-        .nrzi_encode()
-        .resample_nn(samples_per_bit)
-        .map(move |x| match x {
-            true => mark_freq,
-            false => space_freq,
-        })
-        .apply_one_to_one(FmMod::new(amplitude))
+    AfskProfile::BELL_202
+        .encode(iter, sample_rate, amplitude)
         .apply_one_to_one(Decimator::<f32, Out>::default())
 }
 
-pub struct Ax25Debug<'a>(pub &'a [u8]);
-
-impl<'a> Ax25Debug<'a> {
-    /// Returns the length of the address field
-    pub fn addr_len(&self) -> usize {
-        std::cmp::min(
-            self.0.len(),
-            self.0.iter().take_while(|&x| x & 1 != 1).count() + 1,
-        )
-    }
-
-    pub fn addr_bytes(&self) -> &'a [u8] {
-        &self.0[..self.addr_len()]
-    }
-
-    pub fn addr_escaped_ascii(&self) -> impl Iterator<Item = u8> + 'a {
-        self.addr_bytes()
-            .iter()
-            .map(|x| x >> 1)
-            .map(|x| if x.is_ascii() && x > 31 { x } else { b'.' })
-            .flat_map(std::ascii::escape_default)
-    }
-
-    pub fn payload_bytes(&self) -> &'a [u8] {
-        &self.0[self.addr_len()..]
-    }
-
-    pub fn payload_escaped_ascii(&self) -> impl Iterator<Item = u8> + 'a {
-        self.payload_bytes()
-            .iter()
-            .map(|&x| if x.is_ascii() && x > 31 { x } else { b'.' })
-            .flat_map(std::ascii::escape_default)
-    }
-
-    /// Returns true if this looks like a AX25 packet
-    pub fn is_ax25(&self) -> bool {
-        // AX.25 packets always have an address field that is a multiple of 7 bytes.
-        (self.addr_len() % 7) == 0
-    }
+/// Bell 202 FX.25 encoder.
+///
+/// See [`Modem::encode_fx25`].
+pub fn bell_202_encode_fx25<Out>(
+    frame: &[u8],
+    check_bytes: usize,
+    sample_rate: u32,
+    amplitude: f32,
+) -> Option<impl Iterator<Item = <Decimator<f32, Out> as Filter<f32>>::Output>>
+where
+    Decimator<f32, Out>: Default + Filter<f32>,
+    Out: 'static,
+{
+    AfskProfile::BELL_202
+        .encode_fx25(frame, check_bytes, sample_rate, amplitude)
+        .map(|x| x.apply_one_to_one(Decimator::<f32, Out>::default()))
 }
 
-impl<'a> Debug for Ax25Debug<'a> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        use core::str::from_utf8;
-
-        let addr_escaped = self.addr_escaped_ascii().collect::<Vec<_>>();
-        let addr_str = from_utf8(&addr_escaped).unwrap();
-
-        let payload_escaped = self.payload_escaped_ascii().collect::<Vec<_>>();
-        let payload_str = from_utf8(&payload_escaped).unwrap();
+/// Bell 202 IL2P encoder, for frames with an [`Fcs::X25`] FCS.
+///
+/// See [`Modem::encode_il2p`].
+pub fn bell_202_encode_il2p<Out>(
+    frame: &[u8],
+    sample_rate: u32,
+    amplitude: f32,
+) -> Option<impl Iterator<Item = <Decimator<f32, Out> as Filter<f32>>::Output>>
+where
+    Decimator<f32, Out>: Default + Filter<f32>,
+    Out: 'static,
+{
+    AfskProfile::BELL_202
+        .encode_il2p(frame, Fcs::X25, sample_rate, amplitude)
+        .map(|x| x.apply_one_to_one(Decimator::<f32, Out>::default()))
+}
 
-        write!(f, "[{}]{}", addr_str, payload_str)
-    }
+/// Encodes a frame (including an [`Fcs::X25`] FCS) with the given
+/// framing, with Bell 202.
+///
+/// See [`Modem::encode_framed`].
+pub fn bell_202_encode_framed(
+    frame: Vec<u8>,
+    framing: Framing,
+    sample_rate: u32,
+    amplitude: f32,
+) -> Box<dyn Iterator<Item = f32> + Send> {
+    AfskProfile::BELL_202.encode_framed(frame, framing, Fcs::X25, sample_rate, amplitude)
 }
 
 #[cfg(test)]
@@ -156,16 +174,6 @@ mod tests {
     use super::*;
     use crate::filter::Downsampler;
 
-    #[test]
-    fn test_ax25_debug_decode() {
-        let vec: Vec<u8> = hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap();
-        assert!(Ax25Debug(&vec).is_ax25());
-        assert_eq!(
-            format!("{:?}", Ax25Debug(&vec)),
-            "[APU25NpWA8LMF0WIDE1 1]..>202337zhttp://wa8lmf.com..."
-        );
-    }
-
     #[test]
     fn test_bell_202_encode_decode() {
         for sample_rate in (6500u32..14900).step_by(100) {
@@ -190,6 +198,94 @@ mod tests {
         panic!("Unable to decode at {}", sample_rate);
     }
 
+    #[test]
+    fn test_bell_202_soft_encode_decode() {
+        let vec: Vec<u8> = hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap();
+
+        let iter = bell_202_encode::<f32, _>(vec.clone().into_iter(), 8000, 0.75);
+        let mut decoder = bell_202_soft_decoder(8000);
+        let decoded = iter
+            .chain(std::iter::repeat(0.0).take(1000))
+            .find_map(|x| decoder.filter(x))
+            .unwrap();
+
+        assert_eq!(decoded.frame, vec);
+        assert_eq!(decoded.confidence.len(), vec.len() * 8);
+        assert!(decoded.confidence.iter().all(|&x| x > 0.0));
+    }
+
+    #[test]
+    fn test_bell_202_fx25_encode_decode() {
+        let vec: Vec<u8> = hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap();
+
+        for check_bytes in [16, 32, 64] {
+            let iter =
+                bell_202_encode_fx25::<f32>(&vec, check_bytes, BELL202_OPTIMAL_SAMPLE_RATE, 0.75)
+                    .unwrap()
+                    .chain(std::iter::repeat_n(0.0, 1000))
+                    .collect::<Vec<_>>();
+
+            // Plain decoders still see the frame (and the
+            // correlation tag, as a frame with a bad FCS)...
+            let mut decoder = bell_202_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+            let frames = iter
+                .iter()
+                .filter_map(|&x| decoder.filter(x))
+                .filter(|x| Fcs::X25.verify(x).is_ok())
+                .collect::<Vec<_>>();
+            assert_eq!(frames, vec![vec.clone()]);
+
+            // ...and so does the FX.25 one, only once.
+            let mut decoder = bell_202_fx25_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+            let frames = iter
+                .iter()
+                .filter_map(|&x| decoder.filter(x))
+                .filter(|x| Fcs::X25.verify(x).is_ok())
+                .collect::<Vec<_>>();
+            assert_eq!(frames, vec![vec.clone()]);
+        }
+
+        assert!(bell_202_encode_fx25::<f32>(&[0; 300], 16, 8000, 0.75).is_none());
+    }
+
+    #[test]
+    fn test_bell_202_framed_encode_decode() {
+        let vec: Vec<u8> = hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap();
+
+        for framing in [Framing::Hdlc, Framing::Fx25(32), Framing::Il2p] {
+            for sample_rate in [8000, BELL202_OPTIMAL_SAMPLE_RATE, 11025] {
+                let samples = bell_202_encode_framed(vec.clone(), framing, sample_rate, 0.75)
+                    .chain(std::iter::repeat_n(0.0, 1000))
+                    .collect::<Vec<_>>();
+
+                let mut decoder = bell_202_framed_decoder(sample_rate, framing);
+                let frames = samples
+                    .iter()
+                    .filter_map(|&x| decoder.filter(x))
+                    .filter(|x| Fcs::X25.verify(x).is_ok())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    frames,
+                    vec![vec.clone()],
+                    "{:?} at {}",
+                    framing,
+                    sample_rate
+                );
+            }
+        }
+
+        // IL2P isn't decoded by HDLC decoders, and vice versa.
+        let samples = bell_202_encode_framed(vec.clone(), Framing::Il2p, 8000, 0.75);
+        let mut decoder = bell_202_framed_decoder(8000, Framing::Hdlc);
+        assert!(samples
+            .filter_map(|x| decoder.filter(x))
+            .all(|x| Fcs::X25.verify(&x).is_err()));
+
+        let samples = bell_202_encode_framed(vec, Framing::Hdlc, 8000, 0.75);
+        let mut decoder = bell_202_framed_decoder(8000, Framing::Il2p);
+        assert!(samples.filter_map(|x| decoder.filter(x)).next().is_none());
+    }
+
     #[test]
     fn test_bell_202_encode_decode_resample() {
         let vec: Vec<u8> = hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap();
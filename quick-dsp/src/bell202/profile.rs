@@ -0,0 +1,331 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::Bell202DecoderBank;
+use crate::fcs::Fcs;
+use crate::filter::*;
+use crate::modem::{checked_decoder, repair_logged, CheckedDecoder, Decoding, Modem};
+use anyhow::Error;
+use log::debug;
+
+/// Parameters of an AFSK modem: the symbol rate and the audio tones.
+///
+/// The decoders and encoders, from [`Modem`], are the same whatever the
+/// profile. The `bell_202_*` functions are shorthand for
+/// [`AfskProfile::BELL_202`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct AfskProfile {
+    /// Symbols (and bits) per second.
+    pub baud: u32,
+
+    /// Frequency of the mark (one) tone, in Hz.
+    pub mark: u32,
+
+    /// Frequency of the space (zero) tone, in Hz.
+    pub space: u32,
+
+    /// The sample rate the decoders work best at, which puts the tones
+    /// either side of the discriminator's carrier at a quarter of the
+    /// sample rate.
+    pub optimal_sample_rate: u32,
+
+    /// Recover the bit clock with a [`PllBitSampler`], rather than a
+    /// [`BitSampler`]. On by default, since the PLL rides out noise and
+    /// sender clock error better. The soft decoders always use a PLL.
+    pub pll: bool,
+}
+
+impl AfskProfile {
+    /// Bell 202: 1200 baud, as used for VHF packet and APRS.
+    pub const BELL_202: AfskProfile = AfskProfile {
+        baud: super::BELL202_RATE,
+        mark: super::BELL202_MARK,
+        space: super::BELL202_SPACE,
+        optimal_sample_rate: super::BELL202_OPTIMAL_SAMPLE_RATE,
+        pll: true,
+    };
+
+    /// Bell 103 (originate): 300 baud with a 200Hz shift, as used for HF
+    /// packet. On SSB the audio tones depend on the dial frequency, so
+    /// use [`AfskProfile::new`] to match a station using other tones.
+    pub const BELL_103: AfskProfile = AfskProfile::new(300, 1270, 1070);
+
+    /// ITU-T V.23 (mode 2): 1200 baud with a wider shift than Bell 202.
+    pub const V23: AfskProfile = AfskProfile::new(1200, 1300, 2100);
+
+    /// 2400 baud AFSK, as sent by some TNCs on VHF and UHF.
+    pub const AFSK_2400: AfskProfile = AfskProfile::new(2400, 2165, 3970);
+
+    /// Returns a profile for the given symbol rate and tones.
+    pub const fn new(baud: u32, mark: u32, space: u32) -> AfskProfile {
+        AfskProfile {
+            baud,
+            mark,
+            space,
+            optimal_sample_rate: (mark + space) * 2,
+            pll: true,
+        }
+    }
+
+    /// Returns this profile, recovering the bit clock with a
+    /// [`PllBitSampler`] if `pll` is set, or a [`BitSampler`] if not.
+    pub const fn with_pll(self, pll: bool) -> AfskProfile {
+        AfskProfile { pll, ..self }
+    }
+
+    /// Returns the highest sample rate the decoders are usable at: twice
+    /// the optimal sample rate, or 20 samples per bit, after which
+    /// [`HdlcDecode`] takes the gaps between bits for a loss of signal.
+    pub fn max_sample_rate(self) -> u32 {
+        (self.optimal_sample_rate * 2).min(self.baud * 20)
+    }
+
+    /// Decoder with soft decisions.
+    ///
+    /// Like [`Modem::decoder`], but each frame comes with the
+    /// confidence of each of its bits. See [`SoftFrameCollector`].
+    pub fn soft_decoder(self, sample_rate: u32) -> impl Filter<f32, Output = Option<SoftFrame>> {
+        let (space, mark) = self.tones(sample_rate);
+
+        Discriminator::<_>::digital_default()
+            .chain(SoftFskDemod::new(space, mark))
+            .chain(SoftPllBitSampler::new(sample_rate, self.baud))
+            .chain(SoftNrziDecode::new().optional())
+            .chain(SoftFrameCollector::default())
+    }
+
+    /// Like [`Modem::repairing_decoder`], but uses soft decisions
+    /// to guide the repairs. See [`FcsRepair::check_soft`].
+    pub fn soft_repairing_decoder(
+        self,
+        sample_rate: u32,
+        repair: FcsRepair,
+    ) -> impl Filter<f32, Output = Option<CheckedFrame>> {
+        self.soft_decoder(sample_rate).chain(repair)
+    }
+
+    /// Returns the bit sampler for the given sample rate.
+    pub(crate) fn bit_sampler(
+        self,
+        sample_rate: u32,
+    ) -> Box<dyn Filter<Option<bool>, Output = Option<bool>> + Send> {
+        if self.pll {
+            Box::new(PllBitSampler::new(sample_rate, self.baud))
+        } else {
+            Box::new(BitSampler::new(sample_rate, self.baud))
+        }
+    }
+
+    /// Returns the space and mark frequencies, in cycles per sample.
+    pub(crate) fn tones(self, sample_rate: u32) -> (f32, f32) {
+        #[cfg(not(test))]
+        assert!(
+            sample_rate <= self.max_sample_rate(),
+            "max sample rate:{}, given: {}",
+            self.max_sample_rate(),
+            sample_rate
+        );
+
+        let mark = (self.mark as f32) / (sample_rate as f32);
+        let space = (self.space as f32) / (sample_rate as f32);
+        (space, mark)
+    }
+}
+
+impl Modem for AfskProfile {
+    fn optimal_sample_rate(self) -> u32 {
+        self.optimal_sample_rate
+    }
+
+    fn bit_rate(self) -> u32 {
+        self.baud
+    }
+
+    /// Works best at the profile's `optimal_sample_rate`. If your
+    /// sample rate is above `max_sample_rate`, you will need to
+    /// downsample first.
+    fn demod<B, X>(self, sample_rate: u32, bits: B) -> impl Filter<f32, Output = Option<X>> + Send
+    where
+        B: Filter<Option<bool>, Output = Option<X>> + Send + 'static,
+    {
+        let (space, mark) = self.tones(sample_rate);
+
+        Discriminator::<_>::digital_default()
+            .chain(FskDemod::new(space, mark))
+            .chain(self.bit_sampler(sample_rate))
+            .chain(bits)
+    }
+
+    fn modulate<'a>(
+        self,
+        bits: impl Iterator<Item = bool> + Send + 'a,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> impl Iterator<Item = f32> + Send + 'a {
+        let samples_per_bit = (sample_rate as f32) / (self.baud as f32);
+        let mark_freq = (self.mark as f32) / (sample_rate as f32);
+        let space_freq = (self.space as f32) / (sample_rate as f32);
+
+        bits.resample_nn(samples_per_bit)
+            .map(move |x| match x {
+                true => mark_freq,
+                false => space_freq,
+            })
+            .apply_one_to_one(FmMod::new(amplitude))
+    }
+
+    fn checked_decoder(self, decoding: Decoding, fcs: Fcs) -> Result<CheckedDecoder, Error> {
+        let sample_rate = self.optimal_sample_rate;
+        match decoding {
+            Decoding::Repair {
+                mut repair,
+                soft: true,
+            } => {
+                repair.set_fcs(fcs);
+                Ok(repair_logged(
+                    self.soft_repairing_decoder(sample_rate, repair),
+                ))
+            }
+            Decoding::Bank(branches) => {
+                let mut bank = Bell202DecoderBank::with_profile(self, sample_rate, &branches);
+                bank.set_fcs(fcs);
+                Ok(Box::new(move |sample| {
+                    let decoded = bank.filter(sample)?;
+                    debug!(
+                        "Decoded by branch {}: {:?}",
+                        decoded.branch,
+                        bank.branches()[decoded.branch]
+                    );
+                    Some(decoded.frame)
+                }))
+            }
+            decoding => checked_decoder(self, decoding, fcs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modem::Framing;
+
+    fn test_frame() -> Vec<u8> {
+        hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap()
+    }
+
+    const PRESETS: [AfskProfile; 4] = [
+        AfskProfile::BELL_202,
+        AfskProfile::BELL_103,
+        AfskProfile::V23,
+        AfskProfile::AFSK_2400,
+    ];
+
+    #[test]
+    fn afsk_profile_encode_decode() {
+        for profile in PRESETS.iter().flat_map(|&x| [x, x.with_pll(false)]) {
+            for sample_rate in [profile.optimal_sample_rate, profile.max_sample_rate()] {
+                let samples = profile.encode(test_frame().into_iter(), sample_rate, 0.75);
+                let mut decoder = profile.decoder(sample_rate);
+                let decoded = samples
+                    .chain(std::iter::repeat_n(0.0, 1000))
+                    .find_map(|x| decoder.filter(x));
+                assert_eq!(
+                    decoded,
+                    Some(test_frame()),
+                    "{:?} at {}",
+                    profile,
+                    sample_rate
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn afsk_profile_framed_encode_decode() {
+        for profile in PRESETS {
+            for framing in [Framing::Fx25(16), Framing::Il2p] {
+                let sample_rate = profile.optimal_sample_rate;
+                let samples =
+                    profile.encode_framed(test_frame(), framing, Fcs::X25, sample_rate, 0.75);
+                let mut decoder = profile.framed_decoder(sample_rate, framing, Fcs::X25);
+                let decoded = samples
+                    .chain(std::iter::repeat_n(0.0, 1000))
+                    .filter_map(|x| decoder.filter(x))
+                    .find(|x| *x == test_frame());
+                assert!(decoded.is_some(), "{:?} with {:?}", profile, framing);
+            }
+        }
+    }
+
+    #[test]
+    fn afsk_profile_encode_flags() {
+        let duration = std::time::Duration::from_millis(100);
+        for profile in PRESETS {
+            let sample_rate = profile.optimal_sample_rate;
+            let flags = profile.encode_flags(duration, sample_rate, 0.75);
+            let flag_len = (8 * sample_rate / profile.baud) as usize;
+            // Less a sample, for rounding of fractional samples per bit.
+            let len = (sample_rate / 10) as usize - 1;
+
+            let samples: Vec<f32> = flags
+                .chain(profile.encode(test_frame().into_iter(), sample_rate, 0.75))
+                .collect();
+            let mut decoder = profile.decoder(sample_rate);
+            let decoded = samples
+                .iter()
+                .chain(std::iter::repeat_n(&0.0, 1000))
+                .find_map(|&x| decoder.filter(x));
+            assert_eq!(decoded, Some(test_frame()), "{:?}", profile);
+
+            let flags_len = profile.encode_flags(duration, sample_rate, 0.75).count();
+            assert!(
+                (len..len + flag_len).contains(&flags_len),
+                "{:?}: {}",
+                profile,
+                flags_len
+            );
+        }
+    }
+
+    #[test]
+    fn afsk_profile_checked_decoder_crc32() {
+        let profile = AfskProfile::BELL_202;
+        let payload = Fcs::X25.verify(&test_frame()).unwrap().to_vec();
+        let frame = Fcs::Crc32.append(payload);
+
+        for framing in [Framing::Hdlc, Framing::Fx25(16), Framing::Il2p] {
+            let sample_rate = profile.optimal_sample_rate;
+            let decode = |fcs| {
+                let samples =
+                    profile.encode_framed(frame.clone(), framing, Fcs::Crc32, sample_rate, 0.75);
+                let mut decoder = profile
+                    .checked_decoder(Decoding::Framed(framing), fcs)
+                    .unwrap();
+                samples
+                    .chain(std::iter::repeat_n(0.0, 1000))
+                    .filter_map(&mut decoder)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(decode(Fcs::Crc32), vec![frame.clone()], "{:?}", framing);
+            assert!(!decode(Fcs::X25).contains(&frame), "{:?}", framing);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Frame check sequence handling, shared by every PHY and tool.
+//!
+//! The FCS is appended least-significant byte first, as HDLC does.
+
+use std::str::FromStr;
+
+const X25: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_SDLC);
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// Error returned when a frame's FCS doesn't check out.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, thiserror::Error)]
+pub enum FcsError {
+    #[error("Frame of {0} bytes is too short to have an FCS")]
+    TooShort(usize),
+
+    #[error("FCS mismatch: received 0x{received:X}, calculated 0x{calculated:X}")]
+    Mismatch { received: u32, calculated: u32 },
+}
+
+/// A frame check sequence algorithm.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Fcs {
+    /// The 16-bit CRC used by AX.25 and ARNGLL.
+    #[default]
+    X25,
+
+    /// The 32-bit CRC used by HDLC, for longer frames.
+    Crc32,
+}
+
+impl Fcs {
+    /// Size of the FCS, in bytes.
+    pub const fn size(self) -> usize {
+        match self {
+            Fcs::X25 => 2,
+            Fcs::Crc32 => 4,
+        }
+    }
+
+    /// Calculates the FCS of `data`.
+    pub fn calculate(self, data: &[u8]) -> u32 {
+        match self {
+            Fcs::X25 => X25.checksum(data) as u32,
+            Fcs::Crc32 => CRC32.checksum(data),
+        }
+    }
+
+    /// Appends the FCS to `frame`.
+    pub fn append(self, mut frame: Vec<u8>) -> Vec<u8> {
+        let fcs = self.calculate(&frame).to_le_bytes();
+        frame.extend_from_slice(&fcs[..self.size()]);
+        frame
+    }
+
+    /// Checks the FCS at the end of `frame`, returning the frame without it.
+    pub fn verify(self, frame: &[u8]) -> Result<&[u8], FcsError> {
+        if frame.len() <= self.size() {
+            return Err(FcsError::TooShort(frame.len()));
+        }
+
+        let (frame, fcs) = frame.split_at(frame.len() - self.size());
+        let received = fcs.iter().rev().fold(0u32, |acc, &x| (acc << 8) | x as u32);
+        let calculated = self.calculate(frame);
+
+        if received == calculated {
+            Ok(frame)
+        } else {
+            Err(FcsError::Mismatch {
+                received,
+                calculated,
+            })
+        }
+    }
+}
+
+impl FromStr for Fcs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "x25" | "crc16" => Ok(Fcs::X25),
+            "crc32" => Ok(Fcs::Crc32),
+            _ => anyhow::bail!("Expected `x25` or `crc32`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fcs_append_verify() {
+        let frame = hex::decode("054013375CAC70F85CB626E8062839414D2D54414B002918FA9C").unwrap();
+
+        for fcs in [Fcs::X25, Fcs::Crc32] {
+            let with_fcs = fcs.append(frame.clone());
+            assert_eq!(with_fcs.len(), frame.len() + fcs.size());
+            assert_eq!(fcs.verify(&with_fcs), Ok(&frame[..]));
+
+            let mut corrupted = with_fcs.clone();
+            corrupted[3] ^= 0x10;
+            assert!(matches!(
+                fcs.verify(&corrupted),
+                Err(FcsError::Mismatch { .. })
+            ));
+        }
+
+        // The standard check values.
+        assert_eq!(Fcs::X25.calculate(b"123456789"), 0x906E);
+        assert_eq!(Fcs::Crc32.calculate(b"123456789"), 0xCBF43926);
+
+        assert_eq!(Fcs::X25.verify(&[1, 2]), Err(FcsError::TooShort(2)));
+        assert_eq!("CRC32".parse::<Fcs>().unwrap(), Fcs::Crc32);
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Baseband pulse shaping and slicing.
+
+use super::*;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+/// The shape of the pulse sent for each symbol of a baseband signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PulseShape {
+    /// Raised cosine with the given roll-off, from zero to one. The pulse
+    /// is zero at every other symbol's centre, so there is no intersymbol
+    /// interference. Larger roll-offs use more bandwidth, but are less
+    /// sensitive to timing errors.
+    RaisedCosine(f32),
+
+    /// Root raised cosine with the given roll-off. Used at both ends of
+    /// a link, so that the transmitted pulses through the receiver's
+    /// matched filter make a raised cosine.
+    RootRaisedCosine(f32),
+
+    /// A rectangular pulse through a Gaussian filter with the given
+    /// bandwidth-time product, as used by GMSK. Spreads over fewer
+    /// symbols, at the cost of some intersymbol interference.
+    Gaussian(f32),
+}
+
+impl PulseShape {
+    /// Returns the pulse at `t` symbols from its centre.
+    pub fn at(&self, t: f32) -> f32 {
+        match *self {
+            PulseShape::RaisedCosine(rolloff) => {
+                let x = 2.0 * rolloff * t;
+                if (x.abs() - 1.0).abs() < 1e-4 {
+                    PI / 4.0 * sinc(1.0 / (2.0 * rolloff))
+                } else {
+                    sinc(t) * (PI * rolloff * t).cos() / (1.0 - x * x)
+                }
+            }
+            PulseShape::RootRaisedCosine(rolloff) => {
+                let x = 4.0 * rolloff * t;
+                if t == 0.0 {
+                    1.0 - rolloff + 4.0 * rolloff / PI
+                } else if (x.abs() - 1.0).abs() < 1e-4 {
+                    let a = PI / (4.0 * rolloff);
+                    rolloff / 2f32.sqrt()
+                        * ((1.0 + 2.0 / PI) * a.sin() + (1.0 - 2.0 / PI) * a.cos())
+                } else {
+                    ((PI * t * (1.0 - rolloff)).sin() + x * (PI * t * (1.0 + rolloff)).cos())
+                        / (PI * t * (1.0 - x * x))
+                }
+            }
+            PulseShape::Gaussian(bt) => {
+                let k = PI * bt * (2.0 / std::f32::consts::LN_2).sqrt();
+                0.5 * (erf(k * (t + 0.5)) - erf(k * (t - 0.5)))
+            }
+        }
+    }
+
+    /// Returns an FIR kernel of the pulse, covering `span` symbols either
+    /// side of its centre, with unity gain at DC. Used as a matched filter.
+    pub fn kernel(&self, samples_per_symbol: f32, span: usize) -> FilterFirKernel<f32> {
+        let half = (span as f32 * samples_per_symbol) as usize;
+        let mut taps = (0..=half * 2)
+            .map(|i| self.at((i as f32 - half as f32) / samples_per_symbol))
+            .collect::<Vec<_>>();
+
+        let sum = taps.iter().sum::<f32>();
+        taps.iter_mut().for_each(|x| *x /= sum);
+        FilterFirKernel::new(taps, half)
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Error function, to within 1.5e-7 (Abramowitz and Stegun 7.1.26).
+fn erf(x: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly =
+        t * (0.2548296 + t * (-0.28449672 + t * (1.4214138 + t * (-1.4531521 + t * 1.0614054))));
+    (1.0 - poly * (-x * x).exp()).copysign(x)
+}
+
+/// Iterator that turns symbols into a baseband signal, by adding up a
+/// [`PulseShape`] for each symbol. Created by [`IteratorExt::pulse_shape`].
+///
+/// The output is delayed by `span` symbols, and continues for `span`
+/// symbols after the last one, so that every pulse is complete.
+/// Works with fractional samples per symbol.
+pub struct PulseShaper<I> {
+    inner: I,
+    shape: PulseShape,
+    span: usize,
+
+    /// Symbols per sample.
+    step: f64,
+
+    /// Time of the next sample, in symbols.
+    time: f64,
+
+    /// Symbols whose pulses may still overlap the next sample,
+    /// the oldest first, starting with symbol number `first`.
+    symbols: VecDeque<f32>,
+    first: usize,
+    finished: bool,
+}
+
+impl<I: Iterator<Item = f32>> PulseShaper<I> {
+    pub fn new(inner: I, shape: PulseShape, samples_per_symbol: f32, span: usize) -> Self {
+        assert!(samples_per_symbol > 0.0);
+        PulseShaper {
+            inner,
+            shape,
+            span,
+            step: 1.0 / samples_per_symbol as f64,
+            time: 0.0,
+            symbols: VecDeque::new(),
+            first: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = f32>> Iterator for PulseShaper<I> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // Symbol `k` is centred at `k + span`, and its pulse covers `k` to
+        // `k + 2 * span`.
+        let span = self.span as f64;
+        while !self.finished && (self.first + self.symbols.len()) as f64 <= self.time {
+            match self.inner.next() {
+                Some(x) => self.symbols.push_back(x),
+                None => self.finished = true,
+            }
+        }
+
+        while !self.symbols.is_empty() && self.first as f64 + 2.0 * span < self.time {
+            self.symbols.pop_front();
+            self.first += 1;
+        }
+
+        if self.finished && self.symbols.is_empty() {
+            return None;
+        }
+
+        let ret = self
+            .symbols
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let t = self.time - (self.first + i) as f64 - span;
+                x * self.shape.at(t as f32)
+            })
+            .sum();
+
+        self.time += self.step;
+        Some(ret)
+    }
+}
+
+/// Makes hard decisions on a baseband signal: one if above the threshold.
+#[derive(Clone, Debug, Default)]
+pub struct Slicer {
+    pub threshold: f32,
+}
+
+impl Filter<f32> for Slicer {
+    type Output = Option<bool>;
+
+    fn filter(&mut self, sample: f32) -> Self::Output {
+        if sample.is_finite() {
+            Some(sample > self.threshold)
+        } else {
+            None
+        }
+    }
+}
+
+impl Delay for Slicer {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raised_cosine_zero_crossings() {
+        for rolloff in [0.25, 0.5, 1.0] {
+            let shape = PulseShape::RaisedCosine(rolloff);
+            assert_eq!(shape.at(0.0), 1.0);
+            for t in 1..6 {
+                assert!(shape.at(t as f32).abs() < 1e-6, "{} at {}", rolloff, t);
+                assert!(shape.at(-t as f32).abs() < 1e-6, "{} at {}", rolloff, -t);
+            }
+            // Finite where the formula divides by zero.
+            assert!(shape.at(0.5 / rolloff).is_finite());
+        }
+    }
+
+    #[test]
+    fn root_raised_cosine_matched() {
+        // Through its own matched filter, a root raised cosine pulse
+        // makes a raised cosine: one at its centre, zero at other symbols.
+        let sps = 8.0;
+        let shape = PulseShape::RootRaisedCosine(0.35);
+        let mut filter = shape.kernel(sps, 8).into_filter();
+        let delay = filter.delay();
+        let out = (0..200)
+            .map(|i| shape.at((i as f32 - 8.0 * sps) / sps))
+            .map(|x| filter.filter(x))
+            .collect::<Vec<_>>();
+
+        let centre = 64 + delay;
+        for k in 1..4 {
+            let at = |i: usize| out[i] / out[centre];
+            assert!(at(centre + k * 8).abs() < 0.02, "{}", at(centre + k * 8));
+            assert!(at(centre - k * 8).abs() < 0.02, "{}", at(centre - k * 8));
+        }
+        assert!(shape.at(1.0 / 1.4).is_finite());
+    }
+
+    #[test]
+    fn gaussian_pulses_add_up() {
+        // A run of identical symbols gives a constant signal.
+        let shape = PulseShape::Gaussian(0.5);
+        for i in 0..10 {
+            let t = i as f32 / 10.0;
+            let sum = (-5..=5).map(|k| shape.at(t - k as f32)).sum::<f32>();
+            assert!((sum - 1.0).abs() < 1e-5, "{} at {}", sum, t);
+        }
+        assert!(shape.at(0.0) < 1.0);
+        assert!(shape.at(2.0) < 1e-3);
+    }
+
+    #[test]
+    fn pulse_shaper_no_isi() {
+        let symbols = [1.0, -1.0, -1.0, 1.0, 1.0, 1.0, -1.0, 1.0];
+        let shape = PulseShape::RaisedCosine(0.5);
+        let samples = symbols
+            .iter()
+            .copied()
+            .pulse_shape(shape, 4.0, 3)
+            .collect::<Vec<_>>();
+
+        assert_eq!(samples.len(), (symbols.len() - 1 + 6) * 4 + 1);
+        for (i, x) in symbols.iter().enumerate() {
+            assert!((samples[(i + 3) * 4] - x).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn pulse_kernel_dc_gain() {
+        let kernel = PulseShape::RaisedCosine(0.5).kernel(4.6, 3);
+        let mut filter = kernel.into_filter();
+        let out = (0..100).map(|_| filter.filter(1.0)).last().unwrap();
+        assert!((out - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,577 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+use std::collections::VecDeque;
+
+/// Default loop gain of [`PllBitSampler`] while locked.
+pub const PLL_DEFAULT_LOCKED_GAIN: f32 = 0.25;
+
+/// Default loop gain of [`PllBitSampler`] while searching.
+pub const PLL_DEFAULT_SEARCHING_GAIN: f32 = 0.5;
+
+/// Smoothing applied to the phase error used to decide if we are locked.
+const PLL_ERROR_SMOOTHING: f32 = 0.1;
+
+/// Average phase error, in symbols, below which we are locked.
+const PLL_LOCK_THRESHOLD: f32 = 0.1;
+
+/// Average phase error, in symbols, above which we are no longer locked.
+const PLL_UNLOCK_THRESHOLD: f32 = 0.2;
+
+/// Bit sampler with a digital PLL for clock recovery.
+///
+/// A drop-in replacement for [`BitSampler`]. Rather than restarting its
+/// clock on every transition, it nudges the phase of a free-running clock
+/// towards the transitions, by a fraction of the phase error given by the
+/// loop gain. A gain of one restarts the clock on every transition, like
+/// `BitSampler`. Smaller gains ride out jitter, at the cost of taking longer
+/// to acquire the clock, so a larger gain is used until the average phase
+/// error is small enough for us to be locked.
+#[derive(Clone, Debug)]
+pub struct PllBitSampler {
+    /// Symbols per sample.
+    step: f32,
+    locked_gain: f32,
+    searching_gain: f32,
+
+    /// Phase of the symbol clock, from zero to one. Bits are sampled
+    /// as it wraps around, and transitions are expected at one half.
+    phase: f32,
+    average_error: f32,
+    locked: bool,
+    last_bit: bool,
+}
+
+impl PllBitSampler {
+    pub fn new(sample_rate: u32, bit_rate: u32) -> PllBitSampler {
+        Self::with_samples_per_symbol(sample_rate as f32 / bit_rate as f32)
+    }
+
+    pub fn with_samples_per_symbol(samples_per_symbol: f32) -> PllBitSampler {
+        assert!(
+            samples_per_symbol >= 2.0,
+            "bad samples per symbol: {}",
+            samples_per_symbol
+        );
+        PllBitSampler {
+            step: 1.0 / samples_per_symbol,
+            locked_gain: PLL_DEFAULT_LOCKED_GAIN,
+            searching_gain: PLL_DEFAULT_SEARCHING_GAIN,
+            phase: 0.0,
+            average_error: 0.5,
+            locked: false,
+            last_bit: false,
+        }
+    }
+
+    /// Sets the fraction of the phase error corrected on each transition,
+    /// while locked and while searching.
+    pub fn set_loop_gain(&mut self, locked: f32, searching: f32) {
+        assert!(locked > 0.0 && locked <= 1.0, "bad locked gain: {}", locked);
+        assert!(
+            searching > 0.0 && searching <= 1.0,
+            "bad searching gain: {}",
+            searching
+        );
+        self.locked_gain = locked;
+        self.searching_gain = searching;
+    }
+
+    /// Returns true if the clock is locked to the transitions.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Returns the phase of the symbol clock, from zero to one.
+    /// Bits are sampled when it wraps around.
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    fn transition(&mut self) {
+        // Positive if the transition came late.
+        let error = 0.5 - self.phase;
+
+        let gain = if self.locked {
+            self.locked_gain
+        } else {
+            self.searching_gain
+        };
+        self.phase = (self.phase + error * gain).rem_euclid(1.0);
+
+        self.average_error += (error.abs() - self.average_error) * PLL_ERROR_SMOOTHING;
+        if self.average_error < PLL_LOCK_THRESHOLD {
+            self.locked = true;
+        } else if self.average_error > PLL_UNLOCK_THRESHOLD {
+            self.locked = false;
+        }
+    }
+}
+
+impl Delay for PllBitSampler {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Reset for PllBitSampler {
+    fn reset(&mut self) {
+        self.phase = 0.0;
+        self.average_error = 0.5;
+        self.locked = false;
+        self.last_bit = false;
+    }
+}
+
+impl Filter<Option<bool>> for PllBitSampler {
+    type Output = Option<bool>;
+
+    fn filter(&mut self, sample: Option<bool>) -> Self::Output {
+        let sample = match sample {
+            Some(x) => x,
+            None => {
+                self.reset();
+                return None;
+            }
+        };
+
+        if sample != self.last_bit {
+            self.last_bit = sample;
+            self.transition();
+        }
+
+        self.phase += self.step;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+            Some(sample)
+        } else {
+            None
+        }
+    }
+}
+
+/// Soft-decision variant of [`PllBitSampler`].
+///
+/// Takes soft decisions, such as those from [`SoftFskDemod`], and recovers
+/// the clock from their signs, making the same hard decisions as
+/// `PllBitSampler`. The confidence of each bit is the magnitude of the
+/// soft decision at its sampling instant, scaled down if the sign changes
+/// within a quarter of a symbol either side of it, since that is what a
+/// mistimed sample looks like. Bits are output a quarter of a symbol late,
+/// so that the samples after the sampling instant can be checked.
+#[derive(Clone, Debug)]
+pub struct SoftPllBitSampler {
+    pll: PllBitSampler,
+
+    /// The most recent samples, newest first.
+    history: CircularQueue<f32>,
+
+    /// Samples checked either side of the sampling instant.
+    margin: usize,
+
+    /// Samples until the pending bit is output.
+    pending: Option<usize>,
+}
+
+impl SoftPllBitSampler {
+    pub fn new(sample_rate: u32, bit_rate: u32) -> SoftPllBitSampler {
+        Self::with_samples_per_symbol(sample_rate as f32 / bit_rate as f32)
+    }
+
+    pub fn with_samples_per_symbol(samples_per_symbol: f32) -> SoftPllBitSampler {
+        let margin = (samples_per_symbol / 4.0).ceil() as usize;
+        SoftPllBitSampler {
+            pll: PllBitSampler::with_samples_per_symbol(samples_per_symbol),
+            history: CircularQueue::with_capacity(margin * 2 + 1),
+            margin,
+            pending: None,
+        }
+    }
+
+    /// See [`PllBitSampler::set_loop_gain`].
+    pub fn set_loop_gain(&mut self, locked: f32, searching: f32) {
+        self.pll.set_loop_gain(locked, searching)
+    }
+
+    /// Returns true if the clock is locked to the transitions.
+    pub fn is_locked(&self) -> bool {
+        self.pll.is_locked()
+    }
+
+    /// Returns the soft decision for the sample `margin` samples ago.
+    fn decide(&self) -> f32 {
+        let history = self.history.iter().copied().collect::<Vec<_>>();
+        let sample = history[self.margin];
+        let is_one = sample > 0.0;
+
+        // Distance to the nearest sign change, up to `margin + 1`.
+        let distance = (1..=self.margin)
+            .find(|&i| {
+                let changed = |x: Option<&f32>| x.is_some_and(|&x| (x > 0.0) != is_one);
+                changed(history.get(self.margin - i)) || changed(history.get(self.margin + i))
+            })
+            .unwrap_or(self.margin + 1);
+
+        let confidence = sample.abs() * distance as f32 / (self.margin + 1) as f32;
+        if is_one {
+            confidence
+        } else {
+            -confidence
+        }
+    }
+}
+
+impl Delay for SoftPllBitSampler {
+    fn delay(&self) -> usize {
+        self.margin
+    }
+}
+
+impl Reset for SoftPllBitSampler {
+    fn reset(&mut self) {
+        self.pll.reset();
+        self.history.clear();
+        self.pending = None;
+    }
+}
+
+impl Filter<Option<f32>> for SoftPllBitSampler {
+    type Output = Option<f32>;
+
+    fn filter(&mut self, sample: Option<f32>) -> Self::Output {
+        let sample = match sample {
+            Some(x) => x,
+            None => {
+                self.reset();
+                return None;
+            }
+        };
+
+        self.history.push(sample);
+        if self.pll.filter(Some(sample > 0.0)).is_some() {
+            self.pending = Some(self.margin);
+        }
+
+        match self.pending? {
+            0 => {
+                self.pending = None;
+                Some(self.decide())
+            }
+            x => {
+                self.pending = Some(x - 1);
+                None
+            }
+        }
+    }
+}
+
+/// Default loop gain of [`GardnerTimingRecovery`].
+pub const GARDNER_DEFAULT_GAIN: f32 = 0.05;
+
+/// Smoothing applied to the symbol power used to normalize the timing error.
+const GARDNER_POWER_SMOOTHING: f32 = 0.05;
+
+/// Symbol timing recovery with a Gardner timing error detector.
+///
+/// Takes complex baseband samples `(i, q)` after the matched filter, at
+/// two or more (possibly fractional) samples per symbol, and outputs a
+/// sample at each symbol's centre, interpolated between input samples.
+///
+/// The timing error compares the sample halfway between two symbols with
+/// the difference between them: at a transition, the halfway sample is
+/// zero if the timing is right. This doesn't depend on the carrier phase,
+/// so it works before carrier recovery, such as with a [`CostasLoop`].
+#[derive(Clone, Debug)]
+pub struct GardnerTimingRecovery {
+    samples_per_symbol: f32,
+    gain: f32,
+
+    /// The most recent samples, newest last.
+    history: VecDeque<(f32, f32)>,
+
+    /// Samples until the next symbol's centre, after the newest sample.
+    countdown: f32,
+    last_symbol: (f32, f32),
+    power: f32,
+}
+
+impl GardnerTimingRecovery {
+    pub fn new(sample_rate: u32, symbol_rate: u32) -> GardnerTimingRecovery {
+        Self::with_samples_per_symbol(sample_rate as f32 / symbol_rate as f32)
+    }
+
+    pub fn with_samples_per_symbol(samples_per_symbol: f32) -> GardnerTimingRecovery {
+        assert!(
+            samples_per_symbol >= 2.0,
+            "bad samples per symbol: {}",
+            samples_per_symbol
+        );
+        GardnerTimingRecovery {
+            samples_per_symbol,
+            gain: GARDNER_DEFAULT_GAIN,
+            history: VecDeque::new(),
+            countdown: samples_per_symbol,
+            last_symbol: (0.0, 0.0),
+            power: 0.0,
+        }
+    }
+
+    /// Sets the fraction of a symbol the timing moves by per symbol,
+    /// for a normalized timing error of one.
+    pub fn set_loop_gain(&mut self, gain: f32) {
+        assert!(gain > 0.0 && gain < 0.5, "bad loop gain: {}", gain);
+        self.gain = gain;
+    }
+
+    /// Returns the sample `back` samples before the newest one,
+    /// interpolating linearly.
+    fn interpolate(&self, back: f32) -> (f32, f32) {
+        let newest = self.history.len() - 1;
+        let index = newest.saturating_sub(back as usize).max(1);
+        let frac = 1.0 - (back - (newest - index) as f32);
+        let (a, b) = (self.history[index - 1], self.history[index]);
+        (a.0 + (b.0 - a.0) * frac, a.1 + (b.1 - a.1) * frac)
+    }
+}
+
+impl Delay for GardnerTimingRecovery {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Reset for GardnerTimingRecovery {
+    fn reset(&mut self) {
+        self.history.clear();
+        self.countdown = self.samples_per_symbol;
+        self.last_symbol = (0.0, 0.0);
+        self.power = 0.0;
+    }
+}
+
+impl Filter<(f32, f32)> for GardnerTimingRecovery {
+    type Output = Option<(f32, f32)>;
+
+    fn filter(&mut self, sample: (f32, f32)) -> Self::Output {
+        if !sample.0.is_finite() || !sample.1.is_finite() {
+            self.reset();
+            return None;
+        }
+
+        self.history.push_back(sample);
+        if self.history.len() > (self.samples_per_symbol / 2.0) as usize + 3 {
+            self.history.pop_front();
+        }
+
+        self.countdown -= 1.0;
+        if self.countdown > 0.0 || self.history.len() < 2 {
+            return None;
+        }
+
+        let back = -self.countdown;
+        let symbol = self.interpolate(back);
+        let middle = self.interpolate(back + self.samples_per_symbol / 2.0);
+
+        // Negative if we are sampling late.
+        let error =
+            middle.0 * (self.last_symbol.0 - symbol.0) + middle.1 * (self.last_symbol.1 - symbol.1);
+
+        let power = symbol.0 * symbol.0 + symbol.1 * symbol.1;
+        self.power += (power - self.power) * GARDNER_POWER_SMOOTHING;
+        let error = if self.power > 0.0 {
+            (error / self.power).clamp(-1.0, 1.0)
+        } else {
+            0.0
+        };
+
+        self.countdown += self.samples_per_symbol * (1.0 + self.gain * error);
+        self.last_symbol = symbol;
+        Some(symbol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    /// Returns the samples for `bits` at `samples_per_symbol`, with the
+    /// transitions moved by up to `jitter` samples either way.
+    fn symbols(bits: &[bool], samples_per_symbol: f32, jitter: f32) -> Vec<Option<bool>> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let len = (bits.len() as f32 * samples_per_symbol) as usize;
+        let edges = (0..=bits.len())
+            .map(|i| i as f32 * samples_per_symbol + rng.gen_range(-jitter..=jitter))
+            .collect::<Vec<_>>();
+
+        let mut bit = 0;
+        (0..len)
+            .map(|i| {
+                while bit + 1 < bits.len() && i as f32 >= edges[bit + 1] {
+                    bit += 1;
+                }
+                Some(bits[bit])
+            })
+            .collect()
+    }
+
+    fn random_bits(count: usize) -> Vec<bool> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+        (0..count).map(|_| rng.gen()).collect()
+    }
+
+    fn recover<F: Filter<Option<bool>, Output = Option<bool>>>(
+        sampler: &mut F,
+        samples: &[Option<bool>],
+    ) -> Vec<bool> {
+        samples.iter().filter_map(|&x| sampler.filter(x)).collect()
+    }
+
+    /// Counts the bits after the first `skip` that don't match,
+    /// aligning the start of `received` with `sent`.
+    fn bit_errors(sent: &[bool], received: &[bool], skip: usize) -> usize {
+        (0..4)
+            .map(|offset| {
+                sent[skip + offset..]
+                    .iter()
+                    .zip(&received[skip..])
+                    .filter(|(a, b)| a != b)
+                    .count()
+            })
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn pll_bit_sampler_fractional_rate() {
+        let bits = random_bits(2000);
+        for samples_per_symbol in [5.9575, 6.6667, 8.3, 36.75] {
+            let samples = symbols(&bits, samples_per_symbol, 0.0);
+            let mut sampler = PllBitSampler::with_samples_per_symbol(samples_per_symbol);
+            let received = recover(&mut sampler, &samples);
+
+            assert!(sampler.is_locked(), "not locked at {}", samples_per_symbol);
+            assert!(received.len().abs_diff(bits.len()) <= 2);
+            assert_eq!(bit_errors(&bits, &received, 20), 0);
+        }
+    }
+
+    #[test]
+    fn pll_bit_sampler_clock_error() {
+        // The sender's clock is off by 2%.
+        let bits = random_bits(2000);
+        let samples = symbols(&bits, 6.0 * 1.02, 0.0);
+        let mut sampler = PllBitSampler::with_samples_per_symbol(6.0);
+        let received = recover(&mut sampler, &samples);
+        assert!(sampler.is_locked());
+        assert_eq!(bit_errors(&bits, &received, 20), 0);
+    }
+
+    #[test]
+    fn pll_bit_sampler_jitter() {
+        let bits = random_bits(2000);
+        let samples = symbols(&bits, 8.0, 2.5);
+
+        let mut sampler = PllBitSampler::with_samples_per_symbol(8.0);
+        let pll_errors = bit_errors(&bits, &recover(&mut sampler, &samples), 20);
+
+        let mut sampler = BitSampler::new(8, 1);
+        let plain_errors = bit_errors(&bits, &recover(&mut sampler, &samples), 20);
+
+        assert!(
+            pll_errors < plain_errors,
+            "{} vs {}",
+            pll_errors,
+            plain_errors
+        );
+    }
+
+    #[test]
+    fn soft_pll_bit_sampler() {
+        let bits = random_bits(2000);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let samples = symbols(&bits, 8.0, 0.0)
+            .into_iter()
+            .map(|x| {
+                let x = if x.unwrap() { 1.0 } else { -1.0 };
+                Some(x + rng.gen_range(-0.5..=0.5))
+            })
+            .collect::<Vec<_>>();
+
+        let mut sampler = SoftPllBitSampler::with_samples_per_symbol(8.0);
+        let received = samples
+            .iter()
+            .filter_map(|&x| sampler.filter(x))
+            .collect::<Vec<_>>();
+        assert!(sampler.is_locked());
+
+        let hard = received.iter().map(|&x| x > 0.0).collect::<Vec<_>>();
+        assert_eq!(bit_errors(&bits, &hard, 20), 0);
+        assert!(received[20..].iter().all(|x| x.abs() >= 0.5));
+    }
+
+    #[test]
+    fn gardner_timing_recovery() {
+        let bits = random_bits(2000);
+        let phase = 1.0f32;
+        // Sent slightly faster than the receiver expects.
+        let samples = bits
+            .iter()
+            .map(|&x| if x { 1.0 } else { -1.0 })
+            .pulse_shape(PulseShape::RaisedCosine(0.5), 4.98, 4)
+            .map(|x| (x * phase.cos(), x * phase.sin()))
+            .collect::<Vec<_>>();
+
+        let mut recovery = GardnerTimingRecovery::with_samples_per_symbol(5.0);
+        let received = samples
+            .iter()
+            .filter_map(|&x| recovery.filter(x))
+            .map(|(i, q)| i * phase.cos() + q * phase.sin() > 0.0)
+            .collect::<Vec<_>>();
+
+        // Skips the span before the first pulse's centre.
+        let errors = (0..8)
+            .map(|offset| {
+                received[offset + 200..]
+                    .iter()
+                    .zip(&bits[200..1900])
+                    .filter(|(a, b)| a != b)
+                    .count()
+            })
+            .min()
+            .unwrap();
+        assert_eq!(errors, 0);
+    }
+
+    #[test]
+    fn pll_bit_sampler_lock() {
+        let mut sampler = PllBitSampler::new(8000, 1200);
+        assert!(!sampler.is_locked());
+
+        let samples = symbols(&random_bits(100), 8000.0 / 1200.0, 0.0);
+        recover(&mut sampler, &samples);
+        assert!(sampler.is_locked());
+
+        assert_eq!(sampler.filter(None), None);
+        assert!(!sampler.is_locked());
+    }
+}
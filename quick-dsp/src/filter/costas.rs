@@ -0,0 +1,173 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+/// Costas loop: carrier phase recovery for BPSK and QPSK.
+///
+/// Takes complex symbols `(i, q)`, such as those from a
+/// [`GardnerTimingRecovery`], and rotates them so that BPSK symbols
+/// lie on the `i` axis, and QPSK symbols on the diagonals. The phase
+/// error is taken from hard decisions on each symbol, through a second
+/// order [`LoopFilter`], so that it tracks both the phase and the
+/// frequency of the carrier. Like any Costas loop, it can lock to a multiple of
+/// half a turn (BPSK) or a quarter turn (QPSK) out, so use differential
+/// coding on top.
+#[derive(Clone, Debug)]
+pub struct CostasLoop {
+    /// Points in the constellation: two or four.
+    points: u8,
+    nco: Nco,
+    loop_filter: LoopFilter,
+}
+
+impl CostasLoop {
+    /// Returns a Costas loop for BPSK, with the given loop
+    /// bandwidth as a fraction of the symbol rate.
+    pub fn bpsk(bandwidth: f32) -> CostasLoop {
+        Self::new(2, bandwidth)
+    }
+
+    /// Returns a Costas loop for QPSK, with the given loop
+    /// bandwidth as a fraction of the symbol rate.
+    pub fn qpsk(bandwidth: f32) -> CostasLoop {
+        Self::new(4, bandwidth)
+    }
+
+    fn new(points: u8, bandwidth: f32) -> CostasLoop {
+        CostasLoop {
+            points,
+            nco: Nco::default(),
+            loop_filter: LoopFilter::new(bandwidth, LOOP_DEFAULT_DAMPING),
+        }
+    }
+
+    /// Sets the loop's noise bandwidth, as a fraction of
+    /// the symbol rate, and its damping factor.
+    pub fn set_loop_bandwidth(&mut self, bandwidth: f32, damping: f32) {
+        self.loop_filter.set_bandwidth(bandwidth, damping);
+    }
+
+    /// Returns the phase correction applied to the next symbol, in radians.
+    pub fn phase(&self) -> f32 {
+        self.nco.phase()
+    }
+
+    /// Returns the estimated carrier frequency offset,
+    /// in radians per symbol.
+    pub fn frequency(&self) -> f32 {
+        self.loop_filter.integrator()
+    }
+}
+
+impl Delay for CostasLoop {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Reset for CostasLoop {
+    fn reset(&mut self) {
+        self.nco = Nco::default();
+        self.loop_filter.reset();
+    }
+}
+
+impl Filter<(f32, f32)> for CostasLoop {
+    type Output = (f32, f32);
+
+    fn filter(&mut self, sample: (f32, f32)) -> Self::Output {
+        let (i, q) = self.nco.derotate(sample);
+        let magnitude = i.hypot(q);
+        if magnitude == 0.0 || !magnitude.is_finite() {
+            return (i, q);
+        }
+
+        // Roughly the sine of the phase error, positive if ahead.
+        let error = match self.points {
+            2 => i.signum() * q,
+            _ => i.signum() * q - q.signum() * i,
+        } / magnitude;
+
+        let correction = self.loop_filter.filter(error);
+        self.nco.advance(correction);
+
+        (i, q)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// Returns symbols with the given phase and frequency offsets.
+    fn symbols(points: u8, phase: f32, frequency: f32) -> Vec<((f32, f32), u8)> {
+        (0..2000u32)
+            .map(|n| {
+                let k = ((n * 7 + n / 3) % points as u32) as u8;
+                let offset = if points == 4 { PI / 4.0 } else { 0.0 };
+                let angle = offset + k as f32 * 2.0 * PI / points as f32;
+                let angle = angle + phase + frequency * n as f32;
+                ((angle.cos(), angle.sin()), k)
+            })
+            .collect()
+    }
+
+    fn check(mut costas: CostasLoop, points: u8, phase: f32, frequency: f32) {
+        let symbols = symbols(points, phase, frequency);
+        let turn = 2.0 * PI / points as f32;
+        let offset = if points == 4 { PI / 4.0 } else { 0.0 };
+
+        let decided = symbols
+            .iter()
+            .map(|&(x, _)| {
+                let (i, q) = costas.filter(x);
+                ((q.atan2(i) - offset) / turn)
+                    .round()
+                    .rem_euclid(points as f32) as u8
+            })
+            .collect::<Vec<_>>();
+
+        // Correct after settling, up to the loop's ambiguity.
+        let rotation = (decided[1999] + points - symbols[1999].1) % points;
+        for (n, (&(_, k), &d)) in symbols.iter().zip(&decided).enumerate().skip(500) {
+            assert_eq!(d, (k + rotation) % points, "symbol {}", n);
+        }
+        assert!((costas.frequency() - frequency).abs() < 1e-3);
+    }
+
+    #[test]
+    fn costas_loop_bpsk() {
+        check(CostasLoop::bpsk(0.02), 2, 1.0, 0.0);
+        check(CostasLoop::bpsk(0.02), 2, -2.0, 0.02);
+
+        let mut costas = CostasLoop::bpsk(0.02);
+        costas.set_loop_bandwidth(0.05, 1.0);
+        check(costas, 2, 3.0, -0.05);
+    }
+
+    #[test]
+    fn costas_loop_qpsk() {
+        check(CostasLoop::qpsk(0.02), 4, 0.5, 0.0);
+        check(CostasLoop::qpsk(0.02), 4, 2.0, -0.02);
+    }
+}
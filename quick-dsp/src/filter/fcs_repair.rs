@@ -0,0 +1,653 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+use crate::fcs::Fcs;
+use log::debug;
+
+/// Default number of candidate frames [`FcsRepair`] checks before giving up.
+pub const FCS_REPAIR_DEFAULT_MAX_ATTEMPTS: usize = 10_000;
+
+/// Number of least confident bits of a [`SoftFrame`] that
+/// [`FcsRepair::check_soft`] tries flipping in pairs.
+pub const FCS_REPAIR_SOFT_PAIR_BITS: usize = 24;
+
+/// How a frame was repaired. Bit indexes count in the order bits are
+/// sent, starting at the least significant bit of the first octet.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum RepairMethod {
+    /// The given bit was flipped.
+    SingleBit(usize),
+
+    /// The given bit and the one after it were flipped.
+    AdjacentBits(usize),
+
+    /// A frame that didn't end on an octet boundary was fixed by flipping
+    /// the given bit of the bit-stuffed stream, then removing the stuffed
+    /// bits again.
+    Restuffed(usize),
+
+    /// The given two bits, chosen by their confidence, were flipped.
+    TwoBits(usize, usize),
+}
+
+/// A repair made by [`FcsRepair`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Repair {
+    pub method: RepairMethod,
+
+    /// Number of candidates checked, including the successful one.
+    pub attempts: usize,
+}
+
+/// A frame with a valid FCS, which may have needed repairing.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CheckedFrame {
+    /// The frame, including FCS.
+    pub frame: Vec<u8>,
+    pub repair: Option<Repair>,
+}
+
+/// Counts of what [`FcsRepair`] did with each frame.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RepairStats {
+    /// Frames that had a valid FCS to begin with.
+    pub passed: u32,
+    pub single_bit: u32,
+    pub adjacent_bits: u32,
+    pub restuffed: u32,
+    pub two_bits: u32,
+
+    /// Frames that couldn't be repaired, or were too short to try.
+    pub failed: u32,
+
+    /// Frames given up on after running out of attempts.
+    pub over_budget: u32,
+}
+
+impl RepairStats {
+    /// Total number of frames repaired.
+    pub fn repaired(&self) -> u32 {
+        self.single_bit + self.adjacent_bits + self.restuffed + self.two_bits
+    }
+}
+
+struct OverBudget;
+
+/// Frame collector that checks the X.25 FCS of each frame, trying
+/// to repair those that fail by flipping bits until the FCS matches.
+///
+/// Use in place of [`FrameCollector`] after [`HdlcDecode`]. Unlike
+/// `FrameCollector`, frames that don't end on an octet boundary are kept,
+/// since that is what a bit error in a run of ones usually looks like.
+///
+/// Frames with a bad FCS get single-bit flips, then adjacent two-bit
+/// flips. Frames that don't end on an octet boundary get single-bit flips
+/// of the bit-stuffed stream. At most `max_attempts` candidates are
+/// checked per frame, and frames that can't be repaired are dropped.
+///
+/// Given [`SoftFrame`]s, as from [`SoftFrameCollector`], the same flips
+/// are tried least confident bits first, followed by flips of any two of
+/// the least confident bits.
+///
+/// Every repair makes it more likely that a corrupt frame is accepted,
+/// since the FCS is being used for correction rather than detection,
+/// so this is best left off unless signals are marginal.
+#[derive(Debug, Clone)]
+pub struct FcsRepair {
+    frame: Vec<u8>,
+    max_attempts: usize,
+    min_len: usize,
+    fcs: Fcs,
+    stats: RepairStats,
+}
+
+impl Default for FcsRepair {
+    fn default() -> Self {
+        FcsRepair::new(FCS_REPAIR_DEFAULT_MAX_ATTEMPTS)
+    }
+}
+
+impl FcsRepair {
+    pub fn new(max_attempts: usize) -> FcsRepair {
+        FcsRepair {
+            frame: vec![],
+            max_attempts,
+            min_len: 8,
+            fcs: Fcs::default(),
+            stats: Default::default(),
+        }
+    }
+
+    /// Sets the length (including FCS) below which frames are
+    /// dropped rather than repaired. Defaults to 8.
+    pub fn set_min_len(&mut self, min_len: usize) {
+        self.min_len = min_len;
+    }
+
+    /// Sets the FCS frames are checked with. Defaults to [`Fcs::X25`].
+    pub fn set_fcs(&mut self, fcs: Fcs) {
+        self.fcs = fcs;
+    }
+
+    pub fn stats(&self) -> RepairStats {
+        self.stats
+    }
+
+    /// Checks the FCS of `frame`, repairing it if needed and possible.
+    pub fn check(&mut self, frame: Vec<u8>) -> Option<CheckedFrame> {
+        if self.fcs_is_valid(&frame) {
+            self.stats.passed += 1;
+            return Some(CheckedFrame {
+                frame,
+                repair: None,
+            });
+        }
+
+        if frame.len() < self.min_len {
+            self.stats.failed += 1;
+            return None;
+        }
+
+        let mut attempts = 0;
+        let result = self.search_flips(&frame, &mut attempts);
+        self.finish(result, attempts)
+    }
+
+    /// Like [`FcsRepair::check`], but uses the confidence of each bit
+    /// to decide which to flip first.
+    pub fn check_soft(&mut self, frame: SoftFrame) -> Option<CheckedFrame> {
+        debug_assert_eq!(frame.frame.len() * 8, frame.confidence.len());
+
+        if self.fcs_is_valid(&frame.frame) {
+            self.stats.passed += 1;
+            return Some(CheckedFrame {
+                frame: frame.frame,
+                repair: None,
+            });
+        }
+
+        if frame.frame.len() < self.min_len {
+            self.stats.failed += 1;
+            return None;
+        }
+
+        let mut attempts = 0;
+        let result = self.search_soft(&frame, &mut attempts);
+        self.finish(result, attempts)
+    }
+
+    /// Tries to repair a frame that had `len` bits left over after `frame`.
+    pub fn check_misaligned(&mut self, frame: Vec<u8>, bits: u8, len: u8) -> Option<CheckedFrame> {
+        if frame.len() < self.min_len {
+            self.stats.failed += 1;
+            return None;
+        }
+
+        let stuffed = bit_stuff(
+            frame
+                .iter()
+                .flat_map(|&x| (0..8).map(move |i| x & (1 << i) != 0))
+                .chain((0..len).map(|i| bits & (1 << i) != 0)),
+        );
+
+        let mut attempts = 0;
+        let result = self.search_restuffed(&stuffed, &mut attempts);
+        self.finish(result, attempts)
+    }
+
+    fn finish(
+        &mut self,
+        result: Result<Option<(Vec<u8>, RepairMethod)>, OverBudget>,
+        attempts: usize,
+    ) -> Option<CheckedFrame> {
+        match result {
+            Ok(Some((frame, method))) => {
+                match method {
+                    RepairMethod::SingleBit(_) => self.stats.single_bit += 1,
+                    RepairMethod::AdjacentBits(_) => self.stats.adjacent_bits += 1,
+                    RepairMethod::Restuffed(_) => self.stats.restuffed += 1,
+                    RepairMethod::TwoBits(..) => self.stats.two_bits += 1,
+                }
+                debug!(
+                    "Repaired frame with {:?} after {} attempts",
+                    method, attempts
+                );
+                Some(CheckedFrame {
+                    frame,
+                    repair: Some(Repair { method, attempts }),
+                })
+            }
+            Ok(None) => {
+                self.stats.failed += 1;
+                None
+            }
+            Err(OverBudget) => {
+                self.stats.over_budget += 1;
+                None
+            }
+        }
+    }
+
+    fn fcs_is_valid(&self, frame: &[u8]) -> bool {
+        self.fcs.verify(frame).is_ok()
+    }
+
+    fn spend(&self, attempts: &mut usize) -> Result<(), OverBudget> {
+        if *attempts >= self.max_attempts {
+            return Err(OverBudget);
+        }
+        *attempts += 1;
+        Ok(())
+    }
+
+    fn search_flips(
+        &self,
+        frame: &[u8],
+        attempts: &mut usize,
+    ) -> Result<Option<(Vec<u8>, RepairMethod)>, OverBudget> {
+        let bits = frame.len() * 8;
+        let mut work = frame.to_vec();
+
+        for bit in 0..bits {
+            self.spend(attempts)?;
+            flip_bit(&mut work, bit);
+            if self.fcs_is_valid(&work) {
+                return Ok(Some((work, RepairMethod::SingleBit(bit))));
+            }
+            flip_bit(&mut work, bit);
+        }
+
+        for bit in 0..bits.saturating_sub(1) {
+            self.spend(attempts)?;
+            flip_bit(&mut work, bit);
+            flip_bit(&mut work, bit + 1);
+            if self.fcs_is_valid(&work) {
+                return Ok(Some((work, RepairMethod::AdjacentBits(bit))));
+            }
+            flip_bit(&mut work, bit);
+            flip_bit(&mut work, bit + 1);
+        }
+
+        Ok(None)
+    }
+
+    fn search_soft(
+        &self,
+        frame: &SoftFrame,
+        attempts: &mut usize,
+    ) -> Result<Option<(Vec<u8>, RepairMethod)>, OverBudget> {
+        let order = frame.bits_by_confidence();
+        let mut work = frame.frame.clone();
+
+        for &bit in &order {
+            self.spend(attempts)?;
+            flip_bit(&mut work, bit);
+            if self.fcs_is_valid(&work) {
+                return Ok(Some((work, RepairMethod::SingleBit(bit))));
+            }
+            flip_bit(&mut work, bit);
+        }
+
+        // A bad bit before NRZI decoding gives two bad bits in a row.
+        let confidence = &frame.confidence;
+        let mut adjacent = (0..order.len().saturating_sub(1)).collect::<Vec<_>>();
+        adjacent.sort_by(|&a, &b| {
+            let a = confidence[a] + confidence[a + 1];
+            a.total_cmp(&(confidence[b] + confidence[b + 1]))
+        });
+        for bit in adjacent {
+            self.spend(attempts)?;
+            flip_bit(&mut work, bit);
+            flip_bit(&mut work, bit + 1);
+            if self.fcs_is_valid(&work) {
+                return Ok(Some((work, RepairMethod::AdjacentBits(bit))));
+            }
+            flip_bit(&mut work, bit);
+            flip_bit(&mut work, bit + 1);
+        }
+
+        let weakest = &order[..order.len().min(FCS_REPAIR_SOFT_PAIR_BITS)];
+        for (j, &b) in weakest.iter().enumerate() {
+            for &a in weakest[..j].iter().filter(|&&a| a.abs_diff(b) > 1) {
+                self.spend(attempts)?;
+                flip_bit(&mut work, a);
+                flip_bit(&mut work, b);
+                if self.fcs_is_valid(&work) {
+                    let method = RepairMethod::TwoBits(a.min(b), a.max(b));
+                    return Ok(Some((work, method)));
+                }
+                flip_bit(&mut work, a);
+                flip_bit(&mut work, b);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn search_restuffed(
+        &self,
+        stuffed: &[bool],
+        attempts: &mut usize,
+    ) -> Result<Option<(Vec<u8>, RepairMethod)>, OverBudget> {
+        for bit in 0..stuffed.len() {
+            self.spend(attempts)?;
+            match bit_destuff(stuffed, bit) {
+                Some(candidate) if self.fcs_is_valid(&candidate) => {
+                    return Ok(Some((candidate, RepairMethod::Restuffed(bit))));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Reset for FcsRepair {
+    fn reset(&mut self) {
+        self.frame.clear();
+    }
+}
+
+impl Delay for FcsRepair {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Filter<Option<FrameSignal>> for FcsRepair {
+    type Output = Option<CheckedFrame>;
+
+    fn filter(&mut self, sample: Option<FrameSignal>) -> Self::Output {
+        match sample {
+            Some(FrameSignal::Octet(x)) => {
+                self.frame.push(x);
+                None
+            }
+            Some(FrameSignal::FrameMarker) if !self.frame.is_empty() => {
+                let frame = std::mem::take(&mut self.frame);
+                self.check(frame)
+            }
+            Some(FrameSignal::Misaligned { bits, len }) => {
+                let mut frame = std::mem::take(&mut self.frame);
+                if len >= 2 {
+                    // The last octet had the start of the flag in it.
+                    frame.pop();
+                }
+                self.check_misaligned(frame, bits, len)
+            }
+            Some(FrameSignal::DecodeError) => {
+                self.reset();
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Filter<Option<SoftFrame>> for FcsRepair {
+    type Output = Option<CheckedFrame>;
+
+    fn filter(&mut self, sample: Option<SoftFrame>) -> Self::Output {
+        self.check_soft(sample?)
+    }
+}
+
+fn flip_bit(frame: &mut [u8], bit: usize) {
+    frame[bit / 8] ^= 1 << (bit % 8);
+}
+
+/// Returns `bits` as sent, with a zero after every five ones.
+pub(crate) fn bit_stuff<I: Iterator<Item = bool>>(bits: I) -> Vec<bool> {
+    let mut ret = Vec::with_capacity(bits.size_hint().0 * 9 / 8);
+    let mut ones = 0;
+    for x in bits {
+        ret.push(x);
+        if !x {
+            ones = 0;
+        } else if ones == 4 {
+            ret.push(false);
+            ones = 0;
+        } else {
+            ones += 1;
+        }
+    }
+    ret
+}
+
+/// Removes the stuffed bits from `bits` with the bit at `flip` flipped,
+/// returning `None` if that makes it invalid or not a whole number of octets.
+fn bit_destuff(bits: &[bool], flip: usize) -> Option<Vec<u8>> {
+    let mut ret = Vec::with_capacity(bits.len() / 8);
+    let mut ones = 0;
+    let mut accum = 0u8;
+    let mut count = 0;
+
+    for (i, &x) in bits.iter().enumerate() {
+        let x = x ^ (i == flip);
+        if ones == 5 {
+            if x {
+                // Six ones in a row is a flag or an abort.
+                return None;
+            }
+            ones = 0;
+            continue;
+        }
+        ones = if x { ones + 1 } else { 0 };
+
+        accum |= (x as u8) << count;
+        count += 1;
+        if count == 8 {
+            ret.push(accum);
+            accum = 0;
+            count = 0;
+        }
+    }
+
+    if count == 0 {
+        Some(ret)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_frame() -> Vec<u8> {
+        hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap()
+    }
+
+    fn frame_bits(frame: &[u8]) -> Vec<bool> {
+        frame
+            .iter()
+            .flat_map(|&x| (0..8).map(move |i| x & (1 << i) != 0))
+            .collect()
+    }
+
+    /// Runs stuffed bits, wrapped in flags, through `HdlcDecode` and `repair`.
+    fn receive(repair: &mut FcsRepair, stuffed: &[bool]) -> Vec<CheckedFrame> {
+        let flag = [false, true, true, true, true, true, true, false];
+        let bits = flag
+            .iter()
+            .chain(flag.iter())
+            .chain(stuffed.iter())
+            .chain(flag.iter())
+            .copied();
+        let mut decode = HdlcDecode::default();
+        bits.filter_map(|x| repair.filter(decode.filter(x)))
+            .collect()
+    }
+
+    #[test]
+    fn fcs_repair_passes_good_frames() {
+        let mut repair = FcsRepair::default();
+        let frame = test_frame();
+        let stuffed = bit_stuff(frame_bits(&frame).into_iter());
+        assert_eq!(
+            receive(&mut repair, &stuffed),
+            vec![CheckedFrame {
+                frame,
+                repair: None
+            }]
+        );
+        assert_eq!(repair.stats().passed, 1);
+    }
+
+    #[test]
+    fn fcs_repair_single_and_adjacent() {
+        let frame = test_frame();
+        let mut repair = FcsRepair::default();
+
+        let mut corrupted = frame.clone();
+        flip_bit(&mut corrupted, 100);
+        let checked = repair.check(corrupted).unwrap();
+        assert_eq!(checked.frame, frame);
+        assert_eq!(
+            checked.repair.unwrap(),
+            Repair {
+                method: RepairMethod::SingleBit(100),
+                attempts: 101
+            }
+        );
+
+        let mut corrupted = frame.clone();
+        flip_bit(&mut corrupted, 37);
+        flip_bit(&mut corrupted, 38);
+        let checked = repair.check(corrupted).unwrap();
+        assert_eq!(checked.frame, frame);
+        assert_eq!(
+            checked.repair.unwrap().method,
+            RepairMethod::AdjacentBits(37)
+        );
+
+        // Two bits far apart aren't something we try.
+        let mut corrupted = frame.clone();
+        flip_bit(&mut corrupted, 10);
+        flip_bit(&mut corrupted, 200);
+        assert_eq!(repair.check(corrupted), None);
+
+        let stats = repair.stats();
+        assert_eq!(stats.single_bit, 1);
+        assert_eq!(stats.adjacent_bits, 1);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.repaired(), 2);
+    }
+
+    #[test]
+    fn fcs_repair_restuffed() {
+        // A frame with runs of ones long enough to need stuffing.
+        let frame = Fcs::X25.append(vec![0x03, 0xFF, 0x55, 0xF8, 0x01, 0x02, 0x7E, 0x00]);
+        let stuffed = bit_stuff(frame_bits(&frame).into_iter());
+        assert_eq!(bit_destuff(&stuffed, usize::MAX), Some(frame.clone()));
+
+        // Breaking up each run of five ones turns the stuffed zero after it
+        // into data, so the frame no longer ends on an octet boundary.
+        let runs = (5..stuffed.len())
+            .filter(|&i| stuffed[i - 5..i].iter().all(|&x| x))
+            .collect::<Vec<_>>();
+        assert!(runs.len() >= 3);
+
+        for &end in runs.iter() {
+            let bit = end - 3;
+            let mut received = stuffed.clone();
+            received[bit] = false;
+
+            let mut repair = FcsRepair::default();
+            assert_eq!(
+                receive(&mut repair, &received),
+                vec![CheckedFrame {
+                    frame: frame.clone(),
+                    repair: Some(Repair {
+                        method: RepairMethod::Restuffed(bit),
+                        attempts: bit + 1
+                    })
+                }]
+            );
+            assert_eq!(repair.stats().restuffed, 1);
+        }
+    }
+
+    #[test]
+    fn fcs_repair_crc32() {
+        let frame = Fcs::Crc32.append(test_frame());
+        let mut corrupted = frame.clone();
+        flip_bit(&mut corrupted, 100);
+
+        let mut repair = FcsRepair::default();
+        repair.set_fcs(Fcs::Crc32);
+        assert_eq!(repair.check(corrupted).map(|x| x.frame), Some(frame));
+        assert_eq!(repair.stats().single_bit, 1);
+    }
+
+    #[test]
+    fn fcs_repair_budget() {
+        let mut corrupted = test_frame();
+        flip_bit(&mut corrupted, 300);
+
+        let mut repair = FcsRepair::new(100);
+        assert_eq!(repair.check(corrupted.clone()), None);
+        assert_eq!(repair.stats().over_budget, 1);
+
+        let mut repair = FcsRepair::new(301);
+        assert!(repair.check(corrupted).is_some());
+    }
+
+    #[test]
+    fn fcs_repair_soft() {
+        let frame = test_frame();
+        let mut corrupted = frame.clone();
+        flip_bit(&mut corrupted, 40);
+        flip_bit(&mut corrupted, 300);
+
+        // Too far apart for the hard-decision search.
+        let mut repair = FcsRepair::default();
+        assert_eq!(repair.check(corrupted.clone()), None);
+
+        let mut confidence = vec![1.0; frame.len() * 8];
+        confidence[40] = 0.1;
+        confidence[300] = 0.2;
+        confidence[200] = 0.15;
+        let soft = SoftFrame {
+            frame: corrupted,
+            confidence,
+        };
+
+        let mut repair = FcsRepair::default();
+        let checked = repair.check_soft(soft).unwrap();
+        assert_eq!(checked.frame, frame);
+        let repaired = checked.repair.unwrap();
+        assert_eq!(repaired.method, RepairMethod::TwoBits(40, 300));
+        assert!(repaired.attempts <= frame.len() * 16 + 2);
+        assert_eq!(repair.stats().two_bits, 1);
+    }
+
+    #[test]
+    fn fcs_repair_empty_frame() {
+        let mut repair = FcsRepair::default();
+        repair.set_min_len(0);
+        assert_eq!(repair.check(vec![]), None);
+        assert_eq!(repair.check_soft(SoftFrame::default()), None);
+        assert_eq!(repair.stats().failed, 2);
+    }
+}
@@ -37,20 +37,32 @@ impl<T: Real> FskDemod<T> {
             last_mag: T::ZERO,
         }
     }
-}
 
-impl<T: Real> Filter<(T, T)> for FskDemod<T> {
-    type Output = Option<bool>;
+    /// Like [`FskDemod::new`], but with the slicer threshold moved from
+    /// halfway between `zero` and `one` by `threshold` times the distance
+    /// between them. Positive values move it towards `one`.
+    pub fn with_threshold(zero: T, one: T, threshold: T) -> Self {
+        let mut ret = Self::new(zero, one);
+        ret.offset += (one - zero) * threshold;
+        ret
+    }
 
-    fn filter(&mut self, sample: (T, T)) -> Self::Output {
+    /// Returns the soft decision for `sample`: about -1.0 at `zero`
+    /// and 1.0 at `one`, or `None` if there is no signal.
+    pub fn soft(&self, sample: (T, T)) -> Option<T> {
         if !sample.0.is_finite() || sample.0 <= T::ZERO {
             return None;
         }
 
-        // After this, v should be between -1.0 and 1.0
-        let v = (sample.0 - self.offset) * self.scale;
+        Some((sample.0 - self.offset) * self.scale)
+    }
+}
+
+impl<T: Real> Filter<(T, T)> for FskDemod<T> {
+    type Output = Option<bool>;
 
-        Some(v > T::ZERO)
+    fn filter(&mut self, sample: (T, T)) -> Self::Output {
+        self.soft(sample).map(|v| v > T::ZERO)
     }
 }
 
@@ -60,6 +72,36 @@ impl<T> Delay for FskDemod<T> {
     }
 }
 
+/// Soft-decision variant of [`FskDemod`].
+///
+/// Outputs the values from [`FskDemod::soft`] instead of hard decisions.
+#[derive(Clone, Debug)]
+pub struct SoftFskDemod<T>(pub FskDemod<T>);
+
+impl<T: Real> SoftFskDemod<T> {
+    pub fn new(zero: T, one: T) -> Self {
+        SoftFskDemod(FskDemod::new(zero, one))
+    }
+
+    pub fn with_threshold(zero: T, one: T, threshold: T) -> Self {
+        SoftFskDemod(FskDemod::with_threshold(zero, one, threshold))
+    }
+}
+
+impl<T: Real> Filter<(T, T)> for SoftFskDemod<T> {
+    type Output = Option<T>;
+
+    fn filter(&mut self, sample: (T, T)) -> Self::Output {
+        self.0.soft(sample)
+    }
+}
+
+impl<T> Delay for SoftFskDemod<T> {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -89,4 +131,20 @@ mod tests {
             println!("fsk_demod_f32(0.24) = {:?}", result);
         }
     }
+
+    #[test]
+    fn soft_fsk_demod_f32() {
+        let disc = Discriminator::<f32, (), ()>::digital_default();
+        let mut disc = disc.chain(SoftFskDemod::new(0.2, 0.3));
+        let mut modulator = FmMod::<f32>::new(1.0);
+
+        for (freq, expected) in [(0.2, -1.0), (0.3, 1.0), (0.26, 0.2)] {
+            let result = (0..100)
+                .map(|_| disc.filter(modulator.filter(freq)))
+                .last()
+                .unwrap()
+                .unwrap();
+            assert!((result - expected).abs() < 0.1, "{}: {}", freq, result);
+        }
+    }
 }
@@ -0,0 +1,491 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+use crate::fcs::Fcs;
+use log::debug;
+
+/// Maximum number of bit errors tolerated when matching a correlation tag.
+pub const FX25_MAX_TAG_ERRORS: u32 = 8;
+
+/// An FX.25 code block format, identified by its correlation tag.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Fx25Mode {
+    /// Correlation tag, sent least-significant bit first.
+    pub tag: u64,
+
+    /// Total size of the code block, in bytes.
+    pub n: usize,
+
+    /// Size of the data portion of the code block, in bytes.
+    pub k: usize,
+}
+
+impl Fx25Mode {
+    /// The number of Reed-Solomon parity bytes.
+    pub fn check_bytes(&self) -> usize {
+        self.n - self.k
+    }
+
+    /// Finds the smallest mode with `check_bytes` parity bytes (16, 32
+    /// or 64) that can hold `bits` bits of HDLC-encoded frame.
+    pub fn for_bits(bits: usize, check_bytes: usize) -> Option<Fx25Mode> {
+        FX25_MODES
+            .iter()
+            .filter(|x| x.check_bytes() == check_bytes && x.k * 8 >= bits)
+            .min_by_key(|x| x.k)
+            .copied()
+    }
+}
+
+/// The FX.25 code block formats, in correlation tag order (`Tag_01` to `Tag_0B`).
+pub const FX25_MODES: [Fx25Mode; 11] = [
+    Fx25Mode {
+        tag: 0xB74DB7DF8A532F3E,
+        n: 255,
+        k: 239,
+    },
+    Fx25Mode {
+        tag: 0x26FF60A600CC8FDE,
+        n: 144,
+        k: 128,
+    },
+    Fx25Mode {
+        tag: 0xC7DC0508F3D9B09E,
+        n: 80,
+        k: 64,
+    },
+    Fx25Mode {
+        tag: 0x8F056EB4369660EE,
+        n: 48,
+        k: 32,
+    },
+    Fx25Mode {
+        tag: 0x6E260B1AC5835FAE,
+        n: 255,
+        k: 223,
+    },
+    Fx25Mode {
+        tag: 0xFF94DC634F1CFF4E,
+        n: 160,
+        k: 128,
+    },
+    Fx25Mode {
+        tag: 0x1EB7B9CDBC09C00E,
+        n: 96,
+        k: 64,
+    },
+    Fx25Mode {
+        tag: 0xDBF869BD2DBB1776,
+        n: 64,
+        k: 32,
+    },
+    Fx25Mode {
+        tag: 0x3ADB0C13DEAE2836,
+        n: 255,
+        k: 191,
+    },
+    Fx25Mode {
+        tag: 0xAB69DB6A543188D6,
+        n: 192,
+        k: 128,
+    },
+    Fx25Mode {
+        tag: 0x4A4ABEC4A724B796,
+        n: 128,
+        k: 64,
+    },
+];
+
+/// First consecutive root of the FX.25 Reed-Solomon generator polynomial.
+const FX25_FCR: u8 = 1;
+
+const HDLC_FLAG: u8 = 0x7e;
+
+fn bits_of(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    bytes.iter().copied().bits_lsb()
+}
+
+/// Encodes a frame (including FCS) as an FX.25 correlation tag and code block.
+///
+/// The code block holds the HDLC-encoded frame, with its opening and closing
+/// flags, so receivers that don't know about FX.25 can still decode it.
+/// `check_bytes` is the number of Reed-Solomon parity bytes: 16, 32 or 64.
+///
+/// Returns the bits to send, before NRZI encoding, without any preamble.
+/// Returns `None` if the frame is too big to fit in a code block.
+pub fn fx25_encode(frame: &[u8], check_bytes: usize) -> Option<Vec<bool>> {
+    let flag = || bits_of(&[HDLC_FLAG]).collect::<Vec<_>>();
+
+    let mut hdlc = flag();
+    hdlc.extend(bit_stuff(bits_of(frame)));
+    hdlc.extend(flag());
+
+    let mode = Fx25Mode::for_bits(hdlc.len(), check_bytes)?;
+
+    // Fill the rest of the data portion with flags.
+    let fill = flag().into_iter().cycle();
+    let fill_len = mode.k * 8 - hdlc.len();
+    hdlc.extend(fill.take(fill_len));
+
+    let data = hdlc
+        .chunks(8)
+        .map(|x| x.iter().rev().fold(0u8, |a, &b| (a << 1) | b as u8))
+        .collect::<Vec<_>>();
+    let parity = ReedSolomon::new(mode.check_bytes(), FX25_FCR).encode(&data);
+
+    let mut ret = bits_of(&mode.tag.to_le_bytes()).collect::<Vec<_>>();
+    ret.extend(hdlc);
+    ret.extend(bits_of(&parity));
+    Some(ret)
+}
+
+/// A frame received in an FX.25 code block.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Fx25Frame {
+    /// The frame, including its (valid) FCS.
+    pub frame: Vec<u8>,
+
+    pub mode: Fx25Mode,
+
+    /// The number of bytes the Reed-Solomon decoder corrected.
+    pub corrected: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Fx25State {
+    Searching,
+    Receiving {
+        mode: Fx25Mode,
+        block: Vec<u8>,
+        accum: u8,
+        bit: u8,
+    },
+}
+
+/// FX.25 correlator and decoder.
+///
+/// Takes NRZI-decoded bits, like [`HdlcDecode`], and looks for FX.25
+/// correlation tags. The code block following a tag is corrected and
+/// the frame inside it is output if its FCS is valid.
+#[derive(Clone, Debug)]
+pub struct Fx25Decode {
+    /// Most recent bits, the newest in the most-significant bit.
+    shift: u64,
+    state: Fx25State,
+    codecs: [ReedSolomon; 3],
+    fcs: Fcs,
+}
+
+impl Default for Fx25Decode {
+    fn default() -> Self {
+        Fx25Decode::new(Fcs::default())
+    }
+}
+
+impl Fx25Decode {
+    /// Creates a decoder for frames with the given FCS.
+    pub fn new(fcs: Fcs) -> Fx25Decode {
+        Fx25Decode {
+            shift: 0,
+            state: Fx25State::Searching,
+            codecs: [16, 32, 64].map(|x| ReedSolomon::new(x, FX25_FCR)),
+            fcs,
+        }
+    }
+
+    fn decode_block(&self, mode: Fx25Mode, mut block: Vec<u8>) -> Option<Fx25Frame> {
+        let codec = self
+            .codecs
+            .iter()
+            .find(|x| x.nroots() == mode.check_bytes())?;
+
+        let corrected = match codec.decode(&mut block) {
+            Some(x) => x,
+            None => {
+                debug!("FX.25: uncorrectable block ({:?})", mode);
+                return None;
+            }
+        };
+
+        // The data portion is an HDLC-encoded frame, padded with flags.
+        let mut hdlc = HdlcDecode::default();
+        let mut collector = FrameCollector::default();
+        let frame = bits_of(&block[..mode.k]).find_map(|x| collector.filter(hdlc.filter(x)))?;
+
+        if self.fcs.verify(&frame).is_ok() {
+            Some(Fx25Frame {
+                frame,
+                mode,
+                corrected,
+            })
+        } else {
+            debug!("FX.25: bad FCS after correcting {} bytes", corrected);
+            None
+        }
+    }
+}
+
+impl Reset for Fx25Decode {
+    fn reset(&mut self) {
+        self.shift = 0;
+        self.state = Fx25State::Searching;
+    }
+}
+
+impl Delay for Fx25Decode {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Filter<Option<bool>> for Fx25Decode {
+    type Output = Option<Fx25Frame>;
+
+    fn filter(&mut self, sample: Option<bool>) -> Self::Output {
+        self.filter(sample?)
+    }
+}
+
+impl Filter<bool> for Fx25Decode {
+    type Output = Option<Fx25Frame>;
+
+    fn filter(&mut self, sample: bool) -> Self::Output {
+        match &mut self.state {
+            Fx25State::Searching => {
+                self.shift = (self.shift >> 1) | ((sample as u64) << 63);
+                let shift = self.shift;
+                if let Some(mode) = FX25_MODES
+                    .iter()
+                    .find(|x| (x.tag ^ shift).count_ones() <= FX25_MAX_TAG_ERRORS)
+                {
+                    self.state = Fx25State::Receiving {
+                        mode: *mode,
+                        block: Vec::with_capacity(mode.n),
+                        accum: 0,
+                        bit: 0,
+                    };
+                }
+                None
+            }
+            Fx25State::Receiving {
+                mode,
+                block,
+                accum,
+                bit,
+            } => {
+                *accum |= (sample as u8) << *bit;
+                *bit += 1;
+                if *bit < 8 {
+                    return None;
+                }
+                block.push(*accum);
+                *accum = 0;
+                *bit = 0;
+                if block.len() < mode.n {
+                    return None;
+                }
+
+                let mode = *mode;
+                let block = std::mem::take(block);
+                self.reset();
+                self.decode_block(mode, block)
+            }
+        }
+    }
+}
+
+/// Decodes both plain HDLC frames and FX.25 code blocks.
+///
+/// Runs [`HdlcDecode`] and [`Fx25Decode`] side by side on the same bits.
+/// Frames from the plain HDLC decoder are output as they are, without
+/// checking the FCS. Since the frame in an FX.25 code block is usually
+/// also picked up by the HDLC decoder, FX.25 frames identical to the last
+/// HDLC frame are suppressed.
+#[derive(Clone, Debug, Default)]
+pub struct HdlcFx25Decode {
+    hdlc: HdlcDecode,
+    collector: FrameCollector,
+    fx25: Fx25Decode,
+    last_frame: Option<Vec<u8>>,
+}
+
+impl HdlcFx25Decode {
+    /// Creates a decoder for FX.25 frames with the given FCS.
+    pub fn new(fcs: Fcs) -> HdlcFx25Decode {
+        HdlcFx25Decode {
+            fx25: Fx25Decode::new(fcs),
+            ..Default::default()
+        }
+    }
+}
+
+impl Reset for HdlcFx25Decode {
+    fn reset(&mut self) {
+        self.hdlc.reset();
+        self.collector.reset();
+        self.fx25.reset();
+        self.last_frame = None;
+    }
+}
+
+impl Delay for HdlcFx25Decode {
+    fn delay(&self) -> usize {
+        self.hdlc.delay()
+    }
+}
+
+impl Filter<Option<bool>> for HdlcFx25Decode {
+    type Output = Option<Vec<u8>>;
+
+    fn filter(&mut self, sample: Option<bool>) -> Self::Output {
+        let fx25 = self.fx25.filter(sample);
+        let hdlc = self.collector.filter(self.hdlc.filter(sample));
+
+        if let Some(frame) = hdlc {
+            self.last_frame = Some(frame.clone());
+            return Some(frame);
+        }
+
+        let fx25 = fx25?;
+        if self.last_frame.take().as_ref() == Some(&fx25.frame) {
+            None
+        } else {
+            debug!("FX.25: recovered frame, {} bytes corrected", fx25.corrected);
+            Some(fx25.frame)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_frame() -> Vec<u8> {
+        hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap()
+    }
+
+    #[test]
+    fn fx25_modes() {
+        for (i, a) in FX25_MODES.iter().enumerate() {
+            for b in &FX25_MODES[i + 1..] {
+                assert!((a.tag ^ b.tag).count_ones() > 2 * FX25_MAX_TAG_ERRORS);
+            }
+        }
+        assert_eq!(Fx25Mode::for_bits(64 * 8, 16), Some(FX25_MODES[2]));
+        assert_eq!(Fx25Mode::for_bits(64 * 8 + 1, 16), Some(FX25_MODES[1]));
+        assert_eq!(Fx25Mode::for_bits(240 * 8, 16), None);
+        assert_eq!(Fx25Mode::for_bits(10, 64), Some(FX25_MODES[10]));
+        assert_eq!(Fx25Mode::for_bits(10, 20), None);
+    }
+
+    #[test]
+    fn fx25_encode_decode() {
+        let frame = test_frame();
+
+        for check_bytes in [16, 32, 64] {
+            let bits = fx25_encode(&frame, check_bytes).unwrap();
+            let tag = bits[..64]
+                .iter()
+                .rev()
+                .fold(0u64, |a, &b| (a << 1) | b as u64);
+            let mode = *FX25_MODES.iter().find(|x| x.tag == tag).unwrap();
+            assert_eq!(mode.check_bytes(), check_bytes);
+            assert_eq!(bits.len(), 64 + mode.n * 8);
+
+            let mut errors = bits.clone();
+            // Tag errors, then one bit error in each of the correctable bytes.
+            for i in 0..FX25_MAX_TAG_ERRORS as usize {
+                errors[i * 3] = !errors[i * 3];
+            }
+            for i in 0..check_bytes / 2 {
+                let bit = 64 + i * 16 + i % 8;
+                errors[bit] = !errors[bit];
+            }
+
+            let mut decoder = Fx25Decode::default();
+            let decoded = std::iter::repeat_n(false, 100)
+                .chain(errors)
+                .find_map(|x| decoder.filter(x))
+                .unwrap();
+            assert_eq!(decoded.frame, frame);
+            assert_eq!(decoded.mode, mode);
+            assert_eq!(decoded.corrected, check_bytes / 2);
+        }
+    }
+
+    #[test]
+    fn fx25_decode_crc32() {
+        let payload = Fcs::X25.verify(&test_frame()).unwrap().to_vec();
+        let frame = Fcs::Crc32.append(payload);
+        let bits = fx25_encode(&frame, 16).unwrap();
+
+        let mut decoder = Fx25Decode::new(Fcs::Crc32);
+        let decoded = bits.iter().find_map(|&x| decoder.filter(x)).unwrap();
+        assert_eq!(decoded.frame, frame);
+
+        let mut decoder = Fx25Decode::default();
+        assert_eq!(bits.iter().find_map(|&x| decoder.filter(x)), None);
+    }
+
+    #[test]
+    fn fx25_is_valid_hdlc() {
+        let frame = test_frame();
+        let bits = fx25_encode(&frame, 16).unwrap();
+
+        let mut hdlc = HdlcDecode::default();
+        let mut collector = FrameCollector::default();
+        let frames = bits
+            .into_iter()
+            .filter_map(|x| collector.filter(hdlc.filter(x)))
+            .collect::<Vec<_>>();
+        assert_eq!(frames, vec![frame]);
+    }
+
+    #[test]
+    fn hdlc_fx25_decode() {
+        let frame = test_frame();
+        let bits = fx25_encode(&frame, 16).unwrap();
+
+        // Without errors, the frame is only output once.
+        let mut decoder = HdlcFx25Decode::default();
+        let frames = bits
+            .iter()
+            .map(|&x| Some(x))
+            .filter_map(|x| decoder.filter(x))
+            .collect::<Vec<_>>();
+        assert_eq!(frames, vec![frame.clone()]);
+
+        // With an error in the frame, the plain HDLC decoder
+        // outputs a bad frame, followed by the corrected one.
+        let mut errors = bits;
+        errors[64 + 100] = !errors[64 + 100];
+        let mut decoder = HdlcFx25Decode::default();
+        let frames = errors
+            .into_iter()
+            .map(Some)
+            .filter_map(|x| decoder.filter(x))
+            .collect::<Vec<_>>();
+        assert_eq!(frames.len(), 2);
+        assert_ne!(frames[0], frame);
+        assert_eq!(frames[1], frame);
+    }
+}
@@ -20,11 +20,9 @@
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use super::*;
-use crc::*;
+use std::collections::VecDeque;
 use std::mem::swap;
 
-pub const X25: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_SDLC);
-
 // bit-stuffing:
 // * Applied to frames.
 // * frames are prepended with some number of start-of-frame marker patterns: `01111110`
@@ -117,6 +115,17 @@ pub enum FrameSignal {
     Octet(u8),
     FrameMarker,
     DecodeError,
+
+    /// A frame ended with `len` bits left over after the last whole octet,
+    /// which are given in `bits`, least-significant first. Usually caused by
+    /// a bit error adding or removing a stuffed bit.
+    ///
+    /// If `len` is two or more, the last `Octet` was made up of the leftover
+    /// bits and the start of the closing flag, and isn't part of the frame.
+    Misaligned {
+        bits: u8,
+        len: u8,
+    },
 }
 
 /// HDLC Bitstream Decoder.
@@ -125,7 +134,8 @@ pub enum FrameSignal {
 /// Output is Option<FrameSignal>
 #[derive(Clone, Default, Debug)]
 pub struct HdlcDecode {
-    accum: u8,
+    /// Most recent bits, the newest in the most-significant bit.
+    accum: u16,
     bit: u8,
     ones: u8,
     skip_next_zero: bool,
@@ -179,10 +189,17 @@ impl Filter<bool> for HdlcDecode {
                 self.reset();
                 self.is_running = true;
                 Some(FrameSignal::FrameMarker)
-            } else if self.is_running {
+            } else if self.is_running && sample {
                 self.reset();
                 self.is_running = false;
                 Some(FrameSignal::DecodeError)
+            } else if self.is_running {
+                // The last six bits are the start of the flag.
+                let len = (self.bit + 2) % 8;
+                let bits = (self.accum >> (10 - len)) as u8 & ((1u16 << len) - 1) as u8;
+                self.reset();
+                self.is_running = false;
+                Some(FrameSignal::Misaligned { bits, len })
             } else {
                 self.reset();
                 None
@@ -196,7 +213,7 @@ impl Filter<bool> for HdlcDecode {
         }
 
         // Decode least-significant bit first
-        self.accum = (self.accum >> 1) | ((sample as u8) << 7);
+        self.accum = (self.accum >> 1) | ((sample as u16) << 15);
 
         if sample {
             self.ones += 1;
@@ -214,7 +231,7 @@ impl Filter<bool> for HdlcDecode {
         if self.bit >= 8 {
             self.bit = 0;
             if self.is_running {
-                return Some(FrameSignal::Octet(self.accum));
+                return Some(FrameSignal::Octet((self.accum >> 8) as u8));
             }
         }
 
@@ -254,7 +271,7 @@ impl Filter<Option<FrameSignal>> for FrameCollector {
 
                 Some(x)
             }
-            Some(FrameSignal::DecodeError) => {
+            Some(FrameSignal::DecodeError) | Some(FrameSignal::Misaligned { .. }) => {
                 self.reset();
                 None
             }
@@ -263,6 +280,91 @@ impl Filter<Option<FrameSignal>> for FrameCollector {
     }
 }
 
+/// A frame with the confidence of each of its bits.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct SoftFrame {
+    pub frame: Vec<u8>,
+
+    /// The confidence of each bit of `frame`, in the order they were
+    /// received: least-significant first.
+    pub confidence: Vec<f32>,
+}
+
+impl SoftFrame {
+    /// Returns the indexes of the bits of `frame`, least confident first.
+    pub fn bits_by_confidence(&self) -> Vec<usize> {
+        let mut bits = (0..self.confidence.len()).collect::<Vec<_>>();
+        bits.sort_by(|&a, &b| self.confidence[a].total_cmp(&self.confidence[b]));
+        bits
+    }
+}
+
+/// Soft-decision counterpart of [`HdlcDecode`] and [`FrameCollector`].
+///
+/// Takes soft bits, such as from [`SoftNrziDecode`], and decodes frames
+/// from their signs, keeping the confidence of each bit. Stuffed bits
+/// are dropped along with their confidence.
+#[derive(Clone, Default, Debug)]
+pub struct SoftFrameCollector {
+    hdlc: HdlcDecode,
+    frame: SoftFrame,
+
+    /// Confidence of the most recent data bits, the newest last.
+    recent: VecDeque<f32>,
+}
+
+impl Reset for SoftFrameCollector {
+    fn reset(&mut self) {
+        self.hdlc.reset();
+        self.frame = SoftFrame::default();
+        self.recent.clear();
+    }
+}
+
+impl Delay for SoftFrameCollector {
+    fn delay(&self) -> usize {
+        8
+    }
+}
+
+impl Filter<Option<f32>> for SoftFrameCollector {
+    type Output = Option<SoftFrame>;
+
+    fn filter(&mut self, sample: Option<f32>) -> Self::Output {
+        let is_data = !self.hdlc.skip_next_zero && !self.hdlc.reset_next;
+        let signal = self.hdlc.filter(sample.map(|x| x > 0.0));
+
+        if let (Some(x), true) = (sample, is_data) {
+            if self.recent.len() == 8 {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(x.abs());
+        }
+
+        match signal {
+            Some(FrameSignal::Octet(x)) => {
+                self.frame.frame.push(x);
+                self.frame.confidence.extend(self.recent.iter());
+                None
+            }
+            Some(FrameSignal::FrameMarker) => {
+                self.recent.clear();
+                if self.frame.frame.is_empty() {
+                    None
+                } else {
+                    Some(std::mem::take(&mut self.frame))
+                }
+            }
+            Some(FrameSignal::DecodeError) | Some(FrameSignal::Misaligned { .. }) => {
+                self.frame = SoftFrame::default();
+                self.recent.clear();
+                None
+            }
+            None => None,
+        }
+    }
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct BitSampler {
     sample_rate: u32,
@@ -423,6 +525,63 @@ mod tests {
         assert_eq!(decode.filter(true), None);
     }
 
+    #[test]
+    fn hdlc_decode_misaligned() {
+        let mut decode = HdlcDecode::default();
+        let flag = [false, true, true, true, true, true, true, false];
+        let mut bits = flag.to_vec();
+
+        // 0xF0, then three extra bits.
+        bits.extend([false, false, false, false, true, true, true, true]);
+        bits.extend([false, true, true]);
+        bits.extend(flag);
+
+        let signals = bits
+            .into_iter()
+            .filter_map(|x| decode.filter(x))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            signals,
+            vec![
+                FrameSignal::FrameMarker,
+                FrameSignal::Octet(0xF0),
+                // The extra bits and the start of the flag.
+                FrameSignal::Octet(0xF6),
+                FrameSignal::Misaligned {
+                    bits: 0b110,
+                    len: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn soft_frame_collector() {
+        // Long runs of ones would be stuffed, moving the bits around.
+        let frame = vec![0x12, 0x34, 0x56, 0x0f];
+        let weak = 8 * 15 + 13;
+
+        let mut collector = SoftFrameCollector::default();
+        let frames = frame
+            .iter()
+            .copied()
+            .bits_lsb()
+            .hdlc_encode()
+            .enumerate()
+            .map(|(i, x)| {
+                let confidence = if i == weak { 0.25 } else { 1.0 };
+                Some(if x { confidence } else { -confidence })
+            })
+            .filter_map(|x| collector.filter(x))
+            .collect::<Vec<_>>();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].frame, frame);
+        assert_eq!(frames[0].confidence.len(), 32);
+        assert_eq!(frames[0].bits_by_confidence()[0], 13);
+        assert_eq!(frames[0].confidence[13], 0.25);
+    }
+
     #[test]
     fn bit_extractor_decode() {
         let mut decode = BitSampler::new(20, 10);
@@ -0,0 +1,713 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+use crate::fcs::Fcs;
+use log::debug;
+
+/// IL2P sync word, sent most-significant bit first after the preamble.
+pub const IL2P_SYNC_WORD: u32 = 0xf15e48;
+
+/// Maximum number of bytes in an IL2P payload.
+pub const IL2P_MAX_PAYLOAD: usize = 1023;
+
+/// Maximum number of bit errors tolerated when matching the sync word.
+pub const IL2P_MAX_SYNC_ERRORS: u32 = 1;
+
+const IL2P_HEADER_SIZE: usize = 13;
+const IL2P_HEADER_PARITY: usize = 2;
+const IL2P_PREAMBLE: u8 = 0x55;
+
+/// First consecutive root of the IL2P Reed-Solomon generator polynomial.
+const IL2P_FCR: u8 = 0;
+
+/// AX.25 PIDs that translated (type 1) headers can carry, by their
+/// four-bit code. Codes 0 and 1 mark S and U frames, and the
+/// rest are reserved.
+const IL2P_PIDS: [(usize, u8); 10] = [
+    (0x2, 0x20),
+    (0x3, 0x01),
+    (0x4, 0x06),
+    (0x5, 0x07),
+    (0x6, 0x08),
+    (0xb, 0xcc),
+    (0xc, 0xcd),
+    (0xd, 0xce),
+    (0xe, 0xcf),
+    (0xf, 0xf0),
+];
+
+/// Control fields (without the P/F bit) of the AX.25 U frames that
+/// translated headers can carry, by their three-bit opcode: SABM, DISC,
+/// DM, UA, FRMR, UI, XID and TEST.
+const IL2P_U_CONTROLS: [u8; 8] = [0x2f, 0x43, 0x0f, 0x63, 0x87, 0x03, 0xaf, 0xe3];
+
+/// Opcode of UI frames in translated headers.
+const IL2P_UI_OPCODE: usize = 5;
+
+/// Initial state of the scrambler and descrambler.
+const IL2P_TX_LFSR_INIT: u32 = 0x00f;
+const IL2P_RX_LFSR_INIT: u32 = 0x1f0;
+
+fn scramble_bit(x: bool, state: &mut u32) -> bool {
+    let out = ((*state >> 4) ^ *state) & 1 != 0;
+    *state = ((((x as u32) ^ *state) & 1) << 9 | (*state ^ ((*state & 1) << 4))) >> 1;
+    out
+}
+
+fn descramble_bit(x: bool, state: &mut u32) -> bool {
+    let out = (x as u32 ^ *state) & 1 != 0;
+    *state = ((*state >> 1) | ((x as u32) << 8)) ^ ((x as u32) << 3);
+    out
+}
+
+/// Scrambles a block with the x^9 + x^4 + 1 LFSR, restarting for each block.
+///
+/// The scrambler output lags its input by five bits, so the first five
+/// output bits are dropped and the end is flushed out with zeros.
+fn il2p_scramble(block: &[u8]) -> Vec<u8> {
+    let mut state = IL2P_TX_LFSR_INIT;
+    let bits = block
+        .iter()
+        .copied()
+        .bits_msb()
+        .map(|x| scramble_bit(x, &mut state))
+        .skip(5)
+        .collect::<Vec<_>>();
+
+    let flush = std::iter::repeat(false).map(|x| scramble_bit(x, &mut state));
+    let bits = bits.into_iter().chain(flush).take(block.len() * 8);
+
+    bytes_msb(bits)
+}
+
+fn il2p_descramble(block: &[u8]) -> Vec<u8> {
+    let mut state = IL2P_RX_LFSR_INIT;
+    bytes_msb(
+        block
+            .iter()
+            .copied()
+            .bits_msb()
+            .map(|x| descramble_bit(x, &mut state)),
+    )
+}
+
+fn bytes_msb<I: Iterator<Item = bool>>(bits: I) -> Vec<u8> {
+    let bits = bits.collect::<Vec<_>>();
+    bits.chunks(8)
+        .map(|x| x.iter().fold(0u8, |a, &b| (a << 1) | b as u8))
+        .collect()
+}
+
+/// Sets a header field that is spread over bit `bit` of `width`
+/// consecutive header bytes, the least-significant in `lsb_index`.
+fn set_field(header: &mut [u8], bit: u8, lsb_index: usize, width: usize, value: usize) {
+    for i in 0..width {
+        header[lsb_index - i] |= (((value >> i) & 1) as u8) << bit;
+    }
+}
+
+/// Gets a header field set with [`set_field`].
+fn get_field(header: &[u8], bit: u8, lsb_index: usize, width: usize) -> usize {
+    (0..width).fold(0, |a, i| {
+        a | (((header[lsb_index - i] >> bit) & 1) as usize) << i
+    })
+}
+
+/// Translates an AX.25 frame (without FCS) into the fields of a type 1
+/// header, returning the header and the payload (the info field).
+///
+/// Type 1 headers carry a mod-8 frame with just a source and destination,
+/// whose callsigns are letters and digits. They can't carry both C bits,
+/// or reserved bits that aren't set, so this only succeeds if the frame
+/// can be translated back exactly.
+fn il2p_translate(frame: &[u8]) -> Option<([u8; IL2P_HEADER_SIZE], &[u8])> {
+    if frame.len() < 15 {
+        return None;
+    }
+    let (addrs, rest) = frame.split_at(14);
+    let (&control, rest) = rest.split_first()?;
+
+    let mut header = [0u8; IL2P_HEADER_SIZE];
+    for (dst, &src) in header
+        .iter_mut()
+        .zip(addrs[..6].iter().chain(&addrs[7..13]))
+    {
+        *dst = (src >> 1).wrapping_sub(0x20) & 0x3f;
+    }
+    header[12] = ((addrs[6] >> 1) & 0x0f) << 4 | ((addrs[13] >> 1) & 0x0f);
+
+    let command = ((addrs[6] >> 7) as usize) << 2;
+    let poll = (((control >> 4) & 1) as usize) << 6;
+    let encode_pid = |pid| IL2P_PIDS.iter().find(|x| x.1 == pid).map(|x| x.0);
+
+    let (ui, pid, control, payload) = if control & 1 == 0 {
+        // I frame: P/F, N(R), N(S)
+        let (&pid, info) = rest.split_first()?;
+        let control = poll | ((control >> 5) << 3) as usize | ((control >> 1) & 7) as usize;
+        (false, encode_pid(pid)?, control, info)
+    } else if control & 3 == 1 {
+        // S frame: P/F, N(R), C, and the kind
+        let control =
+            poll | ((control >> 5) << 3) as usize | command | ((control >> 2) & 3) as usize;
+        (false, 0, control, rest)
+    } else {
+        // U frame: P/F, opcode, C
+        let opcode = IL2P_U_CONTROLS.iter().position(|&x| x == control & !0x10)?;
+        let control = poll | opcode << 3 | command;
+        if opcode == IL2P_UI_OPCODE {
+            let (&pid, info) = rest.split_first()?;
+            (true, encode_pid(pid)?, control, info)
+        } else {
+            (false, 1, control, rest)
+        }
+    };
+
+    if payload.len() > IL2P_MAX_PAYLOAD {
+        return None;
+    }
+
+    set_field(&mut header, 6, 0, 1, ui as usize);
+    set_field(&mut header, 6, 4, 4, pid);
+    set_field(&mut header, 6, 11, 7, control);
+    set_field(&mut header, 7, 1, 1, 1);
+    set_field(&mut header, 7, 11, 10, payload.len());
+
+    let translated = il2p_untranslate(&header)?;
+    (translated == frame[..frame.len() - payload.len()]).then_some((header, payload))
+}
+
+/// Translates a type 1 header back into an AX.25 frame, without the
+/// info field (which is the payload) or FCS. I frames are always
+/// commands. Returns `None` if a callsign isn't letters and digits,
+/// as happens when the header was miscorrected.
+fn il2p_untranslate(header: &[u8]) -> Option<Vec<u8>> {
+    let mut ret = Vec::with_capacity(16);
+    for (start, ssid) in [(0, header[12] >> 4), (6, header[12] & 0x0f)] {
+        for &x in &header[start..start + 6] {
+            let c = (x & 0x3f) + 0x20;
+            if !(c == b' ' || c.is_ascii_uppercase() || c.is_ascii_digit()) {
+                return None;
+            }
+            ret.push(c << 1);
+        }
+        ret.push(0x60 | ssid << 1);
+    }
+
+    let ui = get_field(header, 6, 0, 1) != 0;
+    let pid = get_field(header, 6, 4, 4);
+    let control = get_field(header, 6, 11, 7) as u8;
+    let decode_pid = |pid| IL2P_PIDS.iter().find(|x| x.0 == pid).map_or(0xf0, |x| x.1);
+
+    let poll = ((control >> 6) & 1) << 4;
+    let opcode = ((control >> 3) & 7) as usize;
+    let mut command = control & 0x04 != 0;
+    let (control, pid) = if ui {
+        (
+            IL2P_U_CONTROLS[IL2P_UI_OPCODE] | poll,
+            Some(decode_pid(pid)),
+        )
+    } else if pid == 0 {
+        (
+            ((control >> 3) & 7) << 5 | poll | (control & 3) << 2 | 1,
+            None,
+        )
+    } else if pid == 1 {
+        let pid = (opcode == IL2P_UI_OPCODE).then_some(0xf0);
+        (IL2P_U_CONTROLS[opcode] | poll, pid)
+    } else {
+        command = true;
+        (
+            ((control >> 3) & 7) << 5 | poll | (control & 7) << 1,
+            Some(decode_pid(pid)),
+        )
+    };
+
+    ret[6] |= (command as u8) << 7;
+    ret[13] |= (!command as u8) << 7 | 1;
+    ret.push(control);
+    ret.extend(pid);
+    Some(ret)
+}
+
+/// How a payload is split into Reed-Solomon blocks.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct PayloadLayout {
+    small_size: usize,
+    large_count: usize,
+    block_count: usize,
+    parity: usize,
+}
+
+impl PayloadLayout {
+    fn new(len: usize, max_fec: bool) -> PayloadLayout {
+        let max_block = if max_fec { 239 } else { 247 };
+        let block_count = len.div_ceil(max_block);
+        let small_size = len.checked_div(block_count).unwrap_or(0);
+        PayloadLayout {
+            small_size,
+            large_count: len - block_count * small_size,
+            block_count,
+            parity: if max_fec { 16 } else { small_size / 32 + 2 },
+        }
+    }
+
+    /// Sizes of the data in each block, large blocks first.
+    fn block_sizes(&self) -> impl Iterator<Item = usize> {
+        let small_size = self.small_size;
+        let large_count = self.large_count;
+        (0..self.block_count).map(move |i| small_size + (i < large_count) as usize)
+    }
+
+    /// Size of the payload on air, including parity.
+    fn encoded_len(&self) -> usize {
+        self.block_sizes().map(|x| x + self.parity).sum()
+    }
+}
+
+/// Encodes a frame (including an FCS of the given kind) as an IL2P packet.
+///
+/// AX.25 frames that can be translated exactly are sent with a translated
+/// (type 1) header, carrying the addresses, control field and PID, with
+/// the info field as the payload. Other frames are sent with a transparent
+/// (type 0) header, carrying the frame without its FCS as the payload.
+/// With `max_fec`, each payload block has 16 Reed-Solomon parity bytes
+/// instead of a number depending on its size.
+///
+/// Returns the bits to send, most-significant bit first, starting with the
+/// preamble and sync word. IL2P doesn't use NRZI, or bit stuffing. Returns
+/// `None` if the frame is too big or too small.
+pub fn il2p_encode(frame: &[u8], fcs: Fcs, max_fec: bool) -> Option<Vec<bool>> {
+    if frame.len() <= fcs.size() {
+        return None;
+    }
+    let frame = &frame[..frame.len() - fcs.size()];
+
+    let (mut header, payload) = match il2p_translate(frame) {
+        Some(x) => x,
+        None if frame.len() <= IL2P_MAX_PAYLOAD => {
+            let mut header = [0u8; IL2P_HEADER_SIZE];
+            set_field(&mut header, 7, 11, 10, frame.len());
+            (header, frame)
+        }
+        None => return None,
+    };
+    set_field(&mut header, 7, 0, 1, max_fec as usize);
+
+    let mut ret = vec![IL2P_PREAMBLE; 15];
+    ret.extend(IL2P_SYNC_WORD.to_be_bytes()[1..].iter());
+
+    let mut push_block = |block: &[u8], parity: usize| {
+        let scrambled = il2p_scramble(block);
+        ret.extend(&scrambled);
+        ret.extend(ReedSolomon::new(parity, IL2P_FCR).encode(&scrambled));
+    };
+
+    push_block(&header, IL2P_HEADER_PARITY);
+
+    let layout = PayloadLayout::new(payload.len(), max_fec);
+    let mut rest = payload;
+    for size in layout.block_sizes() {
+        let (block, tail) = rest.split_at(size);
+        push_block(block, layout.parity);
+        rest = tail;
+    }
+
+    ret.push(IL2P_PREAMBLE);
+    Some(ret.into_iter().bits_msb().collect())
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Il2pState {
+    Searching,
+    Header,
+    Payload(PayloadLayout),
+}
+
+/// IL2P decoder.
+///
+/// Takes raw (not NRZI-decoded) bits and looks for the IL2P sync word,
+/// in either polarity. The header and payload blocks that follow are
+/// corrected and descrambled, and the frame is output with an FCS
+/// appended, [`Fcs::X25`] unless created with [`Il2pDecode::new`], so it
+/// can be handled like a frame from [`HdlcDecode`] and [`FrameCollector`].
+///
+/// Both transparent (type 0) and translated AX.25 (type 1) headers are
+/// supported. Translated frames are output as AX.25 frames.
+#[derive(Clone, Debug)]
+pub struct Il2pDecode {
+    /// Most recent bits, the newest in the least-significant bit.
+    shift: u32,
+    inverted: bool,
+    state: Il2pState,
+
+    /// The start of the frame, from a translated header.
+    translated: Vec<u8>,
+    block: Vec<u8>,
+    accum: u8,
+    bit: u8,
+    header_codec: ReedSolomon,
+    fcs: Fcs,
+}
+
+impl Default for Il2pDecode {
+    fn default() -> Self {
+        Il2pDecode::new(Fcs::default())
+    }
+}
+
+impl Il2pDecode {
+    /// Creates a decoder that appends the given FCS to frames.
+    pub fn new(fcs: Fcs) -> Il2pDecode {
+        Il2pDecode {
+            shift: 0,
+            inverted: false,
+            state: Il2pState::Searching,
+            translated: vec![],
+            block: vec![],
+            accum: 0,
+            bit: 0,
+            header_codec: ReedSolomon::new(IL2P_HEADER_PARITY, IL2P_FCR),
+            fcs,
+        }
+    }
+
+    /// Decodes the header, returning the payload layout and, for
+    /// translated headers, the start of the frame.
+    fn decode_header(&self, mut block: Vec<u8>) -> Option<(PayloadLayout, Vec<u8>)> {
+        self.header_codec.decode(&mut block)?;
+        let header = il2p_descramble(&block[..IL2P_HEADER_SIZE]);
+
+        let max_fec = get_field(&header, 7, 0, 1) != 0;
+        let len = get_field(&header, 7, 11, 10);
+        let layout = PayloadLayout::new(len, max_fec);
+
+        if get_field(&header, 7, 1, 1) != 0 {
+            match il2p_untranslate(&header) {
+                Some(translated) => Some((layout, translated)),
+                None => {
+                    debug!("IL2P: bad translated header");
+                    None
+                }
+            }
+        } else if len == 0 {
+            None
+        } else {
+            Some((layout, vec![]))
+        }
+    }
+
+    /// Decodes the payload, returning the frame (starting with the
+    /// `translated` header, if any) with an FCS appended.
+    fn decode_payload(
+        &self,
+        layout: PayloadLayout,
+        mut payload: Vec<u8>,
+        encoded: &[u8],
+    ) -> Option<Vec<u8>> {
+        let codec = ReedSolomon::new(layout.parity, IL2P_FCR);
+        let mut rest = encoded;
+
+        for size in layout.block_sizes() {
+            let (block, tail) = rest.split_at(size + layout.parity);
+            let mut block = block.to_vec();
+            if codec.decode(&mut block).is_none() {
+                debug!("IL2P: uncorrectable payload block");
+                return None;
+            }
+            payload.extend(il2p_descramble(&block[..size]));
+            rest = tail;
+        }
+
+        Some(self.fcs.append(payload))
+    }
+}
+
+impl Reset for Il2pDecode {
+    fn reset(&mut self) {
+        self.shift = 0;
+        self.state = Il2pState::Searching;
+        self.translated.clear();
+        self.block.clear();
+        self.accum = 0;
+        self.bit = 0;
+    }
+}
+
+impl Delay for Il2pDecode {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Filter<Option<bool>> for Il2pDecode {
+    type Output = Option<Vec<u8>>;
+
+    fn filter(&mut self, sample: Option<bool>) -> Self::Output {
+        self.filter(sample?)
+    }
+}
+
+impl Filter<bool> for Il2pDecode {
+    type Output = Option<Vec<u8>>;
+
+    fn filter(&mut self, sample: bool) -> Self::Output {
+        if self.state == Il2pState::Searching {
+            self.shift = ((self.shift << 1) | sample as u32) & 0xffffff;
+            if (self.shift ^ IL2P_SYNC_WORD).count_ones() <= IL2P_MAX_SYNC_ERRORS {
+                self.inverted = false;
+                self.state = Il2pState::Header;
+            } else if (!self.shift & 0xffffff ^ IL2P_SYNC_WORD).count_ones() <= IL2P_MAX_SYNC_ERRORS
+            {
+                self.inverted = true;
+                self.state = Il2pState::Header;
+            }
+            return None;
+        }
+
+        self.accum = (self.accum << 1) | (sample ^ self.inverted) as u8;
+        self.bit += 1;
+        if self.bit < 8 {
+            return None;
+        }
+        self.block.push(self.accum);
+        self.accum = 0;
+        self.bit = 0;
+
+        match self.state {
+            Il2pState::Header if self.block.len() == IL2P_HEADER_SIZE + IL2P_HEADER_PARITY => {
+                let block = std::mem::take(&mut self.block);
+                match self.decode_header(block) {
+                    Some((layout, translated)) if layout.encoded_len() == 0 => {
+                        self.reset();
+                        Some(self.fcs.append(translated))
+                    }
+                    Some((layout, translated)) => {
+                        self.state = Il2pState::Payload(layout);
+                        self.translated = translated;
+                        None
+                    }
+                    None => {
+                        self.reset();
+                        None
+                    }
+                }
+            }
+            Il2pState::Payload(layout) if self.block.len() == layout.encoded_len() => {
+                let block = std::mem::take(&mut self.block);
+                let translated = std::mem::take(&mut self.translated);
+                self.reset();
+                self.decode_payload(layout, translated, &block)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_frame() -> Vec<u8> {
+        hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap()
+    }
+
+    #[test]
+    fn il2p_scramble_round_trip() {
+        let block = (0..=255u8).collect::<Vec<_>>();
+        let scrambled = il2p_scramble(&block);
+        assert_ne!(scrambled, block);
+        assert_eq!(il2p_descramble(&scrambled), block);
+    }
+
+    #[test]
+    fn il2p_payload_layout() {
+        let layout = PayloadLayout::new(100, true);
+        assert_eq!(layout.block_sizes().collect::<Vec<_>>(), vec![100]);
+        assert_eq!(layout.encoded_len(), 116);
+
+        let layout = PayloadLayout::new(500, true);
+        assert_eq!(
+            layout.block_sizes().collect::<Vec<_>>(),
+            vec![167, 167, 166]
+        );
+        assert_eq!(layout.encoded_len(), 548);
+
+        let layout = PayloadLayout::new(500, false);
+        assert_eq!(
+            layout.block_sizes().collect::<Vec<_>>(),
+            vec![167, 167, 166]
+        );
+        assert_eq!(layout.parity, 7);
+    }
+
+    #[test]
+    fn il2p_encode_decode() {
+        let frame = test_frame();
+
+        for max_fec in [false, true] {
+            let bits = il2p_encode(&frame, Fcs::X25, max_fec).unwrap();
+
+            let mut decoder = Il2pDecode::default();
+            let decoded = bits.iter().find_map(|&x| decoder.filter(x));
+            assert_eq!(decoded, Some(frame.clone()));
+
+            // Inverted polarity, with errors in the sync word,
+            // header, and payload.
+            let mut errors = bits.iter().map(|&x| !x).collect::<Vec<_>>();
+            let start = 15 * 8;
+            for i in [start + 3, start + 24 + 10, start + 24 + 15 * 8 + 20] {
+                errors[i] = !errors[i];
+            }
+            let mut decoder = Il2pDecode::default();
+            let decoded = errors.iter().find_map(|&x| decoder.filter(x));
+            assert_eq!(decoded, Some(frame.clone()));
+        }
+    }
+
+    #[test]
+    fn il2p_encode_decode_crc32() {
+        let payload = Fcs::X25.verify(&test_frame()).unwrap().to_vec();
+        let frame = Fcs::Crc32.append(payload);
+
+        let bits = il2p_encode(&frame, Fcs::Crc32, true).unwrap();
+        let mut decoder = Il2pDecode::new(Fcs::Crc32);
+        assert_eq!(bits.iter().find_map(|&x| decoder.filter(x)), Some(frame));
+    }
+
+    #[test]
+    fn il2p_encode_limits() {
+        assert!(il2p_encode(&[0x55; 2], Fcs::X25, true).is_none());
+        assert!(il2p_encode(&[0x55; IL2P_MAX_PAYLOAD + 3], Fcs::X25, true).is_none());
+
+        let frame = Fcs::X25.append((0..IL2P_MAX_PAYLOAD).map(|x| x as u8).collect());
+        let bits = il2p_encode(&frame, Fcs::X25, false).unwrap();
+        let mut decoder = Il2pDecode::default();
+        assert_eq!(bits.iter().find_map(|&x| decoder.filter(x)), Some(frame));
+    }
+
+    /// Encodes a type 1 header block as sent, scrambled with its parity.
+    fn header_block(header: &[u8]) -> Vec<u8> {
+        let mut block = il2p_scramble(header);
+        let parity = ReedSolomon::new(IL2P_HEADER_PARITY, IL2P_FCR).encode(&block);
+        block.extend(parity);
+        block
+    }
+
+    #[test]
+    fn il2p_translate_examples() {
+        // Examples from the IL2P specification, also used by Dire Wolf's tests.
+        // An RR response with N(R) 5 and P/F set.
+        let frame = hex::decode("968264888aaee4969668908a946fb1").unwrap();
+        let (header, payload) = il2p_translate(&frame).unwrap();
+        assert_eq!(hex::encode(header), "2ba1122425776b2b5468252a27");
+        assert!(payload.is_empty());
+        assert_eq!(
+            hex::encode(header_block(&header)),
+            "26574d57f196cc8542e724f72e8a97"
+        );
+        assert_eq!(il2p_untranslate(&header), Some(frame.clone()));
+
+        // The header block as sent, with no payload.
+        let mut bytes = IL2P_SYNC_WORD.to_be_bytes()[1..].to_vec();
+        bytes.extend(hex::decode("26574d57f196cc8542e724f72e8a97").unwrap());
+        let mut decoder = Il2pDecode::default();
+        let decoded = bytes.into_iter().bits_msb().find_map(|x| decoder.filter(x));
+        assert_eq!(decoded, Some(Fcs::X25.append(frame.clone())));
+        assert_eq!(
+            il2p_encode(&Fcs::X25.append(frame), Fcs::X25, false)
+                .unwrap()
+                .len(),
+            (15 + 3 + 15 + 1) * 8
+        );
+
+        // A UI frame, sent with both C bits clear (as AX.25 v1 did), which
+        // are translated to a response.
+        let frame = hex::decode("86a24040404060969668908a947f03f0").unwrap();
+        assert!(il2p_translate(&frame).is_none());
+        let header = hex::decode("63f1404040006b2b5428252a0f").unwrap();
+        assert_eq!(
+            hex::encode(header_block(&header)),
+            "6aea9cc20111fc141fda6ef25391bd"
+        );
+        let mut translated = frame.clone();
+        translated[13] |= 0x80;
+        assert_eq!(il2p_untranslate(&header), Some(translated.clone()));
+        assert_eq!(il2p_translate(&translated).unwrap().0.to_vec(), header);
+    }
+
+    #[test]
+    fn il2p_encode_decode_translated() {
+        let addrs = "9c6e98aa8a40e0ae8270989a8c61";
+        let frames = [
+            // I frame with PID F0, N(R) 3, N(S) 6, P set.
+            format!("{}7cf0{}", addrs, "48656c6c6f"),
+            // REJ command with N(R) 7.
+            format!("{}e9", addrs),
+            // SABM with P set, DISC, UA with F set, and DM responses.
+            format!("{}3f", addrs),
+            format!("{}43", addrs),
+            "9c6e98aa8a4060ae8270989a8ce173".to_string(),
+            "9c6e98aa8a4060ae8270989a8ce10f".to_string(),
+            // UI response with PID F0.
+            "9c6e98aa8a4060ae8270989a8ce103f0".to_string(),
+            // UI command with PID CF (NET/ROM) and an empty info field.
+            format!("{}03cf", addrs),
+            // XID command with an info field.
+            format!("{}af{}", addrs, "82800600"),
+        ];
+
+        for frame in &frames {
+            let frame = hex::decode(frame).unwrap();
+            let (header, payload) = il2p_translate(&frame).unwrap();
+            assert_eq!(get_field(&header, 7, 1, 1), 1);
+            assert_eq!(payload, &frame[frame.len() - payload.len()..]);
+
+            let frame = Fcs::X25.append(frame);
+            for max_fec in [false, true] {
+                let bits = il2p_encode(&frame, Fcs::X25, max_fec).unwrap();
+                let mut decoder = Il2pDecode::default();
+                let decoded = bits.iter().find_map(|&x| decoder.filter(x));
+                assert_eq!(decoded, Some(frame.clone()));
+            }
+        }
+
+        // Digipeater paths, lower-case callsigns, SABME, PIDs without a code,
+        // and frames with both C bits clear (as AX.25 v1 sent) aren't
+        // translated.
+        let untranslated = [
+            hex::encode(&test_frame()[..test_frame().len() - 2]),
+            addrs.replace("9c6e", "dc6e") + "3f",
+            format!("{}6f", addrs),
+            format!("{}03c3", addrs),
+            "9c6e98aa8a4060ae8270989a8c6103f0".to_string(),
+        ];
+        for frame in &untranslated {
+            let frame = hex::decode(frame).unwrap();
+            assert!(il2p_translate(&frame).is_none(), "{}", hex::encode(&frame));
+
+            let frame = Fcs::X25.append(frame);
+            let bits = il2p_encode(&frame, Fcs::X25, false).unwrap();
+            let mut decoder = Il2pDecode::default();
+            assert_eq!(bits.iter().find_map(|&x| decoder.filter(x)), Some(frame));
+        }
+    }
+}
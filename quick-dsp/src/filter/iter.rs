@@ -19,7 +19,9 @@
 // TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use crate::filter::{Filter, HdlcEncoderIter, NrziEncode, ResampleNN};
+use crate::filter::{
+    Filter, HdlcEncoderIter, NrziEncode, PulseShape, PulseShaper, ResampleNN, Scrambler,
+};
 
 /// Transforms an iterator over bytes into an iterator over bits,
 /// most significant bit first.
@@ -190,6 +192,26 @@ pub trait IteratorExt: Iterator {
     {
         self.apply_one_to_one(NrziEncode::new())
     }
+
+    fn scramble(self) -> OneToOneIter<Self, Scrambler>
+    where
+        Self: std::marker::Sized + Iterator<Item = bool>,
+    {
+        self.apply_one_to_one(Scrambler::default())
+    }
+
+    /// Turns symbols into a baseband signal. See [`PulseShaper`].
+    fn pulse_shape(
+        self,
+        shape: PulseShape,
+        samples_per_symbol: f32,
+        span: usize,
+    ) -> PulseShaper<Self>
+    where
+        Self: std::marker::Sized + Iterator<Item = f32>,
+    {
+        PulseShaper::new(self, shape, samples_per_symbol, span)
+    }
 }
 
 impl<T: Iterator> IteratorExt for T {}
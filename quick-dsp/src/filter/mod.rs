@@ -24,31 +24,49 @@ use std::convert::TryFrom;
 use std::fmt::{Debug, Display};
 use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
 
+mod baseband;
 mod boxfilter;
+mod clock_recovery;
+mod costas;
 mod decimator;
 mod discriminator;
+mod fcs_repair;
 mod fir;
 mod fm_mod;
 mod fsk_demod;
+mod fx25;
 mod hdlc;
 mod iir;
+mod il2p;
 mod iter;
 mod nrzi;
+mod pll;
 mod qam;
+mod reed_solomon;
 mod resample;
+mod scrambler;
 
+pub use baseband::*;
 pub use boxfilter::*;
+pub use clock_recovery::*;
+pub use costas::*;
 pub use decimator::*;
 pub use discriminator::*;
+pub use fcs_repair::*;
 pub use fir::*;
 pub use fm_mod::*;
 pub use fsk_demod::*;
+pub use fx25::*;
 pub use hdlc::*;
 pub use iir::*;
+pub use il2p::*;
 pub use iter::*;
 pub use nrzi::*;
+pub use pll::*;
 pub use qam::*;
+pub use reed_solomon::*;
 pub use resample::*;
+pub use scrambler::*;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Window {
@@ -173,7 +191,7 @@ pub type BoxedFilter<'a, In, Out = In> = Box<dyn Filter<In, Output = Out> + 'a>;
 
 impl<F, T> Filter<T> for Box<F>
 where
-    F: Filter<T>,
+    F: Filter<T> + ?Sized,
 {
     type Output = F::Output;
 
@@ -182,7 +200,7 @@ where
     }
 }
 
-impl<F: Delay> Delay for Box<F> {
+impl<F: Delay + ?Sized> Delay for Box<F> {
     fn delay(&self) -> usize {
         self.as_ref().delay()
     }
@@ -84,6 +84,50 @@ impl Delay for NrziDecode {
     }
 }
 
+/// Soft-decision variant of [`NrziDecode`].
+///
+/// Positive inputs are ones, with the magnitude giving the confidence,
+/// like the output of [`SoftPllBitSampler`]. The output is positive for
+/// no transition, and its confidence is that of the less confident of
+/// the two inputs it was decoded from.
+#[derive(Clone, Debug)]
+pub struct SoftNrziDecode {
+    pub last: f32,
+}
+
+impl SoftNrziDecode {
+    pub fn new() -> Self {
+        SoftNrziDecode { last: -1.0 }
+    }
+}
+
+impl Default for SoftNrziDecode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filter<f32> for SoftNrziDecode {
+    type Output = f32;
+
+    fn filter(&mut self, sample: f32) -> Self::Output {
+        let confidence = sample.abs().min(self.last.abs());
+        let same = (sample > 0.0) == (self.last > 0.0);
+        self.last = sample;
+        if same {
+            confidence
+        } else {
+            -confidence
+        }
+    }
+}
+
+impl Delay for SoftNrziDecode {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -108,4 +152,15 @@ mod tests {
         assert_eq!(chained.filter(false), false);
         assert_eq!(chained.filter(true), true);
     }
+
+    #[test]
+    fn soft_nrzi_decode() {
+        let mut decode = SoftNrziDecode::new();
+
+        assert_eq!(decode.filter(-0.5), 0.5);
+        assert_eq!(decode.filter(0.8), -0.5);
+        assert_eq!(decode.filter(0.9), 0.8);
+        assert_eq!(decode.filter(0.1), 0.1);
+        assert_eq!(decode.filter(-1.0), -0.1);
+    }
 }
@@ -0,0 +1,389 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Phase- and frequency-locked loops.
+
+use super::*;
+use std::f32::consts::PI;
+
+/// Default damping factor of the loop filters: critically damped
+/// enough to settle quickly, with little overshoot.
+pub const LOOP_DEFAULT_DAMPING: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Second order (proportional-integral) loop filter.
+///
+/// Takes the error from a phase or frequency detector, and outputs the
+/// correction to apply to an [`Nco`]. The gains are set from the loop's
+/// noise bandwidth, as a fraction of the update rate, and its damping
+/// factor, assuming a detector and NCO with unity gain. The integrator
+/// tracks a constant offset with no error in the steady state.
+#[derive(Copy, Clone, Debug)]
+pub struct LoopFilter {
+    alpha: f32,
+    beta: f32,
+    integrator: f32,
+}
+
+impl LoopFilter {
+    pub fn new(bandwidth: f32, damping: f32) -> LoopFilter {
+        let mut ret = LoopFilter {
+            alpha: 0.0,
+            beta: 0.0,
+            integrator: 0.0,
+        };
+        ret.set_bandwidth(bandwidth, damping);
+        ret
+    }
+
+    /// Sets the noise bandwidth, as a fraction of the update
+    /// rate, and the damping factor. Keeps the integrator.
+    pub fn set_bandwidth(&mut self, bandwidth: f32, damping: f32) {
+        assert!(
+            bandwidth > 0.0 && bandwidth < 0.5,
+            "bad bandwidth: {}",
+            bandwidth
+        );
+        assert!(damping > 0.0, "bad damping: {}", damping);
+
+        let theta = bandwidth / (damping + 1.0 / (4.0 * damping));
+        let d = 1.0 + 2.0 * damping * theta + theta * theta;
+        self.alpha = 4.0 * damping * theta / d;
+        self.beta = 4.0 * theta * theta / d;
+    }
+
+    /// Returns the integrator: the steady state correction.
+    pub fn integrator(&self) -> f32 {
+        self.integrator
+    }
+}
+
+impl Filter<f32> for LoopFilter {
+    type Output = f32;
+
+    fn filter(&mut self, error: f32) -> Self::Output {
+        self.integrator += self.beta * error;
+        self.integrator + self.alpha * error
+    }
+}
+
+impl Delay for LoopFilter {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Reset for LoopFilter {
+    fn reset(&mut self) {
+        self.integrator = 0.0;
+    }
+}
+
+/// Numerically controlled oscillator.
+///
+/// Its phase advances by its frequency, in radians per update,
+/// plus any correction from a loop filter.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Nco {
+    /// Phase, in radians, from minus pi to pi.
+    phase: f32,
+
+    /// Frequency, in radians per update.
+    frequency: f32,
+}
+
+impl Nco {
+    pub fn new(frequency: f32) -> Nco {
+        Nco {
+            phase: 0.0,
+            frequency,
+        }
+    }
+
+    /// Returns the phase, in radians, from minus pi to pi.
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    /// Returns the frequency, in radians per update.
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    /// Advances the phase by the frequency, plus `correction` radians.
+    pub fn advance(&mut self, correction: f32) {
+        self.phase = (self.phase + self.frequency + correction + PI).rem_euclid(2.0 * PI) - PI;
+    }
+
+    /// Rotates a complex sample `(i, q)` back by the phase.
+    pub fn derotate(&self, sample: (f32, f32)) -> (f32, f32) {
+        let (sin, cos) = self.phase.sin_cos();
+        (
+            sample.0 * cos + sample.1 * sin,
+            sample.1 * cos - sample.0 * sin,
+        )
+    }
+}
+
+/// Second order phase-locked loop.
+///
+/// Takes complex samples `(i, q)`, such as those from a [`QamSplitFixed`],
+/// and locks an [`Nco`] to the phase of the carrier in them, starting
+/// from the given frequency in radians per sample. Outputs the samples
+/// rotated back by the NCO's phase, so that once locked, a steady carrier
+/// is on the `i` axis. Phase errors are measured exactly, so it pulls in
+/// from further out than a multiplying detector would.
+#[derive(Copy, Clone, Debug)]
+pub struct Pll {
+    nco: Nco,
+    loop_filter: LoopFilter,
+    initial_frequency: f32,
+}
+
+impl Pll {
+    pub fn new(frequency: f32, bandwidth: f32, damping: f32) -> Pll {
+        Pll {
+            nco: Nco::new(frequency),
+            loop_filter: LoopFilter::new(bandwidth, damping),
+            initial_frequency: frequency,
+        }
+    }
+
+    /// Sets the loop's noise bandwidth, as a fraction of
+    /// the sample rate, and its damping factor.
+    pub fn set_loop_bandwidth(&mut self, bandwidth: f32, damping: f32) {
+        self.loop_filter.set_bandwidth(bandwidth, damping);
+    }
+
+    /// Returns the estimated phase of the carrier, in radians.
+    pub fn phase(&self) -> f32 {
+        self.nco.phase()
+    }
+
+    /// Returns the estimated frequency of the carrier,
+    /// in radians per sample.
+    pub fn frequency(&self) -> f32 {
+        self.nco.frequency() + self.loop_filter.integrator()
+    }
+}
+
+impl Delay for Pll {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Reset for Pll {
+    fn reset(&mut self) {
+        self.nco = Nco::new(self.initial_frequency);
+        self.loop_filter.reset();
+    }
+}
+
+impl Filter<(f32, f32)> for Pll {
+    type Output = (f32, f32);
+
+    fn filter(&mut self, sample: (f32, f32)) -> Self::Output {
+        let (i, q) = self.nco.derotate(sample);
+        if i == 0.0 && q == 0.0 || !i.is_finite() || !q.is_finite() {
+            self.nco.advance(self.loop_filter.integrator());
+            return (i, q);
+        }
+
+        let correction = self.loop_filter.filter(q.atan2(i));
+        self.nco.advance(correction);
+        (i, q)
+    }
+}
+
+/// Second order frequency-locked loop.
+///
+/// Like [`Pll`], but only locks to the carrier's frequency, by comparing
+/// the phase of each sample with the one before it. It pulls in from
+/// much further out than a PLL, and doesn't mind phase jumps, so it can
+/// tune a PLL or [`CostasLoop`] in close enough to lock. For that, it
+/// needs an unmodulated carrier, or one where the modulation has been
+/// removed, such as by squaring BPSK.
+#[derive(Copy, Clone, Debug)]
+pub struct Fll {
+    nco: Nco,
+    loop_filter: LoopFilter,
+    initial_frequency: f32,
+
+    /// Frequency correction, in radians per sample.
+    correction: f32,
+    last: (f32, f32),
+}
+
+impl Fll {
+    pub fn new(frequency: f32, bandwidth: f32, damping: f32) -> Fll {
+        Fll {
+            nco: Nco::new(frequency),
+            loop_filter: LoopFilter::new(bandwidth, damping),
+            initial_frequency: frequency,
+            correction: 0.0,
+            last: (0.0, 0.0),
+        }
+    }
+
+    /// Sets the loop's noise bandwidth, as a fraction of
+    /// the sample rate, and its damping factor.
+    pub fn set_loop_bandwidth(&mut self, bandwidth: f32, damping: f32) {
+        self.loop_filter.set_bandwidth(bandwidth, damping);
+    }
+
+    /// Returns the phase of the NCO, in radians. This follows the
+    /// carrier's frequency, but not its phase.
+    pub fn phase(&self) -> f32 {
+        self.nco.phase()
+    }
+
+    /// Returns the estimated frequency of the carrier,
+    /// in radians per sample.
+    pub fn frequency(&self) -> f32 {
+        self.nco.frequency() + self.correction
+    }
+}
+
+impl Delay for Fll {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Reset for Fll {
+    fn reset(&mut self) {
+        self.nco = Nco::new(self.initial_frequency);
+        self.loop_filter.reset();
+        self.correction = 0.0;
+        self.last = (0.0, 0.0);
+    }
+}
+
+impl Filter<(f32, f32)> for Fll {
+    type Output = (f32, f32);
+
+    fn filter(&mut self, sample: (f32, f32)) -> Self::Output {
+        let (i, q) = self.nco.derotate(sample);
+        if !i.is_finite() || !q.is_finite() {
+            self.nco.advance(self.correction);
+            return (i, q);
+        }
+
+        // The phase change since the last sample, from this
+        // sample times the conjugate of the last one.
+        let (li, lq) = self.last;
+        let cross = q * li - i * lq;
+        let dot = i * li + q * lq;
+        self.last = (i, q);
+
+        let error = if cross == 0.0 && dot == 0.0 {
+            0.0
+        } else {
+            cross.atan2(dot)
+        };
+
+        // The error is a frequency, so the loop filter's
+        // output is a change of frequency.
+        self.correction += self.loop_filter.filter(error);
+        self.nco.advance(self.correction);
+        (i, q)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frequency: f32, phase: f32) -> impl Iterator<Item = (f32, f32)> {
+        (0..).map(move |n| {
+            let angle = phase + frequency * n as f32;
+            (angle.cos(), angle.sin())
+        })
+    }
+
+    #[test]
+    fn loop_filter_integrates() {
+        let mut loop_filter = LoopFilter::new(0.01, LOOP_DEFAULT_DAMPING);
+        let out = (0..100)
+            .map(|_| loop_filter.filter(1.0))
+            .collect::<Vec<_>>();
+
+        // A proportional part, plus an integrator that ramps up.
+        let step = out[1] - out[0];
+        assert!(step > 0.0 && out[0] > step);
+        assert!(out.windows(2).all(|x| (x[1] - x[0] - step).abs() < 1e-6));
+        assert!((loop_filter.integrator() - step * 100.0).abs() < 1e-4);
+
+        loop_filter.reset();
+        assert_eq!(loop_filter.filter(0.0), 0.0);
+    }
+
+    #[test]
+    fn nco_wraps() {
+        let mut nco = Nco::new(1.0);
+        (0..100).for_each(|_| nco.advance(0.0));
+        let expected = (100.0f32 + PI).rem_euclid(2.0 * PI) - PI;
+        assert!((nco.phase() - expected).abs() < 1e-3);
+
+        let (i, q) = nco.derotate((nco.phase().cos(), nco.phase().sin()));
+        assert!((i - 1.0).abs() < 1e-6 && q.abs() < 1e-6);
+    }
+
+    #[test]
+    fn pll_locks() {
+        for damping in [0.5, LOOP_DEFAULT_DAMPING, 1.0] {
+            let mut pll = Pll::new(0.0, 0.01, damping);
+            let out = tone(0.05, 2.0)
+                .take(2000)
+                .map(|x| pll.filter(x))
+                .last()
+                .unwrap();
+            assert!((pll.frequency() - 0.05).abs() < 1e-4, "{}", pll.frequency());
+            assert!(out.1.abs() < 1e-3 && out.0 > 0.99, "{:?}", out);
+        }
+    }
+
+    #[test]
+    fn pll_tracks_phase() {
+        let mut pll = Pll::new(0.1, 0.02, LOOP_DEFAULT_DAMPING);
+        let samples = tone(0.1, 0.0)
+            .take(1000)
+            .chain(tone(0.1, 1.0 + 0.1 * 1000.0).take(1000));
+        samples.for_each(|x| {
+            pll.filter(x);
+        });
+        let expected = (1.0 + 0.1 * 2000.0 + PI).rem_euclid(2.0 * PI) - PI;
+        assert!((pll.phase() - expected).abs() < 1e-3, "{}", pll.phase());
+    }
+
+    #[test]
+    fn fll_locks() {
+        let mut fll = Fll::new(0.0, 0.005, LOOP_DEFAULT_DAMPING);
+        tone(-0.3, 1.0).take(3000).for_each(|x| {
+            fll.filter(x);
+        });
+        assert!((fll.frequency() + 0.3).abs() < 1e-3, "{}", fll.frequency());
+    }
+}
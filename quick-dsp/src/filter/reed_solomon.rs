@@ -0,0 +1,319 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reed-Solomon codes over GF(2^8), as used by FX.25 and IL2P.
+
+/// Field generator polynomial, x^8 + x^4 + x^3 + x^2 + 1.
+const GF_POLY: u16 = 0x11d;
+
+/// Powers of the primitive element, doubled up so that
+/// the sum of two logarithms can be used as an index directly.
+static GF_EXP: [u8; 512] = gf_exp_table();
+
+/// Discrete logarithms. `GF_LOG[0]` is meaningless.
+static GF_LOG: [u8; 256] = gf_log_table();
+
+const fn gf_exp_table() -> [u8; 512] {
+    let mut table = [0u8; 512];
+    let mut x = 1u16;
+    let mut i = 0;
+    while i < 255 {
+        table[i] = x as u8;
+        table[i + 255] = x as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= GF_POLY;
+        }
+        i += 1;
+    }
+    table
+}
+
+const fn gf_log_table() -> [u8; 256] {
+    let exp = gf_exp_table();
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 255 {
+        table[exp[i] as usize] = i as u8;
+        i += 1;
+    }
+    table
+}
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        0
+    } else {
+        GF_EXP[GF_LOG[a as usize] as usize + GF_LOG[b as usize] as usize]
+    }
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    assert_ne!(b, 0, "division by zero");
+    if a == 0 {
+        0
+    } else {
+        GF_EXP[GF_LOG[a as usize] as usize + 255 - GF_LOG[b as usize] as usize]
+    }
+}
+
+/// Returns the primitive element raised to the power `n`.
+fn gf_pow(n: isize) -> u8 {
+    GF_EXP[n.rem_euclid(255) as usize]
+}
+
+/// Systematic Reed-Solomon codec over GF(2^8) with field polynomial 0x11d.
+///
+/// Code blocks are the data followed by `nroots` parity bytes, and may be
+/// shortened to anything up to 255 bytes. The first byte is the coefficient
+/// of the highest power. The generator polynomial has the consecutive roots
+/// α^fcr … α^(fcr+nroots-1).
+#[derive(Clone, Debug)]
+pub struct ReedSolomon {
+    fcr: u8,
+    /// Generator polynomial, highest power first. The leading one is omitted.
+    genpoly: Vec<u8>,
+}
+
+impl ReedSolomon {
+    pub fn new(nroots: usize, fcr: u8) -> ReedSolomon {
+        assert!(nroots > 0 && nroots < 255, "bad nroots: {}", nroots);
+
+        let mut genpoly = vec![1u8];
+        for i in 0..nroots {
+            // Multiply by (x + α^(fcr+i)).
+            let root = gf_pow(fcr as isize + i as isize);
+            let mut next = genpoly.clone();
+            next.push(0);
+            for (j, &coef) in genpoly.iter().enumerate() {
+                next[j + 1] ^= gf_mul(coef, root);
+            }
+            genpoly = next;
+        }
+        genpoly.remove(0);
+
+        ReedSolomon { fcr, genpoly }
+    }
+
+    /// The number of parity bytes.
+    pub fn nroots(&self) -> usize {
+        self.genpoly.len()
+    }
+
+    /// Returns the parity bytes for `data`.
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        assert!(data.len() + self.nroots() <= 255, "data too long");
+
+        let mut parity = vec![0u8; self.nroots()];
+        for &x in data {
+            let feedback = x ^ parity[0];
+            parity.rotate_left(1);
+            *parity.last_mut().unwrap() = 0;
+            if feedback != 0 {
+                for (p, &g) in parity.iter_mut().zip(self.genpoly.iter()) {
+                    *p ^= gf_mul(feedback, g);
+                }
+            }
+        }
+        parity
+    }
+
+    /// Corrects errors in a code block (data followed by parity) in place.
+    ///
+    /// Returns the number of bytes corrected, or `None` if there were
+    /// more errors than the code can correct. Up to `nroots / 2` errors
+    /// can be corrected; beyond that, a block may also be miscorrected.
+    pub fn decode(&self, block: &mut [u8]) -> Option<usize> {
+        let nroots = self.nroots();
+        let n = block.len();
+        assert!(n > nroots && n <= 255, "bad block length: {}", n);
+
+        let syndromes = (0..nroots)
+            .map(|i| {
+                let root = gf_pow(self.fcr as isize + i as isize);
+                block.iter().fold(0u8, |s, &x| gf_mul(s, root) ^ x)
+            })
+            .collect::<Vec<_>>();
+
+        if syndromes.iter().all(|&s| s == 0) {
+            return Some(0);
+        }
+
+        // Berlekamp-Massey, finding the error locator polynomial
+        // (lowest power first).
+        let mut lambda = vec![1u8];
+        let mut prev = vec![1u8];
+        let mut errors = 0;
+        let mut shift = 1;
+        let mut prev_discrepancy = 1u8;
+
+        for i in 0..nroots {
+            let discrepancy = (1..=errors)
+                .filter(|&j| j < lambda.len())
+                .fold(syndromes[i], |d, j| d ^ gf_mul(lambda[j], syndromes[i - j]));
+
+            if discrepancy == 0 {
+                shift += 1;
+                continue;
+            }
+
+            let scale = gf_div(discrepancy, prev_discrepancy);
+            let mut next = lambda.clone();
+            next.resize(next.len().max(prev.len() + shift), 0);
+            for (j, &b) in prev.iter().enumerate() {
+                next[j + shift] ^= gf_mul(scale, b);
+            }
+
+            if 2 * errors <= i {
+                errors = i + 1 - errors;
+                prev = std::mem::replace(&mut lambda, next);
+                prev_discrepancy = discrepancy;
+                shift = 1;
+            } else {
+                lambda = next;
+                shift += 1;
+            }
+        }
+
+        while lambda.last() == Some(&0) {
+            lambda.pop();
+        }
+        if errors * 2 > nroots || lambda.len() != errors + 1 {
+            return None;
+        }
+
+        let eval = |poly: &[u8], x: u8| poly.iter().rev().fold(0u8, |s, &c| gf_mul(s, x) ^ c);
+
+        // Error evaluator: syndromes * lambda, mod x^nroots.
+        let mut omega = vec![0u8; nroots];
+        for (i, &l) in lambda.iter().enumerate() {
+            for (j, &s) in syndromes.iter().enumerate().take(nroots - i) {
+                omega[i + j] ^= gf_mul(l, s);
+            }
+        }
+
+        // Formal derivative of lambda.
+        let lambda_prime = lambda
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, &l)| if i % 2 == 1 { l } else { 0 })
+            .collect::<Vec<_>>();
+
+        // Chien search and Forney's algorithm.
+        let mut corrections = vec![];
+        for power in 0..n {
+            let x_inv = gf_pow(-(power as isize));
+            if eval(&lambda, x_inv) != 0 {
+                continue;
+            }
+            let denominator = eval(&lambda_prime, x_inv);
+            if denominator == 0 {
+                return None;
+            }
+            let magnitude = gf_mul(
+                gf_pow(power as isize * (1 - self.fcr as isize)),
+                gf_div(eval(&omega, x_inv), denominator),
+            );
+            corrections.push((n - 1 - power, magnitude));
+        }
+
+        if corrections.len() != errors {
+            return None;
+        }
+
+        for &(index, magnitude) in &corrections {
+            block[index] ^= magnitude;
+        }
+        Some(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn gf_tables() {
+        assert_eq!(GF_EXP[0], 1);
+        assert_eq!(GF_EXP[8], 0x1d);
+        for x in 1..=255u8 {
+            assert_eq!(GF_EXP[GF_LOG[x as usize] as usize], x);
+            assert_eq!(gf_mul(x, gf_div(1, x)), 1);
+        }
+    }
+
+    #[test]
+    fn reed_solomon_correct_errors() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+        for &(nroots, fcr, len) in &[
+            (16, 1, 239),
+            (32, 1, 64),
+            (64, 1, 128),
+            (2, 0, 10),
+            (16, 0, 100),
+        ] {
+            let rs = ReedSolomon::new(nroots, fcr);
+            let data = (0..len).map(|_| rng.gen()).collect::<Vec<u8>>();
+            let mut block = data.clone();
+            block.extend(rs.encode(&data));
+
+            let mut copy = block.clone();
+            assert_eq!(rs.decode(&mut copy), Some(0));
+
+            for errors in 1..=nroots / 2 {
+                let mut copy = block.clone();
+                for i in rand::seq::index::sample(&mut rng, copy.len(), errors) {
+                    copy[i] ^= rng.gen_range(1..=255);
+                }
+                assert_eq!(
+                    rs.decode(&mut copy),
+                    Some(errors),
+                    "nroots:{} errors:{}",
+                    nroots,
+                    errors
+                );
+                assert_eq!(copy, block);
+            }
+        }
+    }
+
+    #[test]
+    fn reed_solomon_too_many_errors() {
+        let rs = ReedSolomon::new(16, 1);
+        let data = (0..64u8).collect::<Vec<_>>();
+        let mut block = data.clone();
+        block.extend(rs.encode(&data));
+
+        for x in block.iter_mut().take(9) {
+            *x ^= 0x55;
+        }
+        let mut copy = block.clone();
+        // Beyond the design distance, the codec either gives up or
+        // miscorrects into a different code word. It never panics.
+        if let Some(count) = rs.decode(&mut copy) {
+            assert!(count <= 8);
+            assert_ne!(copy[..64], data[..]);
+        }
+    }
+}
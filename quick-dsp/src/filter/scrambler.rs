@@ -0,0 +1,132 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+/// Self-synchronizing scrambler, as used by G3RUH 9600 baud modems.
+///
+/// Each bit sent is the input bit XORed with the bits sent 12 and 17 bits
+/// before it, for the polynomial x^17 + x^12 + 1. This keeps long runs of
+/// the same bit off the air, so the signal has no DC and plenty of
+/// transitions for clock recovery.
+#[derive(Clone, Debug, Default)]
+pub struct Scrambler {
+    /// The most recent bits sent, the newest in the least-significant bit.
+    state: u32,
+}
+
+impl Filter<bool> for Scrambler {
+    type Output = bool;
+
+    fn filter(&mut self, sample: bool) -> Self::Output {
+        let out = sample ^ scrambler_taps(self.state);
+        self.state = (self.state << 1 | out as u32) & 0x1ffff;
+        out
+    }
+}
+
+impl Delay for Scrambler {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+/// Undoes [`Scrambler`].
+///
+/// Since it only depends on the last 17 bits received, it synchronizes
+/// with the scrambler by itself, and an inverted input gives an inverted
+/// output.
+#[derive(Clone, Debug, Default)]
+pub struct Descrambler {
+    /// The most recent bits received, the newest in the least-significant bit.
+    state: u32,
+}
+
+impl Filter<bool> for Descrambler {
+    type Output = bool;
+
+    fn filter(&mut self, sample: bool) -> Self::Output {
+        let out = sample ^ scrambler_taps(self.state);
+        self.state = (self.state << 1 | sample as u32) & 0x1ffff;
+        out
+    }
+}
+
+impl Delay for Descrambler {
+    fn delay(&self) -> usize {
+        0
+    }
+}
+
+impl Reset for Descrambler {
+    fn reset(&mut self) {
+        self.state = 0;
+    }
+}
+
+fn scrambler_taps(state: u32) -> bool {
+    (state >> 11 ^ state >> 16) & 1 != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_bits() -> Vec<bool> {
+        (0u32..500).map(|i| i % 7 == 0 || i % 3 == 1).collect()
+    }
+
+    #[test]
+    fn scrambler_round_trip() {
+        let bits = test_bits();
+        let mut descrambler = Descrambler::default();
+        let received = bits
+            .iter()
+            .copied()
+            .scramble()
+            .map(|x| descrambler.filter(x))
+            .collect::<Vec<_>>();
+        assert_eq!(received, bits);
+    }
+
+    #[test]
+    fn scrambler_whitens() {
+        let scrambled = std::iter::repeat_n(true, 1000)
+            .scramble()
+            .collect::<Vec<_>>();
+        let ones = scrambled.iter().filter(|&&x| x).count();
+        assert!((400..600).contains(&ones), "{}", ones);
+    }
+
+    #[test]
+    fn descrambler_synchronizes() {
+        let bits = test_bits();
+        let scrambled = bits.iter().copied().scramble().collect::<Vec<_>>();
+
+        // Start part way through, inverted.
+        let mut descrambler = Descrambler::default();
+        let received = scrambled[100..]
+            .iter()
+            .map(|&x| !descrambler.filter(!x))
+            .collect::<Vec<_>>();
+        assert_eq!(received[17..], bits[117..]);
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! G3RUH-compatible 9600 baud FSK modem.
+//!
+//! Unlike AFSK, the data directly modulates the radio's FM deviation, so
+//! this needs a flat-audio radio port: the transmitter's modulator input
+//! and the receiver's discriminator output. On the audio side, it is a
+//! baseband signal: NRZI encoded, scrambled, and pulse shaped.
+
+mod receiver;
+mod sender;
+
+use crate::filter::*;
+use crate::modem::Modem;
+pub use receiver::*;
+pub use sender::*;
+
+pub const G3RUH_RATE: u32 = 9600;
+
+/// Symbols either side of a pulse's centre covered by the pulse shaping.
+const G3RUH_PULSE_SPAN: usize = 4;
+
+/// Parameters of a G3RUH modem: the symbol rate and the pulse shape.
+///
+/// The default is 9600 baud with raised cosine pulses. The decoders
+/// don't depend on the pulse shape.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct G3ruh {
+    /// Symbols (and bits) per second.
+    pub baud: u32,
+
+    /// The shape of the pulses sent.
+    pub pulse: PulseShape,
+}
+
+impl Default for G3ruh {
+    fn default() -> Self {
+        G3ruh::new(G3RUH_RATE)
+    }
+}
+
+impl G3ruh {
+    /// Returns a modem for the given symbol rate, with raised cosine pulses.
+    pub const fn new(baud: u32) -> G3ruh {
+        G3ruh {
+            baud,
+            pulse: PulseShape::RaisedCosine(0.5),
+        }
+    }
+
+    /// Returns this modem with the given pulse shape.
+    pub const fn with_pulse(self, pulse: PulseShape) -> G3ruh {
+        G3ruh { pulse, ..self }
+    }
+
+    fn samples_per_symbol(self, sample_rate: u32) -> f32 {
+        assert!(
+            sample_rate >= self.baud * 2,
+            "min sample rate:{}, given: {}",
+            self.baud * 2,
+            sample_rate
+        );
+        (sample_rate as f32) / (self.baud as f32)
+    }
+}
+
+impl Modem for G3ruh {
+    /// Four samples per symbol.
+    fn optimal_sample_rate(self) -> u32 {
+        self.baud * 4
+    }
+
+    fn bit_rate(self) -> u32 {
+        self.baud
+    }
+
+    /// Needs at least two samples per symbol, and works best
+    /// with four or more. Descrambles the bits.
+    fn demod<B, X>(self, sample_rate: u32, bits: B) -> impl Filter<f32, Output = Option<X>> + Send
+    where
+        B: Filter<Option<bool>, Output = Option<X>> + Send + 'static,
+    {
+        let samples_per_symbol = self.samples_per_symbol(sample_rate);
+
+        // Matched to the symbols before pulse shaping, which only limits
+        // their bandwidth. This does better than matching the pulses, which
+        // adds intersymbol interference.
+        FilterBox::new(samples_per_symbol.round() as usize)
+            .chain(Slicer::default())
+            .chain(PllBitSampler::with_samples_per_symbol(samples_per_symbol))
+            .chain(Descrambler::default().optional())
+            .chain(bits)
+    }
+
+    /// Scrambles and pulse shapes the bits.
+    fn modulate<'a>(
+        self,
+        bits: impl Iterator<Item = bool> + Send + 'a,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> impl Iterator<Item = f32> + Send + 'a {
+        let samples_per_symbol = self.samples_per_symbol(sample_rate);
+
+        bits.scramble()
+            .map(|x| if x { 1.0 } else { -1.0 })
+            .pulse_shape(self.pulse, samples_per_symbol, G3RUH_PULSE_SPAN)
+            .map(move |x| x * amplitude)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fcs::Fcs;
+    use crate::modem::{Decoding, Framing};
+    use rand::{Rng, SeedableRng};
+
+    fn test_frame() -> Vec<u8> {
+        hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap()
+    }
+
+    #[test]
+    fn g3ruh_encode_decode() {
+        let gaussian = G3ruh::default().with_pulse(PulseShape::Gaussian(0.5));
+        for modem in [G3ruh::default(), gaussian] {
+            for sample_rate in [19200, 38400, 44100, 48000] {
+                let samples = modem.encode(test_frame().into_iter(), sample_rate, 0.75);
+                let mut decoder = modem.decoder(sample_rate);
+                let decoded = samples
+                    .chain(std::iter::repeat_n(0.0, 1000))
+                    .find_map(|x| decoder.filter(x));
+                assert_eq!(
+                    decoded,
+                    Some(test_frame()),
+                    "{:?} at {}",
+                    modem,
+                    sample_rate
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn g3ruh_inverted() {
+        // Some radios invert the signal, which NRZI doesn't mind.
+        let modem = G3ruh::default();
+        let samples = modem.encode(test_frame().into_iter(), 38400, 0.75);
+        let mut decoder = modem.decoder(38400);
+        let decoded = samples
+            .chain(std::iter::repeat_n(0.0, 1000))
+            .find_map(|x| decoder.filter(-x));
+        assert_eq!(decoded, Some(test_frame()));
+    }
+
+    #[test]
+    fn g3ruh_framed_encode_decode() {
+        let modem = G3ruh::default();
+        for framing in [Framing::Hdlc, Framing::Fx25(16), Framing::Il2p] {
+            let samples = modem.encode_framed(test_frame(), framing, Fcs::X25, 44100, 0.75);
+            let mut decoder = modem.framed_decoder(44100, framing, Fcs::X25);
+            let frames = samples
+                .chain(std::iter::repeat_n(0.0, 1000))
+                .filter_map(|x| decoder.filter(x))
+                .filter(|x| Fcs::X25.verify(x).is_ok())
+                .collect::<Vec<_>>();
+            assert_eq!(frames, vec![test_frame()], "{:?}", framing);
+        }
+    }
+
+    #[test]
+    fn g3ruh_noise() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(9600);
+        let modem = G3ruh::default();
+        let mut decoded = 0;
+        for _ in 0..20 {
+            let samples = modem.encode(test_frame().into_iter(), 38400, 0.5);
+            let mut decoder = modem.decoder(38400);
+            let noisy = std::iter::repeat_n(0.0, rng.gen_range(0..100))
+                .chain(samples)
+                .chain(std::iter::repeat_n(0.0, 1000))
+                .map(|x| x + rng.gen_range(-0.3..0.3));
+            if noisy
+                .filter_map(|x| decoder.filter(x))
+                .any(|x| x == test_frame())
+            {
+                decoded += 1;
+            }
+        }
+        assert!(decoded >= 18, "{}", decoded);
+    }
+
+    #[test]
+    fn g3ruh_checked_decoder() {
+        let modem = G3ruh::default();
+        assert!(modem
+            .checked_decoder(Decoding::Framed(Framing::Fx25(32)), Fcs::X25)
+            .is_ok());
+        assert!(modem
+            .checked_decoder(Decoding::Bank(vec![]), Fcs::X25)
+            .is_err());
+    }
+}
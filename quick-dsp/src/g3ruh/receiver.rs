@@ -0,0 +1,82 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::G3ruh;
+use crate::fcs::Fcs;
+use crate::filter::FcsRepair;
+use crate::modem::{Decoding, Framing, ModemReceiver};
+use anyhow::{Error, Result};
+use cpal::*;
+
+/// Receives frames from an audio input device with a G3RUH modem.
+///
+/// The device needs a sample rate of at least 38400Hz, and should
+/// be fed from the radio's flat-audio (discriminator) output.
+pub type G3ruhReceiver = ModemReceiver<G3ruh>;
+
+impl G3ruhReceiver {
+    pub fn new(device: &cpal::Device) -> Result<G3ruhReceiver, Error> {
+        Self::new_with_framing(device, Framing::Hdlc)
+    }
+
+    /// Like [`G3ruhReceiver::new`], but for the given framing.
+    pub fn new_with_framing(
+        device: &cpal::Device,
+        framing: Framing,
+    ) -> Result<G3ruhReceiver, Error> {
+        Self::open(
+            device,
+            G3ruh::default(),
+            Decoding::Framed(framing),
+            Fcs::default(),
+        )
+    }
+
+    /// Like [`G3ruhReceiver::new`], but frames that fail the FCS
+    /// check are repaired where possible instead of being dropped.
+    pub fn new_with_repair(
+        device: &cpal::Device,
+        repair: FcsRepair,
+    ) -> Result<G3ruhReceiver, Error> {
+        Self::open(
+            device,
+            G3ruh::default(),
+            Decoding::Repair {
+                repair,
+                soft: false,
+            },
+            Fcs::default(),
+        )
+    }
+
+    pub fn new_with_config(
+        device: &cpal::Device,
+        supported_config: &StreamConfig,
+    ) -> Result<G3ruhReceiver, Error> {
+        Self::open_with_config(
+            device,
+            supported_config,
+            G3ruh::default(),
+            Decoding::Framed(Framing::Hdlc),
+            Fcs::default(),
+        )
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::G3ruh;
+use crate::modem::ModemSender;
+use anyhow::{Error, Result};
+use cpal::*;
+
+/// Sends frames to an audio output device with a G3RUH modem.
+///
+/// The device needs a sample rate of at least 38400Hz, and should
+/// feed the radio's flat-audio (modulator) input.
+pub type G3ruhSender = ModemSender<G3ruh>;
+
+impl G3ruhSender {
+    pub fn new(device: &cpal::Device) -> Result<G3ruhSender, Error> {
+        Self::open(device, G3ruh::default())
+    }
+
+    pub fn new_with_config(
+        device: &cpal::Device,
+        supported_config: &StreamConfig,
+    ) -> Result<G3ruhSender, Error> {
+        Self::open_with_config(device, supported_config, G3ruh::default())
+    }
+}
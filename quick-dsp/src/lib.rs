@@ -22,4 +22,8 @@
 #![warn(clippy::all)]
 
 pub mod bell202;
+pub mod fcs;
 pub mod filter;
+pub mod g3ruh;
+pub mod modem;
+pub mod psk;
@@ -0,0 +1,351 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Sending and receiving frames through audio devices, with any modem.
+
+mod receiver;
+mod sender;
+
+use crate::fcs::Fcs;
+use crate::filter::*;
+use anyhow::{format_err, Error};
+use log::{info, trace};
+pub use receiver::*;
+pub use sender::*;
+use std::fmt::Debug;
+use std::time::Duration;
+
+/// How frames are framed on a channel.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Framing {
+    /// Plain HDLC framing, as used by AX.25.
+    #[default]
+    Hdlc,
+
+    /// HDLC framing inside FX.25 code blocks, with the given number of
+    /// Reed-Solomon parity bytes: 16, 32 or 64. Plain HDLC receivers
+    /// can still decode these.
+    Fx25(usize),
+
+    /// IL2P framing, with maximum forward error correction.
+    Il2p,
+}
+
+/// A modem: the physical layer that turns bits into audio and back.
+///
+/// Modems only need to implement [`Modem::demod`] and [`Modem::modulate`],
+/// and say what rates they work at.
+/// The framing, and the [`ModemSender`] and [`ModemReceiver`] plumbing,
+/// is the same for all of them.
+pub trait Modem: Copy + Debug + Send + 'static {
+    /// The sample rate the decoders work at. Receivers downsample to
+    /// this rate, and senders need at least this rate.
+    fn optimal_sample_rate(self) -> u32;
+
+    /// Bits sent per second.
+    fn bit_rate(self) -> u32;
+
+    /// Whether HDLC framed bits are NRZI coded. Modems that send data as
+    /// changes of phase don't need it. IL2P framed bits never are.
+    fn nrzi(self) -> bool {
+        true
+    }
+
+    /// Demodulates samples at the given sample rate, feeding the bits
+    /// received to `bits`, with `None` between bits.
+    fn demod<B, X>(self, sample_rate: u32, bits: B) -> impl Filter<f32, Output = Option<X>> + Send
+    where
+        B: Filter<Option<bool>, Output = Option<X>> + Send + 'static;
+
+    /// Modulates bits into samples at the given sample rate, with
+    /// peaks of `amplitude`.
+    fn modulate<'a>(
+        self,
+        bits: impl Iterator<Item = bool> + Send + 'a,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> impl Iterator<Item = f32> + Send + 'a;
+
+    /// Decoder.
+    ///
+    /// Feed in samples into the returned filter and it will
+    /// occasionally spit out a frame. Does not check CRC.
+    fn decoder(self, sample_rate: u32) -> impl Filter<f32, Output = Option<Vec<u8>>> + Send {
+        hdlc_decoder(self, sample_rate, FrameCollector::default())
+    }
+
+    /// Decoder that checks the FCS, repairing frames where possible.
+    ///
+    /// Like [`Modem::decoder`], but only outputs frames with a
+    /// valid FCS. See [`FcsRepair`] for details.
+    fn repairing_decoder(
+        self,
+        sample_rate: u32,
+        repair: FcsRepair,
+    ) -> impl Filter<f32, Output = Option<CheckedFrame>> + Send {
+        hdlc_decoder(self, sample_rate, repair)
+    }
+
+    /// Decoder that also decodes FX.25 code blocks.
+    ///
+    /// Like [`Modem::decoder`], but frames sent with FX.25 forward
+    /// error correction are corrected, and output if their `fcs` is valid.
+    /// Frames without FX.25 are output as before, without checking the FCS.
+    fn fx25_decoder(
+        self,
+        sample_rate: u32,
+        fcs: Fcs,
+    ) -> impl Filter<f32, Output = Option<Vec<u8>>> + Send {
+        self.demod(
+            sample_rate,
+            NrziBits::new(self.nrzi(), HdlcFx25Decode::new(fcs)),
+        )
+    }
+
+    /// IL2P decoder.
+    ///
+    /// Like [`Modem::decoder`], but for IL2P framing. Frames are
+    /// output with `fcs` appended. See [`Il2pDecode`] for details.
+    fn il2p_decoder(
+        self,
+        sample_rate: u32,
+        fcs: Fcs,
+    ) -> impl Filter<f32, Output = Option<Vec<u8>>> + Send {
+        self.demod(sample_rate, Il2pDecode::new(fcs))
+    }
+
+    /// Returns the decoder for the given framing. FX.25 code blocks
+    /// are decoded with HDLC framing, as well as with FX.25 framing.
+    fn framed_decoder(
+        self,
+        sample_rate: u32,
+        framing: Framing,
+        fcs: Fcs,
+    ) -> Box<dyn Filter<f32, Output = Option<Vec<u8>>> + Send> {
+        match framing {
+            Framing::Hdlc | Framing::Fx25(_) => Box::new(self.fx25_decoder(sample_rate, fcs)),
+            Framing::Il2p => Box::new(self.il2p_decoder(sample_rate, fcs)),
+        }
+    }
+
+    /// Returns a decoder for the optimal sample rate that only outputs
+    /// frames with a valid `fcs`, or an error if this modem doesn't
+    /// support the given decoding.
+    fn checked_decoder(self, decoding: Decoding, fcs: Fcs) -> Result<CheckedDecoder, Error> {
+        checked_decoder(self, decoding, fcs)
+    }
+
+    /// Encoder.
+    ///
+    /// Encodes a single frame of octets. Does not add CRC.
+    /// Input is an iterator of octets. Output is an iterator
+    /// samples at the given sample rate, with a preamble.
+    fn encode<'a>(
+        self,
+        iter: impl Iterator<Item = u8> + Send + 'a,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> impl Iterator<Item = f32> + Send + 'a {
+        let bits = nrzi_encoded(self.nrzi(), iter.bits_lsb().hdlc_encode());
+        self.modulate(bits, sample_rate, amplitude)
+    }
+
+    /// FX.25 encoder.
+    ///
+    /// Like [`Modem::encode`], but sends the frame (which must include
+    /// the FCS) in an FX.25 code block with `check_bytes` Reed-Solomon parity
+    /// bytes: 16, 32 or 64. Receivers without FX.25 support can still decode
+    /// the frame. Returns `None` if the frame is too big for FX.25.
+    fn encode_fx25(
+        self,
+        frame: &[u8],
+        check_bytes: usize,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> Option<impl Iterator<Item = f32> + Send + use<Self>> {
+        let flags = |count| std::iter::repeat_n(0x7eu8, count).bits_lsb();
+        let bits = flags(15)
+            .chain(fx25_encode(frame, check_bytes)?)
+            .chain(flags(2));
+
+        Some(self.modulate(nrzi_encoded(self.nrzi(), bits), sample_rate, amplitude))
+    }
+
+    /// IL2P encoder.
+    ///
+    /// Like [`Modem::encode`], but sends the frame (which must include
+    /// `fcs`) with IL2P framing. See [`il2p_encode`] for details. Returns
+    /// `None` if the frame is too big for IL2P.
+    fn encode_il2p(
+        self,
+        frame: &[u8],
+        fcs: Fcs,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> Option<impl Iterator<Item = f32> + Send + use<Self>> {
+        let bits = il2p_encode(frame, fcs, true)?;
+        Some(self.modulate(bits.into_iter(), sample_rate, amplitude))
+    }
+
+    /// Encodes HDLC flags for at least `duration`, for keeping the
+    /// transmitter keyed up before or after frames.
+    fn encode_flags(
+        self,
+        duration: Duration,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> impl Iterator<Item = f32> + Send + use<Self> {
+        let count = (duration.as_secs_f64() * self.bit_rate() as f64 / 8.0).ceil() as usize;
+        let bits = std::iter::repeat_n(0x7eu8, count).bits_lsb();
+        self.modulate(nrzi_encoded(self.nrzi(), bits), sample_rate, amplitude)
+    }
+
+    /// Encodes a frame (including `fcs`) with the given framing. Frames
+    /// too big for FX.25 or IL2P are sent with plain HDLC framing.
+    fn encode_framed(
+        self,
+        frame: Vec<u8>,
+        framing: Framing,
+        fcs: Fcs,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> Box<dyn Iterator<Item = f32> + Send> {
+        let framed: Option<Box<dyn Iterator<Item = f32> + Send>> = match framing {
+            Framing::Hdlc => None,
+            Framing::Fx25(check_bytes) => self
+                .encode_fx25(&frame, check_bytes, sample_rate, amplitude)
+                .map(|x| Box::new(x) as _),
+            Framing::Il2p => self
+                .encode_il2p(&frame, fcs, sample_rate, amplitude)
+                .map(|x| Box::new(x) as _),
+        };
+
+        framed.unwrap_or_else(|| Box::new(self.encode(frame.into_iter(), sample_rate, amplitude)))
+    }
+}
+
+/// Demodulates HDLC framed bits, feeding the frame signals to `frames`.
+fn hdlc_decoder<M, F, X>(
+    modem: M,
+    sample_rate: u32,
+    frames: F,
+) -> impl Filter<f32, Output = Option<X>> + Send
+where
+    M: Modem,
+    F: Filter<Option<FrameSignal>, Output = Option<X>> + Send + 'static,
+{
+    let frames = FilterExt::<Option<bool>>::chain(HdlcDecode::default(), frames);
+    let bits = NrziBits::new(modem.nrzi(), frames);
+    modem.demod(sample_rate, bits)
+}
+
+/// NRZI decodes bits, if the modem needs it, before passing them on.
+struct NrziBits<B> {
+    nrzi: Option<NrziDecode>,
+    bits: B,
+}
+
+impl<B> NrziBits<B> {
+    fn new(nrzi: bool, bits: B) -> NrziBits<B> {
+        NrziBits {
+            nrzi: nrzi.then(NrziDecode::new),
+            bits,
+        }
+    }
+}
+
+impl<B: Filter<Option<bool>>> Filter<Option<bool>> for NrziBits<B> {
+    type Output = B::Output;
+
+    fn filter(&mut self, sample: Option<bool>) -> Self::Output {
+        let sample = match self.nrzi.as_mut() {
+            Some(nrzi) => sample.map(|x| nrzi.filter(x)),
+            None => sample,
+        };
+        self.bits.filter(sample)
+    }
+}
+
+/// NRZI encodes bits, if the modem needs it.
+fn nrzi_encoded<'a>(
+    nrzi: bool,
+    bits: impl Iterator<Item = bool> + Send + 'a,
+) -> impl Iterator<Item = bool> + Send + 'a {
+    bits.scan(NrziEncode::new(), move |encode, x| {
+        Some(if nrzi { encode.filter(x) } else { x })
+    })
+}
+
+/// Decoder returned by [`Modem::checked_decoder`].
+pub type CheckedDecoder = Box<dyn FnMut(f32) -> Option<Vec<u8>> + Send>;
+
+/// The decodings every modem supports: [`Decoding::Framed`], and
+/// [`Decoding::Repair`] without soft decisions.
+pub(crate) fn checked_decoder<M: Modem>(
+    modem: M,
+    decoding: Decoding,
+    fcs: Fcs,
+) -> Result<CheckedDecoder, Error> {
+    let sample_rate = modem.optimal_sample_rate();
+    match decoding {
+        Decoding::Framed(framing) => Ok(fcs_checked(
+            modem.framed_decoder(sample_rate, framing, fcs),
+            fcs,
+        )),
+        Decoding::Repair {
+            mut repair,
+            soft: false,
+        } => {
+            repair.set_fcs(fcs);
+            Ok(repair_logged(modem.repairing_decoder(sample_rate, repair)))
+        }
+        decoding => Err(format_err!("{:?} not supported by {:?}", decoding, modem)),
+    }
+}
+
+/// Wraps a decoder to drop frames with a bad `fcs`.
+pub(crate) fn fcs_checked(
+    mut decoder: impl Filter<f32, Output = Option<Vec<u8>>> + Send + 'static,
+    fcs: Fcs,
+) -> CheckedDecoder {
+    Box::new(move |sample| {
+        let frame = decoder.filter(sample)?;
+        match fcs.verify(&frame) {
+            Ok(_) => Some(frame),
+            Err(err) => {
+                trace!("{}", err);
+                None
+            }
+        }
+    })
+}
+
+/// Wraps a repairing decoder to log the repairs it makes.
+pub(crate) fn repair_logged(
+    mut decoder: impl Filter<f32, Output = Option<CheckedFrame>> + Send + 'static,
+) -> CheckedDecoder {
+    Box::new(move |sample| {
+        let checked = decoder.filter(sample)?;
+        if let Some(repair) = checked.repair {
+            info!("Repaired frame: {:?}", repair);
+        }
+        Some(checked.frame)
+    })
+}
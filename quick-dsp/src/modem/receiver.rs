@@ -0,0 +1,193 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::{Framing, Modem};
+use crate::bell202::DemodBranch;
+use crate::fcs::Fcs;
+use crate::filter::{Downsampler, FcsRepair, Filter};
+use anyhow::{format_err, Context as _, Error, Result};
+use cpal::traits::*;
+use cpal::*;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use log::{debug, trace};
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// How a [`ModemReceiver`] decodes frames.
+#[derive(Clone, Debug)]
+pub enum Decoding {
+    /// Decodes frames with the given framing, dropping those with a bad FCS.
+    Framed(Framing),
+
+    /// Repairs frames with a bad FCS where possible, using soft
+    /// decisions to guide the repairs if `soft` is set.
+    Repair { repair: FcsRepair, soft: bool },
+
+    /// Decodes with a [`Bell202DecoderBank`](crate::bell202::Bell202DecoderBank)
+    /// with the given branches. AFSK only.
+    Bank(Vec<DemodBranch>),
+}
+
+/// Receives frames from an audio input device with the given [`Modem`].
+pub struct ModemReceiver<M> {
+    modem: M,
+    input_audio_stream: cpal::Stream,
+    recvframe_receiver: mpsc::Receiver<Vec<u8>>,
+}
+
+impl<M: Modem> ModemReceiver<M> {
+    /// Opens `device` at its highest sample rate, falling back
+    /// to other ones if that doesn't work. Frames are checked
+    /// with `fcs`.
+    pub fn open(
+        device: &cpal::Device,
+        modem: M,
+        decoding: Decoding,
+        fcs: Fcs,
+    ) -> Result<ModemReceiver<M>, Error> {
+        let mut supported_stream_configs = device
+            .supported_input_configs()
+            .context("error while querying configs")?;
+
+        let supported_config_range = supported_stream_configs
+            .next()
+            .expect("no supported config?!");
+
+        let mut supported_config: StreamConfig =
+            supported_config_range.with_max_sample_rate().into();
+
+        // We only care about a single channel.
+        supported_config.channels = 1;
+
+        match Self::open_with_config(device, &supported_config, modem, decoding.clone(), fcs) {
+            Ok(ret) => Ok(ret),
+            Err(err) => {
+                // Try a different sample rate.
+                supported_config.sample_rate = SampleRate(11025);
+                if let Ok(ret) =
+                    Self::open_with_config(device, &supported_config, modem, decoding.clone(), fcs)
+                {
+                    Ok(ret)
+                } else {
+                    // Last try.
+                    supported_config.sample_rate = SampleRate(48000);
+                    if let Ok(ret) =
+                        Self::open_with_config(device, &supported_config, modem, decoding, fcs)
+                    {
+                        Ok(ret)
+                    } else {
+                        Err(err)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Opens `device` with the given config. Fails if the sample rate
+    /// is below the modem's optimal sample rate, or the modem doesn't
+    /// support the given decoding.
+    pub fn open_with_config(
+        device: &cpal::Device,
+        supported_config: &StreamConfig,
+        modem: M,
+        decoding: Decoding,
+        fcs: Fcs,
+    ) -> Result<ModemReceiver<M>, Error> {
+        debug!("Receiver stream config: {:?}", supported_config);
+        let sample_rate = modem.optimal_sample_rate();
+        if supported_config.sample_rate.0 < sample_rate {
+            return Err(format_err!(
+                "Sample rate {} too low for {:?}",
+                supported_config.sample_rate.0,
+                modem
+            ));
+        }
+        let mut downsampler = Downsampler::<f32>::new(supported_config.sample_rate.0, sample_rate);
+
+        // Returns frames with a valid FCS.
+        let mut decoder = modem.checked_decoder(decoding, fcs)?;
+        let (mut recvframe_sender, recvframe_receiver) = mpsc::channel(10);
+        let input_audio_stream = device.build_input_stream(
+            supported_config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                let iter = data.iter().filter_map(|x| downsampler.filter(*x));
+                for sample in iter {
+                    if let Some(frame) = decoder(sample) {
+                        if recvframe_sender.try_send(frame).is_err() {
+                            trace!("Dropped packet");
+                        }
+                    }
+                }
+            },
+            move |err| {
+                // react to errors here.
+                panic!("err: {:?}", err);
+            },
+        )?;
+
+        input_audio_stream.play()?;
+
+        Ok(ModemReceiver {
+            modem,
+            input_audio_stream,
+            recvframe_receiver,
+        })
+    }
+
+    /// Returns the modem frames are received with.
+    pub fn modem(&self) -> M {
+        self.modem
+    }
+
+    pub fn pause(&mut self) -> Result<(), Error> {
+        self.input_audio_stream.pause()?;
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<(), Error> {
+        self.input_audio_stream.play()?;
+        Ok(())
+    }
+}
+
+impl<M> Deref for ModemReceiver<M> {
+    type Target = mpsc::Receiver<Vec<u8>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.recvframe_receiver
+    }
+}
+
+impl<M> DerefMut for ModemReceiver<M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.recvframe_receiver
+    }
+}
+
+impl<M: Unpin> futures::stream::Stream for ModemReceiver<M> {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.recvframe_receiver.poll_next_unpin(cx)
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::{Framing, Modem};
+use crate::fcs::Fcs;
+use anyhow::{format_err, Context as _, Error, Result};
+use async_timer::oneshot::{Oneshot, Timer};
+use cpal::traits::*;
+use cpal::*;
+use futures::channel::mpsc;
+use futures::task::noop_waker;
+use futures::SinkExt;
+use log::debug;
+use rand::Rng;
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// Peak amplitude of the samples sent.
+const AMPLITUDE: f32 = 0.75;
+
+/// Shortest wait between channel checks, so a zero slot
+/// time doesn't spin.
+const MIN_SLOT_TIME: Duration = Duration::from_millis(1);
+
+/// How a [`ModemSender`] shares the channel: how long it keys up
+/// before and after frames, and how it waits for a clear channel.
+/// These are the KISS TNC parameters.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ChannelAccess {
+    /// How long to send flags for before each frame, on top of
+    /// the framing's own preamble.
+    pub tx_delay: Duration,
+
+    /// How long to send flags for after each frame.
+    pub tx_tail: Duration,
+
+    /// Chance of sending in each slot once the channel is
+    /// clear, as `p * 256 - 1`. 255 always sends.
+    pub persistence: u8,
+
+    /// How long to wait before checking the channel again.
+    pub slot_time: Duration,
+
+    /// Send without waiting for a clear channel.
+    pub full_duplex: bool,
+}
+
+impl Default for ChannelAccess {
+    fn default() -> Self {
+        ChannelAccess {
+            tx_delay: Duration::ZERO,
+            tx_tail: Duration::ZERO,
+            persistence: 255,
+            slot_time: Duration::from_millis(10),
+            full_duplex: false,
+        }
+    }
+}
+
+/// Shared handle to the [`ChannelAccess`] of a [`ModemSender`], for
+/// changing it once the sender has been handed off.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelAccessHandle(Arc<Mutex<ChannelAccess>>);
+
+impl ChannelAccessHandle {
+    pub fn get(&self) -> ChannelAccess {
+        *self.0.lock().unwrap()
+    }
+
+    pub fn set(&self, access: ChannelAccess) {
+        *self.0.lock().unwrap() = access;
+    }
+
+    /// Changes the parameters in place, returning what `f` returns.
+    pub fn update<T>(&self, f: impl FnOnce(&mut ChannelAccess) -> T) -> T {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+/// Sends frames to an audio output device with the given [`Modem`].
+pub struct ModemSender<M> {
+    modem: M,
+    output_audio_stream: cpal::Stream,
+    sendframe_sender: mpsc::Sender<Vec<u8>>,
+    is_channel_clear: AtomicBool,
+    channel_clear_waker: Cell<Waker>,
+    cca_backoff_timer: Option<Timer>,
+    framing: Arc<Mutex<Framing>>,
+    fcs: Arc<Mutex<Fcs>>,
+    channel_access: ChannelAccessHandle,
+}
+
+impl<M: Modem> ModemSender<M> {
+    /// Opens `device` at its highest sample rate, falling back
+    /// to lower ones if that doesn't work.
+    pub fn open(device: &cpal::Device, modem: M) -> Result<ModemSender<M>, Error> {
+        let mut supported_stream_configs = device
+            .supported_output_configs()
+            .context("error while querying configs")?;
+
+        let supported_config_range = supported_stream_configs
+            .next()
+            .expect("no supported config?!");
+
+        let mut supported_config: StreamConfig =
+            supported_config_range.with_max_sample_rate().into();
+
+        // We only care about a single channel.
+        supported_config.channels = 1;
+
+        match Self::open_with_config(device, &supported_config, modem) {
+            Ok(ret) => Ok(ret),
+            Err(err) => {
+                // Try a different sample rate.
+                supported_config.sample_rate = SampleRate(11025);
+                if let Ok(ret) = Self::open_with_config(device, &supported_config, modem) {
+                    Ok(ret)
+                } else {
+                    // Last try.
+                    supported_config.sample_rate = SampleRate(8000);
+                    if let Ok(ret) = Self::open_with_config(device, &supported_config, modem) {
+                        Ok(ret)
+                    } else {
+                        Err(err)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Opens `device` with the given config. Fails if the
+    /// sample rate is below the modem's optimal sample rate.
+    pub fn open_with_config(
+        device: &cpal::Device,
+        supported_config: &StreamConfig,
+        modem: M,
+    ) -> Result<ModemSender<M>, Error> {
+        let sample_rate = supported_config.sample_rate.0;
+        if sample_rate < modem.optimal_sample_rate() {
+            return Err(format_err!(
+                "Sample rate {} too low for {:?}",
+                sample_rate,
+                modem
+            ));
+        }
+
+        // Plays as silence until there is a frame to send.
+        let mut encoder: Box<dyn Iterator<Item = f32> + Send> = Box::new(std::iter::empty());
+
+        let framing = Arc::new(Mutex::new(Framing::default()));
+        let frame_framing = framing.clone();
+        let fcs = Arc::new(Mutex::new(Fcs::default()));
+        let frame_fcs = fcs.clone();
+        let channel_access = ChannelAccessHandle::default();
+        let frame_channel_access = channel_access.clone();
+
+        let (sendframe_sender, mut sendframe_receiver) = mpsc::channel::<Vec<u8>>(1);
+
+        debug!("Sender stream config: {:?}", supported_config);
+
+        let output_audio_stream = device.build_output_stream(
+            supported_config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                for sample in data.iter_mut() {
+                    if let Some(value) = encoder.next() {
+                        *sample = value;
+                    } else if let Ok(vec) = sendframe_receiver.try_recv() {
+                        // Set up the next frame.
+                        let framing = *frame_framing.lock().unwrap();
+                        let fcs = *frame_fcs.lock().unwrap();
+                        let access = frame_channel_access.get();
+                        let flags = |duration: Duration| {
+                            (!duration.is_zero())
+                                .then(|| modem.encode_flags(duration, sample_rate, AMPLITUDE))
+                                .into_iter()
+                                .flatten()
+                        };
+                        encoder = Box::new(
+                            flags(access.tx_delay)
+                                .chain(modem.encode_framed(
+                                    vec,
+                                    framing,
+                                    fcs,
+                                    sample_rate,
+                                    AMPLITUDE,
+                                ))
+                                .chain(flags(access.tx_tail)),
+                        );
+                        *sample = encoder.next().unwrap();
+                    } else {
+                        *sample = 0.0;
+                    }
+                }
+            },
+            move |err| {
+                // react to errors here.
+                panic!("err: {:?}", err);
+            },
+        )?;
+
+        output_audio_stream.play()?;
+
+        Ok(ModemSender {
+            modem,
+            output_audio_stream,
+            sendframe_sender,
+            is_channel_clear: AtomicBool::new(true),
+            channel_clear_waker: Cell::new(noop_waker()),
+            cca_backoff_timer: None,
+            framing,
+            fcs,
+            channel_access,
+        })
+    }
+
+    /// Returns the modem frames are sent with.
+    pub fn modem(&self) -> M {
+        self.modem
+    }
+
+    /// Sets the framing used for frames sent from now on. Frames too
+    /// big for the given framing are sent with plain HDLC framing.
+    pub fn set_framing(&self, framing: Framing) {
+        if let Framing::Fx25(check_bytes) = framing {
+            assert!(
+                matches!(check_bytes, 16 | 32 | 64),
+                "bad FX.25 check bytes: {}",
+                check_bytes
+            );
+        }
+        *self.framing.lock().unwrap() = framing;
+    }
+
+    /// Sets the FCS that frames sent from now on end with. Only IL2P
+    /// framing needs to know, since it sends frames without their FCS.
+    pub fn set_fcs(&self, fcs: Fcs) {
+        *self.fcs.lock().unwrap() = fcs;
+    }
+
+    /// Returns a handle for changing how the channel is shared, which
+    /// applies from the next frame on.
+    pub fn channel_access(&self) -> ChannelAccessHandle {
+        self.channel_access.clone()
+    }
+
+    /// Sets channel clear indicator. This should be set to false
+    /// when there is a signal on the channel, true if no signal is detected.
+    pub fn set_channel_clear(&self, is_channel_clear: bool) {
+        debug!("CCA: is_channel_clear={:?}", is_channel_clear);
+        self.is_channel_clear
+            .store(is_channel_clear, Ordering::Relaxed);
+        self.channel_clear_waker.replace(noop_waker()).wake()
+    }
+
+    pub fn pause(&mut self) -> Result<(), Error> {
+        self.output_audio_stream.pause()?;
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<(), Error> {
+        self.output_audio_stream.play()?;
+        Ok(())
+    }
+}
+
+impl<M: Unpin> futures::sink::Sink<Vec<u8>> for ModemSender<M> {
+    type Error = anyhow::Error;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), Self::Error>> {
+        loop {
+            if let Some(timer) = self.cca_backoff_timer.as_mut() {
+                if Pin::new(timer).poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.cca_backoff_timer = None;
+            }
+
+            // p-persistent CSMA: once the channel is clear, send with
+            // the given chance, otherwise wait a slot and try again.
+            let access = self.channel_access.get();
+            let is_clear = access.full_duplex || self.is_channel_clear.load(Ordering::Relaxed);
+            if is_clear && rand::thread_rng().gen::<u8>() <= access.persistence {
+                return self
+                    .sendframe_sender
+                    .poll_ready_unpin(cx)
+                    .map_err(anyhow::Error::from);
+            }
+
+            if !is_clear {
+                self.channel_clear_waker.replace(cx.waker().clone());
+            }
+            self.cca_backoff_timer = Some(Timer::new(access.slot_time.max(MIN_SLOT_TIME)));
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Vec<u8>) -> std::result::Result<(), Self::Error> {
+        if self.channel_access.get().full_duplex || self.is_channel_clear.load(Ordering::Relaxed) {
+            self.sendframe_sender
+                .start_send_unpin(item)
+                .map_err(anyhow::Error::from)
+        } else {
+            Err(format_err!("Channel not clear"))
+        }
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), Self::Error>> {
+        self.sendframe_sender
+            .poll_flush_unpin(cx)
+            .map_err(anyhow::Error::from)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), Self::Error>> {
+        self.sendframe_sender
+            .poll_close_unpin(cx)
+            .map_err(anyhow::Error::from)
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::{PskModulation, PskProfile, PSK_PULSE_SPAN};
+use crate::filter::*;
+use crate::modem::Modem;
+
+/// Loop bandwidth of the Costas loop, as a fraction of the symbol rate.
+const PSK_CARRIER_BANDWIDTH: f32 = 0.02;
+
+/// Demodulates differential BPSK or QPSK, a symbol at a time.
+///
+/// The signal is split into `i` and `q` with a [`QamSplitFixed`], using a
+/// root raised cosine matched filter, so the carrier must be a quarter of
+/// the sample rate. Then the symbols are picked out with a
+/// [`GardnerTimingRecovery`], and the carrier tracked with a [`CostasLoop`].
+/// Outputs the bits of each symbol, the first in the least-significant bit.
+#[derive(Clone, Debug)]
+pub struct PskDemod {
+    modulation: PskModulation,
+    baud: u32,
+    split: QamSplitFixed<f32, FilterFir<f32>>,
+    timing: GardnerTimingRecovery,
+    carrier: CostasLoop,
+
+    /// Phase of the last symbol, in quarter turns.
+    last_phase: u8,
+}
+
+impl PskDemod {
+    pub fn new(profile: PskProfile) -> PskDemod {
+        let samples_per_symbol = profile.samples_per_symbol(profile.optimal_sample_rate());
+        let kernel = PulseShape::RootRaisedCosine(profile.rolloff)
+            .kernel(samples_per_symbol, PSK_PULSE_SPAN);
+
+        PskDemod {
+            modulation: profile.modulation,
+            baud: profile.baud,
+            split: QamSplitFixed::<f32>::new(kernel.into_filter()),
+            timing: GardnerTimingRecovery::with_samples_per_symbol(samples_per_symbol),
+            carrier: match profile.modulation {
+                PskModulation::Bpsk => CostasLoop::bpsk(PSK_CARRIER_BANDWIDTH),
+                PskModulation::Qpsk => CostasLoop::qpsk(PSK_CARRIER_BANDWIDTH),
+            },
+            last_phase: 0,
+        }
+    }
+
+    /// Returns the carrier tracker.
+    pub fn carrier(&self) -> &CostasLoop {
+        &self.carrier
+    }
+
+    /// Returns the estimated offset of the carrier from the profile's, in
+    /// Hz, for tuning indicators. Only meaningful while receiving a signal.
+    pub fn carrier_offset(&self) -> f32 {
+        // QamSplitFixed mixes down so that a carrier above the
+        // profile's turns backwards.
+        -self.carrier.frequency() * self.baud as f32 / (2.0 * std::f32::consts::PI)
+    }
+}
+
+impl Filter<f32> for PskDemod {
+    type Output = Option<u8>;
+
+    fn filter(&mut self, sample: f32) -> Self::Output {
+        let symbol = self.timing.filter(self.split.filter(sample))?;
+        let (i, q) = self.carrier.filter(symbol);
+        if !i.is_finite() || !q.is_finite() {
+            return None;
+        }
+
+        let phase = match self.modulation {
+            PskModulation::Bpsk if i > 0.0 => 0,
+            PskModulation::Bpsk => 2,
+            PskModulation::Qpsk => match (i > 0.0, q > 0.0) {
+                (true, true) => 0,
+                (false, true) => 1,
+                (false, false) => 2,
+                (true, false) => 3,
+            },
+        };
+        let change = (phase + 4 - self.last_phase) % 4;
+        self.last_phase = phase;
+
+        Some(self.modulation.bits(change))
+    }
+}
+
+/// Feeds each bit of the symbols from a [`PskDemod`] to a bit-level
+/// decoder, such as [`HdlcDecode`], along with `None` between symbols.
+pub(super) struct PskBitDecode<B> {
+    pub(super) demod: PskDemod,
+    pub(super) bits: B,
+}
+
+impl<B, X> Filter<f32> for PskBitDecode<B>
+where
+    B: Filter<Option<bool>, Output = Option<X>>,
+{
+    type Output = Option<X>;
+
+    fn filter(&mut self, sample: f32) -> Self::Output {
+        let symbol = match self.demod.filter(sample) {
+            Some(x) => x,
+            None => return self.bits.filter(None),
+        };
+
+        (0..self.demod.modulation.bits_per_symbol()).fold(None, |out, n| {
+            self.bits.filter(Some(symbol >> n & 1 != 0)).or(out)
+        })
+    }
+}
@@ -0,0 +1,414 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Differential BPSK and QPSK modems.
+//!
+//! The carrier is an audio tone, so these work through an ordinary
+//! SSB or FM radio, like AFSK, but with a narrower bandwidth and more
+//! robustness to noise for the same bit rate. Data is sent as changes of
+//! phase, so the receiver doesn't need to know the absolute phase.
+
+mod demod;
+mod receiver;
+mod sender;
+
+use crate::filter::*;
+use crate::modem::Modem;
+pub use demod::*;
+pub use receiver::*;
+pub use sender::*;
+use std::f32::consts::PI;
+
+/// Symbols either side of a pulse's centre covered by the pulse
+/// shaping and matched filters.
+const PSK_PULSE_SPAN: usize = 4;
+
+/// Symbols of alternating phase sent before each frame, for the
+/// receiver's clock and carrier recovery to lock to.
+const PSK_PREAMBLE_SYMBOLS: usize = 32;
+
+/// Phase changes, in quarter turns, for each pair of QPSK bits, the
+/// first in the least-significant bit. Gray coded, so that a phase
+/// error of a quarter turn only gets one bit wrong. Its own inverse.
+const QPSK_GRAY: [u8; 4] = [0, 1, 3, 2];
+
+/// How a [`PskProfile`] modulates the carrier's phase.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PskModulation {
+    /// Differential BPSK: a zero is sent as a change of half a
+    /// turn, and a one as no change, like NRZI.
+    Bpsk,
+
+    /// Differential QPSK: each pair of bits is sent as a change
+    /// of a whole number of quarter turns.
+    Qpsk,
+}
+
+impl PskModulation {
+    pub fn bits_per_symbol(self) -> u32 {
+        match self {
+            PskModulation::Bpsk => 1,
+            PskModulation::Qpsk => 2,
+        }
+    }
+
+    /// Returns the peak of the pulse shaped signal, relative to the
+    /// symbols. Since QPSK symbols are split between `i` and `q`, their
+    /// overshoots don't line up as often.
+    fn peak(self) -> f32 {
+        match self {
+            PskModulation::Bpsk => 1.6,
+            PskModulation::Qpsk => 1.2,
+        }
+    }
+
+    /// Returns the phase change, in quarter turns, for a symbol's bits.
+    fn phase_change(self, bits: u8) -> u8 {
+        match self {
+            PskModulation::Bpsk if bits & 1 != 0 => 0,
+            PskModulation::Bpsk => 2,
+            PskModulation::Qpsk => QPSK_GRAY[bits as usize & 3],
+        }
+    }
+
+    /// Returns a symbol's bits for a phase change, in quarter turns.
+    fn bits(self, phase_change: u8) -> u8 {
+        match self {
+            PskModulation::Bpsk => (phase_change == 0) as u8,
+            PskModulation::Qpsk => QPSK_GRAY[phase_change as usize & 3],
+        }
+    }
+}
+
+/// Parameters of a PSK modem: the modulation, the symbol rate,
+/// the carrier frequency and the roll-off of the pulses.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PskProfile {
+    pub modulation: PskModulation,
+
+    /// Symbols per second.
+    pub baud: u32,
+
+    /// Frequency of the carrier, in Hz.
+    pub carrier: u32,
+
+    /// Roll-off of the root raised cosine pulses, from zero to one.
+    pub rolloff: f32,
+}
+
+impl PskProfile {
+    /// BPSK at 300 baud, for HF.
+    pub const BPSK_300: PskProfile = PskProfile::new(PskModulation::Bpsk, 300, 1500);
+
+    /// BPSK at 1200 baud, in the same bandwidth as Bell 202.
+    pub const BPSK_1200: PskProfile = PskProfile::new(PskModulation::Bpsk, 1200, 1800);
+
+    /// QPSK at 1200 baud: 2400 bits per second.
+    pub const QPSK_2400: PskProfile = PskProfile::new(PskModulation::Qpsk, 1200, 1800);
+
+    /// QPSK at 2400 baud: 4800 bits per second, which needs
+    /// the full audio bandwidth of the radio.
+    pub const QPSK_4800: PskProfile = PskProfile::new(PskModulation::Qpsk, 2400, 1800);
+
+    /// Returns a profile for the given modulation, symbol rate and
+    /// carrier, with a roll-off of 0.35.
+    pub const fn new(modulation: PskModulation, baud: u32, carrier: u32) -> PskProfile {
+        PskProfile {
+            modulation,
+            baud,
+            carrier,
+            rolloff: 0.35,
+        }
+    }
+
+    /// Bits per second.
+    pub fn bit_rate(self) -> u32 {
+        self.baud * self.modulation.bits_per_symbol()
+    }
+
+    fn samples_per_symbol(self, sample_rate: u32) -> f32 {
+        let samples_per_symbol = (sample_rate as f32) / (self.baud as f32);
+        assert!(
+            samples_per_symbol >= 2.0,
+            "min sample rate:{}, given: {}",
+            self.baud * 2,
+            sample_rate
+        );
+        samples_per_symbol
+    }
+
+    /// Returns the symbols for the given bits, as `(i, q)`,
+    /// after a preamble. Phases are differentially encoded.
+    fn symbols(self, bits: impl Iterator<Item = bool>) -> Vec<(f32, f32)> {
+        let bits = bits.collect::<Vec<_>>();
+        let changes = bits
+            .chunks(self.modulation.bits_per_symbol() as usize)
+            .map(|x| {
+                let bits = x.iter().rev().fold(0, |acc, &x| acc << 1 | x as u8);
+                self.modulation.phase_change(bits)
+            });
+
+        // QPSK symbols are on the diagonals.
+        let offset = match self.modulation {
+            PskModulation::Bpsk => 0.0,
+            PskModulation::Qpsk => PI / 4.0,
+        };
+
+        std::iter::repeat_n(2, PSK_PREAMBLE_SYMBOLS)
+            .chain(changes)
+            .scan(0, |phase, change| {
+                *phase = (*phase + change) % 4;
+                let angle = offset + *phase as f32 * PI / 2.0;
+                Some((angle.cos(), angle.sin()))
+            })
+            .collect()
+    }
+}
+
+impl Modem for PskProfile {
+    /// Puts the carrier at a quarter of the sample rate.
+    fn optimal_sample_rate(self) -> u32 {
+        self.carrier * 4
+    }
+
+    fn bit_rate(self) -> u32 {
+        self.baud * self.modulation.bits_per_symbol()
+    }
+
+    /// Data is sent as changes of phase, so needs no NRZI.
+    fn nrzi(self) -> bool {
+        false
+    }
+
+    /// Only works at the profile's `optimal_sample_rate`, so you may
+    /// need to resample first. Works with up to 20 samples per symbol,
+    /// after which [`HdlcDecode`] takes the gaps between symbols for
+    /// a loss of signal.
+    fn demod<B, X>(self, sample_rate: u32, bits: B) -> impl Filter<f32, Output = Option<X>> + Send
+    where
+        B: Filter<Option<bool>, Output = Option<X>> + Send + 'static,
+    {
+        assert_eq!(
+            sample_rate,
+            self.optimal_sample_rate(),
+            "PSK decoders only work at the optimal sample rate"
+        );
+        let samples_per_symbol = self.samples_per_symbol(sample_rate);
+        assert!(
+            samples_per_symbol <= 20.0,
+            "max samples per symbol: 20, given: {}",
+            samples_per_symbol
+        );
+
+        PskBitDecode {
+            demod: PskDemod::new(self),
+            bits,
+        }
+    }
+
+    /// Differentially encodes the bits as phase changes, after a
+    /// preamble, and modulates them onto the carrier.
+    fn modulate<'a>(
+        self,
+        bits: impl Iterator<Item = bool> + Send + 'a,
+        sample_rate: u32,
+        amplitude: f32,
+    ) -> impl Iterator<Item = f32> + Send + 'a {
+        let samples_per_symbol = self.samples_per_symbol(sample_rate);
+        let shape = PulseShape::RootRaisedCosine(self.rolloff);
+        let symbols = self.symbols(bits);
+        let shaped = |x: Vec<f32>| {
+            x.into_iter()
+                .pulse_shape(shape, samples_per_symbol, PSK_PULSE_SPAN)
+        };
+        let i = shaped(symbols.iter().map(|x| x.0).collect());
+        let q = shaped(symbols.iter().map(|x| x.1).collect());
+
+        let step = self.carrier as f64 / sample_rate as f64;
+        let amplitude = amplitude / self.modulation.peak();
+        i.zip(q).enumerate().map(move |(n, (i, q))| {
+            let (sin, cos) = (2.0 * std::f64::consts::PI * (n as f64 * step).fract()).sin_cos();
+            (i * cos as f32 + q * sin as f32) * amplitude
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fcs::Fcs;
+    use crate::modem::{Decoding, Framing};
+    use rand::{Rng, SeedableRng};
+
+    fn test_frame() -> Vec<u8> {
+        hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap()
+    }
+
+    const PRESETS: [PskProfile; 4] = [
+        PskProfile::BPSK_300,
+        PskProfile::BPSK_1200,
+        PskProfile::QPSK_2400,
+        PskProfile::QPSK_4800,
+    ];
+
+    fn decode(profile: PskProfile, samples: impl Iterator<Item = f32>) -> Option<Vec<u8>> {
+        let mut decoder = profile.decoder(profile.optimal_sample_rate());
+        samples
+            .chain(std::iter::repeat_n(0.0, 1000))
+            .find_map(|x| decoder.filter(x))
+    }
+
+    #[test]
+    fn psk_encode_decode() {
+        for profile in PRESETS {
+            let sample_rate = profile.optimal_sample_rate();
+            let samples = profile.encode(test_frame().into_iter(), sample_rate, 0.75);
+            assert_eq!(
+                decode(profile, samples),
+                Some(test_frame()),
+                "{:?}",
+                profile
+            );
+        }
+    }
+
+    #[test]
+    fn psk_encode_decode_resample() {
+        for profile in PRESETS {
+            let mut resampler = Downsampler::new(44100, profile.optimal_sample_rate());
+            let samples = profile
+                .encode(test_frame().into_iter(), 44100, 0.75)
+                .filter_map(|x| resampler.filter(x));
+            assert_eq!(
+                decode(profile, samples),
+                Some(test_frame()),
+                "{:?}",
+                profile
+            );
+        }
+    }
+
+    #[test]
+    fn psk_peak() {
+        for profile in PRESETS {
+            let sample_rate = profile.optimal_sample_rate();
+            let peak = profile
+                .encode(test_frame().into_iter(), sample_rate, 1.0)
+                .fold(0.0f32, |peak, x| peak.max(x.abs()));
+            assert!(peak > 0.5 && peak <= 1.0, "{:?}: {}", profile, peak);
+        }
+    }
+
+    #[test]
+    fn psk_framed_encode_decode() {
+        for profile in PRESETS {
+            for framing in [Framing::Fx25(16), Framing::Il2p] {
+                let sample_rate = profile.optimal_sample_rate();
+                let samples =
+                    profile.encode_framed(test_frame(), framing, Fcs::X25, sample_rate, 0.75);
+                let mut decoder = profile.framed_decoder(sample_rate, framing, Fcs::X25);
+                let decoded = samples
+                    .chain(std::iter::repeat_n(0.0, 1000))
+                    .filter_map(|x| decoder.filter(x))
+                    .find(|x| *x == test_frame());
+                assert!(decoded.is_some(), "{:?} with {:?}", profile, framing);
+            }
+        }
+    }
+
+    #[test]
+    fn psk_carrier_offset() {
+        // Mistuned by a few Hz, with the carrier's phase drifting.
+        for profile in PRESETS {
+            for offset in [-4i32, 3] {
+                let sent = PskProfile {
+                    carrier: (profile.carrier as i32 + offset) as u32,
+                    ..profile
+                };
+                let samples = sent.encode(
+                    test_frame().into_iter(),
+                    profile.optimal_sample_rate(),
+                    0.75,
+                );
+                assert_eq!(
+                    decode(profile, samples),
+                    Some(test_frame()),
+                    "{:?} {}Hz out",
+                    profile,
+                    offset
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn psk_demod_carrier_offset() {
+        let profile = PskProfile::BPSK_1200;
+        let sent = PskProfile {
+            carrier: profile.carrier + 5,
+            ..profile
+        };
+        let mut demod = PskDemod::new(profile);
+        sent.encode(
+            test_frame().into_iter(),
+            profile.optimal_sample_rate(),
+            0.75,
+        )
+        .for_each(|x| {
+            demod.filter(x);
+        });
+        assert!(
+            (demod.carrier_offset() - 5.0).abs() < 0.5,
+            "{}",
+            demod.carrier_offset()
+        );
+    }
+
+    #[test]
+    fn psk_noise() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1200);
+        for profile in PRESETS {
+            let sample_rate = profile.optimal_sample_rate();
+            let mut decoded = 0;
+            for _ in 0..10 {
+                let samples = profile
+                    .encode(test_frame().into_iter(), sample_rate, 0.5)
+                    .map(|x| x + rng.gen_range(-0.15..0.15))
+                    .collect::<Vec<_>>();
+                if decode(profile, samples.into_iter()) == Some(test_frame()) {
+                    decoded += 1;
+                }
+            }
+            assert!(decoded >= 9, "{:?}: {}", profile, decoded);
+        }
+    }
+
+    #[test]
+    fn psk_checked_decoder() {
+        let profile = PskProfile::QPSK_2400;
+        assert!(profile
+            .checked_decoder(Decoding::Framed(Framing::Il2p), Fcs::X25)
+            .is_ok());
+        assert!(profile
+            .checked_decoder(Decoding::Bank(vec![]), Fcs::X25)
+            .is_err());
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::PskProfile;
+use crate::fcs::Fcs;
+use crate::modem::{Decoding, Framing, ModemReceiver};
+use anyhow::{Error, Result};
+use cpal::*;
+
+/// Receives frames from an audio input device with a PSK modem.
+///
+/// Uses [`PskProfile::BPSK_1200`] unless opened with
+/// [`PskReceiver::new_with_profile`].
+pub type PskReceiver = ModemReceiver<PskProfile>;
+
+impl PskReceiver {
+    pub fn new(device: &cpal::Device) -> Result<PskReceiver, Error> {
+        Self::open(
+            device,
+            PskProfile::BPSK_1200,
+            Decoding::Framed(Framing::Hdlc),
+            Fcs::default(),
+        )
+    }
+
+    /// Like [`PskReceiver::new`], but for the given profile and decoding.
+    pub fn new_with_profile(
+        device: &cpal::Device,
+        profile: PskProfile,
+        decoding: Decoding,
+    ) -> Result<PskReceiver, Error> {
+        Self::open(device, profile, decoding, Fcs::default())
+    }
+
+    pub fn new_with_config(
+        device: &cpal::Device,
+        supported_config: &StreamConfig,
+    ) -> Result<PskReceiver, Error> {
+        Self::open_with_config(
+            device,
+            supported_config,
+            PskProfile::BPSK_1200,
+            Decoding::Framed(Framing::Hdlc),
+            Fcs::default(),
+        )
+    }
+}
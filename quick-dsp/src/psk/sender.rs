@@ -0,0 +1,52 @@
+// Copyright (c) 2022, The ARNGLL-Rust Authors.
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use super::PskProfile;
+use crate::modem::ModemSender;
+use anyhow::{Error, Result};
+use cpal::*;
+
+/// Sends frames to an audio output device with a PSK modem.
+///
+/// Uses [`PskProfile::BPSK_1200`] unless opened with
+/// [`PskSender::new_with_profile`].
+pub type PskSender = ModemSender<PskProfile>;
+
+impl PskSender {
+    pub fn new(device: &cpal::Device) -> Result<PskSender, Error> {
+        Self::new_with_profile(device, PskProfile::BPSK_1200)
+    }
+
+    /// Like [`PskSender::new`], but for the given profile.
+    pub fn new_with_profile(
+        device: &cpal::Device,
+        profile: PskProfile,
+    ) -> Result<PskSender, Error> {
+        Self::open(device, profile)
+    }
+
+    pub fn new_with_config(
+        device: &cpal::Device,
+        supported_config: &StreamConfig,
+    ) -> Result<PskSender, Error> {
+        Self::open_with_config(device, supported_config, PskProfile::BPSK_1200)
+    }
+}
@@ -19,23 +19,25 @@
 // TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use ax25::Ax25Frame;
 use quick_dsp::bell202::*;
+use quick_dsp::fcs::Fcs;
 use quick_dsp::filter::*;
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::Instant;
 
-fn run_benchmark<P: AsRef<Path>>(path: P) -> u32 {
+/// Feeds the samples of a WAV file, downsampled to the
+/// optimal sample rate, to `f`.
+fn for_each_sample<P: AsRef<Path>, F: FnMut(f32)>(path: P, mut f: F) {
     use std::fs::File;
-    const X25: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_SDLC);
 
     let mut inp_file = File::open(path.as_ref()).unwrap();
     let (header, data) = wav::read(&mut inp_file).unwrap();
 
-    let mut decoder = bell_202_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
     let mut downsampler =
         Downsampler::<f32>::new(header.sampling_rate, BELL202_OPTIMAL_SAMPLE_RATE);
 
-    let mut framecount = 0u32;
-    let mut badframecount = 0u32;
     let mut drop = false;
 
     match data {
@@ -53,32 +55,38 @@ fn run_benchmark<P: AsRef<Path>>(path: P) -> u32 {
                 let sample = sample as f32 / (std::i16::MAX as f32 / 4.0 * 3.0);
 
                 // Downsample
-                let sample = if let Some(sample) = downsampler.filter(sample) {
-                    sample
-                } else {
-                    continue;
-                };
-
-                // Decode
-                let out = decoder.filter(sample);
-
-                if let Some(frame) = out {
-                    if frame.len() < 7 {
-                        continue;
-                    }
-
-                    if X25.checksum(&frame) != 0x0f47 {
-                        if Ax25Debug(&frame).is_ax25() {
-                            badframecount += 1;
-                        }
-                    } else {
-                        framecount += 1;
-                    }
+                if let Some(sample) = downsampler.filter(sample) {
+                    f(sample);
                 }
             }
         }
         _ => panic!("bad data"),
     }
+}
+
+fn run_benchmark<P: AsRef<Path>>(path: P) -> u32 {
+    let mut decoder = bell_202_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+
+    let mut framecount = 0u32;
+    let mut badframecount = 0u32;
+
+    for_each_sample(path.as_ref(), |sample| {
+        // Decode
+        if let Some(frame) = decoder.filter(sample) {
+            if frame.len() < 7 {
+                return;
+            }
+
+            if Fcs::X25.verify(&frame).is_err() {
+                if Ax25Frame::try_from_bytes(&frame[..frame.len() - 2]).is_ok() {
+                    badframecount += 1;
+                }
+            } else {
+                framecount += 1;
+            }
+        }
+    });
+
     println!(
         "{}: Success:{} Bad-CRC:{}, Total:{}",
         path.as_ref().to_str().unwrap(),
@@ -89,6 +97,39 @@ fn run_benchmark<P: AsRef<Path>>(path: P) -> u32 {
     framecount
 }
 
+/// Like `run_benchmark`, but repairing frames that fail the FCS check.
+fn run_repair_benchmark<P: AsRef<Path>>(path: P) -> u32 {
+    let mut decoder = bell_202_repairing_decoder(BELL202_OPTIMAL_SAMPLE_RATE, FcsRepair::default());
+
+    let mut framecount = 0u32;
+    let mut repaired = HashMap::<&'static str, u32>::new();
+    let started = Instant::now();
+
+    for_each_sample(path.as_ref(), |sample| {
+        if let Some(checked) = decoder.filter(sample) {
+            framecount += 1;
+            if let Some(repair) = checked.repair {
+                let method = match repair.method {
+                    RepairMethod::SingleBit(_) => "single-bit",
+                    RepairMethod::AdjacentBits(_) => "adjacent-bits",
+                    RepairMethod::Restuffed(_) => "restuffed",
+                    RepairMethod::TwoBits(..) => "two-bits",
+                };
+                *repaired.entry(method).or_default() += 1;
+            }
+        }
+    });
+
+    println!(
+        "{}: Success:{} Repaired:{:?} in {:?}",
+        path.as_ref().to_str().unwrap(),
+        framecount,
+        repaired,
+        started.elapsed()
+    );
+    framecount
+}
+
 fn get_path(file: &str) -> String {
     format!("../contrib/TNCTestCD/{}", file)
 }
@@ -140,3 +181,396 @@ fn benchmark_testcd04() {
     }
     assert!(run_benchmark(path) >= 87);
 }
+
+#[test]
+fn benchmark_testcd01_repair() {
+    let path_str = get_path("testcd01.wav");
+    let path = Path::new(&path_str);
+    if !path.exists() {
+        eprintln!("File {:?} doesn't exist, skipping test.", path);
+        return;
+    }
+    assert!(run_repair_benchmark(path) >= run_benchmark(path));
+}
+
+#[test]
+fn benchmark_testcd02_repair() {
+    let path_str = get_path("testcd02.wav");
+    let path = Path::new(&path_str);
+    if !path.exists() {
+        eprintln!("File {:?} doesn't exist, skipping test.", path);
+        return;
+    }
+    assert!(run_repair_benchmark(path) >= run_benchmark(path));
+}
+
+/// Sends 100 copies of a test frame, each encoded by `encode` and
+/// followed by a gap, through a channel with uniform noise of peak-to-peak
+/// amplitude `noise`. The noise is seeded, so runs are repeatable.
+fn noisy_samples<I: IntoIterator<Item = f32>>(
+    noise: f32,
+    mut encode: impl FnMut(Vec<u8>) -> I,
+) -> Vec<f32> {
+    use rand::{Rng, SeedableRng};
+
+    let frame: Vec<u8> = hex::decode("82a0aa646a9ce0ae8270989a8c60ae92888a62406303f03e3230323333377a687474703a2f2f7761386c6d662e636f6d0df782").unwrap();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+    let mut samples = vec![];
+    for _ in 0..100 {
+        samples.extend(encode(frame.clone()));
+        samples.extend(std::iter::repeat_n(0.0, 200));
+    }
+    for sample in samples.iter_mut() {
+        *sample += (rng.gen::<f32>() - 0.5) * noise;
+    }
+    samples
+}
+
+/// Decodes frames sent through a noisy channel, with and without
+/// repair, returning the number of frames with a valid FCS for each.
+fn run_noise_benchmark(noise: f32) -> (u32, u32) {
+    let samples = noisy_samples(noise, |frame| {
+        bell_202_encode::<f32, _>(frame.into_iter(), BELL202_OPTIMAL_SAMPLE_RATE, 0.5)
+    });
+
+    let mut decoder = bell_202_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+    let plain = samples
+        .iter()
+        .filter_map(|&x| decoder.filter(x))
+        .filter(|x| Fcs::X25.verify(x).is_ok())
+        .count() as u32;
+
+    let mut decoder = bell_202_repairing_decoder(BELL202_OPTIMAL_SAMPLE_RATE, FcsRepair::default());
+    let repaired = samples.iter().filter_map(|&x| decoder.filter(x)).count() as u32;
+
+    println!(
+        "Noise {}: Success:{} With repair:{}",
+        noise, plain, repaired
+    );
+    (plain, repaired)
+}
+
+#[test]
+fn benchmark_noise_repair() {
+    let mut total_plain = 0;
+    let mut total_repaired = 0;
+    for noise in [0.6, 0.7, 0.8] {
+        let (plain, repaired) = run_noise_benchmark(noise);
+        assert!(repaired >= plain);
+        total_plain += plain;
+        total_repaired += repaired;
+    }
+    assert!(total_repaired > total_plain);
+}
+
+/// Sends frames through a noisy channel with FX.25, decoding them with
+/// a plain decoder and with an FX.25 decoder. Returns the number of
+/// frames with a valid FCS for each.
+fn run_fx25_noise_benchmark(noise: f32, check_bytes: usize) -> (u32, u32) {
+    let samples = noisy_samples(noise, |frame| {
+        bell_202_encode_fx25::<f32>(&frame, check_bytes, BELL202_OPTIMAL_SAMPLE_RATE, 0.5).unwrap()
+    });
+
+    let mut decoder = bell_202_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+    let plain = samples
+        .iter()
+        .filter_map(|&x| decoder.filter(x))
+        .filter(|x| Fcs::X25.verify(x).is_ok())
+        .count() as u32;
+
+    let mut decoder = bell_202_fx25_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+    let fx25 = samples
+        .iter()
+        .filter_map(|&x| decoder.filter(x))
+        .filter(|x| Fcs::X25.verify(x).is_ok())
+        .count() as u32;
+
+    println!(
+        "Noise {} FX.25 check bytes {}: Success:{} With FX.25:{}",
+        noise, check_bytes, plain, fx25
+    );
+    (plain, fx25)
+}
+
+/// Slow, so only run with `--ignored`.
+#[test]
+#[ignore]
+fn benchmark_noise_fx25() {
+    for check_bytes in [16, 32] {
+        let mut total_plain = 0;
+        let mut total_fx25 = 0;
+        for noise in [0.6, 0.7, 0.8] {
+            let (plain, fx25) = run_fx25_noise_benchmark(noise, check_bytes);
+            assert!(fx25 >= plain);
+            total_plain += plain;
+            total_fx25 += fx25;
+        }
+        assert!(total_fx25 > total_plain);
+    }
+}
+
+/// Sends frames through a noisy channel with the given framing,
+/// returning the number of frames received with a valid FCS.
+fn run_framing_noise_benchmark(noise: f32, framing: Framing) -> u32 {
+    let samples = noisy_samples(noise, |frame| {
+        bell_202_encode_framed(frame, framing, BELL202_OPTIMAL_SAMPLE_RATE, 0.5)
+    });
+
+    let mut decoder = bell_202_framed_decoder(BELL202_OPTIMAL_SAMPLE_RATE, framing);
+    let count = samples
+        .iter()
+        .filter_map(|&x| decoder.filter(x))
+        .filter(|x| Fcs::X25.verify(x).is_ok())
+        .count() as u32;
+
+    println!("Noise {} {:?}: Success:{}", noise, framing, count);
+    count
+}
+
+#[test]
+fn benchmark_noise_il2p() {
+    let mut total_hdlc = 0;
+    let mut total_il2p = 0;
+    for noise in [0.6, 0.7, 0.8] {
+        let hdlc = run_framing_noise_benchmark(noise, Framing::Hdlc);
+        let il2p = run_framing_noise_benchmark(noise, Framing::Il2p);
+        assert!(il2p >= hdlc);
+        total_hdlc += hdlc;
+        total_il2p += il2p;
+    }
+    assert!(total_il2p > total_hdlc);
+}
+
+/// Sends frames from a sender whose bit clock is off by `clock_error`
+/// (as a fraction) through a noisy channel, decoding them with the
+/// given clock recovery. Returns the number of frames with a valid FCS.
+fn run_clock_recovery_benchmark(noise: f32, clock_error: f32, pll: bool) -> u32 {
+    let sender = AfskProfile {
+        baud: (BELL202_RATE as f32 * (1.0 + clock_error)).round() as u32,
+        ..AfskProfile::BELL_202
+    };
+    let samples = noisy_samples(noise, |frame| {
+        sender.encode(frame.into_iter(), BELL202_OPTIMAL_SAMPLE_RATE, 0.5)
+    });
+
+    let mut decoder = AfskProfile::BELL_202
+        .with_pll(pll)
+        .decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+    let count = samples
+        .iter()
+        .filter_map(|&x| decoder.filter(x))
+        .filter(|x| Fcs::X25.verify(x).is_ok())
+        .count() as u32;
+
+    println!(
+        "Noise {} clock error {} PLL {}: Success:{}",
+        noise, clock_error, pll, count
+    );
+    count
+}
+
+/// The PLL is the default bit sampler, so it must do at least as well
+/// as `BitSampler` with noise and with sender clock error.
+#[test]
+fn benchmark_clock_recovery() {
+    let mut total_bit_sampler = 0;
+    let mut total_pll = 0;
+    for clock_error in [0.0, -0.01, 0.01] {
+        for noise in [0.6, 0.7] {
+            let bit_sampler = run_clock_recovery_benchmark(noise, clock_error, false);
+            let pll = run_clock_recovery_benchmark(noise, clock_error, true);
+            assert!(pll >= bit_sampler);
+            total_bit_sampler += bit_sampler;
+            total_pll += pll;
+        }
+    }
+    assert!(total_pll > total_bit_sampler);
+}
+
+/// Sends frames with twist (the space tone's gain relative to the mark
+/// tone's), tones off by `offset` Hz and noise, decoding them with a
+/// single decoder and with a decoder bank. Returns the number of frames
+/// with a valid FCS for each.
+fn run_impairment_benchmark(space_gain: f32, offset: f32, noise: f32) -> (u32, u32) {
+    let sample_rate = BELL202_OPTIMAL_SAMPLE_RATE as f32;
+    let mark = (BELL202_MARK as f32 + offset) / sample_rate;
+    let space = (BELL202_SPACE as f32 + offset) / sample_rate;
+
+    let mut phase = 0.0f32;
+    let samples = noisy_samples(noise, |frame| {
+        let bits = frame
+            .into_iter()
+            .bits_lsb()
+            .hdlc_encode()
+            .nrzi_encode()
+            .resample_nn(sample_rate / BELL202_RATE as f32);
+
+        // Like `FmMod`, but with a different amplitude for each tone.
+        bits.map(|bit| {
+            let (freq, gain) = if bit {
+                (mark, 1.0)
+            } else {
+                (space, space_gain)
+            };
+            phase = (phase + freq).fract();
+            (phase * std::f32::consts::TAU).sin() * 0.5 * gain
+        })
+        .collect::<Vec<_>>()
+    });
+
+    let mut decoder = bell_202_decoder(BELL202_OPTIMAL_SAMPLE_RATE);
+    let single = samples
+        .iter()
+        .filter_map(|&x| decoder.filter(x))
+        .filter(|x| Fcs::X25.verify(x).is_ok())
+        .count() as u32;
+
+    let mut bank =
+        Bell202DecoderBank::new(BELL202_OPTIMAL_SAMPLE_RATE, &DemodBranch::default_bank());
+    let banked = samples.iter().filter_map(|&x| bank.filter(x)).count() as u32;
+
+    println!(
+        "Space gain {} offset {}Hz noise {}: Success:{} With bank:{} By branch:{:?}",
+        space_gain,
+        offset,
+        noise,
+        single,
+        banked,
+        bank.counts()
+    );
+    (single, banked)
+}
+
+/// Slow, so only run with `--ignored`.
+#[test]
+#[ignore]
+fn benchmark_impairment_bank() {
+    // The least number of extra frames the bank should decode. A single
+    // decoder gets about two thirds of 100 frames at noise 0.7 with or
+    // without twist, about a fifth with twist at 0.8, and about a third
+    // at 0.6 with the tones 100Hz off.
+    for (space_gain, offset, noise, margin) in [
+        (1.0, 0.0, 0.7, 3),
+        (2.0, 0.0, 0.7, 25),
+        (2.0, 0.0, 0.8, 50),
+        (1.0, 100.0, 0.6, 40),
+        (1.0, -100.0, 0.6, 40),
+    ] {
+        let (single, banked) = run_impairment_benchmark(space_gain, offset, noise);
+        assert!(
+            banked >= single + margin,
+            "space gain {} offset {}Hz: {} vs {}",
+            space_gain,
+            offset,
+            banked,
+            single
+        );
+    }
+}
+
+fn run_bank_benchmark<P: AsRef<Path>>(path: P) -> u32 {
+    let mut bank =
+        Bell202DecoderBank::new(BELL202_OPTIMAL_SAMPLE_RATE, &DemodBranch::default_bank());
+
+    let mut framecount = 0u32;
+    for_each_sample(path.as_ref(), |sample| {
+        if bank.filter(sample).is_some() {
+            framecount += 1;
+        }
+    });
+
+    println!(
+        "{}: Success:{} By branch:{:?}",
+        path.as_ref().to_str().unwrap(),
+        framecount,
+        bank.counts()
+    );
+    framecount
+}
+
+#[test]
+fn benchmark_testcd01_bank() {
+    let path_str = get_path("testcd01.wav");
+    let path = Path::new(&path_str);
+    if !path.exists() {
+        eprintln!("File {:?} doesn't exist, skipping test.", path);
+        return;
+    }
+    assert!(run_bank_benchmark(path) > run_benchmark(path).max(950));
+}
+
+/// Like `noisy_samples` for Bell 202, with two clicks of impulse noise,
+/// `click_len` samples long, at random places in each frame.
+fn clicky_samples(noise: f32, click_len: usize, click_amplitude: f32) -> Vec<f32> {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    noisy_samples(noise, |frame| {
+        let mut samples =
+            bell_202_encode::<f32, _>(frame.into_iter(), BELL202_OPTIMAL_SAMPLE_RATE, 0.5)
+                .collect::<Vec<_>>();
+
+        // Skip the preamble and the closing flags.
+        let len = samples.len();
+        for _ in 0..2 {
+            let start = rng.gen_range(len / 4..len * 9 / 10);
+            for sample in &mut samples[start..start + click_len] {
+                *sample += (rng.gen::<f32>() - 0.5) * click_amplitude;
+            }
+        }
+        samples
+    })
+}
+
+/// Decodes frames, repairing them with hard and with soft decisions.
+/// Returns the number of frames with a valid FCS and the total repair
+/// attempts for each.
+fn run_soft_benchmark(name: &str, samples: &[f32]) -> ((u32, usize), (u32, usize)) {
+    let count = |frames: Vec<CheckedFrame>| {
+        let attempts = frames
+            .iter()
+            .filter_map(|x| x.repair)
+            .map(|x| x.attempts)
+            .sum::<usize>();
+        (frames.len() as u32, attempts)
+    };
+
+    let mut decoder = bell_202_repairing_decoder(BELL202_OPTIMAL_SAMPLE_RATE, FcsRepair::default());
+    let hard = count(samples.iter().filter_map(|&x| decoder.filter(x)).collect());
+
+    let mut decoder =
+        bell_202_soft_repairing_decoder(BELL202_OPTIMAL_SAMPLE_RATE, FcsRepair::default());
+    let soft = count(samples.iter().filter_map(|&x| decoder.filter(x)).collect());
+
+    println!(
+        "{}: Hard:{} ({} attempts) Soft:{} ({} attempts)",
+        name, hard.0, hard.1, soft.0, soft.1
+    );
+    (hard, soft)
+}
+
+#[test]
+fn benchmark_noise_soft() {
+    // White noise mostly damages one bit at a time (two adjacent bits
+    // after NRZI decoding), which hard decisions repair too, so soft
+    // decisions only make repairs cheaper.
+    for noise in [0.6, 0.7, 0.8] {
+        let samples = noisy_samples(noise, |frame| {
+            bell_202_encode::<f32, _>(frame.into_iter(), BELL202_OPTIMAL_SAMPLE_RATE, 0.5)
+        });
+        let name = format!("Noise {}", noise);
+        let ((hard, hard_attempts), (soft, soft_attempts)) = run_soft_benchmark(&name, &samples);
+        assert!(soft >= hard);
+        assert!(soft_attempts < hard_attempts);
+    }
+
+    // Two clicks in a frame usually damage two separate bits, which only
+    // soft decisions can find.
+    for (click_len, click_amplitude) in [(3, 3.0), (2, 4.0)] {
+        let samples = clicky_samples(0.1, click_len, click_amplitude);
+        let name = format!("Clicks of {} samples", click_len);
+        let ((hard, _), (soft, _)) = run_soft_benchmark(&name, &samples);
+        assert!(soft > hard, "{}: {} vs {}", name, soft, hard);
+    }
+}